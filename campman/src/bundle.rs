@@ -0,0 +1,230 @@
+//! Bundles contain nodes together with the nodes they are linked to, like the tags and places of
+//! an NPC, so they can be moved between campaigns. A bundle is written as json if the file name
+//! ends with .json, and as toml otherwise.
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use database::db::{DB, TAG_NODE_TYPE};
+use database::meta::{self, Meta};
+use file_format::Format;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::database;
+
+const BUNDLE_FORMAT: Format = Format {
+    name: "bundle",
+    legacy_key: Some("version"),
+    migrations: &[file_format::unchanged, meta_from_text],
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub nodes: Vec<BundleNode>,
+    #[serde(default)]
+    pub links: Vec<BundleLink>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleNode {
+    /// identifies the node inside of the bundle, links refer to it
+    pub key: i64,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub r#type: String,
+    #[serde(default, skip_serializing_if = "Meta::is_empty")]
+    pub meta: Meta,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleLink {
+    pub left: i64,
+    pub right: i64,
+    #[serde(rename = "type")]
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+/// what happens to a bundled node, if the campaign already has a node with the same type and
+/// name. Tags are always merged with the existing ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// the existing node is kept, and links are added to it
+    KeepExisting,
+    /// meta and data of the existing node are overwritten
+    Replace,
+    /// the bundled node is imported under a new name
+    Copy,
+}
+
+/// exports the nodes and everything they are linked to
+pub fn export(ids: &[i64], path: &Path) -> Result<usize> {
+    let mut db = database();
+    let mut nodes = BTreeMap::new();
+    let mut links = BTreeMap::new();
+    for id in ids {
+        nodes.insert(*id, db.select_node(*id)?);
+        for (link, other) in db.select_linked_nodes(*id)? {
+            nodes.insert(other.id, other);
+            links.insert(link.id, link);
+        }
+    }
+    drop(db);
+
+    let bundle = Bundle {
+        nodes: nodes
+            .into_values()
+            .map(|n| {
+                Ok(BundleNode {
+                    key: n.id,
+                    data: String::from_utf8(n.data)
+                        .with_context(|| format!("The data of {} is not text", n.name))?,
+                    name: n.name,
+                    r#type: n.r#type,
+                    meta: n.meta,
+                })
+            })
+            .collect::<Result<_>>()?,
+        links: links
+            .into_values()
+            .map(|l| {
+                Ok(BundleLink {
+                    left: l.left,
+                    right: l.right,
+                    r#type: l.r#type,
+                    data: l.data.map(String::from_utf8).transpose()?,
+                })
+            })
+            .collect::<Result<_>>()?,
+    };
+    let text = if is_json(path) {
+        BUNDLE_FORMAT.to_json(&bundle)?
+    } else {
+        BUNDLE_FORMAT.to_toml(&bundle)?
+    };
+    std::fs::write(path, text).context(path.display().to_string())?;
+    Ok(bundle.nodes.len())
+}
+
+pub fn read(path: &Path) -> Result<Bundle> {
+    let text = std::fs::read_to_string(path).context(path.display().to_string())?;
+    if is_json(path) {
+        BUNDLE_FORMAT.from_json(&text)
+    } else {
+        BUNDLE_FORMAT.from_toml(&text)
+    }
+    .context(path.display().to_string())
+}
+
+/// version 1 stored the metadata of nodes as the text of the meta column
+fn meta_from_text(bundle: &mut Map<String, Value>) -> Result<()> {
+    let nodes = bundle.get_mut("nodes").and_then(Value::as_array_mut);
+    for node in nodes.into_iter().flatten().filter_map(Value::as_object_mut) {
+        if let Some(Value::String(text)) = node.get("meta") {
+            let meta = meta::from_column(Some(text.clone()));
+            node.insert("meta".into(), serde_json::to_value(meta)?);
+        }
+    }
+    Ok(())
+}
+
+/// names of the bundled nodes that already exist in the campaign, tags excluded
+pub fn conflicts(bundle: &Bundle) -> Result<Vec<String>> {
+    let mut db = database();
+    let mut res = vec![];
+    for node in bundle.nodes.iter().filter(|n| n.r#type != TAG_NODE_TYPE) {
+        if db.find_node(&node.r#type, &node.name)?.is_some() {
+            res.push(node.name.clone());
+        }
+    }
+    Ok(res)
+}
+
+/// imports the bundle, either completely, or not at all. Returns the number of new nodes.
+pub fn import(bundle: &Bundle, policy: ConflictPolicy) -> Result<usize> {
+    database().in_transaction(|db| {
+        let mut ids = HashMap::new();
+        let mut n_new = 0;
+        for node in &bundle.nodes {
+            let existing = db.find_node(&node.r#type, &node.name)?;
+            let id = match existing {
+                Some(id) if node.r#type == TAG_NODE_TYPE => id,
+                Some(id) if policy == ConflictPolicy::KeepExisting => id,
+                Some(id) if policy == ConflictPolicy::Replace => {
+                    db.update_node(id, &node.meta, node.data.as_bytes())?;
+                    id
+                }
+                Some(_) => {
+                    n_new += 1;
+                    let name = free_name(db, &node.r#type, &node.name)?;
+                    db.insert_node(&name, &node.r#type, &node.meta, node.data.as_bytes())?
+                }
+                None => {
+                    n_new += 1;
+                    db.insert_node(&node.name, &node.r#type, &node.meta, node.data.as_bytes())?
+                }
+            };
+            ids.insert(node.key, id);
+        }
+        for link in &bundle.links {
+            let (left, right) = match (ids.get(&link.left), ids.get(&link.right)) {
+                (Some(left), Some(right)) => (left, right),
+                _ => bail!(
+                    "A {} link refers to a node that is not in the bundle",
+                    link.r#type
+                ),
+            };
+            if !db.has_link(*left, *right, &link.r#type)? {
+                let data = link.data.clone().map(String::into_bytes);
+                db.insert_link(*left, *right, &link.r#type, data)?;
+            }
+        }
+        Ok(n_new)
+    })
+}
+
+/// appends a number to the name, so it doesn't clash with an existing node
+fn free_name(db: &mut DB, r#type: &str, name: &str) -> Result<String> {
+    for i in 2.. {
+        let candidate = format!("{} ({})", name, i);
+        if db.find_node(r#type, &candidate)?.is_none() {
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().map(|e| e == "json").unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::meta::MetaValue;
+
+    #[test]
+    fn test_read_old_bundles() {
+        let v1 = "version = 1\n\n[[nodes]]\nkey = 1\nname = \"Mira\"\ntype = \"npc\"\n\
+                  meta = '{\"age\": \"31\"}'\ndata = \"{}\"\n\n\
+                  [[nodes]]\nkey = 2\nname = \"Harborton\"\ntype = \"place\"\n\
+                  meta = \"a quiet town\"\ndata = \"\"\n";
+        let bundle: Bundle = BUNDLE_FORMAT.from_toml(v1).unwrap();
+        assert_eq!(
+            bundle.nodes[0].meta,
+            Meta::from([("age".to_string(), MetaValue::Text("31".into()))])
+        );
+        assert_eq!(
+            bundle.nodes[1].meta,
+            meta::from_column(Some("a quiet town".into()))
+        );
+
+        let text = BUNDLE_FORMAT.to_toml(&bundle).unwrap();
+        assert!(text.starts_with("format_version = 2\n"));
+        let read: Bundle = BUNDLE_FORMAT.from_toml(&text).unwrap();
+        assert_eq!(read.nodes[1].meta, bundle.nodes[1].meta);
+    }
+}
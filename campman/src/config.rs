@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use file_format::Format;
+use serde::{Deserialize, Serialize};
+
+use crate::CONFIG_PATH;
+
+/// config.toml is written by hand, so it usually has no version, which is the same as version 1
+const CONFIG_FORMAT: Format = Format {
+    name: "config.toml",
+    legacy_key: None,
+    migrations: &[file_format::unchanged],
+};
+
+/// how many options are offered per value a field needs, if config.toml doesn't say otherwise
+pub const DEFAULT_OPTIONS_PER_VALUE: usize = 3;
+
+/// Contents of config.toml. Every key is optional, a missing file equals an empty one. The
+/// settings tab writes the file too.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// url of the manifest that is used to check for updates of the app and installed packs.
+    /// Update checks are disabled if this is not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_manifest: Option<String>,
+    /// deleted entities are purged from the trash after this many days
+    pub trash_retention_days: u32,
+    /// the database is copied into a snapshot this often. 0 disables the snapshots
+    pub snapshot_interval_minutes: u64,
+    /// how many snapshots are kept
+    pub snapshot_count: usize,
+    /// clients of `campman serve` have to send this as bearer token. The server refuses to start
+    /// without it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_token: Option<String>,
+    /// a directory that is shared between devices, for example through Dropbox or git. Every
+    /// device writes its changes there, syncing is disabled if this is not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_dir: Option<String>,
+    /// opens the combat tracker with an encounter from the encounter tab, like
+    /// `alacritty -e combat-tracker`. The participant file is passed as last argument
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combat_command: Option<String>,
+    /// the Markdown that generated NPCs are exported as, see npc_export for its placeholders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub npc_markdown_template: Option<String>,
+    pub theme: ThemeChoice,
+    /// the directory of npc_gen.toml, the option files and the other files that are read from
+    /// the config dir, instead of the directory of config.toml
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_dir: Option<String>,
+    /// where the campaign is stored, instead of campaign.db in the data dir
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_path: Option<String>,
+    /// the blueprint the NPC generator starts with, instead of asking for one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_blueprint: Option<String>,
+    /// how many options the NPC generator offers per value a field needs
+    pub options_per_value: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeChoice {
+    #[default]
+    Light,
+    Dark,
+    /// white on black with bright colors, for projectors at the table
+    HighContrast,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            update_manifest: None,
+            trash_retention_days: 30,
+            snapshot_interval_minutes: 30,
+            snapshot_count: 5,
+            api_token: None,
+            sync_dir: None,
+            combat_command: None,
+            npc_markdown_template: None,
+            theme: ThemeChoice::Light,
+            config_dir: None,
+            database_path: None,
+            default_blueprint: None,
+            options_per_value: DEFAULT_OPTIONS_PER_VALUE,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let path = CONFIG_PATH.get().unwrap();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = std::fs::read_to_string(path).context("Could not load config.toml")?;
+        CONFIG_FORMAT
+            .from_toml(&text)
+            .context("Could not parse config.toml")
+    }
+
+    /// overwrites config.toml, comments in it are lost
+    pub fn save(&self) -> Result<()> {
+        let path = CONFIG_PATH.get().unwrap();
+        let text = CONFIG_FORMAT.to_toml(self)?;
+        std::fs::write(path, text).context("Could not write config.toml")
+    }
+}
@@ -0,0 +1,161 @@
+//! A small demo campaign, so the app can be explored before entering any data. It is generated
+//! into a temporary database, which replaces the campaign until the demo is left again.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+use database::db::DB;
+use database::meta::{Meta, MetaValue};
+
+use crate::notes::NOTE_NODE_TYPE;
+use crate::npc::{FieldKind, Npc};
+use crate::npc_store::NPC_NODE_TYPE;
+use crate::{campaign_db_path, database};
+
+pub const PLACE_NODE_TYPE: &str = "place";
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// name, role, where they live, tags and a note
+const NPCS: &[(&str, &str, &str, &[&str], &str)] = &[
+    (
+        "Mira Quell",
+        "Innkeeper",
+        "The Salty Eel",
+        &["harbor"],
+        "Hears every rumor, sells the good ones.",
+    ),
+    (
+        "Captain Oderic",
+        "Harbor master",
+        "Harborton",
+        &["harbor", "suspect"],
+        "Looks the other way for the right price.",
+    ),
+    (
+        "Brother Tam",
+        "Keeper of the light",
+        "Old Lighthouse",
+        &[],
+        "Saw a ship without lights the night the cargo vanished.",
+    ),
+    (
+        "Sella",
+        "Fence",
+        "The Salty Eel",
+        &["suspect"],
+        "Owes [[Captain Oderic]] a favor.",
+    ),
+];
+
+/// name and description
+const PLACES: &[(&str, &str)] = &[
+    ("Harborton", "A fishing town that grew rich on smuggling."),
+    ("The Salty Eel", "A tavern at the docks, in Harborton."),
+    ("Old Lighthouse", "On the cliffs north of Harborton."),
+];
+
+/// name, text, and the nodes it is about
+const NOTES: &[(&str, &str, &[&str])] = &[
+    (
+        "Session 1",
+        "The party arrived in [[Harborton]] and was hired by [[Mira Quell]] to find a missing \
+         cargo.",
+        &["Mira Quell", "Harborton"],
+    ),
+    (
+        "Rumors",
+        "The cargo was never unloaded. Someone paid [[Captain Oderic]] to forget it. Ask \
+         [[Brother Tam]].",
+        &["Captain Oderic", "Sella"],
+    ),
+];
+
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// replaces the campaign with a freshly generated demo campaign
+pub fn enter() -> Result<()> {
+    let path = std::env::temp_dir().join("campman-demo.db");
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    let mut db = DB::new(&path)?;
+    populate(&mut db)?;
+    *database() = db;
+    ACTIVE.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// opens the real campaign again
+pub fn leave() -> Result<()> {
+    *database() = DB::new(&campaign_db_path())?;
+    ACTIVE.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn populate(db: &mut DB) -> Result<()> {
+    for (name, description) in PLACES {
+        let meta = Meta::from([("description".into(), MetaValue::from(*description))]);
+        db.insert_node(name, PLACE_NODE_TYPE, &meta, description.as_bytes())?;
+    }
+    for (name, role, home, tags, note) in NPCS {
+        let mut npc = Npc::new(None);
+        npc.set("name", FieldKind::Text, vec![name.to_string()]);
+        npc.set("role", FieldKind::Text, vec![role.to_string()]);
+        npc.set("note", FieldKind::Text, vec![note.to_string()]);
+        let meta = Meta::from([("location".into(), MetaValue::from(*home))]);
+        let id = db.insert_node(name, NPC_NODE_TYPE, &meta, &serde_json::to_vec(&npc)?)?;
+        let home = db
+            .find_node(PLACE_NODE_TYPE, home)?
+            .with_context(|| format!("{} is missing", home))?;
+        db.insert_link(id, home, "lives in", None)?;
+        for tag in *tags {
+            db.add_tag(&[id], tag)?;
+        }
+    }
+    for (name, text, about) in NOTES {
+        let id = db.insert_node(name, NOTE_NODE_TYPE, &Meta::new(), text.as_bytes())?;
+        for other in *about {
+            let other = match db.find_node(NPC_NODE_TYPE, other)? {
+                Some(id) => id,
+                None => db
+                    .find_node(PLACE_NODE_TYPE, other)?
+                    .with_context(|| format!("{} is missing", other))?,
+            };
+            db.insert_link(id, other, "about", None)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::dsl::{MetaKey, NodeFieldName};
+
+    #[test]
+    fn test_populate() {
+        let mut db = DB::new(std::path::Path::new(":memory:")).unwrap();
+        populate(&mut db).unwrap();
+        let npcs = db
+            .select_nodes(&NodeFieldName::Type.eq(NPC_NODE_TYPE))
+            .unwrap();
+        assert_eq!(npcs.len(), NPCS.len());
+        let in_tavern = db
+            .select_nodes(&MetaKey::new("location").eq("The Salty Eel"))
+            .unwrap();
+        assert_eq!(in_tavern.len(), 2);
+
+        let oderic = npcs.iter().find(|n| n.name == "Captain Oderic").unwrap();
+        assert_eq!(db.select_tags(oderic.id).unwrap().len(), 2);
+        let linked: Vec<String> = db
+            .select_linked_nodes(oderic.id)
+            .unwrap()
+            .into_iter()
+            .map(|(_, node)| node.name)
+            .collect();
+        assert!(linked.contains(&"Harborton".to_string()));
+        assert!(linked.contains(&"Rumors".to_string()));
+    }
+}
@@ -0,0 +1,111 @@
+use dice::Rolled;
+use iced::widget::{column, row, Button, Column, Scrollable, Text, TextInput};
+use iced::{Alignment, Element, Length};
+use iced_aw::TabLabel;
+
+use super::{Message, Tab};
+
+/// older rolls are dropped from the history
+const HISTORY_LEN: usize = 200;
+
+pub struct DiceTab {
+    expr: String,
+    /// the rolls of this session, the latest last
+    history: Vec<Rolled>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum DiceMessage {
+    ExprChanged(String),
+    Roll,
+    /// rolls the expression of the history entry with the given index again
+    Reroll(usize),
+    ClearHistory,
+}
+
+impl DiceTab {
+    pub fn new() -> DiceTab {
+        DiceTab {
+            expr: String::new(),
+            history: vec![],
+            error: None,
+        }
+    }
+
+    pub fn update(&mut self, message: DiceMessage) {
+        match message {
+            DiceMessage::ExprChanged(expr) => self.expr = expr,
+            DiceMessage::Roll => {
+                let expr = self.expr.clone();
+                self.roll(&expr);
+            }
+            DiceMessage::Reroll(i) => {
+                let expr = self.history[i].expr.clone();
+                self.roll(&expr);
+            }
+            DiceMessage::ClearHistory => self.history.clear(),
+        }
+    }
+
+    fn roll(&mut self, expr: &str) {
+        match dice::roll_detailed(expr) {
+            Ok(rolled) => {
+                self.error = None;
+                if self.history.len() == HISTORY_LEN {
+                    self.history.remove(0);
+                }
+                self.history.push(rolled);
+            }
+            Err(e) => self.error = Some(format!("{:#}", e)),
+        }
+    }
+}
+
+impl Tab for DiceTab {
+    type Message = Message;
+
+    fn tab_label(&self) -> TabLabel {
+        TabLabel::Text("Dice".into())
+    }
+
+    fn content(&self) -> Element<'_, Self::Message> {
+        let mut col = column!(row!(
+            TextInput::new(
+                "Dice, like 3d6+2, 4d6dl (drop the lowest), 2d20kh1 or adv",
+                &self.expr,
+                DiceMessage::ExprChanged
+            )
+            .on_submit(DiceMessage::Roll)
+            .padding(5)
+            .width(Length::Fill),
+            Button::new("Roll").on_press(DiceMessage::Roll),
+            Button::new("Clear history").on_press(DiceMessage::ClearHistory)
+        )
+        .spacing(10))
+        .spacing(20);
+        if let Some(error) = &self.error {
+            col = col.push(Text::new(error));
+        }
+        // the latest roll first
+        let entries = self.history.iter().enumerate().rev().map(|(i, rolled)| {
+            let dice: Vec<String> = rolled.dice.iter().map(|d| d.to_string()).collect();
+            row!(
+                Text::new(rolled.total.to_string())
+                    .size(28)
+                    .width(Length::Units(80)),
+                column!(Text::new(&rolled.expr).size(20), Text::new(dice.join("; ")))
+                    .width(Length::Fill),
+                Button::new("Again").on_press(DiceMessage::Reroll(i))
+            )
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .into()
+        });
+        col = col.push(Scrollable::new(
+            Column::with_children(entries.collect()).spacing(10),
+        ));
+        let content: Element<'_, DiceMessage> = col.into();
+        content.map(Message::DiceMsg)
+    }
+}
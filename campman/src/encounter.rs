@@ -0,0 +1,434 @@
+//! Encounters are assembled from stat blocks, which are monster nodes in the database, or
+//! templates in the bestiary directory of the config dir. Both use the line format of the
+//! combat tracker: monster nodes hold `<HP>[: <Ini>] <Stat>=<Value>...` as data, and bestiary
+//! files declare templates with `@<Name>: <HP>...` lines. The stat XP is used to estimate the
+//! difficulty of an encounter. Saved NPCs can join encounters too, with the values of their `hp`
+//! and `ini` fields. NPCs without HP get them set in the encounter.
+use std::cmp::Reverse;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use database::db::DB;
+use database::dsl::NodeFieldName;
+use database::meta::Meta;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+
+use crate::conf_dir;
+use crate::npc::Npc;
+use crate::npc_store::{self, NPC_NODE_TYPE};
+
+pub const MONSTER_NODE_TYPE: &str = "monster";
+
+/// the source of stat blocks that are stored in the database
+pub const DATABASE_SOURCE: &str = "database";
+/// the source of the stat blocks of saved NPCs
+pub const NPC_SOURCE: &str = "npc";
+
+/// the XP thresholds of a character for an easy, medium, hard and deadly encounter, by level,
+/// like in D&D 5e
+const XP_THRESHOLDS: [[u32; 4]; 20] = [
+    [25, 50, 75, 100],
+    [50, 100, 150, 200],
+    [75, 150, 225, 400],
+    [125, 250, 375, 500],
+    [250, 500, 750, 1100],
+    [300, 600, 900, 1400],
+    [350, 750, 1100, 1700],
+    [450, 900, 1400, 2100],
+    [550, 1100, 1600, 2400],
+    [600, 1200, 1900, 2800],
+    [800, 1600, 2400, 3600],
+    [1000, 2000, 3000, 4500],
+    [1100, 2200, 3400, 5100],
+    [1250, 2500, 3800, 5700],
+    [1400, 2800, 4300, 6400],
+    [1600, 3200, 4800, 7200],
+    [2000, 3900, 5900, 8800],
+    [2100, 4200, 6300, 9500],
+    [2400, 4900, 7300, 10900],
+    [2800, 5700, 8500, 12700],
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatBlock {
+    pub name: String,
+    /// everything after the name, like `7/7 DEX=2 XP=50`
+    pub stats: String,
+    /// the file of the template, or DATABASE_SOURCE
+    pub source: String,
+}
+
+impl StatBlock {
+    /// the value of the stat XP, 0 without it
+    pub fn xp(&self) -> u32 {
+        self.stats
+            .split_whitespace()
+            .find_map(|token| {
+                let (name, value) = token.split_once('=')?;
+                name.eq_ignore_ascii_case("xp")
+                    .then(|| value.parse().ok())?
+            })
+            .unwrap_or(0)
+    }
+
+    /// the HP at the start of the stats, like `7/7`, empty if they aren't set
+    pub fn hp(&self) -> &str {
+        let stats = self.stats.trim_start();
+        let end = stats
+            .find(|c: char| !(c.is_ascii_digit() || c == '/'))
+            .unwrap_or(stats.len());
+        &stats[..end]
+    }
+
+    /// replaces the HP, the other stats are kept
+    pub fn set_hp(&mut self, hp: &str) -> Result<()> {
+        let hp = hp.trim();
+        let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        let valid = match hp.split_once('/') {
+            Some((current, max)) => is_number(current) && is_number(max),
+            None => is_number(hp),
+        };
+        ensure!(valid, "{} are no valid HP, use a number like 7, or 5/7", hp);
+        let rest = &self.stats.trim_start()[self.hp().len()..];
+        self.stats = format!("{}{}", hp, rest);
+        Ok(())
+    }
+}
+
+/// the stats of a saved NPC, from its fields `hp` and `ini`
+fn npc_stats(npc: &Npc) -> String {
+    let value = |name: &str| {
+        npc.fields
+            .iter()
+            .find(|f| f.name.eq_ignore_ascii_case(name))
+            .and_then(|f| f.values.first())
+            .map(|v| v.trim().to_string())
+    };
+    match (value("hp"), value("ini")) {
+        (Some(hp), Some(ini)) => format!("{}: {}", hp, ini),
+        (Some(hp), None) => hp,
+        (None, _) => String::new(),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Bestiary {
+    pub stat_blocks: Vec<StatBlock>,
+    /// files that couldn't be loaded, with their error
+    pub errors: Vec<(String, String)>,
+}
+
+pub fn bestiary_dir() -> PathBuf {
+    conf_dir().join("bestiary")
+}
+
+impl Bestiary {
+    /// loads the monster nodes, the saved NPCs, and the templates of all files in the directory,
+    /// which doesn't need to exist
+    pub fn load(db: &mut DB, dir: &Path) -> Result<Bestiary> {
+        let mut bestiary = Bestiary::default();
+        for node in db.select_nodes(&NodeFieldName::Type.eq(MONSTER_NODE_TYPE))? {
+            bestiary.stat_blocks.push(StatBlock {
+                name: node.name,
+                stats: String::from_utf8_lossy(&node.data).trim().to_string(),
+                source: DATABASE_SOURCE.into(),
+            });
+        }
+        for node in db.select_nodes(&NodeFieldName::Type.eq(NPC_NODE_TYPE))? {
+            // NPCs that can't be decoded are still listed, without stats
+            let stats = npc_store::npc_from_node(&node)
+                .map(|npc| npc_stats(&npc))
+                .unwrap_or_default();
+            bestiary.stat_blocks.push(StatBlock {
+                name: node.name,
+                stats,
+                source: NPC_SOURCE.into(),
+            });
+        }
+        let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path())).collect(),
+            Err(_) => return Ok(bestiary),
+        };
+        paths.sort();
+        for path in paths.into_iter().filter(|p| p.is_file()) {
+            let source = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let res = fs::read_to_string(&path)
+                .map_err(Into::into)
+                .and_then(|text| parse_templates(&source, &text));
+            match res {
+                Ok(stat_blocks) => bestiary.stat_blocks.extend(stat_blocks),
+                Err(e) => bestiary
+                    .errors
+                    .push((path.display().to_string(), format!("{:#}", e))),
+            }
+        }
+        Ok(bestiary)
+    }
+
+    /// the stat blocks whose name matches the query, best match first, with their index
+    pub fn search(&self, query: &str) -> Vec<(usize, &StatBlock)> {
+        if query.trim().is_empty() {
+            return self.stat_blocks.iter().enumerate().collect();
+        }
+        let matcher = SkimMatcherV2::default();
+        let mut hits: Vec<(i64, (usize, &StatBlock))> = self
+            .stat_blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| matcher.fuzzy_match(&b.name, query).map(|s| (s, (i, b))))
+            .collect();
+        hits.sort_by_key(|(score, _)| Reverse(*score));
+        hits.into_iter().map(|(_, hit)| hit).collect()
+    }
+}
+
+/// the template declarations of a bestiary file, other lines are ignored
+fn parse_templates(source: &str, text: &str) -> Result<Vec<StatBlock>> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| line.trim().strip_prefix('@').map(|t| (i, t)))
+        .map(|(i, line)| {
+            let (name, stats) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("line {}: the HP are missing", i + 1))?;
+            ensure!(
+                !name.trim().is_empty(),
+                "line {}: the name is missing",
+                i + 1
+            );
+            Ok(StatBlock {
+                name: name.trim().into(),
+                stats: stats.trim().into(),
+                source: source.into(),
+            })
+        })
+        .collect()
+}
+
+/// stores a stat block, given as `<Name>: <HP>...`, as monster node
+pub fn store_stat_block(db: &mut DB, line: &str) -> Result<StatBlock> {
+    let (name, stats) = line
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Stat blocks must have the format <Name>: <HP> <Stat>=<Value>"))?;
+    let (name, stats) = (name.trim(), stats.trim());
+    ensure!(!name.is_empty(), "The name is missing");
+    ensure!(!stats.is_empty(), "The HP are missing");
+    db.insert_node(name, MONSTER_NODE_TYPE, &Meta::new(), stats.as_bytes())?;
+    Ok(StatBlock {
+        name: name.into(),
+        stats: stats.into(),
+        source: DATABASE_SOURCE.into(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Difficulty {
+    Trivial,
+    Easy,
+    Medium,
+    Hard,
+    Deadly,
+}
+
+impl Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Difficulty::Trivial => "trivial",
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::Deadly => "deadly",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// the stat blocks of an encounter, with how often they appear
+#[derive(Debug, Clone, Default)]
+pub struct Encounter {
+    pub groups: Vec<(StatBlock, usize)>,
+}
+
+impl Encounter {
+    /// adds the stat block count times, a stat block that is already part of the encounter
+    /// just appears more often
+    pub fn add(&mut self, stat_block: &StatBlock, count: usize) {
+        match self.groups.iter_mut().find(|(b, _)| b == stat_block) {
+            Some((_, n)) => *n += count,
+            None => self.groups.push((stat_block.clone(), count)),
+        }
+    }
+
+    pub fn remove(&mut self, idx: usize) {
+        if idx < self.groups.len() {
+            self.groups.remove(idx);
+        }
+    }
+
+    /// sets the HP of all members of the group with the given index
+    pub fn set_hp(&mut self, idx: usize, hp: &str) -> Result<()> {
+        match self.groups.get_mut(idx) {
+            Some((stat_block, _)) => stat_block.set_hp(hp),
+            None => Ok(()),
+        }
+    }
+
+    pub fn n_monsters(&self) -> usize {
+        self.groups.iter().map(|(_, n)| n).sum()
+    }
+
+    /// the XP of all monsters, multiplied for the number of monsters, as larger groups are more
+    /// dangerous than their XP suggest
+    pub fn adjusted_xp(&self) -> u32 {
+        let xp: u32 = self.groups.iter().map(|(b, n)| b.xp() * *n as u32).sum();
+        let multiplier = match self.n_monsters() {
+            0 | 1 => 1.0,
+            2 => 1.5,
+            3..=6 => 2.0,
+            7..=10 => 2.5,
+            11..=14 => 3.0,
+            _ => 4.0,
+        };
+        (xp as f64 * multiplier) as u32
+    }
+
+    /// a rough estimate, based on the XP thresholds of the party
+    pub fn difficulty(&self, party_size: u32, party_level: u32) -> Difficulty {
+        let level = party_level.clamp(1, 20) as usize;
+        let xp = self.adjusted_xp();
+        let thresholds = XP_THRESHOLDS[level - 1].map(|t| t * party_size);
+        let difficulties = [
+            Difficulty::Easy,
+            Difficulty::Medium,
+            Difficulty::Hard,
+            Difficulty::Deadly,
+        ];
+        thresholds
+            .iter()
+            .zip(difficulties)
+            .rev()
+            .find(|(threshold, _)| xp >= **threshold)
+            .map(|(_, d)| d)
+            .unwrap_or(Difficulty::Trivial)
+    }
+
+    /// the encounter as file for the combat tracker. Groups are spawned with `xN=<Count>`. Fails
+    /// if a participant has no HP
+    pub fn participant_file(&self) -> Result<String> {
+        self.groups
+            .iter()
+            .map(|(b, n)| {
+                ensure!(!b.hp().is_empty(), "Set the HP of {} first", b.name);
+                Ok(if *n == 1 {
+                    format!("{}: {}\n", b.name, b.stats)
+                } else {
+                    format!("{}: {} xN={}\n", b.name, b.stats, n)
+                })
+            })
+            .collect()
+    }
+}
+
+/// writes the encounter to a file in the data dir, and opens it with the combat command
+pub fn launch(encounter: &Encounter, command: &str, dir: &Path) -> Result<()> {
+    let mut words = command.split_whitespace();
+    let program = words.next().context("The combat command is empty")?;
+    fs::create_dir_all(dir)?;
+    let path = dir.join("encounter.txt");
+    fs::write(&path, encounter.participant_file()?).context(path.display().to_string())?;
+    Command::new(program)
+        .args(words)
+        .arg(&path)
+        .spawn()
+        .with_context(|| format!("Could not run {}", command))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npc::FieldKind;
+
+    #[test]
+    fn test_encounter() {
+        let mut db = DB::new(Path::new(":memory:")).unwrap();
+        let dir = std::env::temp_dir().join(format!("bestiary-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("goblins.txt"),
+            "@Goblin: 7/7 DEX=2 XP=50\nnot a template\n@Goblin Boss: 21: 14 XP=200\n",
+        )
+        .unwrap();
+        fs::write(dir.join("broken.txt"), "@Nameless\n").unwrap();
+        store_stat_block(&mut db, "Wolf: 11 XP=50").unwrap();
+        assert!(store_stat_block(&mut db, "Wolf").is_err());
+
+        let bestiary = Bestiary::load(&mut db, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<&str> = bestiary
+            .stat_blocks
+            .iter()
+            .map(|b| b.name.as_str())
+            .collect();
+        assert_eq!(names, ["Wolf", "Goblin", "Goblin Boss"]);
+        assert_eq!(bestiary.errors.len(), 1);
+        assert_eq!(bestiary.search("gob boss")[0].0, 2);
+
+        let mut encounter = Encounter::default();
+        encounter.add(&bestiary.stat_blocks[1], 2);
+        encounter.add(&bestiary.stat_blocks[2], 1);
+        encounter.add(&bestiary.stat_blocks[1], 2);
+        assert_eq!(
+            encounter.participant_file().unwrap(),
+            "Goblin: 7/7 DEX=2 XP=50 xN=4\nGoblin Boss: 21: 14 XP=200\n"
+        );
+        // 400 XP, doubled for 5 monsters
+        assert_eq!(encounter.adjusted_xp(), 800);
+        assert_eq!(encounter.difficulty(4, 1), Difficulty::Deadly);
+        assert_eq!(encounter.difficulty(4, 3), Difficulty::Medium);
+        assert_eq!(encounter.difficulty(4, 8), Difficulty::Trivial);
+        encounter.remove(0);
+        assert_eq!(encounter.n_monsters(), 1);
+    }
+
+    #[test]
+    fn test_npc_participants() {
+        let mut db = DB::new(Path::new(":memory:")).unwrap();
+        let mut npc = Npc::new(None);
+        npc.set("HP", FieldKind::Number, vec!["12".into()]);
+        npc.set("ini", FieldKind::Number, vec!["3".into()]);
+        let data = serde_json::to_vec(&npc).unwrap();
+        db.insert_node("Mira", NPC_NODE_TYPE, &Meta::new(), &data)
+            .unwrap();
+        let data = serde_json::to_vec(&Npc::new(None)).unwrap();
+        db.insert_node("Oderic", NPC_NODE_TYPE, &Meta::new(), &data)
+            .unwrap();
+
+        let bestiary = Bestiary::load(&mut db, Path::new("/does/not/exist")).unwrap();
+        let mira = &bestiary.stat_blocks[0];
+        assert_eq!((mira.stats.as_str(), mira.hp()), ("12: 3", "12"));
+        assert_eq!(mira.source, NPC_SOURCE);
+        let oderic = &bestiary.stat_blocks[1];
+        assert_eq!(oderic.hp(), "");
+
+        let mut encounter = Encounter::default();
+        encounter.add(mira, 1);
+        encounter.add(oderic, 1);
+        assert!(encounter.participant_file().is_err());
+        encounter.set_hp(1, "20").unwrap();
+        encounter.set_hp(0, "5/12").unwrap();
+        assert!(encounter.set_hp(0, "many").is_err());
+        assert!(encounter.set_hp(0, "5/").is_err());
+        assert_eq!(
+            encounter.participant_file().unwrap(),
+            "Mira: 5/12: 3\nOderic: 20\n"
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use anyhow::{anyhow, ensure, Context, Result};
+use iced::widget::{column, row, Button, Column, PickList, Scrollable, Text, TextInput};
+use iced::{Alignment, Command, Element, Length};
+use iced_aw::TabLabel;
+
+use super::{Message, Tab};
+use crate::config::Config;
+use crate::encounter::{self, Bestiary, Encounter};
+use crate::iced_utils::{load_async, render_loading};
+use crate::random_encounter::{self, EncounterTables};
+use crate::{database, DATA_DIR};
+
+pub struct EncounterTab {
+    /// set while the bestiary and the tables are loaded in the background
+    loading: bool,
+    bestiary: Bestiary,
+    query: String,
+    /// how many copies the add buttons add
+    count: String,
+    encounter: Encounter,
+    /// the index of the group whose HP are edited, and the entered HP
+    hp_edit: Option<(usize, String)>,
+    party_size: String,
+    party_level: String,
+    /// a stat block that is stored in the database, as `<Name>: <HP>...`
+    new_stat_block: String,
+    path: String,
+    tables: EncounterTables,
+    /// the terrain random encounters are rolled for
+    terrain: Option<String>,
+    /// the result of the last action, or its error
+    notice: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum EncounterMessage {
+    Reload,
+    Loaded(Result<(Bestiary, EncounterTables), String>),
+    QueryChanged(String),
+    CountChanged(String),
+    /// adds the stat block with the given index in the bestiary
+    Add(usize),
+    /// removes the group with the given index from the encounter
+    Remove(usize),
+    /// starts to edit the HP of the group with the given index
+    EditHp(usize),
+    HpChanged(String),
+    ApplyHp,
+    Clear,
+    PartySizeChanged(String),
+    PartyLevelChanged(String),
+    NewStatBlockChanged(String),
+    StoreStatBlock,
+    PathChanged(String),
+    /// writes the participant file to the path
+    Export,
+    /// opens the encounter in the combat tracker
+    Launch,
+    TerrainSelected(String),
+    /// replaces the encounter with a random one for the terrain and the party level
+    Roll,
+    /// rolls a random encounter, and opens it in the combat tracker right away
+    RollAndLaunch,
+}
+
+impl EncounterTab {
+    /// the tab, and the command that loads the bestiary and the tables
+    pub fn new() -> (EncounterTab, Command<Message>) {
+        let mut tab = EncounterTab {
+            loading: false,
+            bestiary: Bestiary::default(),
+            query: String::new(),
+            count: "1".into(),
+            encounter: Encounter::default(),
+            hp_edit: None,
+            party_size: "4".into(),
+            party_level: "1".into(),
+            new_stat_block: String::new(),
+            path: String::new(),
+            tables: EncounterTables::default(),
+            terrain: None,
+            notice: None,
+        };
+        let load = tab.reload();
+        (tab, load)
+    }
+
+    pub fn update(&mut self, message: EncounterMessage) -> Command<Message> {
+        self.inner_update(message).unwrap_or_else(|e| {
+            self.notice = Some(format!("{:#}", e));
+            Command::none()
+        })
+    }
+
+    fn reload(&mut self) -> Command<Message> {
+        self.loading = true;
+        load_async(
+            || {
+                let bestiary = Bestiary::load(&mut database(), &encounter::bestiary_dir())?;
+                let tables = EncounterTables::load(&random_encounter::tables_path())?;
+                Ok((bestiary, tables))
+            },
+            |res| Message::EncounterMsg(EncounterMessage::Loaded(res)),
+        )
+    }
+
+    fn inner_update(&mut self, message: EncounterMessage) -> Result<Command<Message>> {
+        use EncounterMessage::*;
+        match message {
+            Reload => return Ok(self.reload()),
+            Loaded(res) => {
+                self.loading = false;
+                let (bestiary, tables) = res.map_err(|e| anyhow!(e))?;
+                self.bestiary = bestiary;
+                self.tables = tables;
+                let terrains = self.tables.terrains();
+                if !self.terrain.iter().any(|t| terrains.contains(t)) {
+                    self.terrain = terrains.into_iter().next();
+                }
+            }
+            QueryChanged(query) => self.query = query,
+            CountChanged(count) => self.count = count,
+            Add(idx) => {
+                let count: usize = self
+                    .count
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("{} is not a valid count", self.count))?;
+                ensure!(count > 0, "The count must be at least 1");
+                if let Some(stat_block) = self.bestiary.stat_blocks.get(idx) {
+                    self.encounter.add(stat_block, count);
+                }
+            }
+            Remove(idx) => {
+                self.hp_edit = None;
+                self.encounter.remove(idx);
+            }
+            EditHp(idx) => {
+                if let Some((stat_block, _)) = self.encounter.groups.get(idx) {
+                    self.hp_edit = Some((idx, stat_block.hp().into()));
+                }
+            }
+            HpChanged(hp) => {
+                if let Some((_, text)) = &mut self.hp_edit {
+                    *text = hp;
+                }
+            }
+            ApplyHp => {
+                if let Some((idx, hp)) = &self.hp_edit {
+                    self.encounter.set_hp(*idx, hp)?;
+                    self.hp_edit = None;
+                }
+            }
+            Clear => {
+                self.hp_edit = None;
+                self.encounter = Encounter::default();
+            }
+            PartySizeChanged(size) => self.party_size = size,
+            PartyLevelChanged(level) => self.party_level = level,
+            NewStatBlockChanged(line) => self.new_stat_block = line,
+            StoreStatBlock => {
+                let stat_block =
+                    encounter::store_stat_block(&mut database(), &self.new_stat_block)?;
+                self.notice = Some(format!("Stored {}", stat_block.name));
+                self.new_stat_block.clear();
+                return Ok(self.reload());
+            }
+            PathChanged(path) => self.path = path,
+            Export => {
+                let path = self.path.trim();
+                ensure!(
+                    !path.is_empty(),
+                    "Enter the path of the participant file first"
+                );
+                std::fs::write(path, self.encounter.participant_file()?)
+                    .context(path.to_string())?;
+                self.notice = Some(format!("Exported to {}", path));
+            }
+            Launch => self.launch()?,
+            TerrainSelected(terrain) => self.terrain = Some(terrain),
+            Roll => self.roll()?,
+            RollAndLaunch => {
+                self.roll()?;
+                self.launch()?;
+            }
+        }
+        Ok(Command::none())
+    }
+
+    fn launch(&mut self) -> Result<()> {
+        let command = Config::load()?.combat_command.ok_or_else(|| {
+            anyhow!("Set combat-command in config.toml to open the combat tracker")
+        })?;
+        let dir = DATA_DIR.get().unwrap().join("campman/encounters");
+        encounter::launch(&self.encounter, &command, &dir)?;
+        self.notice = None;
+        Ok(())
+    }
+
+    fn roll(&mut self) -> Result<()> {
+        let terrain = self
+            .terrain
+            .as_deref()
+            .ok_or_else(|| anyhow!("Choose a terrain first"))?;
+        let level: u32 = self
+            .party_level
+            .trim()
+            .parse()
+            .with_context(|| format!("{} is not a valid level", self.party_level))?;
+        self.encounter =
+            self.tables
+                .roll(terrain, level, &self.bestiary, &mut rand::thread_rng())?;
+        self.hp_edit = None;
+        self.notice = None;
+        Ok(())
+    }
+
+    /// the difficulty for the party, or why it can't be estimated
+    fn difficulty(&self) -> String {
+        match (
+            self.party_size.trim().parse(),
+            self.party_level.trim().parse(),
+        ) {
+            (Ok(size), Ok(level)) => format!(
+                "{} XP (adjusted for {} monsters), {} for the party",
+                self.encounter.adjusted_xp(),
+                self.encounter.n_monsters(),
+                self.encounter.difficulty(size, level)
+            ),
+            _ => "Enter the size and the level of the party to estimate the difficulty".into(),
+        }
+    }
+}
+
+impl Tab for EncounterTab {
+    type Message = Message;
+
+    fn tab_label(&self) -> TabLabel {
+        TabLabel::Text("Encounter".into())
+    }
+
+    fn content(&self) -> Element<'_, Self::Message> {
+        let content: Element<'_, EncounterMessage> = if self.loading {
+            render_loading()
+        } else {
+            row!(render_bestiary(self), render_encounter(self))
+                .spacing(20)
+                .into()
+        };
+        content.map(Message::EncounterMsg)
+    }
+}
+
+fn render_bestiary(tab: &EncounterTab) -> Element<'_, EncounterMessage> {
+    let mut col = column!(
+        row!(
+            TextInput::new("Search", &tab.query, EncounterMessage::QueryChanged)
+                .padding(5)
+                .width(Length::Fill),
+            TextInput::new("Count", &tab.count, EncounterMessage::CountChanged)
+                .padding(5)
+                .width(Length::Units(60)),
+            Button::new("Reload").on_press(EncounterMessage::Reload)
+        )
+        .spacing(10),
+        row!(
+            TextInput::new(
+                "New stat block, like Goblin: 7 DEX=2 XP=50",
+                &tab.new_stat_block,
+                EncounterMessage::NewStatBlockChanged
+            )
+            .on_submit(EncounterMessage::StoreStatBlock)
+            .padding(5),
+            Button::new("Store").on_press(EncounterMessage::StoreStatBlock)
+        )
+        .spacing(10)
+    )
+    .spacing(10)
+    .width(Length::FillPortion(1));
+    for (file, error) in &tab.bestiary.errors {
+        col = col.push(Text::new(format!("{} failed to load:\n{}", file, error)));
+    }
+    if tab.bestiary.stat_blocks.is_empty() {
+        col = col.push(Text::new(format!(
+            "Store stat blocks above, or put files with templates like @Goblin: 7 XP=50 into {}",
+            encounter::bestiary_dir().display()
+        )));
+    }
+    let stat_blocks = tab.bestiary.search(&tab.query).into_iter().map(|(i, b)| {
+        row!(
+            Text::new(format!("{}: {} ({})", b.name, b.stats, b.source)).width(Length::Fill),
+            Button::new("Add").on_press(EncounterMessage::Add(i))
+        )
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .into()
+    });
+    col.push(Scrollable::new(
+        Column::with_children(stat_blocks.collect()).spacing(5),
+    ))
+    .into()
+}
+
+fn render_encounter(tab: &EncounterTab) -> Element<'_, EncounterMessage> {
+    let groups = tab.encounter.groups.iter().enumerate().map(|(i, (b, n))| {
+        let hp: Element<'_, EncounterMessage> = match &tab.hp_edit {
+            Some((idx, hp)) if *idx == i => row!(
+                TextInput::new("HP", hp, EncounterMessage::HpChanged)
+                    .on_submit(EncounterMessage::ApplyHp)
+                    .padding(5)
+                    .width(Length::Units(80)),
+                Button::new("Set").on_press(EncounterMessage::ApplyHp)
+            )
+            .spacing(5)
+            .align_items(Alignment::Center)
+            .into(),
+            _ => {
+                let label = match b.hp() {
+                    "" => "Set HP".to_string(),
+                    hp => format!("{} HP", hp),
+                };
+                Button::new(Text::new(label))
+                    .on_press(EncounterMessage::EditHp(i))
+                    .into()
+            }
+        };
+        row!(
+            Text::new(format!("{}x {}", n, b.name)).width(Length::Fill),
+            hp,
+            Button::new("Remove").on_press(EncounterMessage::Remove(i))
+        )
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .into()
+    });
+    let mut col = column!(
+        Text::new("Encounter").size(24),
+        Column::with_children(groups.collect()).spacing(5),
+        row!(
+            TextInput::new(
+                "Party size",
+                &tab.party_size,
+                EncounterMessage::PartySizeChanged
+            )
+            .padding(5),
+            TextInput::new(
+                "Party level",
+                &tab.party_level,
+                EncounterMessage::PartyLevelChanged
+            )
+            .padding(5)
+        )
+        .spacing(10),
+        Text::new(tab.difficulty()),
+        row!(
+            TextInput::new("Participant file", &tab.path, EncounterMessage::PathChanged).padding(5),
+            Button::new("Export").on_press(EncounterMessage::Export)
+        )
+        .spacing(10),
+        row!(
+            Button::new("Start the fight").on_press(EncounterMessage::Launch),
+            Button::new("Clear").on_press(EncounterMessage::Clear)
+        )
+        .spacing(10),
+        render_random_encounter(tab)
+    )
+    .spacing(10)
+    .width(Length::FillPortion(1));
+    if let Some(notice) = &tab.notice {
+        col = col.push(Text::new(notice));
+    }
+    col.into()
+}
+
+fn render_random_encounter(tab: &EncounterTab) -> Element<'_, EncounterMessage> {
+    if tab.tables.tables.is_empty() {
+        return Text::new(format!(
+            "Random encounters are rolled on the tables in {}",
+            random_encounter::tables_path().display()
+        ))
+        .into();
+    }
+    row!(
+        Text::new("Random encounter in"),
+        PickList::new(
+            tab.tables.terrains(),
+            tab.terrain.clone(),
+            EncounterMessage::TerrainSelected
+        ),
+        Button::new("Roll").on_press(EncounterMessage::Roll),
+        Button::new("Roll and start the fight").on_press(EncounterMessage::RollAndLaunch)
+    )
+    .spacing(10)
+    .align_items(Alignment::Center)
+    .into()
+}
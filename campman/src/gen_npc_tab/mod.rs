@@ -1,23 +1,34 @@
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::iter::once;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 use anyhow::{anyhow, Context, Result};
 use derive_new::new;
 use iced::alignment::Horizontal;
-use iced::theme::Button as ButtonTheme;
-use iced::widget::{column, row, Button, Column, Container, Row, Space, Text};
-use iced::{Alignment, Color, Element, Length};
+use iced::keyboard::{self, KeyCode};
+use iced::theme::{Button as ButtonTheme, Container as ContainerTheme};
+use iced::widget::{
+    column, container, row, Button, Column, Container, Row, Scrollable, Space, Text, TextInput,
+};
+use iced::{event, Alignment, Color, Command, Element, Event, Length, Subscription, Theme};
 use iced_aw::TabLabel;
 use itertools::Itertools;
-use rand::seq::IteratorRandom;
 use toml::Value;
 
 use super::{Message, Tab};
+use crate::conf_dir;
+use crate::config::Config;
+use crate::iced_utils::{self, load_async, render_loading, render_npc_with_controls};
+use crate::npc::{Npc, NpcField};
+use crate::{npc_export, npc_store};
 use macros::try_as;
 mod npc_builder;
-use npc_builder::{load_blueprints_from_table, NpcBlueprint, NpcBuilder, StringMap};
+use npc_builder::{
+    choose_weighted, load_blueprints_from_table, NpcBlueprint, NpcBuilder, NumberSource,
+};
+pub use npc_builder::{options_cache, DisplayConfig, Problem};
 
 /// enables creation of a new state by moving components of the old state.
 /// first swaps the old state with a placeholder, then creates the new state
@@ -42,67 +53,226 @@ macro_rules! with_state {
 type Blueprints = HashMap<String, NpcBlueprint>;
 pub struct GenNpcTab {
     state: State,
+    /// the name of the NPC that was saved last, shown until the next one is generated
+    saved: Option<String>,
+    /// the problems of npc_gen.toml, once it was validated
+    problems: Option<Vec<String>>,
+    /// the blueprint that is started once the blueprints are loaded, from config.toml
+    default_blueprint: Option<String>,
+    /// from config.toml
+    options_per_value: usize,
 }
 
 #[derive(Debug)]
 enum State {
+    /// the blueprints are read in the background
+    Loading,
     Error(String),
     Initiated(Box<Blueprints>),
     Building(Box<Blueprints>, NpcBuilder, BuildingData),
-    Finalizing(Box<Blueprints>, StringMap),
+    /// the builder holds the completed NPC, so its fields can still be changed
+    Finalizing(Box<Blueprints>, NpcBuilder, FinalizingData),
 }
 
 #[derive(Debug, new)]
 struct BuildingData {
     all_options: Vec<String>,
+    /// the options are rolled by these, options without a weight weigh 1
+    weights: HashMap<String, f64>,
     /// the bool implies whether the option is selected currently
     displayed_options: HashMap<String, bool>,
     n: usize,
     field_name: String,
+    /// the typed or rolled value of number fields, which have no options
+    number: Option<(NumberSource, String)>,
+    /// the option that has the keyboard focus, none until an arrow key or Tab is pressed
+    #[new(default)]
+    focused: Option<String>,
+}
+
+/// how the keyboard focus moves between the displayed options
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusMove {
+    /// down the column, and on to the top of the next one
+    Next,
+    Previous,
+    /// to the neighbouring column
+    Left,
+    Right,
+}
+
+#[derive(Debug)]
+struct FinalizingData {
+    /// the name the NPC is saved under
+    name: String,
+    /// the NPC of the builder with its placeholders resolved, as it is saved
+    npc: Npc,
+    editing: Option<FieldEdit>,
+    /// the result of the last export
+    notice: Option<String>,
+}
+
+/// a field of the completed NPC that is being changed
+#[derive(Debug)]
+struct FieldEdit {
+    field: String,
+    options: Vec<String>,
+    n: usize,
+    selected: Vec<String>,
+    custom_value: String,
+    /// Some for number fields, the custom value must be one of its numbers
+    number: Option<NumberSource>,
 }
 
 #[derive(Debug, Clone)]
 pub enum GenNpcMessage {
+    /// reloads the blueprints
     ReInit,
+    BlueprintsLoaded(Result<Box<Blueprints>, String>),
+    /// checks npc_gen.toml and lists its problems
+    ValidateBlueprints,
     GenNpc(String),
+    /// builds an NPC of the blueprint without asking anything, and goes to the finalizing screen
+    GenRandomNpc(String),
     AttribSelected(String),
+    /// answers the remaining fields by their auto policy, until a field must be prompted for
+    AutoFill,
+    /// rolls other options for the current field, the selected ones are kept, or another number
+    RerollOptions,
+    NumberChanged(String),
+    AcceptNumber,
+    ShowAllOptions,
+    /// leaves the current field out of the NPC, if it is optional
+    SkipField,
+    MoveFocus(FocusMove),
+    /// selects or deselects the focused option, like clicking it
+    ToggleFocused,
+    /// returns to the field that was answered last
+    Back,
+    NameChanged(String),
+    SaveNpc,
+    /// copies the NPC as Markdown
+    CopyToClipboard,
+    ExportMarkdown,
+    ExportJson,
+    /// opens the editor of a field of the completed NPC
+    EditField(String),
+    RerollField(String),
+    EditOptionToggled(String),
+    CustomValueChanged(String),
+    /// sets the field to the selected options
+    ApplyEdit,
+    /// sets the field to the custom value, which is split at commas
+    ApplyCustomValue,
+    CancelEdit,
 }
 
 impl GenNpcTab {
-    pub fn new() -> GenNpcTab {
-        let attempt = || -> Result<GenNpcTab> {
-            let conf_text = std::fs::read_to_string("/home/felix/.config/campman/npc_gen.toml")
-                .context("Could not load npc_gen.toml")?;
-            let t = conf_text.parse::<Value>()?;
-            let t = load_blueprints_from_table(try_as!(t, table)?.clone())?;
-            Ok(GenNpcTab {
-                state: State::Initiated(Box::new(t)),
-            })
+    /// the tab, and the command that loads its blueprints
+    pub fn new() -> (GenNpcTab, Command<Message>) {
+        // the settings tab reports a broken config.toml
+        let config = Config::load().unwrap_or_default();
+        let tab = GenNpcTab {
+            state: State::Loading,
+            saved: None,
+            problems: None,
+            default_blueprint: config.default_blueprint,
+            options_per_value: config.options_per_value,
+        };
+        let load = load_async(
+            || Ok(Box::new(load_blueprints()?)),
+            |res| Message::GenNpcMsg(GenNpcMessage::BlueprintsLoaded(res)),
+        );
+        (tab, load)
+    }
+
+    /// the names of the blueprints, sorted, or none if they couldn't be loaded
+    pub fn blueprint_names(&self) -> Vec<String> {
+        match &self.state {
+            State::Loading | State::Error(_) => vec![],
+            State::Initiated(bps) | State::Building(bps, ..) | State::Finalizing(bps, ..) => {
+                bps.keys().sorted().cloned().collect()
+            }
+        }
+    }
+
+    pub fn update(&mut self, message: GenNpcMessage) -> Command<Message> {
+        let res = match message {
+            GenNpcMessage::ReInit => {
+                let (tab, load) = Self::new();
+                *self = tab;
+                Ok(load)
+            }
+            GenNpcMessage::BlueprintsLoaded(res) => {
+                self.state = match res {
+                    Ok(bps) => State::Initiated(bps),
+                    Err(e) => State::Error(e),
+                };
+                match &self.default_blueprint {
+                    Some(name) if self.blueprint_names().contains(name) => self
+                        .inner_update(GenNpcMessage::GenNpc(name.clone()))
+                        .map(|_| Command::none()),
+                    _ => Ok(Command::none()),
+                }
+            }
+            GenNpcMessage::CopyToClipboard => self.copy_to_clipboard(),
+            message => self.inner_update(message).map(|_| Command::none()),
         };
-        attempt().unwrap_or_else(|err| GenNpcTab {
-            state: State::Error(format!("{}", err)),
+        res.unwrap_or_else(|e| {
+            self.state = State::Error(format!("{}", e));
+            Command::none()
         })
     }
 
-    pub fn update(&mut self, message: GenNpcMessage) {
-        if let Err(e) = self.inner_update(message) {
-            self.state = State::Error(format!("{}", e))
+    /// drops the NPC that is being generated, if there is one
+    pub fn discard_npc(&mut self) {
+        let state = std::mem::replace(&mut self.state, State::Loading);
+        self.state = match state {
+            State::Building(bps, ..) | State::Finalizing(bps, ..) => State::Initiated(bps),
+            state => state,
+        };
+    }
+
+    fn copy_to_clipboard(&mut self) -> Result<Command<Message>> {
+        match &mut self.state {
+            State::Finalizing(_, builder, fd) => {
+                let text = markdown(builder, fd)?;
+                fd.notice = Some("Copied to the clipboard".into());
+                Ok(iced::clipboard::write(text))
+            }
+            _ => Ok(Command::none()),
         }
     }
 
     pub fn inner_update(&mut self, message: GenNpcMessage) -> Result<()> {
         use GenNpcMessage::*;
         match message {
-            ReInit => *self = Self::new(),
+            ValidateBlueprints => {
+                self.problems = Some(
+                    validate_blueprints()?
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                )
+            }
             GenNpc(name) => with_state! {&mut self.state,
                 State::Initiated(bps) => {
+                    self.saved = None;
                     let bp: NpcBlueprint = bps.get(&name).unwrap().clone();
-                    let builder = NpcBuilder::new(bp);
-                    let (field_name, opts, n) = builder.current_field_infos().unwrap();
-                    let rolled_options = roll_options(&opts, n);
-                    let displayed_opts = HashMap::from_iter(rolled_options);
-                    let bd = BuildingData::new(opts, displayed_opts, n, field_name);
-                    State::Building(bps, builder, bd)
+                    let mut builder = NpcBuilder::new(bp);
+                    builder.set_options_per_value(self.options_per_value);
+                    exclude_saved_values(&mut builder)?;
+                    new_building_state(bps, builder)
+                }
+            },
+            GenRandomNpc(name) => with_state! {&mut self.state,
+                State::Initiated(bps) => {
+                    self.saved = None;
+                    let mut builder = NpcBuilder::new(bps.get(&name).unwrap().clone());
+                    builder.set_options_per_value(self.options_per_value);
+                    exclude_saved_values(&mut builder)?;
+                    builder.fill_randomly(&mut rand::thread_rng())?;
+                    finalizing_state(bps, builder)
                 }
             },
             AttribSelected(s) => with_state! {&mut self.state,
@@ -113,8 +283,8 @@ impl GenNpcTab {
                         let selections = bd.displayed_options
                             .into_iter()
                             .filter_map(|(name, selected)| if selected {Some(name)} else {None});
-                            if let Some(npc) = builder.set_current_field_val(selections.collect())? {
-                                State::Finalizing(blueprints, npc)
+                            if builder.answer_field(&bd.field_name, selections.collect())?.is_some() {
+                                finalizing_state(blueprints, builder)
                             } else {
                                 new_building_state(blueprints, builder)
                             }
@@ -123,26 +293,419 @@ impl GenNpcTab {
                         }
                 }
             },
+            AutoFill => with_state! {&mut self.state,
+                State::Building(blueprints, mut builder, _) => {
+                    match builder.auto_fill(&mut rand::thread_rng())? {
+                        Some(_) => finalizing_state(blueprints, builder),
+                        None => new_building_state(blueprints, builder),
+                    }
+                }
+            },
+            RerollOptions => {
+                if let State::Building(_, builder, bd) = &mut self.state {
+                    if let Some((number, value)) = &mut bd.number {
+                        *value = number.roll(&mut rand::thread_rng()).to_string();
+                        return Ok(());
+                    }
+                    // new names, if the field has generators
+                    builder.generate_options(&bd.field_name, &mut rand::thread_rng());
+                    bd.all_options = builder.field_infos(&bd.field_name).0;
+                    let selected = bd.selected().cloned().collect_vec();
+                    let unselected = bd
+                        .all_options
+                        .iter()
+                        .filter(|o| !selected.contains(o))
+                        .cloned()
+                        .collect_vec();
+                    let n_rolled =
+                        (bd.n * builder.options_per_value()).saturating_sub(selected.len());
+                    bd.displayed_options = roll_options(&unselected, n_rolled, &bd.weights);
+                    bd.displayed_options
+                        .extend(selected.into_iter().map(|o| (o, true)));
+                }
+            }
+            NumberChanged(value) => {
+                if let State::Building(_, _, bd) = &mut self.state {
+                    if let Some((_, current)) = &mut bd.number {
+                        *current = value;
+                    }
+                }
+            }
+            AcceptNumber => with_state! {&mut self.state,
+                State::Building(blueprints, mut builder, bd) => {
+                    match &bd.number {
+                        Some((number, value)) if number.accepts(value) => {
+                            let value = value.trim().to_string();
+                            if builder.answer_field(&bd.field_name, vec![value])?.is_some() {
+                                finalizing_state(blueprints, builder)
+                            } else {
+                                new_building_state(blueprints, builder)
+                            }
+                        }
+                        _ => State::Building(blueprints, builder, bd),
+                    }
+                }
+            },
+            MoveFocus(direction) => {
+                if let State::Building(_, _, bd) = &mut self.state {
+                    let order = bd.displayed_options.keys().collect_vec();
+                    let current = bd
+                        .focused
+                        .as_ref()
+                        .and_then(|f| order.iter().position(|o| *o == f));
+                    let per_column = per_column(order.len(), bd.n);
+                    bd.focused = moved_focus(current, order.len(), per_column, direction)
+                        .map(|idx| order[idx].clone());
+                }
+            }
+            ToggleFocused => {
+                let focused = match &self.state {
+                    State::Building(_, _, bd) => bd
+                        .focused
+                        .clone()
+                        .filter(|f| bd.displayed_options.contains_key(f)),
+                    _ => None,
+                };
+                if let Some(option) = focused {
+                    self.inner_update(AttribSelected(option))?;
+                }
+            }
+            ShowAllOptions => {
+                if let State::Building(_, _, bd) = &mut self.state {
+                    for opt in &bd.all_options {
+                        bd.displayed_options.entry(opt.clone()).or_insert(false);
+                    }
+                }
+            }
+            SkipField => with_state! {&mut self.state,
+                State::Building(blueprints, mut builder, bd) => {
+                    if builder.skip_field(&bd.field_name)?.is_some() {
+                        finalizing_state(blueprints, builder)
+                    } else {
+                        new_building_state(blueprints, builder)
+                    }
+                }
+            },
+            Back => with_state! {&mut self.state,
+                State::Building(blueprints, mut builder, bd) => {
+                    match builder.undo() {
+                        Some(field) => building_state_for(blueprints, builder, field),
+                        None => State::Building(blueprints, builder, bd),
+                    }
+                }
+            },
+            NameChanged(name) => {
+                if let State::Finalizing(_, _, fd) = &mut self.state {
+                    fd.name = name;
+                }
+            }
+            SaveNpc => with_state! {&mut self.state,
+                State::Finalizing(blueprints, builder, fd) => {
+                    let trimmed = fd.name.trim();
+                    if trimmed.is_empty() {
+                        State::Finalizing(blueprints, builder, fd)
+                    } else {
+                        npc_store::save_npc(trimmed, &fd.npc)?;
+                        self.saved = Some(trimmed.into());
+                        State::Initiated(blueprints)
+                    }
+                }
+            },
+            ReInit | BlueprintsLoaded(_) | CopyToClipboard => unreachable!("handled by update"),
+            ExportMarkdown => {
+                if let State::Finalizing(_, builder, fd) = &mut self.state {
+                    let path = npc_export::write_export(&fd.name, "md", &markdown(builder, fd)?)?;
+                    fd.notice = Some(format!("Exported to {}", path.display()));
+                }
+            }
+            ExportJson => {
+                if let State::Finalizing(_, _, fd) = &mut self.state {
+                    let text = npc_export::to_json(&fd.name, &fd.npc)?;
+                    let path = npc_export::write_export(&fd.name, "json", &text)?;
+                    fd.notice = Some(format!("Exported to {}", path.display()));
+                }
+            }
+            EditField(field) => {
+                if let State::Finalizing(_, builder, fd) = &mut self.state {
+                    builder.generate_options(&field, &mut rand::thread_rng());
+                    let (options, n) = builder.edit_options(&field)?;
+                    let selected = builder.npc()[field.as_str()].to_vec();
+                    let number = builder.number_source(&field).cloned();
+                    fd.editing = Some(FieldEdit {
+                        custom_value: match number {
+                            Some(_) => selected.join(""),
+                            None => String::new(),
+                        },
+                        selected,
+                        field,
+                        options,
+                        n,
+                        number,
+                    });
+                }
+            }
+            RerollField(field) => {
+                if let State::Finalizing(_, builder, fd) = &mut self.state {
+                    builder.reroll_field(&field, &mut rand::thread_rng())?;
+                    fd.update_npc(builder);
+                }
+            }
+            EditOptionToggled(opt) => {
+                if let State::Finalizing(_, _, fd) = &mut self.state {
+                    if let Some(edit) = &mut fd.editing {
+                        if let Some(idx) = edit.selected.iter().position(|s| *s == opt) {
+                            edit.selected.remove(idx);
+                        } else if edit.selected.len() < edit.n {
+                            edit.selected.push(opt);
+                        }
+                    }
+                }
+            }
+            CustomValueChanged(value) => {
+                if let State::Finalizing(_, _, fd) = &mut self.state {
+                    if let Some(edit) = &mut fd.editing {
+                        edit.custom_value = value;
+                    }
+                }
+            }
+            ApplyEdit | ApplyCustomValue => {
+                if let State::Finalizing(_, builder, fd) = &mut self.state {
+                    if let Some(edit) = fd.editing.take() {
+                        if matches!(message, ApplyEdit) {
+                            builder.change_field(&edit.field, edit.selected)?;
+                        } else if let Some(number) = &edit.number {
+                            if !number.accepts(&edit.custom_value) {
+                                fd.editing = Some(edit);
+                                return Ok(());
+                            }
+                            let value = edit.custom_value.trim().to_string();
+                            builder.change_field(&edit.field, vec![value])?;
+                        } else {
+                            let values = edit
+                                .custom_value
+                                .split(',')
+                                .map(|v| v.trim().to_string())
+                                .filter(|v| !v.is_empty())
+                                .collect_vec();
+                            if values.is_empty() {
+                                fd.editing = Some(edit);
+                                return Ok(());
+                            }
+                            builder.set_custom_values(&edit.field, values)?;
+                        }
+                        fd.update_npc(builder);
+                    }
+                }
+            }
+            CancelEdit => {
+                if let State::Finalizing(_, _, fd) = &mut self.state {
+                    fd.editing = None;
+                }
+            }
         }
         Ok(())
     }
 }
 
+/// the npc_gen.toml that is written on the first run, with an example blueprint that documents
+/// the format
+const DEFAULT_BLUEPRINTS: &str = include_str!("../../npc_gen.toml");
+
+fn blueprints_path() -> PathBuf {
+    conf_dir().join("npc_gen.toml")
+}
+
+/// writes the example npc_gen.toml, unless there already is one
+pub fn write_default_blueprints() -> Result<()> {
+    let path = blueprints_path();
+    if !path.exists() {
+        std::fs::write(&path, DEFAULT_BLUEPRINTS)
+            .with_context(|| format!("Could not write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn load_blueprints() -> Result<Blueprints> {
+    let conf_text =
+        std::fs::read_to_string(blueprints_path()).context("Could not load npc_gen.toml")?;
+    let t = conf_text.parse::<Value>()?;
+    load_blueprints_from_table(try_as!(t, table)?.clone())
+}
+
+/// loads npc_gen.toml, and describes the fields of every blueprint, so blueprint authors can
+/// check their files without opening the app
+pub fn describe_blueprints() -> Result<String> {
+    let blueprints = load_blueprints()?;
+    Ok(blueprints
+        .iter()
+        .sorted_by_key(|(name, _)| name.as_str())
+        .map(|(name, bp)| {
+            let fields = bp.describe().replace('\n', "\n  ");
+            format!("{}:\n  {}", name, fields)
+        })
+        .join("\n\n"))
+}
+
+/// checks npc_gen.toml and the files it refers to, and returns all problems instead of only the
+/// first one
+pub fn validate_blueprints() -> Result<Vec<Problem>> {
+    let path = blueprints_path();
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Could not load {}", path.display()))?;
+    Ok(npc_builder::validate(&text))
+}
+
+/// the NPC as Markdown, shaped by the template of config.toml
+fn markdown(builder: &NpcBuilder, fd: &FinalizingData) -> Result<String> {
+    let template = Config::load()?.npc_markdown_template;
+    Ok(npc_export::to_markdown(
+        &fd.name,
+        &fd.npc,
+        &builder.blueprint().display,
+        template.as_deref(),
+    ))
+}
+
+/// removes the values that saved NPCs already use from the fields that have exclude-saved set
+fn exclude_saved_values(builder: &mut NpcBuilder) -> Result<()> {
+    let fields: Vec<String> = builder
+        .blueprint()
+        .fields_excluding_saved()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    if fields.is_empty() {
+        return Ok(());
+    }
+    for npc in npc_store::saved_npcs()? {
+        for field in &fields {
+            if let Some(vals) = npc.get(field) {
+                builder.exclude_values(field, vals.iter().cloned());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the NPC is named after its name field, if it has one
+fn finalizing_state(bps: Box<Blueprints>, builder: NpcBuilder) -> State {
+    let npc = builder.finished_npc();
+    let fd = FinalizingData {
+        name: name_field(&npc),
+        npc,
+        editing: None,
+        notice: None,
+    };
+    State::Finalizing(bps, builder, fd)
+}
+
+fn name_field(npc: &Npc) -> String {
+    npc.get("name")
+        .and_then(|vals| vals.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+impl FinalizingData {
+    /// takes over the changes of the builder. If the NPC was still named after its name field,
+    /// the new name is taken over too
+    fn update_npc(&mut self, builder: &NpcBuilder) {
+        let old_name_field = name_field(&self.npc);
+        self.npc = builder.finished_npc();
+        if self.name == old_name_field {
+            self.name = name_field(&self.npc);
+        }
+    }
+}
+
 fn new_building_state(bps: Box<Blueprints>, builder: NpcBuilder) -> State {
-    let (field_name, opts, n) = builder.current_field_infos().unwrap();
-    let rolled_options = roll_options(&opts, n);
-    let displayed_opts = HashMap::from_iter(rolled_options);
-    let bd = BuildingData::new(opts, displayed_opts, n, field_name);
+    let (field_name, _, _) = builder.current_field_infos().unwrap();
+    building_state_for(bps, builder, field_name)
+}
+
+/// the field must be one of the available fields of the builder
+fn building_state_for(bps: Box<Blueprints>, mut builder: NpcBuilder, field_name: String) -> State {
+    builder.generate_options(&field_name, &mut rand::thread_rng());
+    let (opts, n) = builder.field_infos(&field_name);
+    let weights = builder.option_weights(&field_name);
+    let displayed_opts = roll_options(&opts, n * builder.options_per_value(), &weights);
+    let number = builder.number_source(&field_name).map(|number| {
+        let rolled = number.roll(&mut rand::thread_rng());
+        (number.clone(), rolled.to_string())
+    });
+    let bd = BuildingData::new(opts, weights, displayed_opts, n, field_name, number);
     State::Building(bps, builder, bd)
 }
 
-fn roll_options(xs: &Vec<String>, n: usize) -> HashMap<String, bool> {
-    HashMap::from_iter(
-        xs.into_iter()
-            .choose_multiple(&mut rand::thread_rng(), n * 3)
-            .into_iter()
-            .map(|x| (x.clone(), false)),
-    )
+/// picks n of the options by their weights, none of them selected
+fn roll_options(xs: &[String], n: usize, weights: &HashMap<String, f64>) -> HashMap<String, bool> {
+    choose_weighted(xs, n, weights, &mut rand::thread_rng())
+        .into_iter()
+        .map(|x| (x, false))
+        .collect()
+}
+
+/// the options are shown in n columns of this height
+fn per_column(n_options: usize, n: usize) -> usize {
+    let columns = n.max(1);
+    (n_options + columns - 1) / columns
+}
+
+/// the index the keyboard focus moves to from the current one, in the order the options are
+/// shown. Next and Previous wrap around, Left and Right stop at the outer columns
+fn moved_focus(
+    current: Option<usize>,
+    len: usize,
+    per_column: usize,
+    direction: FocusMove,
+) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    Some(match (current, direction) {
+        (None, FocusMove::Previous) => len - 1,
+        (None, _) => 0,
+        (Some(current), FocusMove::Next) => (current + 1) % len,
+        (Some(current), FocusMove::Previous) => (current + len - 1) % len,
+        (Some(current), FocusMove::Left) => current.checked_sub(per_column).unwrap_or(current),
+        (Some(current), FocusMove::Right) if current + per_column < len => current + per_column,
+        (Some(current), FocusMove::Right) => current,
+    })
+}
+
+/// the arrow keys and Tab move the focus between the options of the building screen, Space and
+/// Enter select the focused one. Keys that a text input used are left alone
+pub fn shortcuts() -> Subscription<GenNpcMessage> {
+    iced::subscription::events_with(|event, status| match (event, status) {
+        (
+            Event::Keyboard(keyboard::Event::KeyPressed {
+                key_code,
+                modifiers,
+            }),
+            event::Status::Ignored,
+        ) => match key_code {
+            KeyCode::Tab if modifiers.shift() => {
+                Some(GenNpcMessage::MoveFocus(FocusMove::Previous))
+            }
+            KeyCode::Tab | KeyCode::Down => Some(GenNpcMessage::MoveFocus(FocusMove::Next)),
+            KeyCode::Up => Some(GenNpcMessage::MoveFocus(FocusMove::Previous)),
+            KeyCode::Left => Some(GenNpcMessage::MoveFocus(FocusMove::Left)),
+            KeyCode::Right => Some(GenNpcMessage::MoveFocus(FocusMove::Right)),
+            KeyCode::Space | KeyCode::Enter | KeyCode::NumpadEnter => {
+                Some(GenNpcMessage::ToggleFocused)
+            }
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+impl BuildingData {
+    fn selected(&self) -> impl Iterator<Item = &String> {
+        self.displayed_options
+            .iter()
+            .filter_map(|(opt, selected)| selected.then_some(opt))
+    }
 }
 
 impl Tab for GenNpcTab {
@@ -154,9 +717,14 @@ impl Tab for GenNpcTab {
 
     fn content(&self) -> Element<'_, Self::Message> {
         match &self.state {
-            State::Error(e) => render_error(e),
-            State::Finalizing(blueprints, npc) => render_finalizing(&npc),
-            State::Initiated(blueprints) => render_initiated_screen(blueprints),
+            State::Loading => render_loading(),
+            State::Error(e) => render_error(e, &self.problems),
+            State::Finalizing(_, builder, fd) => {
+                render_finalizing(builder, fd).map(Message::GenNpcMsg)
+            }
+            State::Initiated(blueprints) => {
+                render_initiated_screen(blueprints, &self.saved, &self.problems)
+            }
             State::Building(blueprints, builder, builder_data) => {
                 render_building(blueprints, builder, builder_data).map(Message::GenNpcMsg)
             }
@@ -164,9 +732,48 @@ impl Tab for GenNpcTab {
     }
 }
 
-fn render_finalizing(npc: &StringMap) -> Element<'_, Message> {
-    let col = Column::with_children(vec![render_npc(npc)]);
+fn render_finalizing<'a>(
+    builder: &'a NpcBuilder,
+    fd: &'a FinalizingData,
+) -> Element<'a, GenNpcMessage> {
+    let controls = |field: &'a NpcField| -> Option<Element<'a, GenNpcMessage>> {
+        // fields that were added by hand can't be edited here
+        builder.blueprint().field(&field.name)?;
+        Some(
+            row!(
+                text_button("Edit", Some(GenNpcMessage::EditField(field.name.clone()))),
+                text_button(
+                    "Re-roll",
+                    Some(GenNpcMessage::RerollField(field.name.clone()))
+                )
+            )
+            .spacing(5)
+            .width(Length::FillPortion(1))
+            .into(),
+        )
+    };
+    let npc = render_npc_with_controls(&fd.npc, &builder.blueprint().display, None, controls);
+    let mut col = Column::with_children(vec![npc]);
+    if let Some(edit) = &fd.editing {
+        col = col.push(render_field_edit(edit));
+    }
+    let name = &fd.name;
+    let save = (!name.trim().is_empty()).then_some(GenNpcMessage::SaveNpc);
+    let export = |msg: GenNpcMessage| (!name.trim().is_empty()).then_some(msg);
     col.push(
+        row!(
+            h_space(1),
+            TextInput::new("Name", name, GenNpcMessage::NameChanged)
+                .on_submit(GenNpcMessage::SaveNpc)
+                .padding(5)
+                .width(Length::FillPortion(2)),
+            text_button("Save NPC", save).width(Length::FillPortion(1)),
+            h_space(1)
+        )
+        .spacing(10)
+        .align_items(Alignment::Center),
+    )
+    .push(
         row!(
             h_space(1),
             text_button("Add Tag", None).width(Length::FillPortion(1)),
@@ -175,29 +782,72 @@ fn render_finalizing(npc: &StringMap) -> Element<'_, Message> {
         )
         .spacing(10),
     )
+    .push(
+        row!(
+            h_space(1),
+            text_button("Copy to clipboard", Some(GenNpcMessage::CopyToClipboard))
+                .width(Length::FillPortion(1)),
+            text_button("Export as Markdown", export(GenNpcMessage::ExportMarkdown))
+                .width(Length::FillPortion(1)),
+            text_button("Export as JSON", export(GenNpcMessage::ExportJson))
+                .width(Length::FillPortion(1)),
+            h_space(1)
+        )
+        .spacing(10),
+    )
+    .push(Text::new(fd.notice.as_deref().unwrap_or_default()))
     .spacing(10)
     .align_items(Alignment::Center)
     .into()
 }
 
-fn render_npc<'a, Message: 'a>(npc: &'a StringMap) -> Element<'a, Message> {
-    Column::with_children(
-        npc.iter()
-            .map(|(key, vals)| {
-                row!(
-                    Text::new(format!("{}:", key.replace("-", " ").replace("_", " ")))
-                        .size(24)
-                        .width(Length::FillPortion(1))
-                        .horizontal_alignment(Horizontal::Right),
-                    Text::new(vals.join("\n"))
-                        .size(24)
-                        .width(Length::FillPortion(1))
-                )
-                .spacing(10)
-                .into()
-            })
-            .collect(),
+/// every option of the field as a button that toggles it, and an input for a custom value
+fn render_field_edit(edit: &FieldEdit) -> Element<'_, GenNpcMessage> {
+    let options = edit.options.iter().map(|opt| {
+        let b = text_button(opt, Some(GenNpcMessage::EditOptionToggled(opt.clone())))
+            .width(Length::Fill);
+        if edit.selected.contains(opt) {
+            b.style(ButtonTheme::Positive)
+        } else {
+            b
+        }
+        .into()
+    });
+    let apply = (edit.selected.len() == edit.n).then_some(GenNpcMessage::ApplyEdit);
+    let (title, hint, custom_valid) = match &edit.number {
+        Some(number) => (
+            format!("Enter a number for {} ({})", edit.field, number),
+            "Number",
+            number.accepts(&edit.custom_value),
+        ),
+        None => (
+            format!("Choose {} options for {}", edit.n, edit.field),
+            "Custom value, separate several with commas",
+            !edit.custom_value.trim().is_empty(),
+        ),
+    };
+    let custom = custom_valid.then_some(GenNpcMessage::ApplyCustomValue);
+    column!(
+        centered_text(title).size(24),
+        Scrollable::new(Column::with_children(options.collect()).spacing(5))
+            .height(Length::Units(200)),
+        row!(
+            TextInput::new(hint, &edit.custom_value, GenNpcMessage::CustomValueChanged)
+                .on_submit(GenNpcMessage::ApplyCustomValue)
+                .padding(5)
+                .width(Length::FillPortion(2)),
+            text_button("Use Custom Value", custom).width(Length::FillPortion(1))
+        )
+        .spacing(10)
+        .align_items(Alignment::Center),
+        row!(
+            text_button("Apply", apply).width(Length::FillPortion(1)),
+            text_button("Cancel", Some(GenNpcMessage::CancelEdit)).width(Length::FillPortion(1))
+        )
+        .spacing(10)
     )
+    .spacing(10)
+    .padding(20)
     .into()
 }
 
@@ -213,27 +863,45 @@ fn text_button<'a, Message>(
     }
 }
 
-fn render_initiated_screen(bps: &Box<Blueprints>) -> Element<'_, Message> {
+fn render_initiated_screen<'a>(
+    bps: &'a Box<Blueprints>,
+    saved: &Option<String>,
+    problems: &Option<Vec<String>>,
+) -> Element<'a, Message> {
+    let saved = saved
+        .as_ref()
+        .map(|name| format!("Saved {}", name))
+        .unwrap_or_default();
     let content: Element<'_, GenNpcMessage> = row!(
         Space::with_width(Length::FillPortion(1)),
         Container::new(
             column!(
+                Text::new(saved),
                 Text::new("What type of Npc do you want to generate?").size(24),
                 Column::with_children(
                     bps.keys()
                         .map(|k| {
-                            Button::new(
-                                Text::new(k)
-                                    .width(Length::Fill)
-                                    .horizontal_alignment(Horizontal::Center),
+                            row!(
+                                Button::new(
+                                    Text::new(k)
+                                        .width(Length::Fill)
+                                        .horizontal_alignment(Horizontal::Center),
+                                )
+                                .on_press(GenNpcMessage::GenNpc(k.clone()))
+                                .width(Length::FillPortion(3)),
+                                text_button(
+                                    "Generate randomly",
+                                    Some(GenNpcMessage::GenRandomNpc(k.clone()))
+                                )
+                                .width(Length::FillPortion(1))
                             )
-                            .on_press(GenNpcMessage::GenNpc(k.clone()))
-                            .width(Length::Fill)
+                            .spacing(10)
                             .into()
                         })
                         .collect()
                 )
-                .spacing(10)
+                .spacing(10),
+                render_problems(problems)
             )
             .spacing(10)
         )
@@ -245,73 +913,165 @@ fn render_initiated_screen(bps: &Box<Blueprints>) -> Element<'_, Message> {
     content.map(Message::GenNpcMsg)
 }
 
+/// the validate button, and the problems of the last validation below it
+fn render_problems(problems: &Option<Vec<String>>) -> Element<'static, GenNpcMessage> {
+    let mut col = Column::new().spacing(5).push(text_button(
+        "Validate blueprints",
+        Some(GenNpcMessage::ValidateBlueprints),
+    ));
+    match problems {
+        Some(problems) if problems.is_empty() => col = col.push(Text::new("No problems found")),
+        Some(problems) => {
+            for problem in problems {
+                col = col.push(Text::new(format!("npc_gen.toml, {}", problem)));
+            }
+        }
+        None => {}
+    }
+    col.into()
+}
+
 fn h_space<T: 'static>(rel_width: u16) -> Element<'static, T> {
     Space::with_width(Length::FillPortion(rel_width)).into()
 }
 
 fn render_building<'a>(
     _bps: &'a Box<Blueprints>,
-    _builder: &'a NpcBuilder,
+    builder: &'a NpcBuilder,
     bd: &'a BuildingData,
 ) -> Element<'a, GenNpcMessage> {
     // theoretically, iced_lazy::responsive can be used to create a widget that knows its size,
     // but that doesn't compile currently, so this is a workaround for now
 
-    column!(
-        centered_text(format!("Choose {} options for {}", bd.n, bd.field_name)).size(24),
-        Row::with_children({
-            let mut elems: Vec<Element<'_, _>> = (0..bd.n)
-                .map(|idx| {
-                    Column::with_children(
-                        bd.displayed_options
-                            .iter()
-                            .dropping(idx * 3)
-                            .take(3)
-                            .map(|(name, selected)| {
-                                let b = Button::new(centered_text(name))
-                                    .on_press(GenNpcMessage::AttribSelected(name.clone()))
-                                    .width(Length::Fill);
-                                if *selected {
-                                    b.style(ButtonTheme::Positive)
-                                } else {
-                                    b
-                                }
-                                .into()
-                            })
-                            .collect(),
-                    )
-                    .spacing(10)
-                    .width(Length::FillPortion(1))
-                    .into()
-                })
-                .collect();
+    let description = builder
+        .blueprint()
+        .field(&bd.field_name)
+        .and_then(|f| f.description.as_deref())
+        .unwrap_or("");
+    // the options are shown in n columns, that are 3 options high, unless all are shown
+    let per_column = per_column(bd.displayed_options.len(), bd.n);
+    let show_all = (bd.displayed_options.len() < bd.all_options.len())
+        .then_some(GenNpcMessage::ShowAllOptions);
+    let back = builder.can_undo().then_some(GenNpcMessage::Back);
+    let skip = builder
+        .is_optional(&bd.field_name)
+        .then_some(GenNpcMessage::SkipField);
+    let reroll = match bd.number {
+        Some(_) => "Reroll number",
+        None => "Reroll options",
+    };
+    let (title, choices): (_, Element<'_, _>) = match &bd.number {
+        Some((number, value)) => (
+            format!("Enter a number for {} ({})", bd.field_name, number),
+            row!(
+                h_space(1),
+                TextInput::new("Number", value, GenNpcMessage::NumberChanged)
+                    .on_submit(GenNpcMessage::AcceptNumber)
+                    .padding(5)
+                    .width(Length::FillPortion(1)),
+                text_button(
+                    "Accept",
+                    number.accepts(value).then_some(GenNpcMessage::AcceptNumber)
+                )
+                .width(Length::FillPortion(1)),
+                h_space(1)
+            )
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .into(),
+        ),
+        None => (
+            format!("Choose {} options for {}", bd.n, bd.field_name),
+            Scrollable::new(
+                Row::with_children({
+                    let mut elems: Vec<Element<'_, _>> = (0..bd.n)
+                        .map(|idx| {
+                            Column::with_children(
+                                bd.displayed_options
+                                    .iter()
+                                    .dropping(idx * per_column)
+                                    .take(per_column)
+                                    .map(|(name, selected)| {
+                                        let b = Button::new(centered_text(name))
+                                            .on_press(GenNpcMessage::AttribSelected(name.clone()))
+                                            .width(Length::Fill);
+                                        let b = if *selected {
+                                            b.style(ButtonTheme::Positive)
+                                        } else {
+                                            b
+                                        };
+                                        if bd.focused.as_ref() == Some(name) {
+                                            Container::new(b)
+                                                .padding(3)
+                                                .style(ContainerTheme::Custom(focus_appearance))
+                                                .into()
+                                        } else {
+                                            b.into()
+                                        }
+                                    })
+                                    .collect(),
+                            )
+                            .spacing(10)
+                            .width(Length::FillPortion(1))
+                            .into()
+                        })
+                        .collect();
 
-            // this is not efficient, but speed doesn't matter here, and it's the easiest
-            // approach
-            elems.push(h_space(1));
-            elems.insert(0, h_space(1));
-            elems
-        })
+                    // this is not efficient, but speed doesn't matter here, and it's the easiest
+                    // approach
+                    elems.push(h_space(1));
+                    elems.insert(0, h_space(1));
+                    elems
+                })
+                .spacing(10),
+            )
+            .height(Length::Fill)
+            .into(),
+        ),
+    };
+    column!(
+        centered_text(title).size(24),
+        centered_text(description),
+        choices,
+        row!(
+            h_space(1),
+            text_button("Back", back).width(Length::FillPortion(1)),
+            text_button(reroll, Some(GenNpcMessage::RerollOptions)).width(Length::FillPortion(1)),
+            text_button("Show all options", show_all).width(Length::FillPortion(1)),
+            text_button("Leave out", skip).width(Length::FillPortion(1)),
+            text_button("Fill the rest automatically", Some(GenNpcMessage::AutoFill))
+                .width(Length::FillPortion(1)),
+            h_space(1)
+        )
         .spacing(10)
     )
     .spacing(10)
     .into()
 }
 
+/// a frame in the text color around the focused option
+fn focus_appearance(theme: &Theme) -> container::Appearance {
+    let text = theme.palette().text;
+    container::Appearance {
+        border_color: text,
+        border_width: 2.0,
+        border_radius: 5.0,
+        ..Default::default()
+    }
+}
+
 fn centered_text<'a>(s: impl Into<Cow<'a, str>>) -> Text<'a> {
     Text::new(s)
         .width(Length::Fill)
         .horizontal_alignment(Horizontal::Center)
 }
 
-fn render_error(err: &str) -> Element<'static, Message> {
-    let content: Element<'_, GenNpcMessage> = column!(
-        Text::new(format!("An error Occured:\n{}", err)),
-        Button::new("Try Again")
-            .on_press(GenNpcMessage::ReInit)
-            .padding(5)
-    )
-    .spacing(20)
-    .into();
+fn render_error(err: &str, problems: &Option<Vec<String>>) -> Element<'static, Message> {
+    let try_again = Button::new("Try Again")
+        .on_press(GenNpcMessage::ReInit)
+        .padding(5);
+    let content: Element<'_, GenNpcMessage> = iced_utils::render_error(err, try_again)
+        .push(render_problems(problems))
+        .into();
     content.map(Message::GenNpcMsg)
 }
@@ -1,7 +1,8 @@
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use super::*;
-use anyhow::{ensure, Result};
+use anyhow::{bail, ensure, Result};
 use many_to_many::ManyToMany;
 
 /// represents a directed graph, with multiple root nodes
@@ -10,13 +11,14 @@ pub struct DependencyGraph {
     /// nodes that don't depend on other nodes
     roots: Vec<String>,
     /// left depends on right
-    dependencies: Rc<ManyToMany<String, String>>,
+    dependencies: Arc<ManyToMany<String, String>>,
 }
 
 impl DependencyGraph {
     pub fn from_blueprints(bps: &BpMap) -> Result<Self> {
         let mut roots = vec![];
         let mut dependencies = ManyToMany::new();
+        let mut dependency_lists: HashMap<&str, Vec<String>> = HashMap::new();
 
         for (current_field, blueprint) in bps {
             let field_deps = blueprint
@@ -24,27 +26,31 @@ impl DependencyGraph {
                 .iter()
                 .filter_map(|cs| match &cs.filter {
                     ChoiceFilter::None => None,
-                    ChoiceFilter::FieldValue {
-                        target_field,
-                        target_value: _,
-                    } => Some(target_field.clone()),
+                    ChoiceFilter::FieldValue { target_field, .. } => Some(target_field.clone()),
                 })
                 .collect::<Vec<String>>();
-            if field_deps.len() == 0 {
+            if field_deps.is_empty() {
                 roots.push(current_field.clone());
             } else {
-                for dep in field_deps {
-                    dependencies.insert(current_field.into(), dep.into());
+                for dep in &field_deps {
+                    dependencies.insert(current_field.clone(), dep.clone());
                 }
+                dependency_lists.insert(current_field, field_deps);
             }
         }
         ensure!(
-            roots.len() > 0,
+            !roots.is_empty(),
             "There are no fields that don't depend on other fields. This won't work"
         );
+        if let Some(cycle) = cycles(&dependency_lists).first() {
+            bail!(
+                "The fields depend on each other, so none of them can be chosen: {}",
+                cycle.join(" -> ")
+            );
+        }
         Ok(DependencyGraph {
             roots,
-            dependencies: Rc::new(dependencies),
+            dependencies: Arc::new(dependencies),
         })
     }
 
@@ -52,24 +58,164 @@ impl DependencyGraph {
         self.dependencies.get_right(dependant).unwrap_or(vec![])
     }
 
-    pub fn get_available_unset_fields(&self, npc: &StringMap) -> Vec<String> {
+    /// the skipped fields are optional fields that were left out of the NPC, they count as set
+    pub fn get_available_unset_fields(&self, npc: &Npc, skipped: &HashSet<&str>) -> Vec<String> {
         self.roots
             .iter()
             .cloned()
-            .chain(self.get_determined_fields(npc))
-            .filter(|f| !npc.contains_key(f))
+            .chain(self.get_determined_fields(npc, skipped))
+            .filter(|f| !npc.contains(f) && !skipped.contains(f.as_str()))
             .collect()
     }
 
-    pub fn get_determined_fields(&self, npc: &StringMap) -> Vec<String> {
+    pub fn get_determined_fields(&self, npc: &Npc, skipped: &HashSet<&str>) -> Vec<String> {
         let fields_with_deps = self.dependencies.get_left_keys();
         let mut res = vec![];
         for field in fields_with_deps {
             let deps = self.dependencies.get_left(field).unwrap_or(vec![]);
-            if deps.iter().all(|f| npc.contains_key(f)) {
+            if deps
+                .iter()
+                .all(|f| npc.contains(f) || skipped.contains(f.as_str()))
+            {
                 res.push(field.clone())
             }
         }
         res
     }
 }
+
+/// the cycles among the dependencies of the fields, each starting and ending with the same field,
+/// and each reported once
+pub fn cycles(dependencies: &HashMap<&str, Vec<String>>) -> Vec<Vec<String>> {
+    fn visit(
+        field: &str,
+        dependencies: &HashMap<&str, Vec<String>>,
+        path: &mut Vec<String>,
+        done: &mut HashSet<String>,
+        res: &mut Vec<Vec<String>>,
+    ) {
+        if let Some(start) = path.iter().position(|f| f == field) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(field.into());
+            res.push(cycle);
+            return;
+        }
+        if done.contains(field) {
+            return;
+        }
+        path.push(field.into());
+        for dep in dependencies.get(field).into_iter().flatten() {
+            visit(dep, dependencies, path, done, res);
+        }
+        path.pop();
+        done.insert(field.into());
+    }
+
+    let mut res = vec![];
+    let mut done = HashSet::new();
+    let mut fields: Vec<&&str> = dependencies.keys().collect();
+    fields.sort();
+    for field in fields {
+        visit(field, dependencies, &mut vec![], &mut done, &mut res);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(src: &str) -> Result<DependencyGraph> {
+        let table = src.parse::<Value>().unwrap();
+        let bps = try_as!(table, table)
+            .unwrap()
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), FieldBlueprint::parse(v.clone())?)))
+            .collect::<Result<BpMap>>()?;
+        DependencyGraph::from_blueprints(&bps)
+    }
+
+    #[test]
+    fn test_available_fields() {
+        let g = graph(
+            r#"
+            race = ["Elf", "Dwarf"]
+            [name]
+            choices = [
+                { values = ["Legolas"], filter = "race: Elf" },
+                { values = ["Gimli"], filter = "race: Dwarf" },
+            ]
+            [weapon]
+            choices = [{ values = ["Axe"], filter = "name: Gimli" }]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(g.get_depending_fields(&"race".into()), vec!["name"]);
+        assert_eq!(g.get_depending_fields(&"name".into()), vec!["weapon"]);
+        assert!(g.get_depending_fields(&"weapon".into()).is_empty());
+
+        let mut npc = Npc::new(None);
+        let none = HashSet::new();
+        assert_eq!(g.get_available_unset_fields(&npc, &none), vec!["race"]);
+        npc.set("race", FieldKind::Choice, vec!["Elf".into()]);
+        assert_eq!(g.get_available_unset_fields(&npc, &none), vec!["name"]);
+        // a skipped field satisfies the fields that depend on it
+        let skipped = HashSet::from(["name"]);
+        assert_eq!(g.get_available_unset_fields(&npc, &skipped), vec!["weapon"]);
+        npc.set("name", FieldKind::Choice, vec!["Legolas".into()]);
+        assert_eq!(g.get_available_unset_fields(&npc, &none), vec!["weapon"]);
+    }
+
+    #[test]
+    fn test_no_roots() {
+        let err = graph(
+            r#"
+            [a]
+            choices = [{ values = ["x"], filter = "a: x" }]
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("no fields that don't depend"));
+    }
+
+    #[test]
+    fn test_cycles() {
+        let err = graph(
+            r#"
+            race = ["Elf", "Dwarf"]
+            [name]
+            choices = [{ values = ["Legolas"], filter = "title: Prince" }]
+            [title]
+            choices = [{ values = ["Prince"], filter = "name: Legolas" }]
+        "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The fields depend on each other, so none of them can be chosen: name -> title -> name"
+        );
+
+        let err = graph(
+            r#"
+            race = ["Elf", "Dwarf"]
+            [name]
+            choices = [{ values = ["Legolas"], filter = "name: Legolas" }]
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().ends_with("name -> name"));
+    }
+
+    #[test]
+    fn test_cycles_are_reported_once() {
+        let deps = HashMap::from([
+            ("a", vec!["b".to_string()]),
+            ("b", vec!["c".to_string()]),
+            ("c", vec!["a".to_string()]),
+            ("d", vec!["a".to_string()]),
+        ]);
+        assert_eq!(cycles(&deps), vec![vec!["a", "b", "c", "a"]]);
+        let deps = HashMap::from([("a", vec!["b".to_string()]), ("c", vec!["b".to_string()])]);
+        assert!(cycles(&deps).is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use macros::try_as;
+use toml::Value;
+
+use super::parse_string_array;
+use crate::npc::{Npc, NpcField};
+
+/// Describes how the fields of an NPC are presented. It is configured with the optional
+/// `_display` table of a blueprint:
+///
+/// ```toml
+/// [Human._display]
+/// order = ["name", "race"]
+/// hidden = ["name-style"]
+/// sections = { Appearance = ["hair", "eyes"] }
+/// ```
+///
+/// Fields are shown in the given order, followed by the remaining fields in the order in which
+/// they are declared in the blueprint, and finally all other fields in alphabetical order. Fields
+/// that belong to a section are shown below the ungrouped ones, under the title of the section.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayConfig {
+    order: Vec<String>,
+    hidden: HashSet<String>,
+    sections: Vec<(String, Vec<String>)>,
+}
+
+/// a group of fields with their values, as it should be displayed
+#[derive(Debug, PartialEq)]
+pub struct Section<'a> {
+    /// None for the fields that don't belong to a section
+    pub title: Option<&'a str>,
+    pub fields: Vec<&'a NpcField>,
+}
+
+impl DisplayConfig {
+    /// declared_fields are the fields of the blueprint in the order of declaration
+    pub fn parse(val: Option<Value>, declared_fields: &[String]) -> Result<DisplayConfig> {
+        let mut conf = DisplayConfig::default();
+        if let Some(val) = val {
+            let tab = try_as!(val, table)?;
+            if let Some(order) = tab.get("order") {
+                conf.order = parse_string_array(order)?;
+            }
+            if let Some(hidden) = tab.get("hidden") {
+                conf.hidden = parse_string_array(hidden)?.into_iter().collect();
+            }
+            if let Some(sections) = tab.get("sections") {
+                for (title, fields) in try_as!(sections, table)? {
+                    conf.sections
+                        .push((title.clone(), parse_string_array(fields)?));
+                }
+            }
+        }
+        for field in declared_fields {
+            if !conf.order.contains(field) {
+                conf.order.push(field.clone());
+            }
+        }
+        Ok(conf)
+    }
+
+    /// groups the visible fields of the NPC into sections, and orders them. The first section
+    /// is always the untitled one, even if it is empty.
+    pub fn layout<'a>(&'a self, npc: &'a Npc) -> Vec<Section<'a>> {
+        let mut fields: Vec<&NpcField> = npc
+            .fields
+            .iter()
+            .filter(|f| !self.hidden.contains(&f.name))
+            .collect();
+        fields.sort_by_key(|f| match self.order.iter().position(|o| *o == f.name) {
+            Some(i) => (i, ""),
+            None => (self.order.len(), f.name.as_str()),
+        });
+
+        let in_section = |f: &NpcField| {
+            self.sections
+                .iter()
+                .any(|(_, names)| names.contains(&f.name))
+        };
+        let mut res = vec![Section {
+            title: None,
+            fields: fields.iter().copied().filter(|f| !in_section(f)).collect(),
+        }];
+        for (title, names) in &self.sections {
+            let fields: Vec<_> = fields
+                .iter()
+                .copied()
+                .filter(|f| names.contains(&f.name))
+                .collect();
+            if !fields.is_empty() {
+                res.push(Section {
+                    title: Some(title),
+                    fields,
+                });
+            }
+        }
+        res
+    }
+}
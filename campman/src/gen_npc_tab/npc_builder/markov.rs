@@ -0,0 +1,98 @@
+//! Names that are made up when a field is rolled, so a long campaign doesn't run out of them. The
+//! chain learns which letters follow each other in the options of a choice source, and strings
+//! letters together the same way, e.g. `{ file = "names/elf.txt", generator = "markov" }`.
+use std::collections::{HashMap, HashSet};
+use std::iter::once;
+
+use anyhow::{bail, ensure, Result};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+
+/// marks the start of a name in the contexts, it doesn't occur in names
+const START: char = '\u{2}';
+/// how many letters the next one depends on, if the choice source has no order key
+const DEFAULT_ORDER: i64 = 2;
+/// made up names that are too long or already known are dropped, after this many the chain
+/// gives up and returns a known name
+const MAX_TRIES: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct MarkovChain {
+    order: usize,
+    /// the letters that followed the last `order` letters in the known names, with repetitions, so
+    /// frequent ones are picked more often. None ends the name
+    successors: HashMap<Vec<char>, Vec<Option<char>>>,
+    /// the names it was trained on, which it doesn't return as made up names
+    known: HashSet<String>,
+    /// made up names are as long as the known ones
+    min_len: usize,
+    max_len: usize,
+}
+
+impl MarkovChain {
+    /// the generator key of a choice source, and its order key
+    pub fn parse(generator: &str, order: Option<i64>, names: &[String]) -> Result<MarkovChain> {
+        if generator != "markov" {
+            bail!("{} is not a generator, the only one is markov", generator);
+        }
+        let order = order.unwrap_or(DEFAULT_ORDER);
+        ensure!(
+            (1..=5).contains(&order),
+            "the order of a generator must be from 1 to 5, not {}",
+            order
+        );
+        Ok(MarkovChain::train(names, order as usize))
+    }
+
+    pub fn train(names: &[String], order: usize) -> MarkovChain {
+        let mut successors: HashMap<Vec<char>, Vec<Option<char>>> = HashMap::new();
+        for name in names {
+            let mut context = vec![START; order];
+            for c in name.chars().map(Some).chain(once(None)) {
+                successors.entry(context.clone()).or_default().push(c);
+                if let Some(c) = c {
+                    context.remove(0);
+                    context.push(c);
+                }
+            }
+        }
+        let lengths = || names.iter().map(|name| name.chars().count());
+        MarkovChain {
+            order,
+            successors,
+            known: names.iter().cloned().collect(),
+            min_len: lengths().min().unwrap_or(0),
+            max_len: lengths().max().unwrap_or(0),
+        }
+    }
+
+    /// a name that isn't one of the known names, or one of them if the chain knows too few to
+    /// make one up. None if it knows no names
+    pub fn generate<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<String> {
+        (0..MAX_TRIES)
+            .filter_map(|_| self.walk(rng))
+            .find(|name| !self.known.contains(name))
+            .or_else(|| self.known.iter().choose(rng).cloned())
+    }
+
+    /// strings letters together until the chain ends the name. None if it got too short or long
+    fn walk<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<String> {
+        let mut context = vec![START; self.order];
+        let mut name = String::new();
+        let mut len = 0;
+        loop {
+            match self.successors.get(&context)?.choose(rng)? {
+                Some(c) => {
+                    len += 1;
+                    if len > self.max_len {
+                        return None;
+                    }
+                    name.push(*c);
+                    context.remove(0);
+                    context.push(*c);
+                }
+                None => return (len >= self.min_len).then_some(name),
+            }
+        }
+    }
+}
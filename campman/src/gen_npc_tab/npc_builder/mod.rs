@@ -1,50 +1,132 @@
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use fn_utils::PullResult;
+use itertools::Itertools;
 use macros::try_as;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use std::fmt::{Debug, Display};
 use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
-use std::{collections::HashMap, stringify};
+use std::{
+    collections::{HashMap, HashSet},
+    stringify,
+};
 use thiserror::Error;
 use toml::Value;
 
 mod dependency_graph;
+mod display;
+mod markov;
+pub mod options_cache;
+mod template;
+mod validation;
 
 use crate::conf_dir;
+use crate::config::DEFAULT_OPTIONS_PER_VALUE;
+use crate::npc::{FieldKind, Npc};
 use dependency_graph::DependencyGraph;
+pub use display::{DisplayConfig, Section};
+use markov::MarkovChain;
+use template::ComputedField;
+pub use validation::{validate, Problem};
 
-pub type StringMap = HashMap<String, Vec<String>>;
 pub type BpMap = HashMap<String, FieldBlueprint>;
 
+/// the key of a blueprint that holds its DisplayConfig instead of a field
+const DISPLAY_KEY: &str = "_display";
+/// how likely an optional field is rolled, if it has no probability
+const DEFAULT_PROBABILITY: f64 = 0.5;
+
 #[derive(Debug)]
 pub struct NpcBuilder {
-    constructed_npc: StringMap,
+    constructed_npc: Npc,
     blueprint: NpcBlueprint,
+    /// values that are excluded in addition to the exclude lists of the blueprint
+    exclusions: HashMap<String, HashSet<String>>,
+    /// the names the generators made up for this NPC, by field and index of the choice source.
+    /// They are the options of these sources
+    generated: HashMap<(String, usize), Vec<String>>,
+    /// the optional fields that were left out of the NPC, with the number of fields that were
+    /// set at the time, so undo knows whether a field was skipped or set last
+    skipped: Vec<(String, usize)>,
+    /// how many options are offered per value a field needs
+    options_per_value: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct NpcBlueprint {
+    pub name: String,
     blueprints: BpMap,
+    /// fields that aren't chosen, but composed of the others when the NPC is finished
+    computed: Vec<ComputedField>,
     dependency_graph: DependencyGraph,
+    pub display: DisplayConfig,
 }
 
 #[derive(Debug, Clone)]
 pub struct FieldBlueprint {
     n_selections: usize,
     pub sources: Vec<ChoiceSource>,
+    /// values that are removed after the sources were merged
+    exclude: Vec<String>,
+    /// if set, values that are used by NPCs that are saved in the campaign are excluded
+    pub exclude_saved: bool,
+    /// the label of the field, if it should differ from the field name
+    display_name: Option<String>,
+    /// explains the field to users of the blueprint, it is shown while the field is chosen
+    pub description: Option<String>,
+    /// how the field is answered when the NPC is filled automatically
+    pub auto: AutoPolicy,
+    /// Some for fields with a number instead of options, then there are no sources
+    pub number: Option<NumberSource>,
+    /// Some for optional fields, how likely they are rolled. Otherwise they are left out of the
+    /// NPC
+    pub probability: Option<f64>,
+}
+
+/// how the value of a number field is rolled
+#[derive(Debug, Clone, PartialEq)]
+pub enum NumberSource {
+    /// a dice expression, like `3d20+16`
+    Dice(String),
+    /// a number between the bounds, including them
+    Range(i64, i64),
+}
+
+/// how a field is answered without the user, set with the auto key of the field
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutoPolicy {
+    /// every option is equally likely, unless its choice source gives it a weight
+    Uniform,
+    /// options are picked by their weight from the weights table, which overrides the weights
+    /// of the choice sources
+    Weighted(HashMap<String, f64>),
+    /// the first options, in the order of the sources
+    First,
+    /// the field is always chosen by the user, even when the rest is filled automatically
+    Prompt,
 }
 
 #[derive(Debug, Clone)]
 pub struct ChoiceSource {
     options: Vec<String>,
+    /// how likely options are rolled, compared to the options without a weight, which weigh 1
+    weights: HashMap<String, f64>,
     pub filter: ChoiceFilter,
+    /// Some if the options are only what the generator learns from, it makes up new options
+    /// whenever the field is rolled
+    generator: Option<MarkovChain>,
 }
 
 #[derive(Debug, Clone)]
 pub enum ChoiceFilter {
+    /// like `race: Elf|Half-Elf`, or negated, like `race != Dwarf`
     FieldValue {
         target_field: String,
-        target_value: String,
+        /// the source applies if the field has any of these values
+        target_values: Vec<String>,
+        /// the source applies if the field has none of the values instead
+        negated: bool,
     },
     None,
 }
@@ -69,21 +151,129 @@ pub enum SetFieldError {
 
     #[error("The NPC is already completed")]
     NPCCompleteError,
+
+    #[error("Fields can only be changed once the NPC is completed")]
+    NPCIncomplete,
+
+    #[error("There is no field named {0}")]
+    UnknownField(String),
+
+    #[error("{0} is already set, or depends on fields that are not set yet")]
+    FieldNotAvailable(String),
+
+    #[error("{0} is not a number that can be rolled with {1}")]
+    InvalidNumber(String, NumberSource),
+
+    #[error("{0} has {1} options, but {2} must be selected")]
+    NotEnoughOptions(String, usize, usize),
+
+    #[error("The dependencies of these fields can never be satisfied: {0:?}")]
+    UnreachableFields(Vec<String>),
+
+    #[error("{0} must be chosen by the user")]
+    PromptRequired(String),
+
+    #[error("{0} is not optional, so it can't be left out")]
+    NotOptional(String),
 }
 
 impl NpcBlueprint {
-    pub fn parse(toml_val: Value) -> Result<NpcBlueprint> {
-        let tab = try_as!(toml_val, table)?;
+    /// names of the fields that have exclude-saved set
+    pub fn fields_excluding_saved(&self) -> Vec<&str> {
+        self.blueprints
+            .iter()
+            .filter(|(_, bp)| bp.exclude_saved)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldBlueprint> {
+        self.blueprints.get(name)
+    }
+
+    /// one line per field, in alphabetical order, with the number of selections and the
+    /// description, or the template of computed fields
+    pub fn describe(&self) -> String {
+        let computed = self
+            .computed
+            .iter()
+            .map(|c| (&c.name, format!("{} (computed as {})", c.name, c.template)));
+        self.blueprints
+            .iter()
+            .map(|(name, bp)| {
+                let mut line = match &bp.number {
+                    Some(number) => format!("{} ({}", name, number),
+                    None => format!("{} (choose {}", name, bp.n_selections),
+                };
+                match bp.auto {
+                    AutoPolicy::Uniform => {}
+                    AutoPolicy::Weighted(_) => line.push_str(", weighted"),
+                    AutoPolicy::First => line.push_str(", always first"),
+                    AutoPolicy::Prompt => line.push_str(", always prompted"),
+                }
+                if let Some(probability) = bp.probability {
+                    line.push_str(&format!(", in {}% of NPCs", (probability * 100.0).round()));
+                }
+                line.push(')');
+                if let Some(description) = &bp.description {
+                    line.push_str(&format!(": {}", description));
+                }
+                (name, line)
+            })
+            .chain(computed)
+            .sorted_by_key(|(name, _)| name.as_str())
+            .map(|(_, line)| line)
+            .join("\n")
+    }
+
+    pub fn parse(name: &str, toml_val: Value) -> Result<NpcBlueprint> {
+        let mut tab = try_as!(toml_val, table)?.clone();
+        let display_val = tab.remove(DISPLAY_KEY);
+        let declared_fields: Vec<String> = tab.keys().cloned().collect();
+        let (computed, choices): (Vec<_>, Vec<_>) = tab
+            .into_iter()
+            .partition(|(_, v)| v.as_table().is_some_and(|t| t.contains_key("computed")));
+        let computed = computed
+            .into_iter()
+            .map(|(k, v)| parse_computed(k, v))
+            .collect::<Result<Vec<_>>>()?;
         let blueprints = HashMap::from_iter(
-            tab.into_iter()
-                .map(|(k, v)| (k.clone(), FieldBlueprint::parse(v.clone()))),
+            choices
+                .into_iter()
+                .map(|(k, v)| (k, FieldBlueprint::parse(v))),
         )
         .pull_result()?;
 
+        let templates = blueprints
+            .iter()
+            .flat_map(|(field, bp)| {
+                bp.sources
+                    .iter()
+                    .flat_map(|src| &src.options)
+                    .map(move |opt| (field, opt))
+            })
+            .chain(computed.iter().map(|c| (&c.name, &c.template)));
+        for (field, text) in templates {
+            for placeholder in template::placeholders(text) {
+                ensure!(
+                    declared_fields.iter().any(|f| f == placeholder),
+                    "{} of {} refers to {}, which is not a field",
+                    text,
+                    field,
+                    placeholder
+                );
+            }
+        }
+
         let dependency_graph = DependencyGraph::from_blueprints(&blueprints)?;
+        let display = DisplayConfig::parse(display_val, &declared_fields)
+            .context("Invalid _display table")?;
         Ok(NpcBlueprint {
+            name: name.into(),
             blueprints,
+            computed,
             dependency_graph,
+            display,
         })
     }
 }
@@ -91,81 +281,457 @@ impl NpcBlueprint {
 impl NpcBuilder {
     pub fn new(blueprint: NpcBlueprint) -> NpcBuilder {
         NpcBuilder {
-            constructed_npc: HashMap::new(),
+            constructed_npc: Npc::new(Some(blueprint.name.clone())),
             blueprint,
+            exclusions: HashMap::new(),
+            generated: HashMap::new(),
+            skipped: vec![],
+            options_per_value: DEFAULT_OPTIONS_PER_VALUE,
         }
     }
+
+    /// removes the values from the options of the field
+    pub fn exclude_values(&mut self, field: &str, values: impl IntoIterator<Item = String>) {
+        self.exclusions
+            .entry(field.into())
+            .or_default()
+            .extend(values);
+    }
+
+    pub fn options_per_value(&self) -> usize {
+        self.options_per_value
+    }
+
+    /// how many options the building screen offers per value of a field, and how many names
+    /// the generators make up per value. At least one
+    pub fn set_options_per_value(&mut self, n: usize) {
+        self.options_per_value = n.max(1);
+    }
+
+    pub fn blueprint(&self) -> &NpcBlueprint {
+        &self.blueprint
+    }
     /// returns the name of the current field, the values that are allowed, and the number of
     /// values that should be set for this field.
     /// Returns None, if the NPC is complete.
     pub fn current_field_infos(&self) -> Option<(String, Vec<String>, usize)> {
-        let fields = self
-            .blueprint
-            .dependency_graph
-            .get_available_unset_fields(&self.constructed_npc);
-        if fields.len() > 0 {
-            let field = &fields[0];
-            let bp = &self.blueprint.blueprints[field];
-            let opts = bp
-                .sources
-                .iter()
-                .filter_map(|src| match &src.filter {
-                    ChoiceFilter::FieldValue {
-                        target_field,
-                        target_value,
-                    } if self.constructed_npc[target_field].contains(target_value) => {
-                        Some(src.options.clone())
-                    }
-                    ChoiceFilter::None => Some(src.options.clone()),
-                    _ => None,
-                })
-                .flatten()
-                .collect();
-            Some((field.to_owned(), opts, bp.n_selections))
+        let fields = self.available_fields();
+        if !fields.is_empty() {
+            let (opts, n) = self.field_infos(&fields[0]);
+            Some((fields[0].to_owned(), opts, n))
         } else {
             None
         }
     }
 
+    /// the values that are allowed for a field whose dependencies are satisfied, and the number
+    /// of values that should be set
+    pub fn field_infos(&self, field: &str) -> (Vec<String>, usize) {
+        (
+            self.field_options(field),
+            self.blueprint.blueprints[field].n_selections,
+        )
+    }
+
+    /// unsets the field that was answered or skipped last, and returns its name, so it can be
+    /// answered again. Returns None if no field was answered yet
+    pub fn undo(&mut self) -> Option<String> {
+        let n_set = self.constructed_npc.fields.len();
+        if matches!(self.skipped.last(), Some((_, n)) if *n == n_set) {
+            return self.skipped.pop().map(|(field, _)| field);
+        }
+        let field = self.constructed_npc.fields.last()?.name.clone();
+        self.constructed_npc.remove(&field);
+        Some(field)
+    }
+
+    /// whether a field was answered or skipped yet
+    pub fn can_undo(&self) -> bool {
+        !self.constructed_npc.fields.is_empty() || !self.skipped.is_empty()
+    }
+
+    /// all fields that are not set yet, but whose dependencies are satisfied. Any of these can
+    /// be answered via answer_field
+    pub fn available_fields(&self) -> Vec<String> {
+        self.blueprint
+            .dependency_graph
+            .get_available_unset_fields(&self.constructed_npc, &self.skipped_fields())
+            .into_iter()
+            .unique()
+            .collect()
+    }
+
+    /// the options of a field whose dependencies are satisfied
+    fn field_options(&self, field: &str) -> Vec<String> {
+        let bp = &self.blueprint.blueprints[field];
+        let extra_exclusions = self.exclusions.get(field);
+        bp.sources
+            .iter()
+            .enumerate()
+            .filter(|(_, src)| self.source_applies(src))
+            .flat_map(|(i, src)| match src.generator {
+                Some(_) => self
+                    .generated
+                    .get(&(field.to_string(), i))
+                    .cloned()
+                    .unwrap_or_default(),
+                None => src.options.clone(),
+            })
+            .filter(|opt| {
+                !bp.exclude.contains(opt)
+                    && !extra_exclusions.map(|ex| ex.contains(opt)).unwrap_or(false)
+            })
+            .collect()
+    }
+
+    /// the weights of the options of a field whose dependencies are satisfied, from its choice
+    /// sources and its weights table. Options without a weight weigh 1
+    pub fn option_weights(&self, field: &str) -> HashMap<String, f64> {
+        let bp = &self.blueprint.blueprints[field];
+        let mut weights: HashMap<String, f64> = bp
+            .sources
+            .iter()
+            .filter(|src| self.source_applies(src))
+            .flat_map(|src| src.weights.clone())
+            .collect();
+        if let AutoPolicy::Weighted(field_weights) = &bp.auto {
+            weights.extend(field_weights.clone());
+        }
+        weights
+    }
+
+    fn source_applies(&self, src: &ChoiceSource) -> bool {
+        match &src.filter {
+            ChoiceFilter::FieldValue {
+                target_field,
+                target_values,
+                negated,
+            } => {
+                let matches = self
+                    .constructed_npc
+                    .get(target_field)
+                    // the field was left out
+                    .is_some_and(|vals| vals.iter().any(|v| target_values.contains(v)));
+                matches != *negated
+            }
+            ChoiceFilter::None => true,
+        }
+    }
+
     /// proceeds to build an NPC. Accepts a value, which will be set for the current field.
     /// Checks if the value is a valid value, if so returns an option, which will contain the
     /// NPC if building is done, and None otherwise
     pub fn set_current_field_val(
         &mut self,
         values: Vec<String>,
-    ) -> StdResult<Option<StringMap>, SetFieldError> {
+    ) -> StdResult<Option<Npc>, SetFieldError> {
         match self.current_field_infos() {
-            Some((field, opts, n)) => {
-                if values.len() != n {
-                    Err(SetFieldError::WrongN(values.len(), n))
-                } else if values.iter().all(|v| opts.contains(v)) {
-                    self.constructed_npc.insert(field, values);
-                    if self.npc_completed() {
-                        Ok(Some(self.constructed_npc.clone()))
-                    } else {
-                        Ok(None)
+            Some((field, _, _)) => self.answer_field(&field, values),
+            None => Err(SetFieldError::NPCCompleteError),
+        }
+    }
+
+    /// like set_current_field_val, but sets the given field, which can be any of the
+    /// available fields, so fields can be answered out of order
+    pub fn answer_field(
+        &mut self,
+        field: &str,
+        values: Vec<String>,
+    ) -> StdResult<Option<Npc>, SetFieldError> {
+        if self.npc_completed() {
+            return Err(SetFieldError::NPCCompleteError);
+        }
+        let bp = self
+            .blueprint
+            .blueprints
+            .get(field)
+            .ok_or_else(|| SetFieldError::UnknownField(field.into()))?;
+        if !self.available_fields().iter().any(|f| f == field) {
+            return Err(SetFieldError::FieldNotAvailable(field.into()));
+        }
+        self.check_values(field, &values)?;
+        self.constructed_npc.set(field, bp.kind(), values);
+        if let Some(display_name) = &bp.display_name {
+            self.constructed_npc.set_display_name(field, display_name);
+        }
+        if self.npc_completed() {
+            Ok(Some(self.constructed_npc.clone()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// leaves an optional field out of the NPC. The fields that depend on it become available,
+    /// but the choice sources that filter on it don't apply
+    pub fn skip_field(&mut self, field: &str) -> StdResult<Option<Npc>, SetFieldError> {
+        if self.npc_completed() {
+            return Err(SetFieldError::NPCCompleteError);
+        }
+        let bp = self
+            .blueprint
+            .blueprints
+            .get(field)
+            .ok_or_else(|| SetFieldError::UnknownField(field.into()))?;
+        if bp.probability.is_none() {
+            return Err(SetFieldError::NotOptional(field.into()));
+        }
+        if !self.available_fields().iter().any(|f| f == field) {
+            return Err(SetFieldError::FieldNotAvailable(field.into()));
+        }
+        self.skipped
+            .push((field.into(), self.constructed_npc.fields.len()));
+        if self.npc_completed() {
+            Ok(Some(self.constructed_npc.clone()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn is_optional(&self, field: &str) -> bool {
+        self.blueprint
+            .blueprints
+            .get(field)
+            .is_some_and(|bp| bp.probability.is_some())
+    }
+
+    fn skipped_fields(&self) -> HashSet<&str> {
+        self.skipped
+            .iter()
+            .map(|(field, _)| field.as_str())
+            .collect()
+    }
+
+    /// whether an optional field is left out when it is rolled, by its probability. Optional
+    /// fields without options are always left out
+    fn rolls_out<R: Rng + ?Sized>(&mut self, field: &str, rng: &mut R) -> bool {
+        let bp = &self.blueprint.blueprints[field];
+        let (has_options, probability) = (bp.number.is_none(), bp.probability);
+        match probability {
+            None => false,
+            Some(probability) if !rng.gen_bool(probability) => true,
+            Some(_) => {
+                has_options && {
+                    self.generate_options(field, rng);
+                    self.field_options(field).is_empty()
+                }
+            }
+        }
+    }
+
+    /// checks the number of values, and that they are options of the field, or numbers it can
+    /// roll
+    fn check_values(&self, field: &str, values: &[String]) -> StdResult<(), SetFieldError> {
+        let bp = &self.blueprint.blueprints[field];
+        let n = bp.n_selections;
+        if values.len() != n {
+            return Err(SetFieldError::WrongN(values.len(), n));
+        }
+        match &bp.number {
+            Some(number) => match values.iter().find(|v| !number.accepts(v)) {
+                Some(invalid) => Err(SetFieldError::InvalidNumber(invalid.into(), number.clone())),
+                None => Ok(()),
+            },
+            None => {
+                let opts = self.field_options(field);
+                match values.iter().find(|v| !opts.contains(v)) {
+                    Some(invalid) => Err(SetFieldError::InvalidValue(invalid.into(), opts)),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// lets the generators of the field's choice sources make up new names, which are added to
+    /// its options. Each makes up as many names per value the field needs as the building screen
+    /// offers options
+    pub fn generate_options<R: Rng + ?Sized>(&mut self, field: &str, rng: &mut R) {
+        let bp = &self.blueprint.blueprints[field];
+        for (i, src) in bp.sources.iter().enumerate() {
+            let generator = match &src.generator {
+                Some(generator) if self.source_applies(src) => generator,
+                _ => continue,
+            };
+            let names = self.generated.entry((field.into(), i)).or_default();
+            for _ in 0..bp.n_selections * self.options_per_value {
+                if let Some(name) = generator.generate(rng) {
+                    if !names.contains(&name) {
+                        names.push(name);
                     }
-                } else {
-                    Err(SetFieldError::InvalidValue(
-                        values
-                            .iter()
-                            .filter(|v| !opts.contains(v))
-                            .next()
-                            .unwrap()
-                            .into(),
-                        opts,
-                    ))
                 }
             }
-            None => Err(SetFieldError::NPCCompleteError),
         }
     }
 
+    /// rolls values for the field by its auto policy, or its number source. Generated options
+    /// are made up anew first
+    fn roll_values<R: Rng + ?Sized>(
+        &mut self,
+        field: &str,
+        rng: &mut R,
+    ) -> StdResult<Vec<String>, SetFieldError> {
+        self.generate_options(field, rng);
+        let bp = &self.blueprint.blueprints[field];
+        if let Some(number) = &bp.number {
+            return Ok(vec![number.roll(rng).to_string()]);
+        }
+        let (opts, n) = (self.field_options(field), bp.n_selections);
+        if opts.len() < n {
+            return Err(SetFieldError::NotEnoughOptions(field.into(), opts.len(), n));
+        }
+        Ok(bp.auto.choose(&opts, n, &self.option_weights(field), rng))
+    }
+
+    /// the number source of the field, None if it has options
+    pub fn number_source(&self, field: &str) -> Option<&NumberSource> {
+        self.blueprint.blueprints.get(field)?.number.as_ref()
+    }
+
+    /// rolls a value for a number field, None if it has options
+    pub fn roll_number<R: Rng + ?Sized>(&self, field: &str, rng: &mut R) -> Option<i64> {
+        self.number_source(field).map(|number| number.roll(rng))
+    }
+
+    /// answers the unset fields by their auto policy. Returns the finished NPC, or None if
+    /// only fields that must be prompted for are available
+    pub fn auto_fill<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+    ) -> StdResult<Option<Npc>, SetFieldError> {
+        self.fill(rng, false)
+    }
+
+    /// answers all unset fields, the ones that must be prompted for are rolled by the weights of
+    /// their options, like the others. Returns the finished NPC
+    pub fn fill_randomly<R: Rng + ?Sized>(&mut self, rng: &mut R) -> StdResult<Npc, SetFieldError> {
+        Ok(self
+            .fill(rng, true)?
+            .expect("no field is left for the user when prompts are rolled"))
+    }
+
+    fn fill<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+        roll_prompted: bool,
+    ) -> StdResult<Option<Npc>, SetFieldError> {
+        loop {
+            let available = self.available_fields();
+            let field = match available.iter().find(|f| {
+                roll_prompted || self.blueprint.blueprints[f.as_str()].auto != AutoPolicy::Prompt
+            }) {
+                Some(field) => field,
+                None if available.is_empty() => break,
+                None => return Ok(None),
+            };
+            if self.rolls_out(field, rng) {
+                self.skip_field(field)?;
+            } else {
+                let values = self.roll_values(field, rng)?;
+                self.answer_field(field, values)?;
+            }
+        }
+        if self.npc_completed() {
+            Ok(Some(self.constructed_npc.clone()))
+        } else {
+            Err(SetFieldError::UnreachableFields(
+                self.blueprint
+                    .blueprints
+                    .keys()
+                    .filter(|k| {
+                        !self.constructed_npc.contains(k)
+                            && !self.skipped_fields().contains(k.as_str())
+                    })
+                    .cloned()
+                    .collect(),
+            ))
+        }
+    }
+
+    /// like auto_fill, but fails if a field must be prompted for
+    pub fn auto_fill_remaining<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+    ) -> StdResult<Npc, SetFieldError> {
+        match self.auto_fill(rng)? {
+            Some(npc) => Ok(npc),
+            None => Err(SetFieldError::PromptRequired(
+                self.available_fields().swap_remove(0),
+            )),
+        }
+    }
+
+    /// the NPC as far as it is built, with the options as they were chosen
+    pub fn npc(&self) -> &Npc {
+        &self.constructed_npc
+    }
+
+    /// the NPC with the placeholders in its values replaced, and the computed fields added
+    pub fn finished_npc(&self) -> Npc {
+        template::resolve(&self.constructed_npc, &self.blueprint.computed)
+    }
+
+    /// the options a field of the completed NPC can be changed to, given the values of the other
+    /// fields, and the number of values it needs
+    pub fn edit_options(&self, field: &str) -> StdResult<(Vec<String>, usize), SetFieldError> {
+        let bp = self.completed_field(field)?;
+        Ok((self.field_options(field), bp.n_selections))
+    }
+
+    /// changes a field of the completed NPC to other options, or another number. The fields that
+    /// depend on it keep their values, even if they wouldn't be offered anymore
+    pub fn change_field(
+        &mut self,
+        field: &str,
+        values: Vec<String>,
+    ) -> StdResult<&Npc, SetFieldError> {
+        let kind = self.completed_field(field)?.kind();
+        self.check_values(field, &values)?;
+        self.constructed_npc.set(field, kind, values);
+        self.skipped.retain(|(f, _)| f != field);
+        Ok(&self.constructed_npc)
+    }
+
+    /// rolls a field of the completed NPC again, by its auto policy. Fields that must be
+    /// prompted for are rolled uniformly
+    pub fn reroll_field<R: Rng + ?Sized>(
+        &mut self,
+        field: &str,
+        rng: &mut R,
+    ) -> StdResult<&Npc, SetFieldError> {
+        self.completed_field(field)?;
+        let values = self.roll_values(field, rng)?;
+        self.change_field(field, values)
+    }
+
+    /// sets a field of the completed NPC to values that aren't among its options
+    pub fn set_custom_values(
+        &mut self,
+        field: &str,
+        values: Vec<String>,
+    ) -> StdResult<&Npc, SetFieldError> {
+        self.completed_field(field)?;
+        self.constructed_npc.set(field, FieldKind::Choice, values);
+        self.skipped.retain(|(f, _)| f != field);
+        Ok(&self.constructed_npc)
+    }
+
+    fn completed_field(&self, field: &str) -> StdResult<&FieldBlueprint, SetFieldError> {
+        let bp = self
+            .blueprint
+            .blueprints
+            .get(field)
+            .ok_or_else(|| SetFieldError::UnknownField(field.into()))?;
+        if !self.npc_completed() {
+            return Err(SetFieldError::NPCIncomplete);
+        }
+        Ok(bp)
+    }
+
     pub fn npc_completed(&self) -> bool {
+        let skipped = self.skipped_fields();
         self.blueprint
             .blueprints
             .keys()
-            .all(|k| self.constructed_npc.contains_key(k))
+            .all(|k| self.constructed_npc.contains(k) || skipped.contains(k.as_str()))
     }
 }
 
@@ -174,12 +740,30 @@ impl FieldBlueprint {
         FieldBlueprint {
             n_selections: 1,
             sources: vec![cs],
+            exclude: vec![],
+            exclude_saved: false,
+            display_name: None,
+            description: None,
+            auto: AutoPolicy::Uniform,
+            number: None,
+            probability: None,
         }
     }
 
+    fn kind(&self) -> FieldKind {
+        match self.number {
+            Some(_) => FieldKind::Number,
+            None => FieldKind::Choice,
+        }
+    }
+
+    /// a string is the path of a file with the options, numbers are tables with a dice key
     fn parse(toml_val: Value) -> Result<FieldBlueprint> {
         match toml_val {
             Value::String(s) => Ok(FieldBlueprint::simple(choice_source_from_file(s)?)),
+            Value::Table(tab) if tab.contains_key("dice") || tab.contains_key("range") => {
+                FieldBlueprint::parse_number(tab)
+            }
             Value::Table(tab) => {
                 let n_selections = if let Some(n_val) = tab.get("n") {
                     try_as!(n_val, integer)?
@@ -187,16 +771,125 @@ impl FieldBlueprint {
                     1
                 };
 
+                let exclude = parse_exclude(&tab)?;
+                let exclude_saved = if let Some(val) = tab.get("exclude-saved") {
+                    try_as!(val, bool)?
+                } else {
+                    false
+                };
+
+                let display_name = match tab.get("display-name") {
+                    Some(val) => Some(try_as!(val, str)?.to_string()),
+                    None => None,
+                };
+                let description = match tab.get("description") {
+                    Some(val) => Some(try_as!(val, str)?.to_string()),
+                    None => None,
+                };
+
+                let auto = AutoPolicy::parse(&tab)?;
+                let probability = parse_probability(&tab)?;
                 let sources = parse_choice_sources(tab)?;
                 Ok(FieldBlueprint {
                     n_selections: n_selections.try_into()?,
                     sources,
+                    exclude,
+                    exclude_saved,
+                    display_name,
+                    description,
+                    auto,
+                    number: None,
+                    probability,
                 })
             }
             Value::Array(array) => Ok(FieldBlueprint::simple(ChoiceSource::from_array(array)?)),
             otherwise => Err(anyhow!("Unexpected toml node: {:#?}", otherwise)),
         }
     }
+
+    /// a table with either a dice expression, like `dice = "2d6*10"`, or bounds, like
+    /// `range = [16, 80]`
+    fn parse_number(tab: toml::value::Table) -> Result<FieldBlueprint> {
+        for key in [
+            "n",
+            "file",
+            "choices",
+            "exclude",
+            "exclude-saved",
+            "weights",
+        ] {
+            ensure!(!tab.contains_key(key), "A number field can't have {}", key);
+        }
+        let number = match (tab.get("dice"), tab.get("range")) {
+            (Some(expr), None) => NumberSource::parse_dice(try_as!(expr, str)?)?,
+            (None, Some(range)) => match try_as!(range, array)?.as_slice() {
+                [Value::Integer(min), Value::Integer(max)] => {
+                    ensure!(min <= max, "The range {} to {} is reversed", min, max);
+                    NumberSource::Range(*min, *max)
+                }
+                _ => bail!("A range must look like [min, max]"),
+            },
+            _ => bail!("A number field must have either a dice key or a range key, but not both"),
+        };
+        let auto = AutoPolicy::parse(&tab)?;
+        ensure!(
+            matches!(auto, AutoPolicy::Uniform | AutoPolicy::Prompt),
+            "A number field can only be rolled, or prompted for"
+        );
+        let display_name = match tab.get("display-name") {
+            Some(val) => Some(try_as!(val, str)?.to_string()),
+            None => None,
+        };
+        let description = match tab.get("description") {
+            Some(val) => Some(try_as!(val, str)?.to_string()),
+            None => None,
+        };
+        Ok(FieldBlueprint {
+            sources: vec![],
+            display_name,
+            description,
+            auto,
+            number: Some(number),
+            probability: parse_probability(&tab)?,
+            ..FieldBlueprint::simple(ChoiceSource::from_strings(vec![]))
+        })
+    }
+}
+
+impl NumberSource {
+    fn parse_dice(expr: &str) -> Result<NumberSource> {
+        // rolling the expression is the simplest way to check it
+        dice::roll(expr)?;
+        Ok(NumberSource::Dice(expr.into()))
+    }
+
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> i64 {
+        match self {
+            NumberSource::Dice(expr) => {
+                dice::roll_with(expr, rng).expect("the expression was checked when it was parsed")
+            }
+            NumberSource::Range(min, max) => rng.gen_range(*min..=*max),
+        }
+    }
+
+    /// whether the value is a number that can be rolled. Any number is accepted for dice, even
+    /// the ones that are unlikely
+    pub fn accepts(&self, value: &str) -> bool {
+        match (self, value.trim().parse::<i64>()) {
+            (_, Err(_)) => false,
+            (NumberSource::Dice(_), Ok(_)) => true,
+            (NumberSource::Range(min, max), Ok(n)) => (*min..=*max).contains(&n),
+        }
+    }
+}
+
+impl Display for NumberSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumberSource::Dice(expr) => write!(f, "roll {}", expr),
+            NumberSource::Range(min, max) => write!(f, "from {} to {}", min, max),
+        }
+    }
 }
 
 fn parse_choice_sources(tab: toml::value::Table) -> Result<Vec<ChoiceSource>> {
@@ -207,9 +900,9 @@ fn parse_choice_sources(tab: toml::value::Table) -> Result<Vec<ChoiceSource>> {
     let has_choices = tab.contains_key("choices");
 
     if has_file && !has_choices {
-        Ok(vec![choice_source_from_file(try_field_as!(
-            tab, "file", str
-        )?)?])
+        let mut source = choice_source_from_file(try_field_as!(tab, "file", str)?)?;
+        source.generator = parse_generator(&tab, &source.options)?;
+        Ok(vec![source])
     } else if !has_file && has_choices {
         let choice_array = try_field_as!(tab, "choices", array)?;
         Ok(choice_array
@@ -230,36 +923,42 @@ fn parse_choice_sources(tab: toml::value::Table) -> Result<Vec<ChoiceSource>> {
 }
 
 impl ChoiceSource {
+    /// if p is a directory, the options of all files in it are merged. This way option lists
+    /// can be extended by dropping files into the directory.
     fn from_path(p: impl AsRef<Path>) -> Result<Self> {
         let p: &Path = p.as_ref();
-        let contents = std::fs::read_to_string(p).context(p.display().to_string())?;
-        let values = contents
-            .lines()
-            .filter_map(|l| {
-                let clean = l.split('#').next().unwrap().trim();
-                if clean.len() > 0 {
-                    Some(clean.into())
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let values = if p.is_dir() {
+            read_options_dir(p)?
+        } else {
+            read_options_file(p)?
+        };
         Ok(ChoiceSource::from_strings(values))
     }
 
+    /// the elements are options, or pairs of an option and its weight, like `["Elf", 3]`
     fn from_array(a: Vec<Value>) -> Result<Self> {
-        let values = a
-            .into_iter()
-            .map(|v| try_as!(v, str).map(|x| x.into()))
-            .collect::<Vec<Result<String>>>()
-            .pull_result()?;
-        Ok(ChoiceSource::from_strings(values))
+        let mut result = ChoiceSource::from_strings(vec![]);
+        for v in a {
+            match v {
+                Value::Array(pair) => match pair.as_slice() {
+                    [Value::String(opt), w] => {
+                        result.weights.insert(opt.clone(), parse_weight(opt, w)?);
+                        result.options.push(opt.clone());
+                    }
+                    _ => bail!("a weighted option must look like [\"option\", weight]"),
+                },
+                v => result.options.push(try_as!(v, str)?.into()),
+            }
+        }
+        Ok(result)
     }
 
     fn from_strings(vals: Vec<String>) -> ChoiceSource {
         ChoiceSource {
             options: vals,
+            weights: HashMap::new(),
             filter: ChoiceFilter::None,
+            generator: None,
         }
     }
 
@@ -277,27 +976,238 @@ impl ChoiceSource {
             bail!("a choice source must have a file or a values entry, but not both");
         };
 
+        // the weights of some of the values, the others weigh 1
+        if let Some(weights_val) = tab.get("weights") {
+            for (opt, w) in parse_weights(weights_val)? {
+                ensure!(
+                    result.options.contains(&opt),
+                    "a choice source has a weight for {}, which is not one of its values",
+                    opt
+                );
+                result.weights.insert(opt, w);
+            }
+        }
+
         if let Some(filter_val) = tab.get("filter") {
             result.filter = ChoiceFilter::from_str(try_as!(filter_val, str)?)?;
         }
 
+        let exclude = parse_exclude(&tab)?;
+        result.options.retain(|opt| !exclude.contains(opt));
+
+        result.generator = parse_generator(&tab, &result.options)?;
+        ensure!(
+            result.generator.is_none() || result.weights.is_empty(),
+            "the options of a generator can't have weights, they are only learned from"
+        );
         Ok(result)
     }
 }
 
+impl AutoPolicy {
+    /// reads the auto key of a field, and its weights table. A weights table without an auto key
+    /// makes the field weighted
+    fn parse(tab: &toml::value::Table) -> Result<AutoPolicy> {
+        let weights = match tab.get("weights") {
+            Some(val) => Some(parse_weights(val)?),
+            None => None,
+        };
+        let auto = match tab.get("auto") {
+            Some(val) => try_as!(val, str)?,
+            None if weights.is_some() => "weighted",
+            None => "uniform",
+        };
+        match (auto, weights) {
+            ("weighted", Some(weights)) => Ok(AutoPolicy::Weighted(weights)),
+            ("weighted", None) => bail!("auto = \"weighted\" needs a weights table"),
+            (_, Some(_)) => bail!("weights are only used with auto = \"weighted\""),
+            ("uniform", None) => Ok(AutoPolicy::Uniform),
+            ("first", None) => Ok(AutoPolicy::First),
+            ("prompt", None) => Ok(AutoPolicy::Prompt),
+            (other, None) => bail!(
+                "{} is not an auto policy, use uniform, weighted, first or prompt",
+                other
+            ),
+        }
+    }
+
+    /// picks n of the options, which must have at least n elements. The weights are the ones of
+    /// NpcBuilder::option_weights
+    fn choose<R: Rng + ?Sized>(
+        &self,
+        opts: &[String],
+        n: usize,
+        weights: &HashMap<String, f64>,
+        rng: &mut R,
+    ) -> Vec<String> {
+        match self {
+            AutoPolicy::First => opts[..n].to_vec(),
+            AutoPolicy::Uniform | AutoPolicy::Weighted(_) | AutoPolicy::Prompt => {
+                choose_weighted(opts, n, weights, rng)
+            }
+        }
+    }
+}
+
+/// picks up to n of the options by their weights, options without a weight weigh 1
+pub fn choose_weighted<R: Rng + ?Sized>(
+    opts: &[String],
+    n: usize,
+    weights: &HashMap<String, f64>,
+    rng: &mut R,
+) -> Vec<String> {
+    opts.choose_multiple_weighted(rng, n, |opt| weights.get(opt).copied().unwrap_or(1.0))
+        .map(|chosen| chosen.cloned().collect())
+        .unwrap_or_else(|_| opts.choose_multiple(rng, n).cloned().collect())
+}
+
+/// a table of options and their weights, like `{ Red = 2, Black = 0.5 }`
+fn parse_weights(val: &Value) -> Result<HashMap<String, f64>> {
+    try_as!(val, table)?
+        .iter()
+        .map(|(opt, w)| Ok((opt.clone(), parse_weight(opt, w)?)))
+        .collect()
+}
+
+fn parse_weight(opt: &str, w: &Value) -> Result<f64> {
+    let w = match w {
+        Value::Integer(i) => *i as f64,
+        Value::Float(f) => *f,
+        _ => bail!("the weight of {} must be a number", opt),
+    };
+    ensure!(w > 0.0, "the weight of {} must be positive", opt);
+    Ok(w)
+}
+
 impl ChoiceFilter {
+    /// `<field>: <value>`, or `<field> != <value>`. Several values are separated by `|`
     fn from_str(src: &str) -> Result<Self> {
-        let splits: Vec<&str> = src.split(':').map(|x| x.trim()).collect();
+        let (splits, negated): (Vec<&str>, bool) = match src.split_once("!=") {
+            Some((field, values)) => (vec![field, values], true),
+            None => (src.split(':').collect(), false),
+        };
         ensure!(
             splits.len() == 2,
-            "a filter definition must contain exactly one colon, yet I found:\n{}",
+            "a filter definition must contain exactly one colon, or a !=, yet I found:\n{}",
+            src
+        );
+        let target_field = splits[0].trim();
+        let target_values: Vec<String> = splits[1].split('|').map(|v| v.trim().into()).collect();
+        ensure!(
+            !target_field.is_empty() && target_values.iter().all(|v| !v.is_empty()),
+            "a filter needs a field and values, yet I found:\n{}",
             src
         );
         Ok(ChoiceFilter::FieldValue {
-            target_field: splits[0].into(),
-            target_value: splits[1].into(),
+            target_field: target_field.into(),
+            target_values,
+            negated,
+        })
+    }
+}
+
+/// the optional generator key of a choice source, which is trained on its options, and the
+/// order key of the generator
+fn parse_generator(tab: &toml::value::Table, options: &[String]) -> Result<Option<MarkovChain>> {
+    let order = match tab.get("order") {
+        Some(val) => Some(try_as!(val, integer)?),
+        None => None,
+    };
+    match tab.get("generator") {
+        Some(val) => Ok(Some(MarkovChain::parse(
+            try_as!(val, str)?,
+            order,
+            options,
+        )?)),
+        None if order.is_some() => bail!("order is only used with a generator"),
+        None => Ok(None),
+    }
+}
+
+/// the optional and probability keys of a field. A probability makes the field optional
+fn parse_probability(tab: &toml::value::Table) -> Result<Option<f64>> {
+    let optional = match tab.get("optional") {
+        Some(val) => Some(try_as!(val, bool)?),
+        None => None,
+    };
+    let probability = match tab.get("probability") {
+        Some(Value::Integer(i)) => Some(*i as f64),
+        Some(Value::Float(f)) => Some(*f),
+        Some(_) => bail!("the probability must be a number"),
+        None => None,
+    };
+    match (optional, probability) {
+        (Some(false), Some(_)) => bail!("a probability is only used with optional fields"),
+        (Some(false) | None, None) => Ok(None),
+        (Some(true), None) => Ok(Some(DEFAULT_PROBABILITY)),
+        (_, Some(p)) => {
+            ensure!(
+                p > 0.0 && p <= 1.0,
+                "the probability must be above 0, and at most 1, not {}",
+                p
+            );
+            Ok(Some(p))
+        }
+    }
+}
+
+/// reads the optional exclude array of a field or choice source
+fn parse_exclude(tab: &toml::value::Table) -> Result<Vec<String>> {
+    match tab.get("exclude") {
+        Some(val) => parse_string_array(val),
+        None => Ok(vec![]),
+    }
+}
+
+fn parse_string_array(val: &Value) -> Result<Vec<String>> {
+    try_as!(val, array)?
+        .iter()
+        .map(|v| try_as!(v, str).map(|x| x.to_string()))
+        .collect::<Vec<Result<String>>>()
+        .pull_result()
+}
+
+fn read_options_file(p: &Path) -> Result<Vec<String>> {
+    options_cache::get_or_read(p, parse_options_file)
+}
+
+fn parse_options_file(p: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(p).context(p.display().to_string())?;
+    Ok(contents
+        .lines()
+        .filter_map(|l| {
+            let clean = l.split('#').next().unwrap().trim();
+            if !clean.is_empty() {
+                Some(clean.into())
+            } else {
+                None
+            }
         })
+        .collect())
+}
+
+/// reads all non-hidden files in the directory in alphabetical order. Options that occur in
+/// multiple files are only kept once
+fn read_options_dir(p: &Path) -> Result<Vec<String>> {
+    let mut files = std::fs::read_dir(p)
+        .context(p.display().to_string())?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Vec<std::io::Result<PathBuf>>>()
+        .pull_result()?;
+    files.retain(|f| {
+        f.is_file()
+            && !f
+                .file_name()
+                .map(|n| n.to_string_lossy().starts_with('.'))
+                .unwrap_or(true)
+    });
+    files.sort();
+
+    let mut values = vec![];
+    for file in files {
+        values.extend(read_options_file(&file)?);
     }
+    Ok(values.into_iter().unique().collect())
 }
 
 fn relative_to_conf_file(p: impl AsRef<Path>) -> Result<PathBuf> {
@@ -306,6 +1216,22 @@ fn relative_to_conf_file(p: impl AsRef<Path>) -> Result<PathBuf> {
     Ok(conf_dir().join(p))
 }
 
+/// a table like `{ computed = "{first-name} the {job}", display-name = "Title" }`
+fn parse_computed(name: String, val: Value) -> Result<ComputedField> {
+    let tab = try_as!(val, table)?;
+    let display_name = match tab.get("display-name") {
+        Some(val) => Some(try_as!(val, str)?.to_string()),
+        None => None,
+    };
+    Ok(ComputedField {
+        template: try_field_as!(tab, "computed", str)
+            .with_context(|| format!("in the computed field {}", name))?
+            .into(),
+        name,
+        display_name,
+    })
+}
+
 fn choice_source_from_file(p: impl AsRef<Path>) -> Result<ChoiceSource> {
     ChoiceSource::from_path(relative_to_conf_file(p)?)
 }
@@ -313,6 +1239,631 @@ fn choice_source_from_file(p: impl AsRef<Path>) -> Result<ChoiceSource> {
 pub fn load_blueprints_from_table(
     tab: toml::value::Table,
 ) -> Result<HashMap<String, NpcBlueprint>> {
-    let entries = tab.into_iter().map(|(k, v)| (k, NpcBlueprint::parse(v)));
+    let entries = tab.into_iter().map(|(k, v)| {
+        let bp = NpcBlueprint::parse(&k, v);
+        (k, bp)
+    });
     HashMap::from_iter(entries).pull_result()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_blueprint() -> NpcBlueprint {
+        let src = r#"
+            race = ["Elf", "Dwarf"]
+            hair = ["Red", "Black", "Blond"]
+
+            [name]
+            choices = [
+                { values = ["Legolas"], filter = "race: Elf" },
+                { values = ["Gimli"], filter = "race: Dwarf" },
+            ]
+        "#;
+        NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_answer_field_out_of_order() {
+        let mut builder = NpcBuilder::new(test_blueprint());
+        assert!(matches!(
+            builder.answer_field("name", vec!["Gimli".into()]),
+            Err(SetFieldError::FieldNotAvailable(_))
+        ));
+        assert!(matches!(
+            builder.answer_field("race", vec!["Orc".into()]),
+            Err(SetFieldError::InvalidValue(..))
+        ));
+        assert!(builder
+            .answer_field("race", vec!["Dwarf".into()])
+            .unwrap()
+            .is_none());
+        assert!(builder
+            .answer_field("name", vec!["Gimli".into()])
+            .unwrap()
+            .is_none());
+        let npc = builder
+            .auto_fill_remaining(&mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(npc["name"], vec!["Gimli".to_string()]);
+        assert_eq!(npc["hair"].len(), 1);
+    }
+
+    #[test]
+    fn test_auto_fill_respects_filters() {
+        for _ in 0..10 {
+            let mut builder = NpcBuilder::new(test_blueprint());
+            let npc = builder
+                .auto_fill_remaining(&mut rand::thread_rng())
+                .unwrap();
+            let expected = if npc["race"][0] == "Elf" {
+                "Legolas"
+            } else {
+                "Gimli"
+            };
+            assert_eq!(npc["name"], vec![expected.to_string()]);
+        }
+    }
+
+    #[test]
+    fn test_filter_alternatives_and_negation() {
+        let src = r#"
+            race = ["Elf", "Half-Elf", "Dwarf", "Orc"]
+
+            [[weapon.choices]]
+            values = ["Bow"]
+            filter = "race: Elf | Half-Elf"
+
+            [[weapon.choices]]
+            values = ["Sword"]
+            filter = "race != Dwarf|Orc"
+
+            [[weapon.choices]]
+            values = ["Axe"]
+            filter = "race != Elf"
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        for (race, weapons) in [
+            ("Elf", vec!["Bow", "Sword"]),
+            ("Half-Elf", vec!["Bow", "Sword", "Axe"]),
+            ("Dwarf", vec!["Axe"]),
+        ] {
+            let mut builder = NpcBuilder::new(bp.clone());
+            builder.answer_field("race", vec![race.into()]).unwrap();
+            let (_, opts, _) = builder.current_field_infos().unwrap();
+            assert_eq!(opts, weapons, "{}", race);
+        }
+
+        for invalid in [
+            "race",
+            "race: Elf: Dwarf",
+            "race != ",
+            ": Elf",
+            "race: Elf|",
+        ] {
+            assert!(ChoiceFilter::from_str(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_exclude() {
+        let src = r#"
+            [hair]
+            exclude = ["Blond"]
+            choices = [
+                { values = ["Red", "Black", "Blond"] },
+                { values = ["Grey", "White"], exclude = ["White"] },
+            ]
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        let mut builder = NpcBuilder::new(bp);
+        builder.exclude_values("hair", vec!["Red".to_string()]);
+        let (_, opts, _) = builder.current_field_infos().unwrap();
+        assert_eq!(opts, vec!["Black".to_string(), "Grey".to_string()]);
+    }
+
+    #[test]
+    fn test_description() {
+        let src = r#"
+            quirk = ["Hums constantly"]
+
+            [demeanor]
+            description = "How they act towards strangers, unlike quirk, which is a habit"
+            n = 2
+            choices = [{ values = ["Friendly", "Suspicious", "Bored"] }]
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        assert_eq!(bp.field("quirk").unwrap().description, None);
+        assert_eq!(
+            bp.describe(),
+            "demeanor (choose 2): How they act towards strangers, unlike quirk, which is a \
+             habit\nquirk (choose 1)"
+        );
+    }
+
+    #[test]
+    fn test_auto_policies() {
+        let src = r#"
+            [race]
+            auto = "first"
+            choices = [{ values = ["Human", "Elf"] }]
+
+            [name]
+            auto = "prompt"
+            choices = [{ values = ["Ada", "Bo"] }]
+
+            [hair]
+            weights = { Red = 1000000, Black = 0.001 }
+            choices = [{ values = ["Red", "Black"] }]
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        assert_eq!(
+            bp.describe(),
+            "hair (choose 1, weighted)\nname (choose 1, always prompted)\n\
+             race (choose 1, always first)"
+        );
+        let mut builder = NpcBuilder::new(bp.clone());
+        assert!(builder
+            .auto_fill(&mut rand::thread_rng())
+            .unwrap()
+            .is_none());
+        assert_eq!(builder.available_fields(), vec!["name".to_string()]);
+        assert!(builder
+            .answer_field("name", vec!["Bo".into()])
+            .unwrap()
+            .is_some());
+        let npc = builder.constructed_npc.clone();
+        assert_eq!(npc["race"], vec!["Human".to_string()]);
+        assert_eq!(npc["hair"], vec!["Red".to_string()]);
+        assert!(matches!(
+            NpcBuilder::new(bp.clone()).auto_fill_remaining(&mut rand::thread_rng()),
+            Err(SetFieldError::PromptRequired(f)) if f == "name"
+        ));
+        let npc = NpcBuilder::new(bp)
+            .fill_randomly(&mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(npc["race"], vec!["Human".to_string()]);
+        assert_eq!(npc["name"].len(), 1);
+
+        for invalid in [
+            r#"hair = { auto = "weighted", choices = [{ values = ["Red"] }] }"#,
+            r#"hair = { auto = "sometimes", choices = [{ values = ["Red"] }] }"#,
+            r#"hair = { auto = "first", weights = { Red = 2 }, choices = [{ values = ["Red"] }] }"#,
+            r#"hair = { weights = { Red = 0 }, choices = [{ values = ["Red"] }] }"#,
+        ] {
+            let val = invalid.parse::<Value>().unwrap();
+            assert!(NpcBlueprint::parse("Test", val).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_display_layout() {
+        let src = r#"
+            race = ["Elf"]
+            hair = ["Red"]
+            name = ["Legolas"]
+
+            [_display]
+            order = ["name"]
+            hidden = ["race"]
+            sections = { Looks = ["hair"] }
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        let mut builder = NpcBuilder::new(bp);
+        let mut npc = builder
+            .auto_fill_remaining(&mut rand::thread_rng())
+            .unwrap();
+        npc.set("note", FieldKind::Text, vec![]);
+        fn fields<'a>(s: &Section<'a>) -> Vec<&'a str> {
+            s.fields.iter().map(|f| f.name.as_str()).collect()
+        }
+        let layout = builder.blueprint().display.layout(&npc);
+        assert_eq!(layout.len(), 2);
+        assert_eq!(fields(&layout[0]), vec!["name", "note"]);
+        assert_eq!(layout[1].title, Some("Looks"));
+        assert_eq!(fields(&layout[1]), vec!["hair"]);
+    }
+
+    #[test]
+    fn test_default_blueprints() {
+        let t = include_str!("../../../npc_gen.toml")
+            .parse::<Value>()
+            .unwrap();
+        let blueprints = load_blueprints_from_table(t.as_table().unwrap().clone()).unwrap();
+        let mut builder = NpcBuilder::new(blueprints["Villager"].clone());
+        builder.answer_field("secret", vec!["None".into()]).unwrap();
+        let npc = builder
+            .auto_fill_remaining(&mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(npc["demeanor"].len(), 2);
+        assert_eq!(npc["secret"], vec!["None".to_string()]);
+        let title = &builder.finished_npc()["title"][0];
+        assert_eq!(*title, format!("{} the {}", npc["name"][0], npc["job"][0]));
+    }
+
+    #[test]
+    fn test_edit_fields() {
+        let mut builder = NpcBuilder::new(test_blueprint());
+        assert!(matches!(
+            builder.edit_options("race"),
+            Err(SetFieldError::NPCIncomplete)
+        ));
+        builder.answer_field("race", vec!["Elf".into()]).unwrap();
+        builder
+            .auto_fill_remaining(&mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(
+            builder.edit_options("name").unwrap(),
+            (vec!["Legolas".to_string()], 1)
+        );
+        builder.change_field("race", vec!["Dwarf".into()]).unwrap();
+        // the name isn't changed with the race, but now the dwarf names are offered
+        assert_eq!(builder.npc()["name"], vec!["Legolas".to_string()]);
+        assert!(matches!(
+            builder.change_field("name", vec!["Legolas".into()]),
+            Err(SetFieldError::InvalidValue(..))
+        ));
+        let npc = builder
+            .reroll_field("name", &mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(npc["name"], vec!["Gimli".to_string()]);
+        assert!(matches!(
+            builder.change_field("hair", vec!["Red".into(), "Black".into()]),
+            Err(SetFieldError::WrongN(2, 1))
+        ));
+        let npc = builder
+            .set_custom_values("hair", vec!["Green".into()])
+            .unwrap();
+        assert_eq!(npc["hair"], vec!["Green".to_string()]);
+        assert!(matches!(
+            builder.reroll_field("eyes", &mut rand::thread_rng()),
+            Err(SetFieldError::UnknownField(_))
+        ));
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut builder = NpcBuilder::new(test_blueprint());
+        assert_eq!(builder.undo(), None);
+        builder.answer_field("race", vec!["Elf".into()]).unwrap();
+        builder
+            .answer_field("name", vec!["Legolas".into()])
+            .unwrap();
+        assert_eq!(builder.undo(), Some("name".into()));
+        assert!(!builder.npc().contains("name"));
+        assert_eq!(builder.field_infos("name"), (vec!["Legolas".into()], 1));
+        assert_eq!(builder.undo(), Some("race".into()));
+        assert!(builder
+            .answer_field("race", vec!["Dwarf".into()])
+            .unwrap()
+            .is_none());
+        assert!(builder
+            .answer_field("name", vec!["Gimli".into()])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_weighted_options() {
+        let src = r#"
+            race = [["Human", 1000000], ["Elf", 0.001], "Dwarf"]
+
+            [name]
+            choices = [
+                { values = ["Ada", "Bo"], weights = { Ada = 0.001, Bo = 1000000 }, filter = "race: Human" },
+                { values = ["Gimli"], filter = "race: Dwarf" },
+            ]
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        let mut builder = NpcBuilder::new(bp.clone());
+        assert_eq!(builder.option_weights("race")["Elf"], 0.001);
+        assert!(!builder.option_weights("race").contains_key("Dwarf"));
+        let npc = builder
+            .auto_fill_remaining(&mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(npc["race"], vec!["Human".to_string()]);
+        assert_eq!(npc["name"], vec!["Bo".to_string()]);
+        // the weights of sources that don't apply are ignored
+        assert!(!builder.option_weights("name").contains_key("Gimli"));
+        builder.change_field("race", vec!["Dwarf".into()]).unwrap();
+        assert!(builder.option_weights("name").is_empty());
+
+        for invalid in [
+            r#"race = [["Human"]]"#,
+            r#"race = [["Human", "ten"]]"#,
+            r#"race = [["Human", -1]]"#,
+            r#"race = { choices = [{ values = ["Human", "Elf"], weights = [1, 1] }] }"#,
+            r#"race = { choices = [{ values = ["Human", "Elf"], weights = { Orc = 1 } }] }"#,
+        ] {
+            let val = invalid.parse::<Value>().unwrap();
+            assert!(NpcBlueprint::parse("Test", val).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_templates() {
+        let src = r#"
+            first-name = ["Ada"]
+            job = ["Smith", "{first-name}'s apprentice"]
+            motto = ["{job} {not a placeholder}", "{motto}"]
+
+            [title]
+            computed = "{first-name} the {job}"
+            display-name = "Known as"
+
+            [greeting]
+            computed = "Hail, {title}!"
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        assert!(bp.field("title").is_none());
+        assert!(bp
+            .describe()
+            .ends_with("motto (choose 1)\ntitle (computed as {first-name} the {job})"));
+        let mut builder = NpcBuilder::new(bp);
+        builder
+            .answer_field("job", vec!["{first-name}'s apprentice".into()])
+            .unwrap();
+        builder
+            .answer_field("first-name", vec!["Ada".into()])
+            .unwrap();
+        builder
+            .answer_field("motto", vec!["{motto}".into()])
+            .unwrap();
+        let npc = builder.finished_npc();
+        assert_eq!(npc["job"], vec!["Ada's apprentice".to_string()]);
+        // a field can't refer to itself
+        assert_eq!(npc["motto"], vec!["{motto}".to_string()]);
+        assert_eq!(npc["title"], vec!["Ada the Ada's apprentice".to_string()]);
+        assert_eq!(
+            npc["greeting"],
+            vec!["Hail, Ada the Ada's apprentice!".to_string()]
+        );
+        let title = npc.field("title").unwrap();
+        assert_eq!(title.kind, FieldKind::Computed);
+        assert_eq!(title.display_name, "Known as");
+        // the chosen values keep their placeholders, so they are resolved again after edits
+        assert_eq!(builder.npc()["job"][0], "{first-name}'s apprentice");
+        builder.change_field("job", vec!["Smith".into()]).unwrap();
+        assert_eq!(
+            builder.finished_npc()["title"],
+            vec!["Ada the Smith".to_string()]
+        );
+
+        for invalid in [
+            r#"job = ["{profession}"]"#,
+            r#"title = { computed = "{name}" }"#,
+            r#"title = { computed = 3 }"#,
+        ] {
+            let val = invalid.parse::<Value>().unwrap();
+            assert!(NpcBlueprint::parse("Test", val).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_number_fields() {
+        let src = r#"
+            age = { dice = "3d20+16" }
+            gold = { dice = "2d6*10", display-name = "Gold pieces" }
+            height = { range = [150, 190], auto = "prompt" }
+            names = ["Ada"]
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        assert_eq!(
+            bp.describe(),
+            "age (roll 3d20+16)\ngold (roll 2d6*10)\nheight (from 150 to 190, always prompted)\n\
+             names (choose 1)"
+        );
+        let mut builder = NpcBuilder::new(bp);
+        assert!(builder
+            .auto_fill(&mut rand::thread_rng())
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            builder.answer_field("height", vec!["200".into()]),
+            Err(SetFieldError::InvalidNumber(..))
+        ));
+        assert!(matches!(
+            builder.answer_field("height", vec!["tall".into()]),
+            Err(SetFieldError::InvalidNumber(..))
+        ));
+        let rolled = builder
+            .roll_number("height", &mut rand::thread_rng())
+            .unwrap();
+        assert!((150..=190).contains(&rolled));
+        assert!(builder
+            .roll_number("names", &mut rand::thread_rng())
+            .is_none());
+        let npc = builder
+            .answer_field("height", vec!["170".into()])
+            .unwrap()
+            .unwrap();
+        let age: i64 = npc["age"][0].parse().unwrap();
+        assert!((19..=76).contains(&age));
+        let gold = npc.field("gold").unwrap();
+        assert_eq!(gold.kind, FieldKind::Number);
+        assert_eq!(gold.display_name, "Gold pieces");
+        assert_eq!(builder.edit_options("age").unwrap(), (vec![], 1));
+        builder.change_field("age", vec!["99".into()]).unwrap();
+        builder
+            .reroll_field("height", &mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(builder.npc()["age"], vec!["99".to_string()]);
+
+        for invalid in [
+            r#"age = { dice = "3d" }"#,
+            r#"age = { range = [80, 16] }"#,
+            r#"age = { range = [16] }"#,
+            r#"age = { dice = "1d6", range = [1, 6] }"#,
+            r#"age = { dice = "1d6", n = 2 }"#,
+            r#"age = { dice = "1d6", auto = "first" }"#,
+            // a string is always a file, even if it looks like dice
+            r#"age = "1d6""#,
+        ] {
+            let val = invalid.parse::<Value>().unwrap();
+            assert!(NpcBlueprint::parse("Test", val).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        let src = r#"
+            race = ["Elf"]
+            secret = { optional = true, choices = [{ values = ["Cursed"] }] }
+            quirk = { probability = 0.000001, choices = [{ values = ["Hums"] }] }
+            gold = { dice = "2d6", probability = 1 }
+            [curse]
+            optional = true
+            choices = [{ values = ["Turns into a frog"], filter = "secret: Cursed" }]
+        "#;
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        assert_eq!(
+            bp.describe(),
+            "curse (choose 1, in 50% of NPCs)\ngold (roll 2d6, in 100% of NPCs)\n\
+             quirk (choose 1, in 0% of NPCs)\nrace (choose 1)\nsecret (choose 1, in 50% of NPCs)"
+        );
+        let mut builder = NpcBuilder::new(bp.clone());
+        assert!(matches!(
+            builder.skip_field("race"),
+            Err(SetFieldError::NotOptional(_))
+        ));
+        builder.skip_field("secret").unwrap();
+        // the curse depends on the secret, which counts as set once it is left out
+        assert!(builder.available_fields().contains(&"curse".to_string()));
+        assert!(builder.field_infos("curse").0.is_empty());
+        assert_eq!(builder.undo(), Some("secret".into()));
+        builder
+            .answer_field("secret", vec!["Cursed".into()])
+            .unwrap();
+        builder.skip_field("curse").unwrap();
+        assert_eq!(builder.undo(), Some("curse".into()));
+        assert_eq!(builder.undo(), Some("secret".into()));
+        assert!(!builder.can_undo());
+
+        for _ in 0..10 {
+            let npc = NpcBuilder::new(bp.clone())
+                .fill_randomly(&mut rand::thread_rng())
+                .unwrap();
+            assert!(!npc.contains("quirk"));
+            assert!(npc.contains("gold"));
+            // without the secret, the curse has no options, so it is left out too
+            assert!(npc.contains("secret") || !npc.contains("curse"));
+        }
+
+        for invalid in [
+            r#"secret = { optional = false, probability = 0.5, choices = [{ values = ["A"] }] }"#,
+            r#"secret = { probability = 0, choices = [{ values = ["A"] }] }"#,
+            r#"secret = { probability = 1.5, choices = [{ values = ["A"] }] }"#,
+            r#"secret = { optional = "yes", choices = [{ values = ["A"] }] }"#,
+        ] {
+            let val = invalid.parse::<Value>().unwrap();
+            assert!(NpcBlueprint::parse("Test", val).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_generated_names() {
+        let src = r#"
+            race = ["Elf", "Dwarf"]
+
+            [[name.choices]]
+            values = ["Legolas", "Galadriel", "Elrond", "Arwen", "Celeborn", "Thranduil"]
+            generator = "markov"
+            filter = "race: Elf"
+
+            [[name.choices]]
+            values = ["Gimli"]
+            filter = "race: Dwarf"
+        "#;
+        let elves = [
+            "Legolas",
+            "Galadriel",
+            "Elrond",
+            "Arwen",
+            "Celeborn",
+            "Thranduil",
+        ];
+        let bp = NpcBlueprint::parse("Test", src.parse::<Value>().unwrap()).unwrap();
+        let mut rng = rand::thread_rng();
+        let mut builder = NpcBuilder::new(bp);
+        builder.answer_field("race", vec!["Elf".into()]).unwrap();
+        // the names of a generator aren't offered, only the ones it made up
+        assert!(builder.field_infos("name").0.is_empty());
+        builder.generate_options("name", &mut rng);
+        let (opts, _) = builder.field_infos("name");
+        assert!((1..=3).contains(&opts.len()), "{:?}", opts);
+        for name in &opts {
+            assert!(!elves.contains(&name.as_str()), "{}", name);
+            assert!((5..=9).contains(&name.chars().count()), "{}", name);
+        }
+        let npc = builder
+            .answer_field("name", vec![opts[0].clone()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(npc["name"], vec![opts[0].clone()]);
+        let name = builder.reroll_field("name", &mut rng).unwrap()["name"][0].clone();
+        assert!(!elves.contains(&name.as_str()), "{}", name);
+        builder.change_field("race", vec!["Dwarf".into()]).unwrap();
+        let npc = builder.reroll_field("name", &mut rng).unwrap();
+        assert_eq!(npc["name"], vec!["Gimli".to_string()]);
+
+        for invalid in [
+            r#"name = { choices = [{ values = ["Ada"], generator = "neural" }] }"#,
+            r#"name = { choices = [{ values = ["Ada"], generator = "markov", order = 9 }] }"#,
+            r#"name = { choices = [{ values = ["Ada"], order = 2 }] }"#,
+            r#"name = { choices = [{ values = [["Ada", 2]], generator = "markov" }] }"#,
+        ] {
+            let val = invalid.parse::<Value>().unwrap();
+            assert!(NpcBlueprint::parse("Test", val).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let src = r#"[Villager]
+race = ["Human", "Elf"]
+job = "no/such/file.txt"
+hair = { choices = [{ values = ["Red"], exclude = ["Red"] }] }
+title = { computed = 3 }
+
+[Villager.name]
+choices = [{ values = ["Ada"], filter = "race: Human" }, { values = ["Bo"], filter = "species: Elf" }]
+
+[Cultist]
+rank = { choices = [{ values = ["Acolyte"], filter = "cult: Old Ones" }] }
+cult = { choices = [{ values = ["Old Ones"], filter = "rank: Acolyte" }] }
+
+[Ghost]
+mood = ["{creed}"]
+"#;
+        let problems: Vec<String> = validate(src).iter().map(|p| p.to_string()).collect();
+        assert_eq!(problems.len(), 6, "{:#?}", problems);
+        assert!(problems[0].starts_with("line 3: Villager.job: "));
+        assert!(problems[0].contains("no/such/file.txt"));
+        assert_eq!(
+            problems[1],
+            "line 4: Villager.hair: Choice source 1 has no options"
+        );
+        assert!(problems[2].starts_with("line 5: Villager.title: "));
+        assert_eq!(
+            problems[3],
+            "line 7: Villager.name: Choice source 2 filters on species, which is not a field"
+        );
+        assert_eq!(
+            problems[4],
+            "line 12: Cultist.cult: The fields depend on each other, so none of them can be \
+             chosen: cult -> rank -> cult"
+        );
+        // the placeholders are checked with the whole blueprint
+        assert!(
+            problems[5].starts_with("line 14: Ghost: {creed} of mood refers to creed"),
+            "{}",
+            problems[5]
+        );
+
+        let invalid = validate("[Villager\nrace = []");
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].line, Some(1));
+        assert!(validate(include_str!("../../../npc_gen.toml")).is_empty());
+    }
+}
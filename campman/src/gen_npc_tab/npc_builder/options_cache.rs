@@ -0,0 +1,129 @@
+//! Option files are often shared by many fields and blueprints, and large name lists are slow
+//! to read. The cache keeps the parsed options of every file until the file is modified, so
+//! ReInit doesn't read them again.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+
+static CACHE: Lazy<Mutex<OptionsCache>> = Lazy::new(Default::default);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub files: usize,
+    pub options: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct OptionsCache {
+    files: HashMap<PathBuf, CachedFile>,
+    hits: u64,
+    misses: u64,
+}
+
+struct CachedFile {
+    modified: SystemTime,
+    options: Vec<String>,
+}
+
+impl OptionsCache {
+    fn get_or_read(
+        &mut self,
+        p: &Path,
+        read: impl FnOnce(&Path) -> Result<Vec<String>>,
+    ) -> Result<Vec<String>> {
+        let modified = std::fs::metadata(p)
+            .and_then(|m| m.modified())
+            .context(p.display().to_string())?;
+        if let Some(cached) = self.files.get(p) {
+            if cached.modified == modified {
+                self.hits += 1;
+                return Ok(cached.options.clone());
+            }
+        }
+        self.misses += 1;
+        let options = read(p)?;
+        self.files.insert(
+            p.to_path_buf(),
+            CachedFile {
+                modified,
+                options: options.clone(),
+            },
+        );
+        Ok(options)
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            files: self.files.len(),
+            options: self.files.values().map(|f| f.options.len()).sum(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+/// the options of the file from the cache, if it wasn't modified since it was cached, and
+/// otherwise read with the given function
+pub fn get_or_read(
+    p: &Path,
+    read: impl FnOnce(&Path) -> Result<Vec<String>>,
+) -> Result<Vec<String>> {
+    CACHE.lock().unwrap().get_or_read(p, read)
+}
+
+pub fn stats() -> CacheStats {
+    CACHE.lock().unwrap().stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cache() {
+        let path = std::env::temp_dir().join(format!("options-{}.txt", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut cache = OptionsCache::default();
+        let read = |_: &Path| Ok(vec!["Bob".to_string()]);
+
+        assert_eq!(cache.get_or_read(&path, read).unwrap(), vec!["Bob"]);
+        assert_eq!(
+            cache.get_or_read(&path, |_| unreachable!()).unwrap(),
+            vec!["Bob"]
+        );
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                files: 1,
+                options: 1,
+                hits: 1,
+                misses: 1
+            }
+        );
+
+        // a modified file is read again
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let read = |_: &Path| Ok(vec!["Alice".to_string(), "Eve".to_string()]);
+        assert_eq!(
+            cache.get_or_read(&path, read).unwrap(),
+            vec!["Alice", "Eve"]
+        );
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.stats().options, 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
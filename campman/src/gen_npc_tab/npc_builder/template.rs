@@ -0,0 +1,108 @@
+//! Placeholders like `{profession}` in options and in computed fields, which are replaced by the
+//! values of the named field once the NPC is finished. Braces around anything that isn't a field
+//! name are kept as they are.
+use itertools::Itertools;
+
+use crate::npc::{FieldKind, Npc};
+
+/// a field whose value is composed of other fields, like `computed = "{first-name} the {job}"`
+#[derive(Debug, Clone)]
+pub struct ComputedField {
+    pub name: String,
+    pub template: String,
+    pub display_name: Option<String>,
+}
+
+/// the field names of the placeholders in the text
+pub fn placeholders(text: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(name) = placeholder_at(rest) {
+            names.push(name);
+            rest = &rest[name.len() + 1..];
+        }
+    }
+    names
+}
+
+/// replaces the placeholders in the values of the NPC, and adds the computed fields. Fields that
+/// aren't set, or refer to themselves, are left as placeholders
+pub fn resolve(npc: &Npc, computed: &[ComputedField]) -> Npc {
+    let resolver = Resolver { npc, computed };
+    let mut resolved = npc.clone();
+    for field in &mut resolved.fields {
+        let mut visiting = vec![field.name.clone()];
+        for value in &mut field.values {
+            *value = resolver.expand(value, &mut visiting);
+        }
+    }
+    for field in computed {
+        let value = resolver
+            .field_text(&field.name, &mut vec![])
+            .unwrap_or_else(|| field.template.clone());
+        resolved.set(&field.name, FieldKind::Computed, vec![value]);
+        if let Some(display_name) = &field.display_name {
+            resolved.set_display_name(&field.name, display_name);
+        }
+    }
+    resolved
+}
+
+struct Resolver<'a> {
+    npc: &'a Npc,
+    computed: &'a [ComputedField],
+}
+
+impl Resolver<'_> {
+    /// the resolved values of a field, joined by commas, or None if it can't be resolved
+    fn field_text(&self, name: &str, visiting: &mut Vec<String>) -> Option<String> {
+        if visiting.iter().any(|v| v == name) {
+            return None;
+        }
+        visiting.push(name.into());
+        let text = match self.npc.get(name) {
+            Some(values) => Some(values.iter().map(|v| self.expand(v, visiting)).join(", ")),
+            None => self
+                .computed
+                .iter()
+                .find(|c| c.name == name)
+                .map(|c| self.expand(&c.template, visiting)),
+        };
+        visiting.pop();
+        text
+    }
+
+    fn expand(&self, text: &str, visiting: &mut Vec<String>) -> String {
+        let mut res = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            res.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            match placeholder_at(rest) {
+                Some(name) => {
+                    match self.field_text(name, visiting) {
+                        Some(value) => res.push_str(&value),
+                        None => res.push_str(&format!("{{{}}}", name)),
+                    }
+                    rest = &rest[name.len() + 1..];
+                }
+                None => res.push('{'),
+            }
+        }
+        res.push_str(rest);
+        res
+    }
+}
+
+/// the field name, if the text starts with one that is followed by a closing brace
+fn placeholder_at(text: &str) -> Option<&str> {
+    let end = text.find('}')?;
+    let name = &text[..end];
+    let is_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    is_name.then_some(name)
+}
@@ -0,0 +1,177 @@
+//! Checks of npc_gen.toml that report all problems at once, with the line they are in, instead of
+//! failing at the first one, or only once an NPC of a broken blueprint is generated.
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use toml::Value;
+
+use super::dependency_graph::cycles;
+use super::{parse_computed, ChoiceFilter, FieldBlueprint, NpcBlueprint, DISPLAY_KEY};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// the line of the blueprint or field in the file, starting at 1, if it was found
+    pub line: Option<usize>,
+    /// like `Villager.name`, empty for problems of the whole file
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        if !self.location.is_empty() {
+            write!(f, "{}: ", self.location)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// checks the text of npc_gen.toml, and the files it refers to
+pub fn validate(text: &str) -> Vec<Problem> {
+    let table = match text.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        Ok(_) => unreachable!("a toml document is a table"),
+        Err(e) => {
+            return vec![Problem {
+                line: e.line_col().map(|(line, _)| line + 1),
+                location: String::new(),
+                message: e.to_string(),
+            }]
+        }
+    };
+    let mut problems = vec![];
+    for (name, value) in &table {
+        let mut report = |field: Option<&str>, message: String| {
+            let location = match field {
+                Some(field) => format!("{}.{}", name, field),
+                None => name.clone(),
+            };
+            problems.push(Problem {
+                line: find_line(text, name, field),
+                location,
+                message,
+            })
+        };
+        let fields = match value {
+            Value::Table(fields) => fields,
+            _ => {
+                report(None, "A blueprint must be a table of fields".into());
+                continue;
+            }
+        };
+        let n_problems = problems_of_blueprint(fields, &mut report);
+        // the checks of the whole blueprint only make sense if the fields are fine
+        if n_problems == 0 {
+            if let Err(e) = NpcBlueprint::parse(name, value.clone()) {
+                report(None, format!("{:#}", e));
+            }
+        }
+    }
+    problems.sort_by_key(|p| p.line);
+    problems
+}
+
+/// reports the problems of the fields, and returns how many there were
+fn problems_of_blueprint(
+    fields: &toml::value::Table,
+    report: &mut impl FnMut(Option<&str>, String),
+) -> usize {
+    let mut n_problems = 0;
+    let mut report = |field: Option<&str>, message: String| {
+        n_problems += 1;
+        report(field, message);
+    };
+    let declared: HashSet<&str> = fields
+        .keys()
+        .map(String::as_str)
+        .filter(|k| *k != DISPLAY_KEY)
+        .collect();
+    // the fields each field depends on through its filters
+    let mut dependencies: HashMap<&str, Vec<String>> = HashMap::new();
+    for (field, value) in fields {
+        if field == DISPLAY_KEY {
+            continue;
+        }
+        if value.as_table().is_some_and(|t| t.contains_key("computed")) {
+            if let Err(e) = parse_computed(field.clone(), value.clone()) {
+                report(Some(field), format!("{:#}", e));
+            }
+            continue;
+        }
+        let bp = match FieldBlueprint::parse(value.clone()) {
+            Ok(bp) => bp,
+            Err(e) => {
+                report(Some(field), format!("{:#}", e));
+                continue;
+            }
+        };
+        for (i, source) in bp.sources.iter().enumerate() {
+            if source.options.is_empty() {
+                report(
+                    Some(field),
+                    format!("Choice source {} has no options", i + 1),
+                );
+            }
+            if let ChoiceFilter::FieldValue { target_field, .. } = &source.filter {
+                if declared.contains(target_field.as_str()) {
+                    dependencies
+                        .entry(field)
+                        .or_default()
+                        .push(target_field.clone());
+                } else {
+                    report(
+                        Some(field),
+                        format!(
+                            "Choice source {} filters on {}, which is not a field",
+                            i + 1,
+                            target_field
+                        ),
+                    );
+                }
+            }
+        }
+    }
+    for cycle in cycles(&dependencies) {
+        report(
+            Some(&cycle[0]),
+            format!(
+                "The fields depend on each other, so none of them can be chosen: {}",
+                cycle.join(" -> ")
+            ),
+        );
+    }
+    n_problems
+}
+
+/// the line of the field's table or key, or of the blueprint's table if the field isn't found
+fn find_line(text: &str, blueprint: &str, field: Option<&str>) -> Option<usize> {
+    let is_header = |line: &str, name: &str| {
+        line.strip_prefix('[')
+            .and_then(|l| l.strip_suffix(']'))
+            .is_some_and(|l| l.trim() == name)
+    };
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let bp_line = lines.iter().position(|l| is_header(l, blueprint));
+    if let Some(field) = field {
+        let field_header = format!("{}.{}", blueprint, field);
+        if let Some(i) = lines.iter().position(|l| is_header(l, &field_header)) {
+            return Some(i + 1);
+        }
+        if let Some(start) = bp_line {
+            let in_blueprint = lines[start + 1..]
+                .iter()
+                .take_while(|l| !l.starts_with('['))
+                .position(|l| {
+                    l.strip_prefix(field)
+                        .is_some_and(|rest| rest.trim_start().starts_with('='))
+                });
+            if let Some(i) = in_blueprint {
+                return Some(start + 1 + i + 1);
+            }
+        }
+    }
+    bp_line.map(|i| i + 1)
+}
@@ -1,3 +1,180 @@
+use anyhow::Result;
 use derive_new::new;
-use iced::widget::button;
-use iced::{Background, Color};
+use iced::alignment::Horizontal;
+use iced::theme::{Button as ButtonTheme, Container as ContainerTheme, Palette};
+use iced::widget::{
+    button, column, container, row, tooltip, Button, Column, Container, Row, Text, Tooltip,
+};
+use iced::{Background, Color, Command, Element, Length, Theme};
+
+use crate::config::ThemeChoice;
+use crate::gen_npc_tab::DisplayConfig;
+use crate::npc::{FieldKind, Npc, NpcField};
+use crate::wiki_links::{self, Segment, Targets};
+
+/// resolved references, and the message that opens a node. Without them, references are shown
+/// as they were typed
+pub type Links<'a, Message> = Option<(&'a Targets, fn(i64) -> Message)>;
+
+/// runs the blocking function on the thread pool of the executor, so reading files or the
+/// database doesn't freeze the window. Errors are passed as text, because messages must be Clone
+pub fn load_async<T: Send + 'static, Message>(
+    load: impl FnOnce() -> Result<T> + Send + 'static,
+    message: impl Fn(Result<T, String>) -> Message + Send + 'static,
+) -> Command<Message> {
+    Command::perform(
+        async move { load().map_err(|e| format!("{:#}", e)) },
+        message,
+    )
+}
+
+/// the iced theme of the theme setting
+pub fn theme(choice: ThemeChoice) -> Theme {
+    match choice {
+        ThemeChoice::Light => Theme::Light,
+        ThemeChoice::Dark => Theme::Dark,
+        ThemeChoice::HighContrast => Theme::custom(Palette {
+            background: Color::BLACK,
+            text: Color::WHITE,
+            primary: Color::from_rgb(1.0, 0.85, 0.0),
+            success: Color::from_rgb(0.0, 1.0, 0.4),
+            danger: Color::from_rgb(1.0, 0.3, 0.3),
+        }),
+    }
+}
+
+/// the error of a tab, framed in the danger color of the theme, above the button that leads
+/// back
+pub fn render_error<'a, Message: 'a>(err: &str, back: Button<'a, Message>) -> Column<'a, Message> {
+    column!(
+        Container::new(Text::new(format!("An error Occured:\n{}", err)))
+            .padding(10)
+            .style(ContainerTheme::Custom(error_appearance)),
+        back
+    )
+    .spacing(20)
+}
+
+fn error_appearance(theme: &Theme) -> container::Appearance {
+    let danger = theme.palette().danger;
+    container::Appearance {
+        text_color: Some(danger),
+        border_color: danger,
+        border_width: 2.0,
+        border_radius: 5.0,
+        ..Default::default()
+    }
+}
+
+/// shown by tabs while they load their content
+pub fn render_loading<'a, Message: 'a>() -> Element<'a, Message> {
+    Text::new("Loading...").size(24).into()
+}
+
+pub fn render_npc<'a, Message: Clone + 'a>(
+    npc: &'a Npc,
+    display: &'a DisplayConfig,
+    links: Links<'a, Message>,
+) -> Element<'a, Message> {
+    render_npc_with_controls(npc, display, links, |_| None)
+}
+
+/// like render_npc, but every field can have controls, like buttons, right of its values
+pub fn render_npc_with_controls<'a, Message: Clone + 'a>(
+    npc: &'a Npc,
+    display: &'a DisplayConfig,
+    links: Links<'a, Message>,
+    controls: impl Fn(&'a NpcField) -> Option<Element<'a, Message>>,
+) -> Element<'a, Message> {
+    let mut col = Column::new().spacing(10);
+    for section in display.layout(npc) {
+        if let Some(title) = section.title {
+            col = col.push(Text::new(title).size(28));
+        }
+        col = col.push(Column::with_children(
+            section
+                .fields
+                .into_iter()
+                .map(|field| {
+                    let line = row!(
+                        Text::new(format!("{}:", field.display_name))
+                            .size(24)
+                            .width(Length::FillPortion(1))
+                            .horizontal_alignment(Horizontal::Right),
+                        match links {
+                            Some((targets, on_open)) if field.kind == FieldKind::Text => {
+                                Column::with_children(
+                                    field
+                                        .values
+                                        .iter()
+                                        .map(|v| render_linked_text(v, targets, on_open, 24))
+                                        .collect(),
+                                )
+                                .width(Length::FillPortion(1))
+                                .into()
+                            }
+                            _ => Element::from(
+                                Text::new(field.values.join("\n"))
+                                    .size(24)
+                                    .width(Length::FillPortion(1)),
+                            ),
+                        }
+                    )
+                    .spacing(10);
+                    match controls(field) {
+                        Some(controls) => line.push(controls).into(),
+                        None => line.into(),
+                    }
+                })
+                .collect(),
+        ));
+    }
+    col.into()
+}
+
+/// renders the text line by line. References to nodes become buttons that open the node, and
+/// show a preview of it on hover
+pub fn render_linked_text<'a, Message: Clone + 'a>(
+    text: &str,
+    targets: &Targets,
+    on_open: fn(i64) -> Message,
+    size: u16,
+) -> Element<'a, Message> {
+    Column::with_children(
+        text.lines()
+            .map(|line| {
+                let segments = wiki_links::parse(line)
+                    .into_iter()
+                    .map(|segment| -> Element<'a, Message> {
+                        match segment {
+                            Segment::Text(text) => Text::new(text).size(size).into(),
+                            Segment::Link(name) => match wiki_links::lookup(targets, name) {
+                                Some(target) => Tooltip::new(
+                                    Button::new(Text::new(name).size(size))
+                                        .padding(0)
+                                        .style(ButtonTheme::Text)
+                                        .on_press(on_open(target.id)),
+                                    &target.preview,
+                                    tooltip::Position::FollowCursor,
+                                )
+                                .padding(10)
+                                .style(ContainerTheme::Box)
+                                .into(),
+                                None => Tooltip::new(
+                                    Text::new(format!("[[{}]]", name)).size(size),
+                                    format!("There is nothing called {}", name),
+                                    tooltip::Position::FollowCursor,
+                                )
+                                .padding(10)
+                                .style(ContainerTheme::Box)
+                                .into(),
+                            },
+                        }
+                    })
+                    .collect();
+                Row::with_children(segments).into()
+            })
+            .collect(),
+    )
+    .into()
+}
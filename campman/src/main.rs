@@ -1,11 +1,17 @@
 use once_cell::sync::OnceCell;
+use std::cell::RefCell;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use argh::FromArgs;
 use iced::{
     alignment::{Horizontal, Vertical},
+    executor,
     widget::{Column, Container, Text},
-    Element, Font, Length, Sandbox, Settings,
+    Application, Command, Element, Font, Length, Settings, Subscription, Theme,
 };
 use iced_aw::{style::TabBarStyles, TabLabel, Tabs};
 
@@ -13,6 +19,9 @@ use database as db;
 
 const HEADER_SIZE: u16 = 32;
 const TAB_PADDING: u16 = 16;
+/// the indices of the tabs that the quick switcher opens
+const GEN_NPC_TAB: usize = 0;
+const VIEW_NPC_TAB: usize = 1;
 
 mod gen_npc_tab;
 use gen_npc_tab::{GenNpcMessage, GenNpcTab};
@@ -20,20 +29,130 @@ use gen_npc_tab::{GenNpcMessage, GenNpcTab};
 mod view_npc_tab;
 use view_npc_tab::{ViewNpcMessage, ViewNpcTab};
 
+mod notes_tab;
+use notes_tab::{NotesMessage, NotesTab};
+
+mod trash_tab;
+use trash_tab::{TrashMessage, TrashTab};
+
+mod plugins_tab;
+use plugins_tab::{PluginsMessage, PluginsTab};
+
+mod reference_tab;
+use reference_tab::{ReferenceMessage, ReferenceTab};
+
+mod encounter_tab;
+use encounter_tab::{EncounterMessage, EncounterTab};
+
+mod dice_tab;
+use dice_tab::{DiceMessage, DiceTab};
+
+mod settings_tab;
+use settings_tab::{SettingsMessage, SettingsTab};
+
+mod quick_add;
+use quick_add::{QuickAdd, QuickAddMessage};
+
+mod switcher;
+use switcher::{Switcher, SwitcherMessage};
+
+mod bundle;
+mod config;
+mod demo;
+mod encounter;
 mod iced_utils;
+mod notes;
+mod npc;
+mod npc_export;
+mod npc_search;
+mod npc_store;
+mod plugins;
+mod random_encounter;
+mod reference;
+mod relations;
+mod server;
+mod snapshots;
+mod sync;
+mod updates;
+mod wiki_links;
 
+/// overrides the config dir, unless --config-dir is given
+const CONFIG_DIR_VAR: &str = "CAMPMAN_CONFIG_DIR";
 static CONFIG_PATH: OnceCell<PathBuf> = OnceCell::new();
+/// the directory of config.toml, unless config.toml moves the other files elsewhere
+static CONF_DIR: OnceCell<PathBuf> = OnceCell::new();
 static DATA_DIR: OnceCell<PathBuf> = OnceCell::new();
+static DB_PATH: OnceCell<PathBuf> = OnceCell::new();
+static DATABASE: OnceCell<Mutex<db::DB>> = OnceCell::new();
+
+#[derive(FromArgs)]
+/// Manage NPCs and the rest of a campaign
+struct Cli {
+    #[argh(option)]
+    /// the directory of config.toml and npc_gen.toml, overrides $CAMPMAN_CONFIG_DIR, and is
+    /// ~/.config/campman by default
+    config_dir: Option<PathBuf>,
+    #[argh(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum CliCommand {
+    Serve(ServeArgs),
+    CheckBlueprints(CheckBlueprintsArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "serve")]
+/// Serve the campaign database as JSON api instead of opening the window
+struct ServeArgs {
+    #[argh(option, default = "String::from(\"127.0.0.1:8080\")")]
+    /// the address to listen on, 127.0.0.1:8080 by default
+    addr: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "check-blueprints")]
+/// Check npc_gen.toml and the files it refers to, and report all problems, or list the fields
+/// of every blueprint with their descriptions if there are none
+struct CheckBlueprintsArgs {}
 
 fn main() -> Result<()> {
-    init()?;
-    Ok(CampMan::run(Settings::default())?)
+    let args: Cli = argh::from_env();
+    init(args.config_dir)?;
+    match args.command {
+        Some(CliCommand::Serve(serve_args)) => {
+            let token = config::Config::load()?
+                .api_token
+                .context("Set api-token in config.toml before serving the database")?;
+            // an empty token would let through every request with an empty bearer token
+            ensure!(
+                !token.trim().is_empty(),
+                "The api-token in config.toml must not be empty"
+            );
+            server::serve(&serve_args.addr, &token)
+        }
+        Some(CliCommand::CheckBlueprints(_)) => check_blueprints(),
+        None => Ok(CampMan::run(Settings::default())?),
+    }
 }
 
 struct CampMan {
     active_tab: usize,
     gen_npc_tab: GenNpcTab,
     view_npc_tab: ViewNpcTab,
+    notes_tab: NotesTab,
+    trash_tab: TrashTab,
+    plugins_tab: PluginsTab,
+    reference_tab: ReferenceTab,
+    encounter_tab: EncounterTab,
+    dice_tab: DiceTab,
+    settings_tab: SettingsTab,
+    /// the quick add dialog is shown instead of the tabs while it is open
+    quick_add: Option<QuickAdd>,
+    /// like the quick add dialog, the switcher is shown instead of the tabs while it is open
+    switcher: Option<Switcher>,
 }
 
 #[derive(Clone, Debug)]
@@ -41,35 +160,125 @@ enum Message {
     TabSelected(usize),
     GenNpcMsg(GenNpcMessage),
     ViewNpcMsg(ViewNpcMessage),
+    NotesMsg(NotesMessage),
+    TrashMsg(TrashMessage),
+    PluginsMsg(PluginsMessage),
+    ReferenceMsg(ReferenceMessage),
+    EncounterMsg(EncounterMessage),
+    DiceMsg(DiceMessage),
+    SettingsMsg(SettingsMessage),
+    QuickAddMsg(QuickAddMessage),
+    SwitcherMsg(SwitcherMessage),
 }
 
-impl Sandbox for CampMan {
+impl Application for CampMan {
+    type Executor = executor::Default;
     type Message = Message;
+    type Theme = Theme;
+    type Flags = ();
 
-    fn new() -> Self {
-        CampMan {
+    fn new(_flags: ()) -> (Self, Command<Message>) {
+        let plugins = Rc::new(RefCell::new(
+            plugins::Plugins::load(&plugins::plugins_dir()),
+        ));
+        // the tabs that read the database or many files load their content in the background
+        let (gen_npc_tab, load_blueprints) = GenNpcTab::new();
+        let (view_npc_tab, load_npcs) = ViewNpcTab::new(plugins.clone());
+        let (notes_tab, load_notes) = NotesTab::new();
+        let (trash_tab, load_trash) = TrashTab::new();
+        let (encounter_tab, load_bestiary) = EncounterTab::new();
+        let app = CampMan {
             active_tab: 0,
-            gen_npc_tab: GenNpcTab::new(),
-            view_npc_tab: ViewNpcTab::new(),
-        }
+            gen_npc_tab,
+            view_npc_tab,
+            notes_tab,
+            trash_tab,
+            plugins_tab: PluginsTab::new(plugins),
+            reference_tab: ReferenceTab::new(),
+            encounter_tab,
+            dice_tab: DiceTab::new(),
+            settings_tab: SettingsTab::new(),
+            quick_add: None,
+            switcher: None,
+        };
+        let load = Command::batch([
+            load_blueprints,
+            load_npcs,
+            load_notes,
+            load_trash,
+            load_bestiary,
+        ]);
+        (app, load)
     }
 
     fn title(&self) -> String {
         String::from("Campaign Manager")
     }
 
-    fn update(&mut self, message: Self::Message) {
+    fn theme(&self) -> Theme {
+        iced_utils::theme(self.settings_tab.theme())
+    }
+
+    fn update(&mut self, message: Self::Message) -> Command<Message> {
         match message {
             Message::TabSelected(selected) => self.active_tab = selected,
-            Message::GenNpcMsg(message) => self.gen_npc_tab.update(message),
-            Message::ViewNpcMsg(message) => self.view_npc_tab.update(message),
+            Message::GenNpcMsg(message) => return self.gen_npc_tab.update(message),
+            Message::ViewNpcMsg(message) => return self.view_npc_tab.update(message),
+            Message::NotesMsg(message) => return self.notes_tab.update(message),
+            Message::TrashMsg(message) => return self.trash_tab.update(message),
+            Message::PluginsMsg(message) => self.plugins_tab.update(message),
+            Message::ReferenceMsg(message) => self.reference_tab.update(message),
+            Message::EncounterMsg(message) => return self.encounter_tab.update(message),
+            Message::DiceMsg(message) => self.dice_tab.update(message),
+            Message::SettingsMsg(message) => return self.settings_tab.update(message),
+            Message::QuickAddMsg(message) => return self.update_quick_add(message),
+            Message::SwitcherMsg(message) => return self.update_switcher(message),
         }
+        Command::none()
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        // the option picker only listens while it can be seen, so the arrow keys of the
+        // switcher don't move its focus
+        let picker_visible =
+            self.active_tab == GEN_NPC_TAB && self.switcher.is_none() && self.quick_add.is_none();
+        let picker = if picker_visible {
+            gen_npc_tab::shortcuts().map(Message::GenNpcMsg)
+        } else {
+            Subscription::none()
+        };
+        Subscription::batch([
+            quick_add::shortcuts().map(Message::QuickAddMsg),
+            switcher::shortcuts().map(Message::SwitcherMsg),
+            picker,
+        ])
     }
 
     fn view(&self) -> Element<'_, Self::Message> {
+        let dialog = match (&self.switcher, &self.quick_add) {
+            (Some(switcher), _) => Some(switcher.view().map(Message::SwitcherMsg)),
+            (None, Some(dialog)) => Some(dialog.view().map(Message::QuickAddMsg)),
+            (None, None) => None,
+        };
+        if let Some(dialog) = dialog {
+            return Container::new(dialog)
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .center_x()
+                .center_y()
+                .padding(TAB_PADDING)
+                .into();
+        }
         Tabs::new(self.active_tab, Message::TabSelected)
             .push(self.gen_npc_tab.tab_label(), self.gen_npc_tab.view())
             .push(self.view_npc_tab.tab_label(), self.view_npc_tab.view())
+            .push(self.notes_tab.tab_label(), self.notes_tab.view())
+            .push(self.trash_tab.tab_label(), self.trash_tab.view())
+            .push(self.plugins_tab.tab_label(), self.plugins_tab.view())
+            .push(self.reference_tab.tab_label(), self.reference_tab.view())
+            .push(self.encounter_tab.tab_label(), self.encounter_tab.view())
+            .push(self.dice_tab.tab_label(), self.dice_tab.view())
+            .push(self.settings_tab.tab_label(), self.settings_tab.view())
             .tab_bar_style(TabBarStyles::default())
             //.icon_font(ICON_FONT)
             //.tab_bar_position(TabBarPosition::Top)
@@ -77,6 +286,115 @@ impl Sandbox for CampMan {
     }
 }
 
+impl CampMan {
+    fn update_quick_add(&mut self, message: QuickAddMessage) -> Command<Message> {
+        match message {
+            QuickAddMessage::Open if self.quick_add.is_none() => {
+                self.quick_add = Some(QuickAdd::default());
+                return quick_add::focus_name_input();
+            }
+            QuickAddMessage::Open => {}
+            QuickAddMessage::Cancel => self.quick_add = None,
+            QuickAddMessage::Save => {
+                if let Some(dialog) = &mut self.quick_add {
+                    if dialog.save().is_ok() {
+                        self.quick_add = None;
+                        return self.view_npc_tab.update(ViewNpcMessage::ShowList);
+                    }
+                }
+            }
+            message => {
+                if let Some(dialog) = &mut self.quick_add {
+                    dialog.update(message);
+                }
+            }
+        }
+        Command::none()
+    }
+}
+
+impl CampMan {
+    fn update_switcher(&mut self, message: SwitcherMessage) -> Command<Message> {
+        match message {
+            SwitcherMessage::Open if self.switcher.is_none() => {
+                self.switcher = Some(Switcher::new(self.switcher_entries()));
+                return switcher::focus_query_input();
+            }
+            SwitcherMessage::Open => {}
+            SwitcherMessage::Cancel => self.switcher = None,
+            SwitcherMessage::Submit | SwitcherMessage::Pick(_) => {
+                let action = self.switcher.as_ref().and_then(|s| match message {
+                    SwitcherMessage::Pick(result) => s.action(result),
+                    _ => s.action(s.selection()),
+                });
+                if let Some(action) = action {
+                    self.switcher = None;
+                    return self.run_switcher_action(action);
+                }
+            }
+            message => {
+                if let Some(switcher) = &mut self.switcher {
+                    switcher.update(message);
+                }
+            }
+        }
+        Command::none()
+    }
+
+    /// the tabs and generators, the switcher adds the nodes itself
+    fn switcher_entries(&self) -> Vec<switcher::Entry> {
+        let tabs = [
+            self.gen_npc_tab.tab_label(),
+            self.view_npc_tab.tab_label(),
+            self.notes_tab.tab_label(),
+            self.trash_tab.tab_label(),
+            self.plugins_tab.tab_label(),
+            self.reference_tab.tab_label(),
+            self.encounter_tab.tab_label(),
+            self.dice_tab.tab_label(),
+            self.settings_tab.tab_label(),
+        ];
+        let tab_entries = tabs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, label)| match label {
+                TabLabel::Text(title) | TabLabel::IconText(_, title) => Some(switcher::Entry {
+                    label: format!("Go to {}", title),
+                    action: switcher::Action::SwitchTab(i),
+                }),
+                TabLabel::Icon(_) => None,
+            });
+        let generator_entries =
+            self.gen_npc_tab
+                .blueprint_names()
+                .into_iter()
+                .map(|name| switcher::Entry {
+                    label: format!("Generate {}", name),
+                    action: switcher::Action::Generate(name),
+                });
+        tab_entries.chain(generator_entries).collect()
+    }
+
+    fn run_switcher_action(&mut self, action: switcher::Action) -> Command<Message> {
+        match action {
+            switcher::Action::OpenNode(id) => {
+                self.active_tab = VIEW_NPC_TAB;
+                self.view_npc_tab.update(ViewNpcMessage::Open(id))
+            }
+            switcher::Action::SwitchTab(tab) => {
+                self.active_tab = tab;
+                Command::none()
+            }
+            switcher::Action::Generate(name) => {
+                // an NPC that is being generated is dropped
+                self.active_tab = GEN_NPC_TAB;
+                self.gen_npc_tab.discard_npc();
+                self.gen_npc_tab.update(GenNpcMessage::GenNpc(name))
+            }
+        }
+    }
+}
+
 trait Tab {
     type Message;
 
@@ -95,18 +413,73 @@ trait Tab {
     fn content(&self) -> Element<'_, Self::Message>;
 }
 
-fn init() -> Result<()> {
-    CONFIG_PATH
-        .set(
-            dirs::config_dir()
+/// prints the problems of npc_gen.toml and fails if there are any, and describes the fields of
+/// every blueprint otherwise
+fn check_blueprints() -> Result<()> {
+    let problems = gen_npc_tab::validate_blueprints()?;
+    if problems.is_empty() {
+        println!("{}", gen_npc_tab::describe_blueprints()?);
+        return Ok(());
+    }
+    let path = conf_dir().join("npc_gen.toml");
+    for problem in &problems {
+        println!("{}, {}", path.display(), problem);
+    }
+    bail!("Found {} problems", problems.len())
+}
+
+/// config_dir is the one passed on the command line, which takes precedence over the
+/// environment
+fn init(config_dir: Option<PathBuf>) -> Result<()> {
+    let config_dir =
+        match config_dir.or_else(|| std::env::var_os(CONFIG_DIR_VAR).map(PathBuf::from)) {
+            Some(dir) => dir,
+            None => dirs::config_dir()
                 .ok_or(anyhow!("Couldn't find config dir"))?
-                .join("campman/config.toml"),
-        )
+                .join("campman"),
+        };
+    CONFIG_PATH
+        .set(config_dir.join("config.toml"))
         .map_err(|_| anyhow!("init was called twice"))?;
+    std::fs::create_dir_all(&config_dir)
+        .with_context(|| format!("Could not create {}", config_dir.display()))?;
+    let config = config::Config::load()?;
+    let conf_dir = config.config_dir.as_ref().map_or(config_dir, PathBuf::from);
+    std::fs::create_dir_all(&conf_dir)
+        .with_context(|| format!("Could not create {}", conf_dir.display()))?;
+    CONF_DIR
+        .set(conf_dir)
+        .map_err(|_| anyhow!("init was called twice"))?;
+    gen_npc_tab::write_default_blueprints()?;
     DATA_DIR.set(dirs::data_dir().unwrap()).unwrap();
+
+    let db_path = match &config.database_path {
+        Some(path) => PathBuf::from(path),
+        None => DATA_DIR.get().unwrap().join("campman/campaign.db"),
+    };
+    std::fs::create_dir_all(db_path.parent().unwrap())?;
+    DATABASE
+        .set(Mutex::new(db::DB::new(&db_path)?))
+        .map_err(|_| anyhow!("init was called twice"))?;
+    DB_PATH.set(db_path).unwrap();
+
+    if config.snapshot_interval_minutes > 0 {
+        snapshots::start_periodic(
+            Duration::from_secs(config.snapshot_interval_minutes * 60),
+            config.snapshot_count,
+        );
+    }
     Ok(())
 }
 
+fn campaign_db_path() -> PathBuf {
+    DB_PATH.get().unwrap().clone()
+}
+
 fn conf_dir() -> &'static Path {
-    CONFIG_PATH.get().unwrap().parent().unwrap()
+    CONF_DIR.get().unwrap()
+}
+
+fn database() -> MutexGuard<'static, db::DB> {
+    DATABASE.get().unwrap().lock().unwrap()
 }
@@ -0,0 +1,136 @@
+//! Free-form session and campaign notes. They are nodes with the text as data, and are tagged
+//! like NPCs.
+use std::collections::HashMap;
+
+use anyhow::{ensure, Result};
+use database::db::{Node, DB};
+use database::dsl::NodeFieldName;
+use database::meta::Meta;
+use itertools::Itertools;
+
+pub const NOTE_NODE_TYPE: &str = "note";
+
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub node: Node,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+/// the notes, newest first
+pub fn load(db: &mut DB) -> Result<Vec<Note>> {
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (id, tag) in db.select_all_tags()? {
+        tags.entry(id).or_default().push(tag);
+    }
+    Ok(db
+        .select_nodes(&NodeFieldName::Type.eq(NOTE_NODE_TYPE))?
+        .into_iter()
+        .sorted_by_key(|node| -node.id)
+        .map(|node| Note {
+            text: String::from_utf8_lossy(&node.data).to_string(),
+            tags: tags.remove(&node.id).unwrap_or_default(),
+            node,
+        })
+        .collect())
+}
+
+impl Note {
+    /// whether the note has the tag, if one is given, and all words of the text occur in its title
+    /// or text, ignoring case
+    pub fn matches(&self, text: &str, tag: Option<&str>) -> bool {
+        if let Some(tag) = tag {
+            if !self.tags.iter().any(|t| t == tag) {
+                return false;
+            }
+        }
+        let haystack = format!("{}\n{}", self.node.name, self.text).to_lowercase();
+        text.to_lowercase()
+            .split_whitespace()
+            .all(|word| haystack.contains(word))
+    }
+}
+
+/// the tags of the notes, sorted, for the tag filter
+pub fn tags(notes: &[Note]) -> Vec<String> {
+    notes
+        .iter()
+        .flat_map(|n| &n.tags)
+        .unique()
+        .sorted()
+        .cloned()
+        .collect()
+}
+
+/// creates the note if id is None, otherwise updates it, and sets its tags. Returns the id
+pub fn save(db: &mut DB, id: Option<i64>, title: &str, text: &str, tags: &[String]) -> Result<i64> {
+    let title = title.trim();
+    ensure!(!title.is_empty(), "A note needs a title");
+    let id = match id {
+        Some(id) => {
+            db.rename_node(id, title)?;
+            db.update_node(id, &Meta::new(), text.as_bytes())?;
+            id
+        }
+        None => db.insert_node(title, NOTE_NODE_TYPE, &Meta::new(), text.as_bytes())?,
+    };
+    let old_tags = db.select_tags(id)?;
+    for tag in old_tags.iter().filter(|t| !tags.contains(t)) {
+        db.remove_tag(&[id], tag)?;
+    }
+    for tag in tags.iter().filter(|t| !old_tags.contains(t)) {
+        db.add_tag(&[id], tag)?;
+    }
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes() -> Result<()> {
+        let mut db = DB::new(std::path::Path::new(":memory:"))?;
+        let session = save(
+            &mut db,
+            None,
+            "Session 1",
+            "The party met Mira",
+            &["session".into(), "harbor".into()],
+        )?;
+        save(&mut db, None, "Plot", "The harbor master smuggles", &[])?;
+        assert!(save(&mut db, None, " ", "", &[]).is_err());
+        db.insert_node("Mira", "npc", &Meta::new(), &[])?;
+
+        let notes = load(&mut db)?;
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].node.name, "Plot");
+        assert_eq!(tags(&notes), ["harbor", "session"]);
+        let titles = |text: &str, tag: Option<&str>| -> Vec<String> {
+            notes
+                .iter()
+                .filter(|n| n.matches(text, tag))
+                .map(|n| n.node.name.clone())
+                .collect()
+        };
+        assert_eq!(titles("HARBOR", None), ["Plot"]);
+        assert_eq!(titles("", Some("harbor")), ["Session 1"]);
+        assert_eq!(titles("session mira", None), ["Session 1"]);
+
+        save(
+            &mut db,
+            Some(session),
+            "Session 1: The Harbor",
+            "The party met Mira and Oderic",
+            &["session".into()],
+        )?;
+        let note = load(&mut db)?
+            .into_iter()
+            .find(|n| n.node.id == session)
+            .unwrap();
+        assert_eq!(note.node.name, "Session 1: The Harbor");
+        assert_eq!(note.text, "The party met Mira and Oderic");
+        assert_eq!(note.tags, ["session"]);
+        Ok(())
+    }
+}
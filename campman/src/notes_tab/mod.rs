@@ -0,0 +1,312 @@
+use anyhow::Result;
+use iced::widget::{column, row, Button, Column, PickList, Row, Scrollable, Text, TextInput};
+use iced::{Alignment, Command, Element, Length};
+use iced_aw::TabLabel;
+
+use super::{Message, Tab};
+use crate::database;
+use crate::iced_utils::{load_async, render_error, render_loading};
+use crate::notes::{self, Note};
+
+/// the entry of the tag filter that doesn't filter
+const ANY: &str = "(any)";
+
+pub struct NotesTab {
+    state: State,
+    /// words that must occur in the listed notes
+    search: String,
+    /// only notes with this tag are listed
+    tag: Option<String>,
+}
+
+enum State {
+    /// the notes are loaded in the background
+    Loading,
+    Error(String),
+    List(Vec<Note>),
+    Edit(Editor),
+}
+
+/// a note that is written or changed. TextInputs have a single line, so the text is edited as
+/// paragraphs, which are separated by blank lines when it is saved
+struct Editor {
+    /// None for a new note
+    id: Option<i64>,
+    title: String,
+    paragraphs: Vec<String>,
+    tags: Vec<String>,
+    tag_input: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum NotesMessage {
+    /// reloads the notes, and lists them
+    ShowList,
+    ListLoaded(Result<Vec<Note>, String>),
+    SearchChanged(String),
+    TagFilterChanged(String),
+    NewNote,
+    Open(i64),
+    TitleChanged(String),
+    ParagraphChanged(usize, String),
+    /// inserts an empty paragraph after the one with the index
+    InsertParagraph(usize),
+    RemoveParagraph(usize),
+    TagInputChanged(String),
+    AddTag,
+    RemoveTag(String),
+    Save,
+    /// moves the open note to the trash
+    Delete,
+}
+
+impl NotesTab {
+    /// the tab, and the command that loads the notes
+    pub fn new() -> (NotesTab, Command<Message>) {
+        let mut tab = NotesTab {
+            state: State::Loading,
+            search: String::new(),
+            tag: None,
+        };
+        let load = tab.show_list();
+        (tab, load)
+    }
+
+    pub fn update(&mut self, message: NotesMessage) -> Command<Message> {
+        self.inner_update(message).unwrap_or_else(|e| {
+            self.state = State::Error(format!("{:#}", e));
+            Command::none()
+        })
+    }
+
+    /// shows the loading screen until the notes are loaded
+    fn show_list(&mut self) -> Command<Message> {
+        self.state = State::Loading;
+        load_async(
+            || notes::load(&mut database()),
+            |res| Message::NotesMsg(NotesMessage::ListLoaded(res)),
+        )
+    }
+
+    fn inner_update(&mut self, message: NotesMessage) -> Result<Command<Message>> {
+        use NotesMessage::*;
+        match message {
+            ShowList => return Ok(self.show_list()),
+            ListLoaded(res) => {
+                self.state = match res {
+                    Ok(notes) => State::List(notes),
+                    Err(e) => State::Error(e),
+                }
+            }
+            SearchChanged(text) => self.search = text,
+            TagFilterChanged(tag) => self.tag = (tag != ANY).then_some(tag),
+            NewNote => {
+                self.state = State::Edit(Editor {
+                    id: None,
+                    title: String::new(),
+                    paragraphs: vec![String::new()],
+                    tags: vec![],
+                    tag_input: String::new(),
+                })
+            }
+            Open(id) => {
+                if let State::List(notes) = &self.state {
+                    if let Some(note) = notes.iter().find(|n| n.node.id == id) {
+                        self.state = State::Edit(Editor {
+                            id: Some(id),
+                            title: note.node.name.clone(),
+                            paragraphs: note.text.split("\n\n").map(String::from).collect(),
+                            tags: note.tags.clone(),
+                            tag_input: String::new(),
+                        });
+                    }
+                }
+            }
+            Save => {
+                if let State::Edit(editor) = &self.state {
+                    let text = editor
+                        .paragraphs
+                        .iter()
+                        .map(|p| p.trim())
+                        .filter(|p| !p.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("\n\n");
+                    notes::save(
+                        &mut database(),
+                        editor.id,
+                        &editor.title,
+                        &text,
+                        &editor.tags,
+                    )?;
+                    return Ok(self.show_list());
+                }
+            }
+            Delete => {
+                if let State::Edit(editor) = &self.state {
+                    if let Some(id) = editor.id {
+                        database().delete_node(id)?;
+                    }
+                    return Ok(self.show_list());
+                }
+            }
+            message => {
+                if let State::Edit(editor) = &mut self.state {
+                    editor.update(message);
+                }
+            }
+        }
+        Ok(Command::none())
+    }
+}
+
+impl Editor {
+    fn update(&mut self, message: NotesMessage) {
+        use NotesMessage::*;
+        match message {
+            TitleChanged(title) => self.title = title,
+            ParagraphChanged(i, text) => self.paragraphs[i] = text,
+            InsertParagraph(i) => self.paragraphs.insert(i + 1, String::new()),
+            RemoveParagraph(i) => {
+                self.paragraphs.remove(i);
+                if self.paragraphs.is_empty() {
+                    self.paragraphs.push(String::new());
+                }
+            }
+            TagInputChanged(tag) => self.tag_input = tag,
+            AddTag => {
+                let tag = self.tag_input.trim().to_string();
+                if !tag.is_empty() && !self.tags.contains(&tag) {
+                    self.tags.push(tag);
+                }
+                self.tag_input.clear();
+            }
+            RemoveTag(tag) => self.tags.retain(|t| *t != tag),
+            _ => {}
+        }
+    }
+}
+
+impl Tab for NotesTab {
+    type Message = Message;
+
+    fn tab_label(&self) -> TabLabel {
+        TabLabel::Text("Notes".into())
+    }
+
+    fn content(&self) -> Element<'_, Self::Message> {
+        let content: Element<'_, NotesMessage> = match &self.state {
+            State::Loading => render_loading(),
+            State::Error(e) => render_error(
+                e,
+                Button::new("Back to Notes").on_press(NotesMessage::ShowList),
+            )
+            .into(),
+            State::List(notes) => self.render_list(notes),
+            State::Edit(editor) => render_editor(editor),
+        };
+        content.map(Message::NotesMsg)
+    }
+}
+
+impl NotesTab {
+    fn render_list<'a>(&'a self, notes: &'a [Note]) -> Element<'a, NotesMessage> {
+        let tags = std::iter::once(ANY.to_string())
+            .chain(notes::tags(notes))
+            .collect();
+        let filter = row!(
+            TextInput::new("Search", &self.search, NotesMessage::SearchChanged)
+                .padding(5)
+                .width(Length::Fill),
+            Text::new("Tag:"),
+            PickList::new(
+                tags,
+                Some(self.tag.clone().unwrap_or_else(|| ANY.into())),
+                NotesMessage::TagFilterChanged
+            ),
+            Button::new("New Note").on_press(NotesMessage::NewNote)
+        )
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let rows: Vec<_> = notes
+            .iter()
+            .filter(|n| n.matches(&self.search, self.tag.as_deref()))
+            .map(|note| {
+                let preview: String = note.text.lines().next().unwrap_or_default().into();
+                row!(
+                    Button::new(Text::new(&note.node.name))
+                        .on_press(NotesMessage::Open(note.node.id)),
+                    Text::new(preview).width(Length::Fill),
+                    Text::new(note.tags.join(", "))
+                )
+                .spacing(10)
+                .align_items(Alignment::Center)
+                .into()
+            })
+            .collect();
+        let list: Element<'_, NotesMessage> = if rows.is_empty() {
+            Text::new("No notes found").into()
+        } else {
+            Scrollable::new(Column::with_children(rows).spacing(5)).into()
+        };
+        column!(filter, list).spacing(10).into()
+    }
+}
+
+fn render_editor(editor: &Editor) -> Element<'_, NotesMessage> {
+    let paragraphs = editor.paragraphs.iter().enumerate().map(|(i, p)| {
+        row!(
+            TextInput::new("Paragraph", p, move |text| {
+                NotesMessage::ParagraphChanged(i, text)
+            })
+            .on_submit(NotesMessage::InsertParagraph(i))
+            .padding(5)
+            .width(Length::Fill),
+            Button::new("Remove").on_press(NotesMessage::RemoveParagraph(i))
+        )
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .into()
+    });
+    let tags = editor.tags.iter().map(|tag| {
+        Button::new(Text::new(format!("{} x", tag)))
+            .on_press(NotesMessage::RemoveTag(tag.clone()))
+            .into()
+    });
+    let mut save = Button::new("Save");
+    if !editor.title.trim().is_empty() {
+        save = save.on_press(NotesMessage::Save);
+    }
+    let mut delete = Button::new("Delete");
+    if editor.id.is_some() {
+        delete = delete.on_press(NotesMessage::Delete);
+    }
+    Scrollable::new(
+        column!(
+            TextInput::new("Title", &editor.title, NotesMessage::TitleChanged)
+                .size(24)
+                .padding(5),
+            Text::new("Press enter to start a new paragraph"),
+            Column::with_children(paragraphs.collect()).spacing(5),
+            row!(
+                Text::new("Tags:"),
+                Row::with_children(tags.collect()).spacing(5),
+                TextInput::new("Tag", &editor.tag_input, NotesMessage::TagInputChanged)
+                    .on_submit(NotesMessage::AddTag)
+                    .padding(5)
+                    .width(Length::Units(200)),
+                Button::new("Add Tag").on_press(NotesMessage::AddTag)
+            )
+            .spacing(10)
+            .align_items(Alignment::Center),
+            row!(
+                save,
+                Button::new("Cancel").on_press(NotesMessage::ShowList),
+                delete
+            )
+            .spacing(10)
+        )
+        .spacing(10),
+    )
+    .into()
+}
@@ -0,0 +1,98 @@
+//! The NPC model that is shared by the builder, the renderer and the database.
+use std::ops::Index;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldKind {
+    /// chosen from the options of a blueprint
+    #[default]
+    Choice,
+    /// entered by hand
+    Text,
+    /// rolled by a dice expression or in a range of a blueprint
+    Number,
+    /// composed of other fields by the blueprint
+    Computed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NpcField {
+    pub name: String,
+    /// how the field is labeled in the UI and in exports
+    pub display_name: String,
+    pub kind: FieldKind,
+    pub values: Vec<String>,
+}
+
+/// An NPC is an ordered list of fields. The order is the one in which the fields were set,
+/// the order in which they are displayed is decided by a DisplayConfig.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Npc {
+    /// the name of the blueprint the NPC was built from, None if it was created by hand
+    pub blueprint: Option<String>,
+    pub fields: Vec<NpcField>,
+}
+
+impl Npc {
+    pub fn new(blueprint: Option<String>) -> Npc {
+        Npc {
+            blueprint,
+            fields: vec![],
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&NpcField> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.field(name).map(|f| f.values.as_slice())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.field(name).is_some()
+    }
+
+    /// sets the values of the field. An existing field keeps its position, a new one is
+    /// appended, and labeled with the default display name.
+    pub fn set(&mut self, name: &str, kind: FieldKind, values: Vec<String>) {
+        match self.fields.iter_mut().find(|f| f.name == name) {
+            Some(field) => {
+                field.kind = kind;
+                field.values = values;
+            }
+            None => self.fields.push(NpcField {
+                name: name.into(),
+                display_name: default_display_name(name),
+                kind,
+                values,
+            }),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<NpcField> {
+        let idx = self.fields.iter().position(|f| f.name == name)?;
+        Some(self.fields.remove(idx))
+    }
+
+    pub fn set_display_name(&mut self, name: &str, display_name: &str) {
+        if let Some(field) = self.fields.iter_mut().find(|f| f.name == name) {
+            field.display_name = display_name.into();
+        }
+    }
+}
+
+impl Index<&str> for Npc {
+    type Output = [String];
+
+    fn index(&self, name: &str) -> &[String] {
+        self.get(name)
+            .unwrap_or_else(|| panic!("The NPC has no field named {}", name))
+    }
+}
+
+pub fn default_display_name(field: &str) -> String {
+    field.replace(['-', '_'], " ")
+}
@@ -0,0 +1,124 @@
+//! Exports of single NPCs as Markdown or JSON, to paste them into notes or a VTT.
+//!
+//! The Markdown can be shaped with `npc-markdown-template` in config.toml. `{name}` and
+//! `{blueprint}` are replaced by the name of the NPC and its blueprint, `{fields}` by the list of
+//! all visible fields, and the name of any field, like `{race}`, by its values. Other braces are
+//! kept as they are.
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::gen_npc_tab::DisplayConfig;
+use crate::npc::Npc;
+use crate::DATA_DIR;
+
+const DEFAULT_TEMPLATE: &str = "# {name}\n\n{fields}";
+
+pub fn to_markdown(
+    name: &str,
+    npc: &Npc,
+    display: &DisplayConfig,
+    template: Option<&str>,
+) -> String {
+    let mut res = template.unwrap_or(DEFAULT_TEMPLATE).to_string();
+    let mut replace = |placeholder: &str, value: &str| {
+        res = res.replace(&format!("{{{}}}", placeholder), value);
+    };
+    replace("name", name);
+    replace("blueprint", npc.blueprint.as_deref().unwrap_or_default());
+    replace("fields", &markdown_fields(npc, display));
+    for field in &npc.fields {
+        replace(&field.name, &field.values.join(", "));
+    }
+    res
+}
+
+/// a bullet per field, and a heading above the fields of each section
+fn markdown_fields(npc: &Npc, display: &DisplayConfig) -> String {
+    display
+        .layout(npc)
+        .iter()
+        .filter(|section| !section.fields.is_empty())
+        .map(|section| {
+            let fields = section
+                .fields
+                .iter()
+                .map(|f| format!("- **{}:** {}", f.display_name, f.values.join(", ")))
+                .join("\n");
+            match section.title {
+                Some(title) => format!("## {}\n\n{}", title, fields),
+                None => fields,
+            }
+        })
+        .join("\n\n")
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    name: &'a str,
+    #[serde(flatten)]
+    npc: &'a Npc,
+}
+
+pub fn to_json(name: &str, npc: &Npc) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&JsonExport { name, npc })?)
+}
+
+/// writes the text to the exports directory, and returns the path of the file
+pub fn write_export(name: &str, extension: &str, text: &str) -> Result<PathBuf> {
+    let dir = DATA_DIR.get().unwrap().join("campman/exports");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.{}", name, extension));
+    std::fs::write(&path, text).context(path.display().to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npc::FieldKind;
+    use toml::Value;
+
+    fn test_npc() -> Npc {
+        let mut npc = Npc::new(Some("Villager".into()));
+        npc.set("race", FieldKind::Choice, vec!["Elf".into()]);
+        npc.set("hair", FieldKind::Choice, vec!["Red".into(), "Long".into()]);
+        npc.set("first-name", FieldKind::Text, vec!["Ann".into()]);
+        npc
+    }
+
+    #[test]
+    fn test_markdown() {
+        let npc = test_npc();
+        let declared = ["first-name", "race", "hair"].map(String::from);
+        let display = DisplayConfig::parse(
+            Some(r#"sections = { Appearance = ["hair"] }"#.parse::<Value>().unwrap()),
+            &declared,
+        )
+        .unwrap();
+        assert_eq!(
+            to_markdown("Ann", &npc, &display, None),
+            "# Ann\n\n- **first name:** Ann\n- **race:** Elf\n\n## Appearance\n\n- **hair:** Red, Long"
+        );
+        assert_eq!(
+            to_markdown(
+                "Ann",
+                &npc,
+                &display,
+                Some("{name} ({blueprint}), {race} with {hair} hair {unknown}")
+            ),
+            "Ann (Villager), Elf with Red, Long hair {unknown}"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json("Ann", &test_npc()).unwrap()).unwrap();
+        assert_eq!(json["name"], "Ann");
+        assert_eq!(json["blueprint"], "Villager");
+        assert_eq!(json["fields"][1]["values"][1], "Long");
+    }
+}
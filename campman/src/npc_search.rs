@@ -0,0 +1,168 @@
+//! Searching the saved NPCs by their names and field values, their blueprint, and their tags.
+use std::collections::HashMap;
+
+use anyhow::Result;
+use database::db::{Node, DB};
+use database::dsl::NodeFieldName;
+use itertools::Itertools;
+
+use crate::npc::Npc;
+use crate::npc_store::{self, NPC_NODE_TYPE};
+
+/// a saved NPC, with everything the search looks at
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub node: Node,
+    /// None if the data couldn't be decoded, then only the name is searched
+    pub npc: Option<Npc>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    /// words that must all occur in the name or the fields, ignoring case
+    pub text: String,
+    pub blueprint: Option<String>,
+    pub tag: Option<String>,
+}
+
+pub fn load(db: &mut DB) -> Result<Vec<Entry>> {
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (id, tag) in db.select_all_tags()? {
+        tags.entry(id).or_default().push(tag);
+    }
+    Ok(db
+        .select_nodes(&NodeFieldName::Type.eq(NPC_NODE_TYPE))?
+        .into_iter()
+        .map(|node| Entry {
+            npc: npc_store::npc_from_node(&node).ok(),
+            tags: tags.remove(&node.id).unwrap_or_default(),
+            node,
+        })
+        .collect())
+}
+
+impl Entry {
+    pub fn blueprint(&self) -> Option<&str> {
+        self.npc.as_ref().and_then(|npc| npc.blueprint.as_deref())
+    }
+
+    pub fn matches(&self, query: &Query) -> bool {
+        if query.blueprint.is_some() && self.blueprint() != query.blueprint.as_deref() {
+            return false;
+        }
+        if let Some(tag) = &query.tag {
+            if !self.tags.contains(tag) {
+                return false;
+            }
+        }
+        let mut text = self.node.name.to_lowercase();
+        if let Some(npc) = &self.npc {
+            for value in npc.fields.iter().flat_map(|f| &f.values) {
+                text.push('\n');
+                text.push_str(&value.to_lowercase());
+            }
+        }
+        query
+            .text
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| text.contains(word))
+    }
+}
+
+pub fn search<'a>(entries: &'a [Entry], query: &'a Query) -> impl Iterator<Item = &'a Entry> {
+    entries.iter().filter(move |e| e.matches(query))
+}
+
+/// the blueprints of the entries, sorted, for the blueprint filter
+pub fn blueprints(entries: &[Entry]) -> Vec<String> {
+    entries
+        .iter()
+        .filter_map(|e| e.blueprint())
+        .unique()
+        .sorted()
+        .map(String::from)
+        .collect()
+}
+
+/// the tags of the entries, sorted, for the tag filter
+pub fn tags(entries: &[Entry]) -> Vec<String> {
+    entries
+        .iter()
+        .flat_map(|e| &e.tags)
+        .unique()
+        .sorted()
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npc::FieldKind;
+    use database::meta::Meta;
+
+    fn insert(db: &mut DB, name: &str, blueprint: Option<&str>, fields: &[(&str, &str)]) -> i64 {
+        let mut npc = Npc::new(blueprint.map(String::from));
+        for (field, value) in fields {
+            npc.set(field, FieldKind::Choice, vec![value.to_string()]);
+        }
+        let data = serde_json::to_vec(&npc).unwrap();
+        db.insert_node(name, NPC_NODE_TYPE, &Meta::new(), &data)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_search() {
+        let mut db = DB::new(std::path::Path::new(":memory:")).unwrap();
+        let mira = insert(
+            &mut db,
+            "Mira Quell",
+            Some("Human"),
+            &[("job", "Smuggler"), ("hair", "Red")],
+        );
+        let oderic = insert(
+            &mut db,
+            "Oderic",
+            Some("Dwarf"),
+            &[("job", "Harbor master")],
+        );
+        insert(&mut db, "Nameless", None, &[]);
+        db.insert_node("Not an NPC", "note", &Meta::new(), &[])
+            .unwrap();
+        db.add_tag(&[mira, oderic], "harborton").unwrap();
+        db.add_tag(&[mira], "villain").unwrap();
+
+        let entries = load(&mut db).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(blueprints(&entries), ["Dwarf", "Human"]);
+        assert_eq!(tags(&entries), ["harborton", "villain"]);
+
+        let names = |query: &Query| -> Vec<String> {
+            search(&entries, query)
+                .map(|e| e.node.name.clone())
+                .collect()
+        };
+        let text = |text: &str| Query {
+            text: text.into(),
+            ..Query::default()
+        };
+        assert_eq!(names(&Query::default()).len(), 3);
+        assert_eq!(names(&text("smuggler QUELL")), ["Mira Quell"]);
+        assert_eq!(names(&text("harbor")), ["Oderic"]);
+        assert!(names(&text("red dwarf")).is_empty());
+        let tagged = Query {
+            tag: Some("harborton".into()),
+            ..Query::default()
+        };
+        assert_eq!(names(&tagged), ["Mira Quell", "Oderic"]);
+        assert_eq!(
+            names(&Query {
+                blueprint: Some("Dwarf".into()),
+                ..tagged
+            }),
+            ["Oderic"]
+        );
+    }
+}
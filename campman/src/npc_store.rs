@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use database::db::Node;
+use database::dsl::NodeFieldName;
+use itertools::Itertools;
+
+use crate::database;
+use crate::npc::{FieldKind, Npc};
+
+/// NPCs are stored as nodes of this type, with the fields of the NPC as json in the data column
+pub const NPC_NODE_TYPE: &str = "npc";
+
+pub fn saved_npc_nodes() -> Result<Vec<Node>> {
+    database().select_nodes(&NodeFieldName::Type.eq(NPC_NODE_TYPE))
+}
+
+pub fn saved_npcs() -> Result<Vec<Npc>> {
+    saved_npc_nodes()?.iter().map(npc_from_node).collect()
+}
+
+/// NPCs that were saved before the Npc struct existed are plain maps from field to values
+type LegacyNpc = HashMap<String, Vec<String>>;
+
+pub fn npc_from_node(node: &Node) -> Result<Npc> {
+    node.data_as()
+        .or_else(|_| node.data_as::<LegacyNpc>().map(npc_from_legacy))
+        .with_context(|| format!("Could not decode the data of NPC {}", node.name))
+}
+
+fn npc_from_legacy(map: LegacyNpc) -> Npc {
+    let mut npc = Npc::new(None);
+    for (field, values) in map.into_iter().sorted() {
+        npc.set(&field, FieldKind::Choice, values);
+    }
+    npc
+}
+
+pub fn save_npc(name: &str, npc: &Npc) -> Result<()> {
+    database().insert_typed(name, NPC_NODE_TYPE, npc)?;
+    Ok(())
+}
@@ -0,0 +1,358 @@
+//! Plugins are rhai scripts in the plugins directory next to the config file. The top level
+//! statements of a script run once when it is loaded, and register what the plugin provides:
+//!
+//! ```text
+//! generator("Tavern Name", "tavern_name");
+//! table("Weather", ["Rain", "Fog", "Sunshine"]);
+//! exporter("Stat Block", "md", "stat_block");
+//!
+//! fn tavern_name() { `The ${choose(["Red", "Golden"])} ${choose(["Dragon", "Goose"])}` }
+//! fn stat_block(npc) { `# ${npc.name}\nHP: ${roll("2d8+2")}` }
+//! ```
+//!
+//! Generators take no arguments, exporters get the NPC as a map with the keys name, blueprint and
+//! fields. Besides the registration functions, scripts can call `roll(expr)` to roll dice,
+//! `choose(array)` to pick a random element, `nodes(type)` and `linked(id)` to query the campaign
+//! database, and `npcs()` to get all saved NPCs.
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{anyhow, Context, Result};
+use database::db::Node;
+use database::dsl::NodeFieldName;
+use database::meta::MetaValue;
+use rand::seq::SliceRandom;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, AST};
+
+use crate::npc::Npc;
+use crate::{conf_dir, database, npc_store};
+
+/// the plugins are shared by the tabs that use them, and replaced when they are reloaded
+pub type SharedPlugins = Rc<RefCell<Plugins>>;
+
+pub struct Plugins {
+    engine: Engine,
+    /// only the functions of the scripts, so calling them doesn't rerun the registrations
+    scripts: Vec<AST>,
+    pub generators: Vec<Generator>,
+    pub tables: Vec<Table>,
+    pub exporters: Vec<Exporter>,
+    /// file names of the scripts that failed to load, with the reason
+    pub errors: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pub name: String,
+    script: usize,
+    function: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub name: String,
+    pub entries: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    pub name: String,
+    /// the file extension of the exported files
+    pub extension: String,
+    script: usize,
+    function: String,
+}
+
+/// collects the registrations while the scripts are loaded
+#[derive(Default)]
+struct Registry {
+    /// the index of the script that is currently loaded
+    current: usize,
+    generators: Vec<Generator>,
+    tables: Vec<Table>,
+    exporters: Vec<Exporter>,
+}
+
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+pub fn plugins_dir() -> PathBuf {
+    conf_dir().join("plugins")
+}
+
+impl Plugins {
+    /// loads all .rhai files in the directory. A missing directory means there are no plugins,
+    /// and scripts that fail are listed in the errors instead of failing the whole load.
+    pub fn load(dir: &Path) -> Plugins {
+        let registry = Rc::new(RefCell::new(Registry::default()));
+        let engine = make_engine(&registry);
+        let mut scripts = vec![];
+        let mut errors = vec![];
+        for path in script_paths(dir) {
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            let lens = {
+                let mut reg = registry.borrow_mut();
+                reg.current = scripts.len();
+                (reg.generators.len(), reg.tables.len(), reg.exporters.len())
+            };
+            let res = std::fs::read_to_string(&path)
+                .context(path.display().to_string())
+                .and_then(|text| engine.compile(text).map_err(|e| anyhow!("{}", e)))
+                .and_then(|ast| {
+                    engine.run_ast(&ast).map_err(|e| anyhow!("{}", e))?;
+                    Ok(ast)
+                });
+            match res {
+                Ok(ast) => scripts.push(ast.clone_functions_only()),
+                Err(e) => {
+                    // registrations of a failed script would refer to the next script
+                    let mut reg = registry.borrow_mut();
+                    reg.generators.truncate(lens.0);
+                    reg.tables.truncate(lens.1);
+                    reg.exporters.truncate(lens.2);
+                    errors.push((file_name, format!("{:#}", e)));
+                }
+            }
+        }
+        let registry = registry.take();
+        Plugins {
+            engine,
+            scripts,
+            generators: registry.generators,
+            tables: registry.tables,
+            exporters: registry.exporters,
+            errors,
+        }
+    }
+
+    pub fn run_generator(&self, name: &str) -> Result<String> {
+        let generator = find(&self.generators, |g| g.name == name, "generator", name)?;
+        let res = self
+            .engine
+            .call_fn::<Dynamic>(
+                &mut Default::default(),
+                &self.scripts[generator.script],
+                &generator.function,
+                (),
+            )
+            .map_err(|e| anyhow!("{}: {}", name, e))?;
+        Ok(to_text(res))
+    }
+
+    pub fn roll_table(&self, name: &str) -> Result<String> {
+        let table = find(&self.tables, |t| t.name == name, "table", name)?;
+        table
+            .entries
+            .choose(&mut rand::thread_rng())
+            .cloned()
+            .ok_or_else(|| anyhow!("The table {} is empty", name))
+    }
+
+    pub fn export(&self, exporter: &Exporter, name: &str, npc: &Npc) -> Result<String> {
+        let res = self
+            .engine
+            .call_fn::<Dynamic>(
+                &mut Default::default(),
+                &self.scripts[exporter.script],
+                &exporter.function,
+                (npc_to_map(name, npc),),
+            )
+            .map_err(|e| anyhow!("{}: {}", exporter.name, e))?;
+        Ok(to_text(res))
+    }
+}
+
+fn make_engine(registry: &Rc<RefCell<Registry>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let reg = registry.clone();
+    engine.register_fn("generator", move |name: &str, function: &str| {
+        let mut reg = reg.borrow_mut();
+        let script = reg.current;
+        reg.generators.push(Generator {
+            name: name.into(),
+            script,
+            function: function.into(),
+        });
+    });
+    let reg = registry.clone();
+    engine.register_fn("table", move |name: &str, entries: Array| {
+        reg.borrow_mut().tables.push(Table {
+            name: name.into(),
+            entries: entries.into_iter().map(to_text).collect(),
+        });
+    });
+    let reg = registry.clone();
+    engine.register_fn(
+        "exporter",
+        move |name: &str, extension: &str, function: &str| {
+            let mut reg = reg.borrow_mut();
+            let script = reg.current;
+            reg.exporters.push(Exporter {
+                name: name.into(),
+                extension: extension.into(),
+                script,
+                function: function.into(),
+            });
+        },
+    );
+
+    engine.register_fn("roll", |expr: &str| -> ScriptResult<i64> {
+        dice::roll(expr).map_err(|e| format!("{:#}", e).into())
+    });
+    engine.register_fn("choose", |xs: Array| -> ScriptResult<Dynamic> {
+        xs.choose(&mut rand::thread_rng())
+            .cloned()
+            .ok_or_else(|| "choose was called with an empty array".into())
+    });
+    engine.register_fn("nodes", |r#type: &str| -> ScriptResult<Array> {
+        let nodes = database()
+            .select_nodes(&NodeFieldName::Type.eq(r#type))
+            .map_err(|e| format!("{:#}", e))?;
+        Ok(nodes.iter().map(node_to_map).map(Dynamic::from).collect())
+    });
+    engine.register_fn("linked", |id: i64| -> ScriptResult<Array> {
+        let linked = database()
+            .select_linked_nodes(id)
+            .map_err(|e| format!("{:#}", e))?;
+        Ok(linked
+            .iter()
+            .map(|(link, node)| {
+                let mut map = node_to_map(node);
+                map.insert("link".into(), link.r#type.clone().into());
+                Dynamic::from(map)
+            })
+            .collect())
+    });
+    engine.register_fn("npcs", || -> ScriptResult<Array> {
+        let nodes = npc_store::saved_npc_nodes().map_err(|e| format!("{:#}", e))?;
+        nodes
+            .iter()
+            .map(|node| {
+                let npc = npc_store::npc_from_node(node).map_err(|e| format!("{:#}", e))?;
+                let mut map = npc_to_map(&node.name, &npc);
+                map.insert("id".into(), node.id.into());
+                Ok(Dynamic::from(map))
+            })
+            .collect()
+    });
+    engine
+}
+
+/// the .rhai files in the directory, sorted, so the load order is stable
+fn script_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|e| e == "rhai").unwrap_or(false))
+            .collect(),
+        Err(_) => vec![],
+    };
+    paths.sort();
+    paths
+}
+
+fn find<'a, T>(xs: &'a [T], pred: impl Fn(&T) -> bool, kind: &str, name: &str) -> Result<&'a T> {
+    xs.iter()
+        .find(|x| pred(x))
+        .ok_or_else(|| anyhow!("There is no {} called {}", kind, name))
+}
+
+fn node_to_map(node: &Node) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), node.id.into());
+    map.insert("name".into(), node.name.clone().into());
+    map.insert("type".into(), node.r#type.clone().into());
+    let meta: Map = node
+        .meta
+        .iter()
+        .map(|(k, v)| {
+            let v = match v {
+                MetaValue::Bool(b) => Dynamic::from(*b),
+                MetaValue::Number(n) => Dynamic::from(*n),
+                MetaValue::Text(s) => Dynamic::from(s.clone()),
+            };
+            (k.as_str().into(), v)
+        })
+        .collect();
+    map.insert("meta".into(), meta.into());
+    map.insert(
+        "data".into(),
+        String::from_utf8_lossy(&node.data).to_string().into(),
+    );
+    map
+}
+
+fn npc_to_map(name: &str, npc: &Npc) -> Map {
+    let mut fields = Map::new();
+    for field in &npc.fields {
+        let values: Array = field.values.iter().cloned().map(Dynamic::from).collect();
+        fields.insert(field.name.as_str().into(), values.into());
+    }
+    let mut map = Map::new();
+    map.insert("name".into(), name.to_string().into());
+    map.insert(
+        "blueprint".into(),
+        npc.blueprint
+            .clone()
+            .map(Dynamic::from)
+            .unwrap_or(Dynamic::UNIT),
+    );
+    map.insert("fields".into(), fields.into());
+    map
+}
+
+/// strings are used as they are, arrays become one line per element, and maps one line per key
+fn to_text(value: Dynamic) -> String {
+    if value.is::<Array>() {
+        let xs = value.cast::<Array>();
+        xs.into_iter().map(to_text).collect::<Vec<_>>().join("\n")
+    } else if value.is::<Map>() {
+        let map = value.cast::<Map>();
+        map.into_iter()
+            .map(|(k, v)| format!("{}: {}", k, to_text(v)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_script(script: &str) -> Plugins {
+        let dir = std::env::temp_dir().join(format!("campman-plugins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("test.rhai"), script).unwrap();
+        std::fs::write(dir.join("broken.rhai"), "generator(").unwrap();
+        let plugins = Plugins::load(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        plugins
+    }
+
+    #[test]
+    fn test_plugins() {
+        let plugins = load_script(
+            r#"
+            generator("Fixed", "fixed");
+            table("Weather", ["Rain"]);
+            exporter("Names", "txt", "names");
+            fn fixed() { `${roll("2d1+1")} ${choose(["x"])}` }
+            fn names(npc) { [npc.name, npc.fields.job[0]] }
+            "#,
+        );
+        assert_eq!(plugins.errors.len(), 1);
+        assert_eq!(plugins.errors[0].0, "broken.rhai");
+        assert_eq!(plugins.run_generator("Fixed").unwrap(), "3 x");
+        assert_eq!(plugins.roll_table("Weather").unwrap(), "Rain");
+        assert!(plugins.roll_table("Loot").is_err());
+
+        let mut npc = Npc::new(None);
+        npc.set("job", Default::default(), vec!["Smith".into()]);
+        let text = plugins.export(&plugins.exporters[0], "Bob", &npc).unwrap();
+        assert_eq!(text, "Bob\nSmith");
+    }
+}
@@ -0,0 +1,98 @@
+use iced::widget::{column, row, Button, Row, Scrollable, Text};
+use iced::{Element, Length};
+use iced_aw::TabLabel;
+
+use super::{Message, Tab};
+use crate::plugins::{self, Plugins, SharedPlugins};
+
+pub struct PluginsTab {
+    plugins: SharedPlugins,
+    /// the result of the last generator or table, or the error it produced
+    output: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PluginsMessage {
+    Reload,
+    RunGenerator(String),
+    RollTable(String),
+}
+
+impl PluginsTab {
+    pub fn new(plugins: SharedPlugins) -> PluginsTab {
+        PluginsTab {
+            plugins,
+            output: None,
+        }
+    }
+
+    pub fn update(&mut self, message: PluginsMessage) {
+        let res = match message {
+            PluginsMessage::Reload => {
+                *self.plugins.borrow_mut() = Plugins::load(&plugins::plugins_dir());
+                self.output = None;
+                return;
+            }
+            PluginsMessage::RunGenerator(name) => self.plugins.borrow().run_generator(&name),
+            PluginsMessage::RollTable(name) => self.plugins.borrow().roll_table(&name),
+        };
+        self.output = Some(res.unwrap_or_else(|e| format!("An error Occured:\n{:#}", e)));
+    }
+}
+
+impl Tab for PluginsTab {
+    type Message = Message;
+
+    fn tab_label(&self) -> TabLabel {
+        TabLabel::Text("Plugins".into())
+    }
+
+    fn content(&self) -> Element<'_, Self::Message> {
+        let plugins = self.plugins.borrow();
+        let generators = plugins.generators.iter().map(|g| {
+            Button::new(Text::new(g.name.clone()))
+                .on_press(PluginsMessage::RunGenerator(g.name.clone()))
+                .into()
+        });
+        let tables = plugins.tables.iter().map(|t| {
+            Button::new(Text::new(t.name.clone()))
+                .on_press(PluginsMessage::RollTable(t.name.clone()))
+                .into()
+        });
+        let exporters = plugins
+            .exporters
+            .iter()
+            .map(|e| format!("{} (.{})", e.name, e.extension))
+            .collect::<Vec<_>>();
+
+        let mut col = column!(row!(
+            Text::new(format!("Scripts in {}", plugins::plugins_dir().display()))
+                .width(Length::Fill),
+            Button::new("Reload").on_press(PluginsMessage::Reload)
+        )
+        .spacing(10))
+        .spacing(20);
+        for (file, error) in &plugins.errors {
+            col = col.push(Text::new(format!("{} failed to load:\n{}", file, error)));
+        }
+        col = col.push(column!(
+            Text::new("Generators:").size(24),
+            Row::with_children(generators.collect()).spacing(10)
+        ));
+        col = col.push(column!(
+            Text::new("Random Tables:").size(24),
+            Row::with_children(tables.collect()).spacing(10)
+        ));
+        if !exporters.is_empty() {
+            col = col.push(Text::new(format!(
+                "Exporters, available on the NPC pages: {}",
+                exporters.join(", ")
+            )));
+        }
+        if let Some(output) = &self.output {
+            col = col.push(Text::new(output.clone()).size(24));
+        }
+        let content: Element<'_, PluginsMessage> = Scrollable::new(col).into();
+        content.map(Message::PluginsMsg)
+    }
+}
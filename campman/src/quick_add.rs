@@ -0,0 +1,105 @@
+//! A minimal dialog to capture an improvised NPC with a name and a note. It is opened with
+//! Ctrl+N from anywhere in the app, and the stub is saved right away, so it can be fleshed out
+//! later.
+use anyhow::{ensure, Result};
+use iced::keyboard::{self, KeyCode};
+use iced::widget::{column, row, text_input, Button, Text, TextInput};
+use iced::{event, Command, Element, Event, Subscription};
+
+use crate::npc::{FieldKind, Npc};
+use crate::npc_store;
+
+#[derive(Debug, Default)]
+pub struct QuickAdd {
+    name: String,
+    note: String,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum QuickAddMessage {
+    Open,
+    NameChanged(String),
+    NoteChanged(String),
+    Save,
+    Cancel,
+}
+
+impl QuickAdd {
+    /// handles the messages that edit the dialog. Open, Save and Cancel are handled by the
+    /// owner of the dialog.
+    pub fn update(&mut self, message: QuickAddMessage) {
+        match message {
+            QuickAddMessage::NameChanged(name) => self.name = name,
+            QuickAddMessage::NoteChanged(note) => self.note = note,
+            _ => {}
+        }
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        let res = self.try_save();
+        if let Err(e) = &res {
+            self.error = Some(format!("{:#}", e));
+        }
+        res
+    }
+
+    fn try_save(&self) -> Result<()> {
+        let name = self.name.trim();
+        ensure!(!name.is_empty(), "The NPC needs a name");
+        let mut npc = Npc::new(None);
+        npc.set("name", FieldKind::Text, vec![name.into()]);
+        if !self.note.trim().is_empty() {
+            npc.set("note", FieldKind::Text, vec![self.note.trim().into()]);
+        }
+        npc_store::save_npc(name, &npc)
+    }
+
+    pub fn view(&self) -> Element<'_, QuickAddMessage> {
+        let mut col = column!(
+            Text::new("Quick Add NPC").size(32),
+            TextInput::new("Name", &self.name, QuickAddMessage::NameChanged)
+                .id(name_input_id())
+                .on_submit(QuickAddMessage::Save)
+                .padding(5),
+            TextInput::new("Note", &self.note, QuickAddMessage::NoteChanged)
+                .on_submit(QuickAddMessage::Save)
+                .padding(5),
+            row!(
+                Button::new("Save").on_press(QuickAddMessage::Save),
+                Button::new("Cancel").on_press(QuickAddMessage::Cancel)
+            )
+            .spacing(10)
+        )
+        .spacing(10)
+        .max_width(600);
+        if let Some(e) = &self.error {
+            col = col.push(Text::new(e));
+        }
+        col.into()
+    }
+}
+
+/// focuses the name input, so the name can be typed right after opening the dialog
+pub fn focus_name_input<Message: 'static>() -> Command<Message> {
+    text_input::focus(name_input_id())
+}
+
+fn name_input_id() -> text_input::Id {
+    text_input::Id::new("quick-add-name")
+}
+
+/// Ctrl+N opens the dialog, Escape closes it again
+pub fn shortcuts() -> Subscription<QuickAddMessage> {
+    iced::subscription::events_with(|event, _status: event::Status| match event {
+        Event::Keyboard(keyboard::Event::KeyPressed {
+            key_code,
+            modifiers,
+        }) => match key_code {
+            KeyCode::N if modifiers.control() => Some(QuickAddMessage::Open),
+            KeyCode::Escape => Some(QuickAddMessage::Cancel),
+            _ => None,
+        },
+        _ => None,
+    })
+}
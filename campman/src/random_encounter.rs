@@ -0,0 +1,231 @@
+//! Random encounters are rolled on tables in encounter_tables.toml in the config dir. Every
+//! table belongs to a terrain and a range of party levels, and its entries list the monsters by
+//! the names of stat blocks in the bestiary, each with the number that appears as dice:
+//!
+//! ```toml
+//! [[table]]
+//! terrain = "Forest"
+//! min-level = 1
+//! max-level = 4
+//!
+//! [[table.entry]]
+//! weight = 3
+//! monsters = ["2d4 Goblin"]
+//!
+//! [[table.entry]]
+//! monsters = ["1 Goblin Boss", "1d4+1 Goblin"]
+//! ```
+//!
+//! The weight is 1 by default, the levels range from 1 to 20. If several tables match, all of
+//! their entries are rolled on together.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use itertools::Itertools;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::conf_dir;
+use crate::encounter::{Bestiary, Encounter};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EncounterTables {
+    #[serde(default, rename = "table")]
+    pub tables: Vec<EncounterTable>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EncounterTable {
+    pub terrain: String,
+    #[serde(default = "default_min_level")]
+    pub min_level: u32,
+    #[serde(default = "default_max_level")]
+    pub max_level: u32,
+    #[serde(rename = "entry")]
+    pub entries: Vec<TableEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TableEntry {
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// like `1d4+1 Goblin`, a single monster doesn't need a number
+    pub monsters: Vec<String>,
+}
+
+fn default_min_level() -> u32 {
+    1
+}
+
+fn default_max_level() -> u32 {
+    20
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+pub fn tables_path() -> PathBuf {
+    conf_dir().join("encounter_tables.toml")
+}
+
+impl EncounterTables {
+    /// loads the tables from the file, which doesn't need to exist
+    pub fn load(path: &Path) -> Result<EncounterTables> {
+        if !path.exists() {
+            return Ok(EncounterTables::default());
+        }
+        let text = fs::read_to_string(path).context(path.display().to_string())?;
+        EncounterTables::parse(&text).context(path.display().to_string())
+    }
+
+    pub fn parse(text: &str) -> Result<EncounterTables> {
+        let tables: EncounterTables = toml::from_str(text)?;
+        for table in &tables.tables {
+            ensure!(
+                table.min_level <= table.max_level,
+                "The levels of a {} table are reversed",
+                table.terrain
+            );
+            for monster in table.entries.iter().flat_map(|e| &e.monsters) {
+                let (count, name) = split_monster(monster);
+                ensure!(!name.is_empty(), "{} has no name", monster);
+                dice::roll(count).with_context(|| format!("in {}", monster))?;
+            }
+        }
+        Ok(tables)
+    }
+
+    /// the terrains of the tables, sorted, without duplicates
+    pub fn terrains(&self) -> Vec<String> {
+        self.tables
+            .iter()
+            .map(|t| t.terrain.clone())
+            .unique()
+            .sorted()
+            .collect()
+    }
+
+    /// rolls an encounter for the terrain and the level, on the entries of all matching tables.
+    /// The monsters are taken from the stat blocks of the bestiary
+    pub fn roll(
+        &self,
+        terrain: &str,
+        level: u32,
+        bestiary: &Bestiary,
+        rng: &mut impl Rng,
+    ) -> Result<Encounter> {
+        let entries: Vec<&TableEntry> = self
+            .tables
+            .iter()
+            .filter(|t| t.terrain.eq_ignore_ascii_case(terrain))
+            .filter(|t| (t.min_level..=t.max_level).contains(&level))
+            .flat_map(|t| &t.entries)
+            .filter(|e| e.weight > 0)
+            .collect();
+        let total: u32 = entries.iter().map(|e| e.weight).sum();
+        ensure!(
+            total > 0,
+            "There is no table for {} at level {}",
+            terrain,
+            level
+        );
+        let mut pick = rng.gen_range(0..total);
+        let entry = entries
+            .into_iter()
+            .find(|e| {
+                if pick < e.weight {
+                    true
+                } else {
+                    pick -= e.weight;
+                    false
+                }
+            })
+            .expect("the pick is less than the total weight");
+
+        let mut encounter = Encounter::default();
+        for monster in &entry.monsters {
+            let (count, name) = split_monster(monster);
+            let stat_block = bestiary
+                .stat_blocks
+                .iter()
+                .find(|b| b.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| anyhow!("There is no stat block for {} in the bestiary", name))?;
+            let count = dice::roll(count)?;
+            if count > 0 {
+                encounter.add(stat_block, count as usize);
+            }
+        }
+        Ok(encounter)
+    }
+}
+
+/// splits `2d4 Goblin` into the number and the name, the number is 1 if there is none
+fn split_monster(monster: &str) -> (&str, &str) {
+    let monster = monster.trim();
+    match monster.split_once(char::is_whitespace) {
+        Some((count, name)) if count.starts_with(|c: char| c.is_ascii_digit()) => {
+            (count, name.trim())
+        }
+        _ => ("1", monster),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encounter::StatBlock;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_random_encounters() {
+        let tables = EncounterTables::parse(
+            "[[table]]\nterrain = \"Forest\"\nmax-level = 4\n\
+             [[table.entry]]\nmonsters = [\"3 Goblin\", \"Goblin Boss\"]\n\n\
+             [[table]]\nterrain = \"Forest\"\nmin-level = 3\n\
+             [[table.entry]]\nweight = 0\nmonsters = [\"1d4 Wolf\"]\n\n\
+             [[table]]\nterrain = \"Cave\"\n\
+             [[table.entry]]\nmonsters = [\"1d2-1d2 Bat\"]\n",
+        )
+        .unwrap();
+        assert_eq!(tables.terrains(), ["Cave", "Forest"]);
+
+        let block = |name: &str| StatBlock {
+            name: name.into(),
+            stats: "7 XP=50".into(),
+            source: "test.txt".into(),
+        };
+        let bestiary = Bestiary {
+            stat_blocks: vec![block("Goblin"), block("Goblin Boss"), block("Bat")],
+            errors: vec![],
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let encounter = tables.roll("forest", 4, &bestiary, &mut rng).unwrap();
+        assert_eq!(
+            encounter.participant_file().unwrap(),
+            "Goblin: 7 XP=50 xN=3\nGoblin Boss: 7 XP=50\n"
+        );
+        // the wolves are the only entry at level 5, but they have no weight
+        assert!(tables.roll("Forest", 5, &bestiary, &mut rng).is_err());
+        assert!(tables.roll("Swamp", 1, &bestiary, &mut rng).is_err());
+        // bats that roll no bats are left out
+        for _ in 0..10 {
+            let bats = tables.roll("Cave", 1, &bestiary, &mut rng).unwrap();
+            assert!(bats.n_monsters() <= 1);
+        }
+
+        let missing = EncounterTables::parse(
+            "[[table]]\nterrain = \"Cave\"\n[[table.entry]]\nmonsters = [\"2 Troll\"]\n",
+        )
+        .unwrap();
+        assert!(missing.roll("Cave", 1, &bestiary, &mut rng).is_err());
+        let reversed = "[[table]]\nterrain = \"Cave\"\nmin-level = 5\nmax-level = 2\n\
+                        [[table.entry]]\nmonsters = [\"Bat\"]\n";
+        assert!(EncounterTables::parse(reversed).is_err());
+        let invalid = "[[table]]\nterrain = \"Cave\"\n[[table.entry]]\nmonsters = [\"2x Bat\"]\n";
+        assert!(EncounterTables::parse(invalid).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Rules references for the reference tab, loaded from markdown and toml files in the reference
+//! directory of the config dir.
+//!
+//! Every heading of a markdown file starts an entry. In toml files every table is a section, and
+//! every key in it an entry, which is either a text, or a table whose fields are listed.
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+
+use crate::conf_dir;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// the file stem, and the section in toml files
+    pub source: String,
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Default)]
+pub struct Reference {
+    pub entries: Vec<Entry>,
+    /// files that couldn't be loaded, with their error
+    pub errors: Vec<(String, String)>,
+}
+
+pub fn reference_dir() -> PathBuf {
+    conf_dir().join("reference")
+}
+
+impl Reference {
+    /// loads all files of the directory, which doesn't need to exist
+    pub fn load(dir: &Path) -> Reference {
+        let mut reference = Reference::default();
+        let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path())).collect(),
+            Err(_) => return reference,
+        };
+        paths.sort();
+        for path in paths {
+            let stem = match path.file_stem() {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let res = match path.extension().and_then(|e| e.to_str()) {
+                Some("md") => fs::read_to_string(&path)
+                    .map_err(Into::into)
+                    .map(|text| parse_markdown(&stem, &text)),
+                Some("toml") => fs::read_to_string(&path)
+                    .map_err(Into::into)
+                    .and_then(|text| parse_toml(&stem, &text)),
+                _ => continue,
+            };
+            match res {
+                Ok(entries) => reference.entries.extend(entries),
+                Err(e) => reference
+                    .errors
+                    .push((path.display().to_string(), format!("{:#}", e))),
+            }
+        }
+        reference
+    }
+
+    /// the entries that match the query, best match first. Titles count more than the text
+    pub fn search(&self, query: &str) -> Vec<&Entry> {
+        if query.trim().is_empty() {
+            return self.entries.iter().collect();
+        }
+        let matcher = SkimMatcherV2::default();
+        let mut hits: Vec<(i64, &Entry)> = self
+            .entries
+            .iter()
+            .filter_map(|e| {
+                let title = matcher.fuzzy_match(&e.title, query).map(|s| s * 2);
+                let body = matcher.fuzzy_match(&e.body, query);
+                title.max(body).map(|score| (score, e))
+            })
+            .collect();
+        hits.sort_by_key(|(score, _)| Reverse(*score));
+        hits.into_iter().map(|(_, e)| e).collect()
+    }
+}
+
+fn parse_markdown(source: &str, text: &str) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut current: Option<Entry> = None;
+    for line in text.lines() {
+        if let Some(heading) = line.strip_prefix('#') {
+            entries.extend(current.take());
+            current = Some(Entry {
+                source: source.into(),
+                title: heading.trim_start_matches('#').trim().into(),
+                body: String::new(),
+            });
+        } else if let Some(entry) = &mut current {
+            entry.body.push_str(line);
+            entry.body.push('\n');
+        }
+    }
+    entries.extend(current);
+    for entry in &mut entries {
+        entry.body = entry.body.trim().into();
+    }
+    entries
+}
+
+fn parse_toml(source: &str, text: &str) -> Result<Vec<Entry>> {
+    let table: toml::value::Table = toml::from_str(text).context("Invalid toml")?;
+    let mut entries = vec![];
+    for (section, value) in table {
+        match value {
+            toml::Value::Table(items) => {
+                let source = format!("{} / {}", source, section);
+                for (title, value) in items {
+                    entries.push(Entry {
+                        source: source.clone(),
+                        title,
+                        body: toml_body(value),
+                    });
+                }
+            }
+            value => entries.push(Entry {
+                source: source.into(),
+                title: section,
+                body: toml_body(value),
+            }),
+        }
+    }
+    Ok(entries)
+}
+
+fn toml_body(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        toml::Value::Table(fields) => fields
+            .into_iter()
+            .map(|(k, v)| format!("{}: {}", k, toml_body(v)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        toml::Value::Array(items) => items
+            .into_iter()
+            .map(toml_body)
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_search() {
+        let md = "intro is ignored\n# Conditions\n## Prone\nDisadvantage on attacks.\n\n## Blinded\nCan't see.\n";
+        let toml = "[prices]\nrope = \"1 gp\"\ntorch = { cost = \"1 cp\", weight = 1 }\n";
+        let mut entries = parse_markdown("rules", md);
+        entries.extend(parse_toml("shop", toml).unwrap());
+        let titles: Vec<_> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, ["Conditions", "Prone", "Blinded", "rope", "torch"]);
+        assert_eq!(entries[1].body, "Disadvantage on attacks.");
+        assert_eq!(entries[4].source, "shop / prices");
+        assert_eq!(entries[4].body, "cost: 1 cp\nweight: 1");
+
+        let reference = Reference {
+            entries,
+            errors: vec![],
+        };
+        assert_eq!(reference.search("prne")[0].title, "Prone");
+        assert_eq!(reference.search("trch")[0].title, "torch");
+        assert_eq!(reference.search("").len(), 5);
+        assert!(reference.search("xyzzy").is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+use iced::widget::{column, row, Button, Column, Scrollable, Text, TextInput};
+use iced::{Element, Length};
+use iced_aw::TabLabel;
+
+use super::{Message, Tab};
+use crate::reference::{self, Reference};
+
+pub struct ReferenceTab {
+    reference: Reference,
+    query: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum ReferenceMessage {
+    QueryChanged(String),
+    Reload,
+}
+
+impl ReferenceTab {
+    pub fn new() -> ReferenceTab {
+        ReferenceTab {
+            reference: Reference::load(&reference::reference_dir()),
+            query: String::new(),
+        }
+    }
+
+    pub fn update(&mut self, message: ReferenceMessage) {
+        match message {
+            ReferenceMessage::QueryChanged(query) => self.query = query,
+            ReferenceMessage::Reload => {
+                self.reference = Reference::load(&reference::reference_dir())
+            }
+        }
+    }
+}
+
+impl Tab for ReferenceTab {
+    type Message = Message;
+
+    fn tab_label(&self) -> TabLabel {
+        TabLabel::Text("Reference".into())
+    }
+
+    fn content(&self) -> Element<'_, Self::Message> {
+        let mut col = column!(row!(
+            TextInput::new("Search", &self.query, ReferenceMessage::QueryChanged)
+                .padding(5)
+                .width(Length::Fill),
+            Button::new("Reload").on_press(ReferenceMessage::Reload)
+        )
+        .spacing(10))
+        .spacing(20);
+        for (file, error) in &self.reference.errors {
+            col = col.push(Text::new(format!("{} failed to load:\n{}", file, error)));
+        }
+        if self.reference.entries.is_empty() {
+            col = col.push(Text::new(format!(
+                "Put markdown or toml files into {} to look up rules here",
+                reference::reference_dir().display()
+            )));
+        }
+        let entries = self.reference.search(&self.query).into_iter().map(|e| {
+            column!(
+                Text::new(format!("{} ({})", e.title, e.source)).size(24),
+                Text::new(e.body.clone())
+            )
+            .spacing(5)
+            .into()
+        });
+        col = col.push(Column::with_children(entries.collect()).spacing(15));
+        let content: Element<'_, ReferenceMessage> = Scrollable::new(col).into();
+        content.map(Message::ReferenceMsg)
+    }
+}
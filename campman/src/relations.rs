@@ -0,0 +1,175 @@
+//! Typed relationships between nodes, like an NPC that is a member of a guild. They are stored as
+//! links from the node the relationship is about to the other one, so the type reads from left to
+//! right: `Gimli` member of `Fellowship`.
+use std::collections::HashSet;
+
+use anyhow::{ensure, Result};
+use database::attachments::ATTACHMENT_LINK_TYPE;
+use database::db::{Node, DB, TAG_LINK_TYPE};
+
+/// the relationship types that can be created in the View NPC tab, and how they read from the
+/// right node of the link
+pub const RELATION_TYPES: &[(&str, &str)] = &[
+    ("employer of", "employed by"),
+    ("member of", "has member"),
+    ("located in", "location of"),
+    ("lives in", "home of"),
+    ("knows", "known by"),
+    ("about", "mentioned in"),
+];
+
+/// a node that is linked to another one, together with the nodes it is linked to in turn
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relation {
+    /// the type of the link, as it reads from the other node
+    pub label: String,
+    pub node: Node,
+    pub relations: Vec<Relation>,
+}
+
+/// the type as it reads from the left node if outgoing is true, otherwise from the right one
+pub fn label(link_type: &str, outgoing: bool) -> String {
+    if outgoing {
+        return link_type.into();
+    }
+    match RELATION_TYPES.iter().find(|(t, _)| *t == link_type) {
+        Some((_, inverse)) => inverse.to_string(),
+        None => format!("{} of", link_type),
+    }
+}
+
+/// the relations of the node, and theirs up to the given depth. Tags are listed, but not followed,
+/// because they link too many nodes. Attachments aren't listed, the View NPC tab shows them
+/// separately. No node is listed twice on the same path
+pub fn relations(db: &mut DB, id: i64, depth: usize) -> Result<Vec<Relation>> {
+    fn collect(
+        db: &mut DB,
+        id: i64,
+        depth: usize,
+        path: &mut HashSet<i64>,
+    ) -> Result<Vec<Relation>> {
+        let mut res = vec![];
+        path.insert(id);
+        for (link, node) in db.select_linked_nodes(id)? {
+            if path.contains(&node.id) || link.r#type == ATTACHMENT_LINK_TYPE {
+                continue;
+            }
+            let relations = if depth > 1 && link.r#type != TAG_LINK_TYPE {
+                collect(db, node.id, depth - 1, path)?
+            } else {
+                vec![]
+            };
+            res.push(Relation {
+                label: label(&link.r#type, link.left == id),
+                node,
+                relations,
+            });
+        }
+        path.remove(&id);
+        res.sort_by(|a, b| (&a.label, &a.node.name).cmp(&(&b.label, &b.node.name)));
+        Ok(res)
+    }
+    collect(db, id, depth, &mut HashSet::new())
+}
+
+/// links the nodes, unless they already are linked with this type. Fails for nodes that don't
+/// exist
+pub fn link(db: &mut DB, left: i64, right: i64, link_type: &str) -> Result<()> {
+    ensure!(left != right, "A node can't be related to itself");
+    for id in [left, right] {
+        ensure!(
+            db.try_select_node(id)?.is_some(),
+            "There is no node with id {}",
+            id
+        );
+    }
+    if !db.has_link(left, right, link_type)? {
+        db.insert_link(left, right, link_type, None)?;
+    }
+    Ok(())
+}
+
+/// the id of the node that isn't deleted and has the name, ignoring case
+pub fn find_by_name(db: &mut DB, name: &str) -> Result<i64> {
+    let name = name.trim();
+    let matches: Vec<(i64, String)> = db
+        .select_node_names()?
+        .into_iter()
+        .filter(|(_, n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(id, _, r#type)| (id, r#type))
+        .collect();
+    match matches.as_slice() {
+        [(id, _)] => Ok(*id),
+        [] => anyhow::bail!("There is no node called {}", name),
+        _ => anyhow::bail!(
+            "There are {} nodes called {}, of the types {}",
+            matches.len(),
+            name,
+            matches
+                .iter()
+                .map(|(_, t)| t.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::meta::Meta;
+
+    #[test]
+    fn test_relations() -> Result<()> {
+        let mut db = DB::new(std::path::Path::new(":memory:"))?;
+        let gimli = db.insert_node("Gimli", "npc", &Meta::new(), &[])?;
+        let legolas = db.insert_node("Legolas", "npc", &Meta::new(), &[])?;
+        let fellowship = db.insert_node("Fellowship", "faction", &Meta::new(), &[])?;
+        let rivendell = db.insert_node("Rivendell", "place", &Meta::new(), &[])?;
+        link(&mut db, gimli, fellowship, "member of")?;
+        link(&mut db, gimli, fellowship, "member of")?;
+        link(&mut db, legolas, fellowship, "member of")?;
+        link(&mut db, fellowship, rivendell, "located in")?;
+        db.add_tag(&[gimli, legolas], "hero")?;
+        db.attach(gimli, "axe.png", "image/png", &[])?;
+        assert!(link(&mut db, gimli, gimli, "knows").is_err());
+        assert!(link(&mut db, gimli, 100, "knows").is_err());
+
+        let names = |rels: &[Relation]| -> Vec<(String, String)> {
+            rels.iter()
+                .map(|r| (r.label.clone(), r.node.name.clone()))
+                .collect()
+        };
+        let rels = relations(&mut db, gimli, 2)?;
+        assert_eq!(
+            names(&rels),
+            vec![
+                ("member of".into(), "Fellowship".into()),
+                ("tag".into(), "hero".into())
+            ]
+        );
+        // the path back to Gimli isn't listed, and the tag isn't followed
+        assert_eq!(
+            names(&rels[0].relations),
+            vec![
+                ("has member".into(), "Legolas".into()),
+                ("located in".into(), "Rivendell".into())
+            ]
+        );
+        assert!(rels[1].relations.is_empty());
+        assert!(relations(&mut db, gimli, 1)?[0].relations.is_empty());
+
+        assert_eq!(find_by_name(&mut db, " rivendell")?, rivendell);
+        assert!(find_by_name(&mut db, "Mordor").is_err());
+        db.insert_node("Rivendell", "faction", &Meta::new(), &[])?;
+        assert!(find_by_name(&mut db, "Rivendell").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label("member of", true), "member of");
+        assert_eq!(label("member of", false), "has member");
+        assert_eq!(label("rival", false), "rival of");
+    }
+}
@@ -0,0 +1,234 @@
+//! `campman serve` exposes the campaign database as a small JSON API, for companion apps and
+//! virtual tabletops. Every request needs the api-token from the config as bearer token.
+//!
+//! - `GET /nodes?type=npc` lists the nodes of a type
+//! - `POST /nodes` creates a node from `{"name", "type", "meta", "data"}`, meta is an object with
+//!   typed values
+//! - `GET /nodes/<id>`, `PUT /nodes/<id>` with `{"meta", "data"}`, `DELETE /nodes/<id>`
+//! - `GET /nodes/<id>/links` lists the linked nodes together with the links
+//! - `POST /links` creates a link from `{"left", "right", "type", "data"}`
+//! - `GET /search?q=text&type=npc` finds nodes by name, the type is optional
+//!
+//! Node data is passed as text, deleting moves the node to the trash.
+use std::io::Read;
+
+use anyhow::{anyhow, bail, Context, Result};
+use database::db::{Link, Node};
+use database::dsl::NodeFieldName;
+use database::meta::Meta;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+
+use crate::database;
+
+#[derive(Serialize)]
+struct ApiNode {
+    id: i64,
+    name: String,
+    r#type: String,
+    meta: Meta,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct ApiLink {
+    id: i64,
+    left: i64,
+    right: i64,
+    r#type: String,
+    data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NewNode {
+    name: String,
+    r#type: String,
+    #[serde(default)]
+    meta: Meta,
+    #[serde(default)]
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct NodeUpdate {
+    #[serde(default)]
+    meta: Meta,
+    #[serde(default)]
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct NewLink {
+    left: i64,
+    right: i64,
+    r#type: String,
+    data: Option<String>,
+}
+
+/// the status code and the json body of a response
+type Reply = (u16, Value);
+
+/// handles requests until the process is killed
+pub fn serve(addr: &str, token: &str) -> Result<()> {
+    let server = Server::http(addr).map_err(|e| anyhow!("Could not listen on {}: {}", addr, e))?;
+    println!("Serving the campaign database on http://{}", addr);
+    for mut request in server.incoming_requests() {
+        let (status, body) = if !is_authorized(&request, token) {
+            error(401, "Missing or wrong bearer token")
+        } else {
+            handle(&mut request).unwrap_or_else(|e| error(400, &format!("{:#}", e)))
+        };
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+            );
+        if let Err(e) = request.respond(response) {
+            eprintln!("Could not send a response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Authorization") && h.value.as_str() == expected)
+}
+
+fn handle(request: &mut Request) -> Result<Reply> {
+    // the url of a request only contains the path and the query
+    let url = Url::parse(&format!("http://localhost{}", request.url()))?;
+    let segments: Vec<&str> = url.path_segments().map(|s| s.collect()).unwrap_or_default();
+    let query = |key: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.to_string())
+    };
+
+    let method = request.method().clone();
+    let reply = match (&method, segments.as_slice()) {
+        (Method::Get, ["nodes"]) => {
+            let r#type = query("type").context("The type parameter is missing")?;
+            let nodes = database().select_nodes(&NodeFieldName::Type.eq(&r#type))?;
+            (
+                200,
+                to_json(nodes.into_iter().map(api_node).collect::<Vec<_>>())?,
+            )
+        }
+        (Method::Post, ["nodes"]) => {
+            let node: NewNode = read_body(request)?;
+            let id = database().insert_node(
+                &node.name,
+                &node.r#type,
+                &node.meta,
+                node.data.as_bytes(),
+            )?;
+            (201, json!({ "id": id }))
+        }
+        (Method::Get, ["nodes", id]) => match database().try_select_node(parse_id(id)?)? {
+            Some(node) => (200, to_json(api_node(node))?),
+            None => not_found(),
+        },
+        (Method::Put, ["nodes", id]) => {
+            let id = parse_id(id)?;
+            let update: NodeUpdate = read_body(request)?;
+            let mut db = database();
+            if db.try_select_node(id)?.is_none() {
+                return Ok(not_found());
+            }
+            db.update_node(id, &update.meta, update.data.as_bytes())?;
+            (200, json!({ "id": id }))
+        }
+        (Method::Delete, ["nodes", id]) => {
+            let id = parse_id(id)?;
+            let mut db = database();
+            if db.try_select_node(id)?.is_none() {
+                return Ok(not_found());
+            }
+            db.delete_node(id)?;
+            (200, json!({ "id": id }))
+        }
+        (Method::Get, ["nodes", id, "links"]) => {
+            let linked = database().select_linked_nodes(parse_id(id)?)?;
+            let linked: Vec<Value> = linked
+                .into_iter()
+                .map(|(link, node)| Ok(json!({ "link": api_link(link)?, "node": api_node(node) })))
+                .collect::<Result<_>>()?;
+            (200, Value::Array(linked))
+        }
+        (Method::Post, ["links"]) => {
+            let link: NewLink = read_body(request)?;
+            let mut db = database();
+            for id in [link.left, link.right] {
+                if db.try_select_node(id)?.is_none() {
+                    bail!("There is no node with the id {}", id);
+                }
+            }
+            let data = link.data.map(String::into_bytes);
+            let id = db.insert_link(link.left, link.right, &link.r#type, data)?;
+            (201, json!({ "id": id }))
+        }
+        (Method::Get, ["search"]) => {
+            let term = query("q").context("The q parameter is missing")?;
+            let r#type = query("type");
+            let nodes =
+                database().select_nodes(&NodeFieldName::Name.like(&format!("%{}%", term)))?;
+            let nodes: Vec<ApiNode> = nodes
+                .into_iter()
+                .filter(|n| r#type.as_ref().map(|t| &n.r#type == t).unwrap_or(true))
+                .map(api_node)
+                .collect();
+            (200, to_json(nodes)?)
+        }
+        _ => not_found(),
+    };
+    Ok(reply)
+}
+
+fn read_body<T: for<'de> Deserialize<'de>>(request: &mut Request) -> Result<T> {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    serde_json::from_str(&body).context("Invalid request body")
+}
+
+fn parse_id(id: &str) -> Result<i64> {
+    id.parse()
+        .with_context(|| format!("{} is not a valid id", id))
+}
+
+fn to_json(value: impl Serialize) -> Result<Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+fn not_found() -> Reply {
+    error(404, "Not found")
+}
+
+fn error(status: u16, message: &str) -> Reply {
+    (status, json!({ "error": message }))
+}
+
+fn api_node(node: Node) -> ApiNode {
+    ApiNode {
+        id: node.id,
+        name: node.name,
+        r#type: node.r#type,
+        meta: node.meta,
+        data: String::from_utf8_lossy(&node.data).to_string(),
+    }
+}
+
+fn api_link(link: Link) -> Result<ApiLink> {
+    Ok(ApiLink {
+        id: link.id,
+        left: link.left,
+        right: link.right,
+        r#type: link.r#type,
+        data: link.data.map(String::from_utf8).transpose()?,
+    })
+}
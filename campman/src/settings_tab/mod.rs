@@ -0,0 +1,507 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use iced::theme::Button as ButtonTheme;
+use iced::widget::{column, row, Button, Column, Scrollable, Text, TextInput};
+use iced::{Alignment, Command, Element, Length};
+use iced_aw::TabLabel;
+
+use super::{Message, Tab};
+use crate::config::{Config, ThemeChoice};
+use crate::demo;
+use crate::gen_npc_tab::options_cache;
+use crate::iced_utils::load_async;
+use crate::snapshots::{self, Snapshot};
+use crate::sync;
+use crate::updates::{self, UpdateReport, APP_VERSION};
+
+pub struct SettingsTab {
+    config: Config,
+    updates: UpdateState,
+    snapshots: Vec<Snapshot>,
+    /// the result of the last snapshot operation
+    snapshot_notice: Option<String>,
+    /// the result of the last sync
+    sync_notice: Option<String>,
+    /// the result of entering or leaving the demo campaign
+    demo_notice: Option<String>,
+    /// the preferences as they are typed, until they are saved to the config
+    preferences: Preferences,
+    /// the result of the last save of the preferences
+    preferences_notice: Option<String>,
+}
+
+/// the settings of config.toml that can be changed in the tab, empty strings unset them
+#[derive(Debug, Clone)]
+struct Preferences {
+    theme: ThemeChoice,
+    config_dir: String,
+    database_path: String,
+    default_blueprint: String,
+    options_per_value: String,
+}
+
+enum UpdateState {
+    NotChecked,
+    /// the manifest is downloaded, or a pack is installed, in the background
+    Checking,
+    Checked(UpdateReport),
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum SettingsMessage {
+    CheckUpdates,
+    UpdatesChecked(Result<UpdateReport, String>),
+    /// installs the pack, and checks for updates again afterwards
+    InstallPack(String),
+    TakeSnapshot,
+    RestoreSnapshot(PathBuf),
+    Sync,
+    /// replaces the campaign with the demo campaign (true), or opens it again (false)
+    Demo(bool),
+    ThemeSelected(ThemeChoice),
+    ConfigDirChanged(String),
+    DatabasePathChanged(String),
+    DefaultBlueprintChanged(String),
+    OptionsPerValueChanged(String),
+    /// writes the preferences to config.toml
+    SavePreferences,
+}
+
+impl SettingsTab {
+    pub fn new() -> SettingsTab {
+        let (config, updates) = match Config::load() {
+            Ok(config) => (config, UpdateState::NotChecked),
+            Err(e) => (Config::default(), UpdateState::Error(format!("{:#}", e))),
+        };
+        let mut tab = SettingsTab {
+            preferences: Preferences::of(&config),
+            config,
+            updates,
+            snapshots: vec![],
+            snapshot_notice: None,
+            sync_notice: None,
+            demo_notice: None,
+            preferences_notice: None,
+        };
+        tab.reload_snapshots();
+        tab
+    }
+
+    /// the saved theme, the app uses it for all tabs
+    pub fn theme(&self) -> ThemeChoice {
+        self.config.theme
+    }
+
+    pub fn update(&mut self, message: SettingsMessage) -> Command<Message> {
+        let prefs = &mut self.preferences;
+        match message {
+            SettingsMessage::ThemeSelected(theme) => prefs.theme = theme,
+            SettingsMessage::ConfigDirChanged(dir) => prefs.config_dir = dir,
+            SettingsMessage::DatabasePathChanged(path) => prefs.database_path = path,
+            SettingsMessage::DefaultBlueprintChanged(name) => prefs.default_blueprint = name,
+            SettingsMessage::OptionsPerValueChanged(n) => prefs.options_per_value = n,
+            SettingsMessage::SavePreferences => self.save_preferences(),
+            SettingsMessage::CheckUpdates => return self.check_updates(),
+            SettingsMessage::UpdatesChecked(res) => {
+                self.updates = match res {
+                    Ok(report) => UpdateState::Checked(report),
+                    Err(e) => UpdateState::Error(e),
+                }
+            }
+            SettingsMessage::Sync => self.sync(),
+            SettingsMessage::Demo(enter) => {
+                let res = if enter { demo::enter() } else { demo::leave() };
+                self.demo_notice = Some(match res {
+                    Ok(()) if enter => "The demo campaign was loaded. Refresh the other tabs to \
+                                        explore it. Changes to it are not kept."
+                        .into(),
+                    Ok(()) => {
+                        "Your campaign was opened again. Refresh the other tabs to see it.".into()
+                    }
+                    Err(e) => format!("Switching the campaign failed:\n{:#}", e),
+                });
+            }
+            SettingsMessage::InstallPack(name) => {
+                if let UpdateState::Checked(report) = &self.updates {
+                    let release = report
+                        .packs
+                        .iter()
+                        .filter_map(|(_, status)| status.as_ref().ok())
+                        .find(|status| status.release.name == name)
+                        .map(|status| status.release.clone());
+                    if let (Some(release), Some(url)) =
+                        (release, self.config.update_manifest.clone())
+                    {
+                        self.updates = UpdateState::Checking;
+                        return load_async(
+                            move || {
+                                updates::install_pack(&release)?;
+                                updates::check_updates(&url)
+                            },
+                            |res| Message::SettingsMsg(SettingsMessage::UpdatesChecked(res)),
+                        );
+                    }
+                }
+            }
+            SettingsMessage::TakeSnapshot => {
+                self.snapshot_notice = Some(match snapshots::take(self.config.snapshot_count) {
+                    Ok(_) => "Snapshot taken".into(),
+                    Err(e) => format!("Taking the snapshot failed:\n{:#}", e),
+                });
+                self.reload_snapshots();
+            }
+            SettingsMessage::RestoreSnapshot(path) => {
+                if let Some(snapshot) = self.snapshots.iter().find(|s| s.path == path) {
+                    let res = snapshots::restore(snapshot, self.config.snapshot_count);
+                    self.snapshot_notice = Some(match res {
+                        Ok(()) => format!(
+                            "Restored the snapshot from {}. The previous state was saved as a \
+                             snapshot. Refresh the other tabs to see the restored data.",
+                            format_age(snapshot.created)
+                        ),
+                        Err(e) => format!("Restoring the snapshot failed:\n{:#}", e),
+                    });
+                    self.reload_snapshots();
+                }
+            }
+        }
+        Command::none()
+    }
+
+    fn save_preferences(&mut self) {
+        let res = self
+            .preferences
+            .apply(self.config.clone())
+            .and_then(|config| {
+                config.save()?;
+                Ok(config)
+            });
+        self.preferences_notice = Some(match res {
+            Ok(config) => {
+                self.config = config;
+                "Saved. The config dir and the database path are used after a restart, the \
+                 NPC generator uses the other settings once it is refreshed."
+                    .into()
+            }
+            Err(e) => format!("Saving the settings failed:\n{:#}", e),
+        });
+    }
+
+    fn sync(&mut self) {
+        if let Some(dir) = &self.config.sync_dir {
+            self.sync_notice = Some(match sync::sync(Path::new(dir)) {
+                Ok(report) => format!(
+                    "Sent {} changes, {} nodes were changed by other devices. Refresh the other \
+                     tabs to see them.",
+                    report.sent, report.received
+                ),
+                Err(e) => format!("Syncing failed:\n{:#}", e),
+            });
+        }
+    }
+
+    fn reload_snapshots(&mut self) {
+        match snapshots::list() {
+            Ok(list) => self.snapshots = list,
+            Err(e) => self.snapshot_notice = Some(format!("{:#}", e)),
+        }
+    }
+
+    fn check_updates(&mut self) -> Command<Message> {
+        match self.config.update_manifest.clone() {
+            Some(url) => {
+                self.updates = UpdateState::Checking;
+                load_async(
+                    move || updates::check_updates(&url),
+                    |res| Message::SettingsMsg(SettingsMessage::UpdatesChecked(res)),
+                )
+            }
+            None => Command::none(),
+        }
+    }
+}
+
+impl Tab for SettingsTab {
+    type Message = Message;
+
+    fn tab_label(&self) -> TabLabel {
+        TabLabel::Text("Settings".into())
+    }
+
+    fn content(&self) -> Element<'_, Self::Message> {
+        let content: Element<'_, SettingsMessage> = column!(
+            Text::new(format!("campman version {}", APP_VERSION)).size(24),
+            render_preferences(&self.preferences, self.preferences_notice.as_deref()),
+            render_updates(self.config.update_manifest.is_some(), &self.updates),
+            render_snapshots(&self.snapshots, self.snapshot_notice.as_deref()),
+            render_sync(self.config.sync_dir.as_deref(), self.sync_notice.as_deref()),
+            render_demo(self.demo_notice.as_deref()),
+            render_diagnostics()
+        )
+        .spacing(20)
+        .into();
+        content.map(Message::SettingsMsg)
+    }
+}
+
+impl Preferences {
+    fn of(config: &Config) -> Preferences {
+        let text = |val: &Option<String>| val.clone().unwrap_or_default();
+        Preferences {
+            theme: config.theme,
+            config_dir: text(&config.config_dir),
+            database_path: text(&config.database_path),
+            default_blueprint: text(&config.default_blueprint),
+            options_per_value: config.options_per_value.to_string(),
+        }
+    }
+
+    /// the config with these preferences
+    fn apply(&self, mut config: Config) -> Result<Config> {
+        let optional = |val: &str| Some(val.trim().to_string()).filter(|v| !v.is_empty());
+        config.theme = self.theme;
+        config.config_dir = optional(&self.config_dir);
+        config.database_path = optional(&self.database_path);
+        config.default_blueprint = optional(&self.default_blueprint);
+        config.options_per_value = match self.options_per_value.trim().parse() {
+            Ok(n) if n > 0 => n,
+            _ => bail!(
+                "The options per value must be a positive number, not {}",
+                self.options_per_value
+            ),
+        };
+        Ok(config)
+    }
+}
+
+fn render_preferences<'a>(
+    prefs: &'a Preferences,
+    notice: Option<&'a str>,
+) -> Element<'a, SettingsMessage> {
+    let theme_button = |label: &'a str, theme: ThemeChoice| {
+        let b = Button::new(label).on_press(SettingsMessage::ThemeSelected(theme));
+        if prefs.theme == theme {
+            b.style(ButtonTheme::Positive)
+        } else {
+            b
+        }
+    };
+    let input = |label: &'a str,
+                 hint: &'a str,
+                 value: &'a str,
+                 on_change: fn(String) -> SettingsMessage| {
+        row!(
+            Text::new(label).width(Length::Units(200)),
+            TextInput::new(hint, value, on_change)
+                .on_submit(SettingsMessage::SavePreferences)
+                .padding(5)
+                .width(Length::Fill)
+        )
+        .spacing(10)
+        .align_items(Alignment::Center)
+    };
+    let mut col = column!(
+        Text::new("Preferences:").size(24),
+        row!(
+            Text::new("Theme").width(Length::Units(200)),
+            theme_button("Light", ThemeChoice::Light),
+            theme_button("Dark", ThemeChoice::Dark),
+            theme_button("High contrast", ThemeChoice::HighContrast)
+        )
+        .spacing(10)
+        .align_items(Alignment::Center),
+        input(
+            "Config dir",
+            "The directory of config.toml",
+            &prefs.config_dir,
+            SettingsMessage::ConfigDirChanged
+        ),
+        input(
+            "Database",
+            "campaign.db in the data dir",
+            &prefs.database_path,
+            SettingsMessage::DatabasePathChanged
+        ),
+        input(
+            "Default blueprint",
+            "Ask for the blueprint",
+            &prefs.default_blueprint,
+            SettingsMessage::DefaultBlueprintChanged
+        ),
+        input(
+            "Options per value",
+            "3",
+            &prefs.options_per_value,
+            SettingsMessage::OptionsPerValueChanged
+        ),
+        Button::new("Save").on_press(SettingsMessage::SavePreferences)
+    )
+    .spacing(10);
+    if let Some(notice) = notice {
+        col = col.push(Text::new(notice));
+    }
+    col.into()
+}
+
+fn render_snapshots<'a>(
+    list: &'a [Snapshot],
+    notice: Option<&'a str>,
+) -> Element<'a, SettingsMessage> {
+    let rows = list.iter().map(|snapshot| {
+        row!(
+            Text::new(format_age(snapshot.created)).width(Length::Fill),
+            Button::new("Restore")
+                .on_press(SettingsMessage::RestoreSnapshot(snapshot.path.clone()))
+        )
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .into()
+    });
+    let mut col = column!(
+        Text::new("Snapshots:").size(24),
+        Button::new("Take Snapshot Now").on_press(SettingsMessage::TakeSnapshot)
+    )
+    .spacing(10);
+    if let Some(notice) = notice {
+        col = col.push(Text::new(notice));
+    }
+    col.push(Scrollable::new(
+        Column::with_children(rows.collect()).spacing(5),
+    ))
+    .into()
+}
+
+fn render_sync<'a>(dir: Option<&'a str>, notice: Option<&'a str>) -> Element<'a, SettingsMessage> {
+    let mut col = column!(Text::new("Sync:").size(24)).spacing(10);
+    match dir {
+        Some(dir) => {
+            col = col.push(
+                row!(
+                    Text::new(format!("Changes are shared through {}", dir)).width(Length::Fill),
+                    Button::new("Sync Now").on_press(SettingsMessage::Sync)
+                )
+                .spacing(10)
+                .align_items(Alignment::Center),
+            )
+        }
+        None => col = col.push(Text::new("Set sync-dir in config.toml to enable syncing")),
+    }
+    if let Some(notice) = notice {
+        col = col.push(Text::new(notice));
+    }
+    col.into()
+}
+
+fn render_demo(notice: Option<&str>) -> Element<'_, SettingsMessage> {
+    let button = if demo::is_active() {
+        Button::new("Back to My Campaign").on_press(SettingsMessage::Demo(false))
+    } else {
+        Button::new("Load Demo Campaign").on_press(SettingsMessage::Demo(true))
+    };
+    let mut col = column!(
+        Text::new("Demo:").size(24),
+        row!(
+            Text::new("A small campaign with NPCs, places, notes and links to try things out")
+                .width(Length::Fill),
+            button
+        )
+        .spacing(10)
+        .align_items(Alignment::Center)
+    )
+    .spacing(10);
+    if let Some(notice) = notice {
+        col = col.push(Text::new(notice));
+    }
+    col.into()
+}
+
+fn render_diagnostics<'a>() -> Element<'a, SettingsMessage> {
+    let cache = options_cache::stats();
+    column!(
+        Text::new("Diagnostics:").size(24),
+        Text::new(format!(
+            "Option file cache: {} files with {} options, {} hits, {} misses",
+            cache.files, cache.options, cache.hits, cache.misses
+        ))
+    )
+    .spacing(10)
+    .into()
+}
+
+fn format_age(created: u64) -> String {
+    let minutes = snapshots::now().saturating_sub(created) / 60;
+    match minutes {
+        0 => "just now".into(),
+        m if m < 60 => format!("{} minutes ago", m),
+        m if m < 60 * 24 => format!("{} hours ago", m / 60),
+        m => format!("{} days ago", m / (60 * 24)),
+    }
+}
+
+fn render_updates(enabled: bool, state: &UpdateState) -> Element<'_, SettingsMessage> {
+    if !enabled {
+        return Text::new("Set update-manifest in config.toml to enable update checks").into();
+    }
+    let check_button = Button::new("Check for Updates").on_press(SettingsMessage::CheckUpdates);
+    match state {
+        UpdateState::NotChecked => check_button.into(),
+        UpdateState::Checking => Text::new("Checking for updates...").into(),
+        UpdateState::Error(e) => column!(
+            Text::new(format!("Update check failed:\n{}", e)),
+            check_button
+        )
+        .spacing(10)
+        .into(),
+        UpdateState::Checked(report) => {
+            let app_line = match &report.app_update {
+                Some(release) => format!(
+                    "campman {} is available{}",
+                    release.version,
+                    release
+                        .url
+                        .as_ref()
+                        .map(|u| format!(" at {}", u))
+                        .unwrap_or_default()
+                ),
+                None => "campman is up to date".into(),
+            };
+            let pack_rows = report.packs.iter().map(|(name, status)| {
+                let status = match status {
+                    Ok(status) => status,
+                    Err(e) => return Text::new(format!("{}: check failed:\n{}", name, e)).into(),
+                };
+                let installed = status.installed.as_deref().unwrap_or("not installed");
+                let label = format!(
+                    "{}: {} (available: {})",
+                    name, installed, status.release.version
+                );
+                let mut r = row!(Text::new(label).width(Length::Fill))
+                    .spacing(10)
+                    .align_items(Alignment::Center);
+                if status.update_available {
+                    let button_text = if status.installed.is_some() {
+                        "Update"
+                    } else {
+                        "Install"
+                    };
+                    r = r.push(
+                        Button::new(button_text)
+                            .on_press(SettingsMessage::InstallPack(name.clone())),
+                    );
+                }
+                r.into()
+            });
+            column!(
+                Text::new(app_line),
+                Text::new("Blueprint Packs:").size(24),
+                Column::with_children(pack_rows.collect()).spacing(10),
+                check_button
+            )
+            .spacing(10)
+            .into()
+        }
+    }
+}
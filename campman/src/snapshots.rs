@@ -0,0 +1,97 @@
+//! Periodic copies of the campaign database. The newest snapshots are kept in
+//! `<data_dir>/campman/snapshots/`, and any of them can be restored from the settings tab.
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{database, demo, DATA_DIR};
+
+const PREFIX: &str = "campaign-";
+const EXTENSION: &str = "db";
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    /// unix timestamp of the creation
+    pub created: u64,
+}
+
+pub fn snapshots_dir() -> PathBuf {
+    DATA_DIR.get().unwrap().join("campman/snapshots")
+}
+
+/// all snapshots, the newest first
+pub fn list() -> Result<Vec<Snapshot>> {
+    let dir = snapshots_dir();
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut res = vec![];
+    for entry in std::fs::read_dir(&dir).context(dir.display().to_string())? {
+        let path = entry?.path();
+        if let Some(created) = parse_timestamp(&path) {
+            res.push(Snapshot { path, created });
+        }
+    }
+    res.sort_by_key(|s| Reverse(s.created));
+    Ok(res)
+}
+
+/// copies the database into a new snapshot, and deletes the oldest ones, so that only keep
+/// snapshots remain
+pub fn take(keep: usize) -> Result<Snapshot> {
+    ensure!(!demo::is_active(), "The demo campaign can't be snapshotted");
+    let dir = snapshots_dir();
+    std::fs::create_dir_all(&dir).context(dir.display().to_string())?;
+    let created = now();
+    let path = dir.join(format!("{}{}.{}", PREFIX, created, EXTENSION));
+    database().backup_to(&path)?;
+    for old in list()?.iter().skip(keep.max(1)) {
+        std::fs::remove_file(&old.path).context(old.path.display().to_string())?;
+    }
+    Ok(Snapshot { path, created })
+}
+
+/// replaces the campaign with the snapshot. The current state is snapshotted before, so a
+/// restore can be undone.
+pub fn restore(snapshot: &Snapshot, keep: usize) -> Result<()> {
+    // the snapshot could be rotated away by the safety snapshot otherwise
+    let tmp = snapshots_dir().join("restoring.tmp");
+    std::fs::copy(&snapshot.path, &tmp).context(snapshot.path.display().to_string())?;
+    let res = take(keep + 1).and_then(|_| database().restore_from(&tmp));
+    std::fs::remove_file(&tmp)?;
+    res
+}
+
+/// takes a snapshot every interval on a background thread
+pub fn start_periodic(interval: Duration, keep: usize) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        if demo::is_active() {
+            continue;
+        }
+        if let Err(e) = take(keep) {
+            eprintln!("Taking a snapshot failed: {:#}", e);
+        }
+    });
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn parse_timestamp(path: &Path) -> Option<u64> {
+    if path.extension()? != EXTENSION {
+        return None;
+    }
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix(PREFIX)?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,207 @@
+//! The quick switcher, opened with Ctrl+K from anywhere in the app. It finds nodes by name, tabs
+//! and NPC generators with a fuzzy search, so everything is a few keystrokes away, no matter how
+//! many tabs there are.
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use iced::keyboard::{self, KeyCode};
+use iced::theme::Button as ButtonTheme;
+use iced::widget::{column, text_input, Button, Text, TextInput};
+use iced::{event, Command, Element, Event, Length, Subscription};
+
+use crate::database;
+
+/// the number of results that are shown
+const MAX_RESULTS: usize = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    OpenNode(i64),
+    SwitchTab(usize),
+    /// generates an NPC with the blueprint of that name
+    Generate(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub label: String,
+    pub action: Action,
+}
+
+#[derive(Debug, Default)]
+pub struct Switcher {
+    query: String,
+    entries: Vec<Entry>,
+    /// indices into entries, best match first
+    results: Vec<usize>,
+    /// index into results
+    selection: usize,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SwitcherMessage {
+    Open,
+    QueryChanged(String),
+    Next,
+    Previous,
+    /// runs the action of the selected result
+    Submit,
+    /// runs the action of the result with that index
+    Pick(usize),
+    Cancel,
+}
+
+impl Switcher {
+    /// the entries are the given ones, followed by all nodes of the database
+    pub fn new(mut entries: Vec<Entry>) -> Switcher {
+        let mut error = None;
+        match database().select_node_names() {
+            Ok(nodes) => entries.extend(nodes.into_iter().map(|(id, name, r#type)| Entry {
+                label: format!("{} ({})", name, r#type),
+                action: Action::OpenNode(id),
+            })),
+            Err(e) => error = Some(format!("{:#}", e)),
+        }
+        let results = rank(&entries, "");
+        Switcher {
+            entries,
+            results,
+            error,
+            ..Default::default()
+        }
+    }
+
+    /// handles the messages that edit the switcher. Open, Submit, Pick and Cancel are handled
+    /// by the owner of the switcher.
+    pub fn update(&mut self, message: SwitcherMessage) {
+        match message {
+            SwitcherMessage::QueryChanged(query) => {
+                self.results = rank(&self.entries, &query);
+                self.query = query;
+                self.selection = 0;
+            }
+            SwitcherMessage::Next if self.selection + 1 < self.results.len() => self.selection += 1,
+            SwitcherMessage::Previous => self.selection = self.selection.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    pub fn selection(&self) -> usize {
+        self.selection
+    }
+
+    /// the action of the result with that index
+    pub fn action(&self, result: usize) -> Option<Action> {
+        let idx = self.results.get(result)?;
+        Some(self.entries[*idx].action.clone())
+    }
+
+    pub fn view(&self) -> Element<'_, SwitcherMessage> {
+        let mut col = column!(
+            Text::new("Go to").size(32),
+            TextInput::new(
+                "Node, tab or generator",
+                &self.query,
+                SwitcherMessage::QueryChanged
+            )
+            .id(query_input_id())
+            .on_submit(SwitcherMessage::Submit)
+            .padding(5),
+        )
+        .spacing(5)
+        .max_width(600);
+        for (i, idx) in self.results.iter().enumerate() {
+            let style = if i == self.selection {
+                ButtonTheme::Primary
+            } else {
+                ButtonTheme::Secondary
+            };
+            col = col.push(
+                Button::new(Text::new(&self.entries[*idx].label))
+                    .on_press(SwitcherMessage::Pick(i))
+                    .style(style)
+                    .width(Length::Fill),
+            );
+        }
+        if let Some(e) = &self.error {
+            col = col.push(Text::new(e));
+        }
+        col.into()
+    }
+}
+
+/// the indices of the entries whose label matches the query, best match first. An empty query
+/// matches all entries in their order
+pub fn rank(entries: &[Entry], query: &str) -> Vec<usize> {
+    let query = query.trim();
+    if query.is_empty() {
+        return (0..entries.len()).take(MAX_RESULTS).collect();
+    }
+    let matcher = SkimMatcherV2::default();
+    let mut scored: Vec<(i64, usize)> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| matcher.fuzzy_match(&e.label, query).map(|score| (score, i)))
+        .collect();
+    scored.sort_by_key(|(score, i)| (std::cmp::Reverse(*score), *i));
+    scored
+        .into_iter()
+        .take(MAX_RESULTS)
+        .map(|(_, i)| i)
+        .collect()
+}
+
+/// focuses the query input, so the query can be typed right after opening the switcher
+pub fn focus_query_input<Message: 'static>() -> Command<Message> {
+    text_input::focus(query_input_id())
+}
+
+fn query_input_id() -> text_input::Id {
+    text_input::Id::new("switcher-query")
+}
+
+/// Ctrl+K opens the switcher, the arrow keys move the selection, and Escape closes it again
+pub fn shortcuts() -> Subscription<SwitcherMessage> {
+    iced::subscription::events_with(|event, _status: event::Status| match event {
+        Event::Keyboard(keyboard::Event::KeyPressed {
+            key_code,
+            modifiers,
+        }) => match key_code {
+            KeyCode::K if modifiers.control() => Some(SwitcherMessage::Open),
+            KeyCode::Down => Some(SwitcherMessage::Next),
+            KeyCode::Up => Some(SwitcherMessage::Previous),
+            KeyCode::Escape => Some(SwitcherMessage::Cancel),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(labels: &[&str]) -> Vec<Entry> {
+        labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| Entry {
+                label: label.to_string(),
+                action: Action::OpenNode(i as i64),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rank() {
+        let entries = entries(&[
+            "Go to Gen NPC",
+            "Bartender Bob (npc)",
+            "The Rusty Nail (place)",
+        ]);
+        assert_eq!(rank(&entries, ""), vec![0, 1, 2]);
+        assert_eq!(rank(&entries, "bob"), vec![1]);
+        assert_eq!(rank(&entries, "rstyn")[0], 2);
+        assert!(rank(&entries, "xyz").is_empty());
+    }
+}
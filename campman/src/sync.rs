@@ -0,0 +1,422 @@
+//! Syncing between devices through a shared directory, like a Dropbox folder or a git repo.
+//! Every device appends the changes it made since the last sync to its own log file in that
+//! directory, so the files never conflict. Syncing then merges the logs of all devices: the
+//! newest change of every node field wins, and links are never removed, only added.
+use std::collections::{BTreeSet, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{ensure, Context, Result};
+use database::db::{Node, SyncedNode, DB};
+use database::meta::{self, Meta};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{database, demo, DATA_DIR};
+
+const LOG_EXTENSION: &str = "jsonl";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// unix time in milliseconds
+    time: u64,
+    device: String,
+    change: Change,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum Change {
+    Node {
+        uid: String,
+        r#type: String,
+        field: Field,
+    },
+    Link {
+        left: String,
+        right: String,
+        r#type: String,
+        data: Option<Vec<u8>>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Field {
+    Name(String),
+    Meta(#[serde(deserialize_with = "meta::deserialize_lenient")] Meta),
+    Data(Vec<u8>),
+    Deleted(bool),
+}
+
+/// orders changes of the same field. The device breaks ties, so all devices pick the same one
+type Stamp = (u64, String);
+
+/// the newest value of a field, and when it was set
+type Lww<T> = Option<(Stamp, T)>;
+
+#[derive(Debug, Default)]
+struct MergedNode {
+    r#type: String,
+    name: Lww<String>,
+    meta: Lww<Meta>,
+    data: Lww<Vec<u8>>,
+    deleted: Lww<bool>,
+}
+
+type LinkKey = (String, String, String);
+
+#[derive(Debug, Default)]
+struct Merged {
+    nodes: HashMap<String, MergedNode>,
+    links: HashMap<LinkKey, Option<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+pub struct SyncReport {
+    /// changes that were written to the log of this device
+    pub sent: usize,
+    /// nodes that were created or changed because of changes of other devices
+    pub received: usize,
+}
+
+/// writes the local changes to the log of this device, and applies the changes of all
+/// other devices
+pub fn sync(dir: &Path) -> Result<SyncReport> {
+    ensure!(!demo::is_active(), "The demo campaign can't be synced");
+    std::fs::create_dir_all(dir).context(dir.display().to_string())?;
+    let device = device_id()?;
+    let mut merged = Merged::default();
+    for entry in &read_logs(dir)? {
+        merged.add(entry);
+    }
+
+    database().in_transaction(|db| {
+        let new_entries = local_changes(db, &device, &merged)?;
+        append_log(
+            &dir.join(format!("{}.{}", device, LOG_EXTENSION)),
+            &new_entries,
+        )?;
+        for entry in &new_entries {
+            merged.add(entry);
+        }
+        let received = apply(db, &device, &merged)?;
+        Ok(SyncReport {
+            sent: new_entries.len(),
+            received,
+        })
+    })
+}
+
+/// compares the nodes and links with their state at the last sync
+fn local_changes(db: &mut DB, device: &str, merged: &Merged) -> Result<Vec<Entry>> {
+    let synced: HashMap<i64, SyncedNode> = db
+        .select_synced_nodes()?
+        .into_iter()
+        .filter_map(|s| s.node.map(|id| (id, s)))
+        .collect();
+    let time = now_millis();
+    let mut entries = vec![];
+    let mut push = |uid: &str, r#type: &str, field: Field| {
+        entries.push(Entry {
+            time,
+            device: device.into(),
+            change: Change::Node {
+                uid: uid.into(),
+                r#type: r#type.into(),
+                field,
+            },
+        })
+    };
+
+    let mut uids = HashMap::new();
+    for (node, deleted) in db.select_all_nodes()? {
+        match synced.get(&node.id) {
+            Some(s) => {
+                if s.name != node.name {
+                    push(&s.uid, &s.r#type, Field::Name(node.name.clone()));
+                }
+                if s.meta != node.meta {
+                    push(&s.uid, &s.r#type, Field::Meta(node.meta.clone()));
+                }
+                if s.data != node.data {
+                    push(&s.uid, &s.r#type, Field::Data(node.data.clone()));
+                }
+                if s.deleted != deleted {
+                    push(&s.uid, &s.r#type, Field::Deleted(deleted));
+                }
+                uids.insert(node.id, s.uid.clone());
+            }
+            None => {
+                let uid = format!("{}-{}", device, node.id);
+                push(&uid, &node.r#type, Field::Name(node.name.clone()));
+                push(&uid, &node.r#type, Field::Meta(node.meta.clone()));
+                push(&uid, &node.r#type, Field::Data(node.data.clone()));
+                push(&uid, &node.r#type, Field::Deleted(deleted));
+                uids.insert(node.id, uid);
+            }
+        }
+    }
+    // purged nodes are deleted on the other devices
+    for s in synced.values() {
+        if !uids.contains_key(&s.node.unwrap()) && !s.deleted {
+            push(&s.uid, &s.r#type, Field::Deleted(true));
+        }
+    }
+
+    for link in db.select_all_links()? {
+        if let (Some(left), Some(right)) = (uids.get(&link.left), uids.get(&link.right)) {
+            let key = (left.clone(), right.clone(), link.r#type.clone());
+            if !merged.links.contains_key(&key) {
+                entries.push(Entry {
+                    time,
+                    device: device.into(),
+                    change: Change::Link {
+                        left: key.0,
+                        right: key.1,
+                        r#type: key.2,
+                        data: link.data,
+                    },
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// brings the database to the merged state, and returns how many nodes were touched
+fn apply(db: &mut DB, device: &str, merged: &Merged) -> Result<usize> {
+    let synced: HashMap<String, SyncedNode> = db
+        .select_synced_nodes()?
+        .into_iter()
+        .map(|s| (s.uid.clone(), s))
+        .collect();
+    let local: HashMap<i64, (Node, bool)> = db
+        .select_all_nodes()?
+        .into_iter()
+        .map(|(n, deleted)| (n.id, (n, deleted)))
+        .collect();
+    let mut ids = HashMap::new();
+    let mut received = 0;
+
+    for (uid, m) in &merged.nodes {
+        let (name, meta, data, deleted) = match (&m.name, &m.meta, &m.data, &m.deleted) {
+            (Some(name), Some(meta), Some(data), Some(deleted)) => {
+                (&name.1, &meta.1, &data.1, deleted.1)
+            }
+            // the creation of the node was not written completely
+            _ => continue,
+        };
+        let local_id = match synced.get(uid) {
+            Some(s) => s.node,
+            // nodes of this device that were synced for the first time just now
+            None => uid
+                .strip_prefix(&format!("{}-", device))
+                .and_then(|id| id.parse().ok()),
+        };
+        let existing = local_id.and_then(|id| local.get(&id));
+        let id = match existing {
+            Some((node, is_deleted)) => {
+                let mut changed = false;
+                if &node.name != name {
+                    db.rename_node(node.id, name)?;
+                    changed = true;
+                }
+                if &node.meta != meta || &node.data != data {
+                    db.update_node(node.id, meta, data)?;
+                    changed = true;
+                }
+                if *is_deleted != deleted {
+                    if deleted {
+                        db.delete_node(node.id)?;
+                    } else {
+                        db.restore_node(node.id)?;
+                    }
+                    changed = true;
+                }
+                received += changed as usize;
+                Some(node.id)
+            }
+            // there is no reason to create nodes that are deleted anyway
+            None if deleted => None,
+            None => {
+                received += 1;
+                Some(db.insert_node(name, &m.r#type, meta, data)?)
+            }
+        };
+        if let Some(id) = id {
+            ids.insert(uid.clone(), id);
+        }
+        db.save_synced_node(&SyncedNode {
+            uid: uid.clone(),
+            node: id,
+            name: name.clone(),
+            r#type: m.r#type.clone(),
+            meta: meta.clone(),
+            data: data.clone(),
+            deleted,
+        })?;
+    }
+
+    for ((left, right, r#type), data) in &merged.links {
+        if let (Some(left), Some(right)) = (ids.get(left), ids.get(right)) {
+            if !db.has_link(*left, *right, r#type)? {
+                db.insert_link(*left, *right, r#type, data.clone())?;
+            }
+        }
+    }
+    Ok(received)
+}
+
+impl Merged {
+    fn add(&mut self, entry: &Entry) {
+        let stamp = (entry.time, entry.device.clone());
+        match &entry.change {
+            Change::Node { uid, r#type, field } => {
+                let node = self.nodes.entry(uid.clone()).or_default();
+                node.r#type = r#type.clone();
+                match field {
+                    Field::Name(v) => assign(&mut node.name, stamp, v),
+                    Field::Meta(v) => assign(&mut node.meta, stamp, v),
+                    Field::Data(v) => assign(&mut node.data, stamp, v),
+                    Field::Deleted(v) => assign(&mut node.deleted, stamp, v),
+                }
+            }
+            Change::Link {
+                left,
+                right,
+                r#type,
+                data,
+            } => {
+                self.links
+                    .entry((left.clone(), right.clone(), r#type.clone()))
+                    .or_insert_with(|| data.clone());
+            }
+        }
+    }
+}
+
+fn assign<T: Clone>(slot: &mut Lww<T>, stamp: Stamp, value: &T) {
+    if slot.as_ref().map(|(s, _)| *s < stamp).unwrap_or(true) {
+        *slot = Some((stamp, value.clone()));
+    }
+}
+
+fn read_logs(dir: &Path) -> Result<Vec<Entry>> {
+    let mut paths: BTreeSet<PathBuf> = BTreeSet::new();
+    for entry in std::fs::read_dir(dir).context(dir.display().to_string())? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map(|e| e == LOG_EXTENSION)
+            .unwrap_or(false)
+        {
+            paths.insert(path);
+        }
+    }
+    let mut entries = vec![];
+    for path in paths {
+        let text = std::fs::read_to_string(&path).context(path.display().to_string())?;
+        let n_lines = text.lines().count();
+        for (i, line) in text.lines().enumerate() {
+            // the last line might still be written by a file sync tool
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) if i + 1 == n_lines => {}
+                Err(e) => return Err(e).with_context(|| format!("{}:{}", path.display(), i + 1)),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn append_log(path: &Path, entries: &[Entry]) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(&serde_json::to_string(entry)?);
+        text.push('\n');
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(text.as_bytes()))
+        .context(path.display().to_string())
+}
+
+/// a random id that is created on the first sync, and identifies this device in the logs
+fn device_id() -> Result<String> {
+    let path = DATA_DIR.get().unwrap().join("campman/device-id");
+    if path.exists() {
+        return Ok(std::fs::read_to_string(&path)?.trim().to_string());
+    }
+    let id = format!("{:08x}", rand::thread_rng().gen::<u32>());
+    std::fs::write(&path, &id).context(path.display().to_string())?;
+    Ok(id)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: u64, device: &str, change: Change) -> Entry {
+        Entry {
+            time,
+            device: device.into(),
+            change,
+        }
+    }
+
+    fn rename(uid: &str, name: &str) -> Change {
+        Change::Node {
+            uid: uid.into(),
+            r#type: "npc".into(),
+            field: Field::Name(name.into()),
+        }
+    }
+
+    fn link(data: u8) -> Change {
+        Change::Link {
+            left: "a-1".into(),
+            right: "a-2".into(),
+            r#type: "knows".into(),
+            data: Some(vec![data]),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let entries = [
+            entry(2, "b", rename("a-1", "Newer")),
+            entry(1, "a", rename("a-1", "Older")),
+            entry(2, "a", rename("a-1", "Tie")),
+            entry(1, "a", link(1)),
+            entry(3, "b", link(2)),
+        ];
+        // the order in which the logs are read must not matter
+        for order in [[0, 1, 2, 3, 4], [4, 3, 2, 1, 0]] {
+            let mut merged = Merged::default();
+            for i in order {
+                merged.add(&entries[i]);
+            }
+            let name = &merged.nodes["a-1"].name.as_ref().unwrap().1;
+            assert_eq!(name, "Newer");
+            assert_eq!(merged.links.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use database::db::Node;
+use iced::widget::{column, row, Button, Column, Scrollable, Text};
+use iced::{Alignment, Command, Element, Length};
+use iced_aw::TabLabel;
+
+use super::{Message, Tab};
+use crate::config::Config;
+use crate::database;
+use crate::iced_utils::{load_async, render_error, render_loading};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+pub struct TrashTab {
+    state: State,
+}
+
+enum State {
+    /// the trash is loaded in the background
+    Loading,
+    Error(String),
+    /// the deleted nodes with the unix timestamp of their deletion
+    List(Vec<(Node, i64)>),
+    ConfirmPurge(Node),
+}
+
+#[derive(Debug, Clone)]
+pub enum TrashMessage {
+    Refresh,
+    /// the deleted nodes with the unix timestamp of their deletion
+    Loaded(Result<Vec<(Node, i64)>, String>),
+    Restore(i64),
+    /// asks for confirmation before deleting the node for good
+    PreparePurge(i64),
+    ConfirmPurge,
+}
+
+impl TrashTab {
+    /// the tab, and the command that purges the expired nodes, and loads the remaining ones
+    pub fn new() -> (TrashTab, Command<Message>) {
+        let tab = TrashTab {
+            state: State::Loading,
+        };
+        let load = load_async(
+            || {
+                purge_expired()?;
+                database().select_deleted_nodes()
+            },
+            |res| Message::TrashMsg(TrashMessage::Loaded(res)),
+        );
+        (tab, load)
+    }
+
+    pub fn update(&mut self, message: TrashMessage) -> Command<Message> {
+        self.inner_update(message).unwrap_or_else(|e| {
+            self.state = State::Error(format!("{:#}", e));
+            Command::none()
+        })
+    }
+
+    /// shows the loading screen until the deleted nodes are loaded
+    fn refresh(&mut self) -> Command<Message> {
+        self.state = State::Loading;
+        load_async(
+            || database().select_deleted_nodes(),
+            |res| Message::TrashMsg(TrashMessage::Loaded(res)),
+        )
+    }
+
+    fn inner_update(&mut self, message: TrashMessage) -> Result<Command<Message>> {
+        use TrashMessage::*;
+        match message {
+            Refresh => return Ok(self.refresh()),
+            Loaded(res) => {
+                self.state = match res {
+                    Ok(nodes) => State::List(nodes),
+                    Err(e) => State::Error(e),
+                }
+            }
+            Restore(id) => {
+                database().restore_node(id)?;
+                return Ok(self.refresh());
+            }
+            PreparePurge(id) => {
+                if let State::List(nodes) = &self.state {
+                    if let Some((node, _)) = nodes.iter().find(|(n, _)| n.id == id) {
+                        self.state = State::ConfirmPurge(node.clone());
+                    }
+                }
+            }
+            ConfirmPurge => {
+                if let State::ConfirmPurge(node) = &self.state {
+                    database().purge_node(node.id)?;
+                    return Ok(self.refresh());
+                }
+            }
+        }
+        Ok(Command::none())
+    }
+}
+
+/// deletes everything that has been in the trash for longer than the configured retention period
+fn purge_expired() -> Result<usize> {
+    let retention = Config::load()?.trash_retention_days as i64 * SECS_PER_DAY;
+    database().purge_deleted_before(now() - retention)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+impl Tab for TrashTab {
+    type Message = Message;
+
+    fn tab_label(&self) -> TabLabel {
+        TabLabel::Text("Trash".into())
+    }
+
+    fn content(&self) -> Element<'_, Self::Message> {
+        let content: Element<'_, TrashMessage> = match &self.state {
+            State::Loading => render_loading(),
+            State::Error(e) => render_error(
+                e,
+                Button::new("Back to Trash").on_press(TrashMessage::Refresh),
+            )
+            .into(),
+            State::List(nodes) => render_list(nodes),
+            State::ConfirmPurge(node) => column!(
+                Text::new(format!(
+                    "Delete {} permanently? This can't be undone.",
+                    node.name
+                ))
+                .size(24),
+                row!(
+                    Button::new("Delete Permanently").on_press(TrashMessage::ConfirmPurge),
+                    Button::new("Cancel").on_press(TrashMessage::Refresh)
+                )
+                .spacing(10)
+            )
+            .spacing(20)
+            .into(),
+        };
+        content.map(Message::TrashMsg)
+    }
+}
+
+fn render_list(nodes: &[(Node, i64)]) -> Element<'_, TrashMessage> {
+    let now = now();
+    let rows = nodes.iter().map(|(node, deleted_at)| {
+        let days = (now - deleted_at) / SECS_PER_DAY;
+        let label = format!(
+            "{} ({}), deleted {} day{} ago",
+            node.name,
+            node.r#type,
+            days,
+            if days == 1 { "" } else { "s" }
+        );
+        row!(
+            Text::new(label).width(Length::Fill),
+            Button::new("Restore").on_press(TrashMessage::Restore(node.id)),
+            Button::new("Delete Permanently").on_press(TrashMessage::PreparePurge(node.id))
+        )
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .into()
+    });
+    let list: Element<'_, TrashMessage> = if nodes.is_empty() {
+        Text::new("The trash is empty").into()
+    } else {
+        Scrollable::new(Column::with_children(rows.collect()).spacing(5)).into()
+    };
+    column!(Button::new("Refresh").on_press(TrashMessage::Refresh), list)
+        .spacing(10)
+        .into()
+}
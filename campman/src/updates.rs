@@ -0,0 +1,197 @@
+//! Checks for new versions of campman and of the installed blueprint packs.
+//!
+//! A blueprint pack is a directory in `<conf_dir>/packs/` which contains option files (and
+//! anything else a blueprint may reference), plus a pack.toml with the name and version of the
+//! pack. The manifest is a toml file that lists the current releases:
+//!
+//! ```toml
+//! [apps.campman]
+//! version = "0.2.0"
+//! url = "https://example.com/campman"
+//!
+//! [[packs]]
+//! name = "fantasy-names"
+//! version = "1.1.0"
+//! base-url = "https://example.com/packs/fantasy-names/"
+//! files = ["elves.txt", "dwarves.txt"]
+//! ```
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::conf_dir;
+
+pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+const PACK_INFO_FILE: &str = "pack.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    #[serde(default)]
+    pub apps: HashMap<String, AppRelease>,
+    #[serde(default)]
+    pub packs: Vec<PackRelease>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppRelease {
+    pub version: String,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackRelease {
+    pub name: String,
+    pub version: String,
+    /// the url of every file is base_url + file name
+    pub base_url: String,
+    pub files: Vec<String>,
+}
+
+/// the contents of the pack.toml of an installed pack
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PackInfo {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateReport {
+    /// contains the release, if it is newer than the running binary
+    pub app_update: Option<AppRelease>,
+    /// one entry per pack of the manifest, with the reason if its state couldn't be determined,
+    /// so a broken pack doesn't hide the others
+    pub packs: Vec<(String, Result<PackStatus, String>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackStatus {
+    pub release: PackRelease,
+    /// the installed version, None if the pack is not installed
+    pub installed: Option<String>,
+    pub update_available: bool,
+}
+
+pub fn packs_dir() -> PathBuf {
+    conf_dir().join("packs")
+}
+
+pub fn check_updates(manifest_url: &str) -> Result<UpdateReport> {
+    let manifest = fetch_manifest(manifest_url)?;
+    let app_update = match manifest.apps.get(APP_NAME) {
+        Some(release) if is_newer(&release.version, APP_VERSION)? => Some(release.clone()),
+        _ => None,
+    };
+    let packs = manifest
+        .packs
+        .into_iter()
+        .map(|release| {
+            let name = release.name.clone();
+            let status = pack_status(release).map_err(|e| format!("{:#}", e));
+            (name, status)
+        })
+        .collect();
+    Ok(UpdateReport { app_update, packs })
+}
+
+/// compares the release with the installed version of the pack, which is in the directory
+/// that install_pack puts it in
+fn pack_status(release: PackRelease) -> Result<PackStatus> {
+    ensure!(
+        is_plain_relative(Path::new(&release.name)),
+        "Invalid pack name: {}",
+        release.name
+    );
+    Version::parse(&release.version)
+        .with_context(|| format!("Invalid version in the manifest: {}", release.version))?;
+    let info_path = packs_dir().join(&release.name).join(PACK_INFO_FILE);
+    let installed = if info_path.exists() {
+        let text = std::fs::read_to_string(&info_path).context(info_path.display().to_string())?;
+        let info: PackInfo = toml::from_str(&text).context(info_path.display().to_string())?;
+        Some(info.version)
+    } else {
+        None
+    };
+    let update_available = match &installed {
+        Some(version) => is_newer(&release.version, version)
+            .with_context(|| format!("Invalid installed version: {}", version))?,
+        None => true,
+    };
+    Ok(PackStatus {
+        release,
+        installed,
+        update_available,
+    })
+}
+
+/// downloads all files of the release into a fresh directory, and replaces the currently
+/// installed version of the pack with it afterwards, so a failed download doesn't leave a
+/// half updated pack behind.
+pub fn install_pack(release: &PackRelease) -> Result<()> {
+    ensure!(
+        is_plain_relative(Path::new(&release.name)),
+        "Invalid pack name: {}",
+        release.name
+    );
+    let target_dir = packs_dir().join(&release.name);
+    let download_dir = packs_dir().join(format!(".{}.download", release.name));
+    if download_dir.exists() {
+        std::fs::remove_dir_all(&download_dir)?;
+    }
+    std::fs::create_dir_all(&download_dir)?;
+
+    for file in &release.files {
+        ensure!(
+            is_plain_relative(Path::new(file)),
+            "Invalid file name in pack {}: {}",
+            release.name,
+            file
+        );
+        let url = format!("{}{}", release.base_url, file);
+        let target = download_dir.join(file);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&target, fetch_bytes(&url)?).context(target.display().to_string())?;
+    }
+    let info = PackInfo {
+        name: release.name.clone(),
+        version: release.version.clone(),
+    };
+    std::fs::write(download_dir.join(PACK_INFO_FILE), toml::to_string(&info)?)?;
+
+    if target_dir.exists() {
+        std::fs::remove_dir_all(&target_dir)?;
+    }
+    std::fs::rename(&download_dir, &target_dir)?;
+    Ok(())
+}
+
+fn fetch_manifest(url: &str) -> Result<Manifest> {
+    let text = String::from_utf8(fetch_bytes(url)?).context("The manifest is not valid utf8")?;
+    toml::from_str(&text).context("Could not parse the update manifest")
+}
+
+fn fetch_bytes(url: &str) -> Result<Vec<u8>> {
+    let resp = ureq::get(url)
+        .call()
+        .map_err(|e| anyhow!("Request to {} failed: {}", url, e))?;
+    let mut buf = vec![];
+    resp.into_reader().read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn is_newer(available: &str, installed: &str) -> Result<bool> {
+    Ok(Version::parse(available)? > Version::parse(installed)?)
+}
+
+/// makes sure the manifest can't make us write outside of the packs dir
+fn is_plain_relative(p: &Path) -> bool {
+    !p.as_os_str().is_empty() && p.components().all(|c| matches!(c, Component::Normal(_)))
+}
@@ -1,22 +1,371 @@
-use iced::widget::Text;
-use iced::Element;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use database::attachments::{self, Attachment};
+use database::db::Node;
+use iced::widget::image::{self, Image};
+use iced::widget::{column, row, Button, Column, PickList, Row, Scrollable, Text, TextInput};
+use iced::{Alignment, Command, Element, Length};
 use iced_aw::TabLabel;
 
 use super::{Message, Tab};
+use crate::bundle::{self, Bundle, ConflictPolicy};
+use crate::gen_npc_tab::DisplayConfig;
+use crate::iced_utils::{load_async, render_error, render_linked_text, render_loading, render_npc};
+use crate::npc::{FieldKind, Npc};
+use crate::npc_search::{self, Entry, Query};
+use crate::plugins::SharedPlugins;
+use crate::relations::{self, Relation, RELATION_TYPES};
+use crate::wiki_links::{self, Targets};
+use crate::{database, npc_export, npc_store};
+
+pub struct ViewNpcTab {
+    state: State,
+    /// only NPCs that match this are listed
+    query: Query,
+    tag_input: String,
+    bundle_path: String,
+    /// the result of the last export or import
+    notice: Option<String>,
+    /// provides the exporters on the detail page
+    plugins: SharedPlugins,
+    /// the type of the relationship that is added to the current node
+    relation_type: String,
+    /// the name of the node the relationship is added to
+    relation_target: String,
+    /// the file that is attached to the current node
+    attachment_path: String,
+}
+
+enum State {
+    /// the NPCs are loaded in the background
+    Loading,
+    Error(String),
+    List(Vec<Entry>),
+    Detail(DetailPage),
+    ConfirmBulkTag(BulkTag),
+    /// an import whose nodes clash with existing ones, and needs a ConflictPolicy
+    ConfirmImport(Bundle, Vec<String>),
+}
+
+/// adding or removing a tag on many NPCs at once, which is previewed before it is applied
+struct BulkTag {
+    add: bool,
+    tag: String,
+    nodes: Vec<Node>,
+}
 
-pub struct ViewNpcTab;
+struct DetailPage {
+    /// the nodes that were visited to get here, the last one is the current node
+    breadcrumbs: Vec<(i64, String)>,
+    node: Node,
+    /// the decoded fields, if the node is an NPC
+    npc: Option<Npc>,
+    /// the data as text, if the node isn't an NPC
+    notes: Option<String>,
+    /// the nodes that the notes and the text fields of the NPC reference
+    references: Targets,
+    /// saved NPCs don't know their blueprint, so they are displayed in the default order
+    display: DisplayConfig,
+    /// the linked nodes, and the ones that are linked to them
+    relations: Vec<Relation>,
+    /// the attached images, like portraits or token art
+    images: Vec<image::Handle>,
+    /// the names of the attachments that aren't images
+    files: Vec<String>,
+}
 
 #[derive(Debug, Clone)]
 pub enum ViewNpcMessage {
-    None,
+    /// reloads the NPCs, and lists them
+    ShowList,
+    ListLoaded(Result<Vec<Entry>, String>),
+    /// opens a node from the list, which starts a new breadcrumb trail
+    Open(i64),
+    /// follows a link of the current node
+    Follow(i64),
+    /// jumps back to the breadcrumb with the given index
+    Breadcrumb(usize),
+    SearchChanged(String),
+    /// the blueprint to list NPCs of, ANY for all
+    BlueprintFilterChanged(String),
+    /// the tag to list NPCs with, ANY for all
+    TagFilterChanged(String),
+    TagInputChanged(String),
+    /// previews adding (true) or removing (false) the tag on all listed NPCs
+    PrepareBulkTag(bool),
+    ConfirmBulkTag,
+    /// moves the current node to the trash
+    Delete,
+    BundlePathChanged(String),
+    /// exports all listed NPCs with the nodes that are linked to them
+    ExportListed,
+    Import,
+    ConfirmImport(ConflictPolicy),
+    /// exports the current NPC with the plugin exporter of the given name
+    PluginExport(String),
+    RelationTypeSelected(String),
+    RelationTargetChanged(String),
+    /// links the current node to the node with the name of relation_target
+    AddRelation,
+    AttachmentPathChanged(String),
+    /// attaches the file at attachment_path to the current node
+    Attach,
 }
 
 impl ViewNpcTab {
-    pub fn new() -> ViewNpcTab {
-        ViewNpcTab
+    /// the tab, and the command that loads the NPCs
+    pub fn new(plugins: SharedPlugins) -> (ViewNpcTab, Command<Message>) {
+        let mut tab = ViewNpcTab {
+            state: State::Loading,
+            query: Query::default(),
+            tag_input: String::new(),
+            bundle_path: String::new(),
+            notice: None,
+            plugins,
+            relation_type: RELATION_TYPES[0].0.into(),
+            relation_target: String::new(),
+            attachment_path: String::new(),
+        };
+        let load = tab.show_list();
+        (tab, load)
     }
 
-    pub fn update(&mut self, message: ViewNpcMessage) {}
+    pub fn update(&mut self, message: ViewNpcMessage) -> Command<Message> {
+        self.inner_update(message).unwrap_or_else(|e| {
+            self.state = State::Error(format!("{:#}", e));
+            Command::none()
+        })
+    }
+
+    /// shows the loading screen until the NPCs are loaded
+    fn show_list(&mut self) -> Command<Message> {
+        self.state = State::Loading;
+        load_async(
+            || npc_search::load(&mut database()),
+            |res| Message::ViewNpcMsg(ViewNpcMessage::ListLoaded(res)),
+        )
+    }
+
+    fn inner_update(&mut self, message: ViewNpcMessage) -> Result<Command<Message>> {
+        use ViewNpcMessage::*;
+        match message {
+            ShowList => return Ok(self.show_list()),
+            ListLoaded(res) => {
+                self.state = match res {
+                    Ok(entries) => State::List(entries),
+                    Err(e) => State::Error(e),
+                }
+            }
+            Open(id) => {
+                self.notice = None;
+                self.state = State::Detail(DetailPage::load(id, vec![])?);
+            }
+            Follow(id) => {
+                let breadcrumbs = match &self.state {
+                    State::Detail(page) => page.breadcrumbs.clone(),
+                    _ => vec![],
+                };
+                self.state = State::Detail(DetailPage::load(id, breadcrumbs)?);
+            }
+            Breadcrumb(idx) => {
+                if let State::Detail(page) = &self.state {
+                    let mut breadcrumbs = page.breadcrumbs.clone();
+                    breadcrumbs.truncate(idx + 1);
+                    let (id, _) = breadcrumbs.pop().unwrap();
+                    self.state = State::Detail(DetailPage::load(id, breadcrumbs)?);
+                }
+            }
+            SearchChanged(text) => self.query.text = text,
+            BlueprintFilterChanged(blueprint) => self.query.blueprint = from_option(blueprint),
+            TagFilterChanged(tag) => self.query.tag = from_option(tag),
+            TagInputChanged(tag) => self.tag_input = tag,
+            PrepareBulkTag(add) => {
+                let tag = self.tag_input.trim();
+                if let State::List(entries) = &self.state {
+                    if !tag.is_empty() {
+                        let nodes = npc_search::search(entries, &self.query)
+                            .map(|e| e.node.clone())
+                            .collect();
+                        self.state = State::ConfirmBulkTag(BulkTag {
+                            add,
+                            tag: tag.into(),
+                            nodes,
+                        });
+                    }
+                }
+            }
+            ConfirmBulkTag => {
+                if let State::ConfirmBulkTag(op) = &self.state {
+                    let ids: Vec<i64> = op.nodes.iter().map(|n| n.id).collect();
+                    if op.add {
+                        database().add_tag(&ids, &op.tag)?;
+                    } else {
+                        database().remove_tag(&ids, &op.tag)?;
+                    }
+                    self.tag_input.clear();
+                    return Ok(self.show_list());
+                }
+            }
+            Delete => {
+                if let State::Detail(page) = &self.state {
+                    database().delete_node(page.node.id)?;
+                    return Ok(self.show_list());
+                }
+            }
+            BundlePathChanged(path) => self.bundle_path = path,
+            ExportListed => {
+                if let State::List(entries) = &self.state {
+                    let ids: Vec<i64> = npc_search::search(entries, &self.query)
+                        .map(|e| e.node.id)
+                        .collect();
+                    let path = self.bundle_path()?;
+                    let n = bundle::export(&ids, path)?;
+                    self.notice = Some(format!("Exported {} nodes to {}", n, path.display()));
+                }
+            }
+            Import => {
+                let bundle = bundle::read(self.bundle_path()?)?;
+                let conflicts = bundle::conflicts(&bundle)?;
+                if conflicts.is_empty() {
+                    return self.import(&bundle, ConflictPolicy::KeepExisting);
+                } else {
+                    self.state = State::ConfirmImport(bundle, conflicts);
+                }
+            }
+            ConfirmImport(policy) => {
+                if let State::ConfirmImport(bundle, _) = &self.state {
+                    let bundle = bundle.clone();
+                    return self.import(&bundle, policy);
+                }
+            }
+            PluginExport(name) => {
+                if let State::Detail(DetailPage {
+                    node,
+                    npc: Some(npc),
+                    ..
+                }) = &self.state
+                {
+                    let plugins = self.plugins.borrow();
+                    let exporter = plugins
+                        .exporters
+                        .iter()
+                        .find(|e| e.name == name)
+                        .ok_or_else(|| anyhow!("There is no exporter called {}", name))?;
+                    let text = plugins.export(exporter, &node.name, npc)?;
+                    let path = npc_export::write_export(&node.name, &exporter.extension, &text)?;
+                    self.notice = Some(format!("Exported to {}", path.display()));
+                }
+            }
+            RelationTypeSelected(relation_type) => self.relation_type = relation_type,
+            RelationTargetChanged(name) => self.relation_target = name,
+            AddRelation => {
+                ensure!(
+                    !self.relation_target.trim().is_empty(),
+                    "Enter the name of the related node first"
+                );
+                if let State::Detail(page) = &self.state {
+                    let mut db = database();
+                    let target = relations::find_by_name(&mut db, &self.relation_target)?;
+                    relations::link(&mut db, page.node.id, target, &self.relation_type)?;
+                    drop(db);
+                    let mut breadcrumbs = page.breadcrumbs.clone();
+                    let (id, _) = breadcrumbs.pop().unwrap();
+                    self.relation_target.clear();
+                    self.state = State::Detail(DetailPage::load(id, breadcrumbs)?);
+                }
+            }
+            AttachmentPathChanged(path) => self.attachment_path = path,
+            Attach => {
+                if let State::Detail(page) = &self.state {
+                    let path = Path::new(self.attachment_path.trim());
+                    ensure!(
+                        !path.as_os_str().is_empty(),
+                        "Enter the path of the file first"
+                    );
+                    let mime = attachments::mime_from_path(path)
+                        .ok_or_else(|| anyhow!("Unknown file type: {}", path.display()))?;
+                    let bytes = std::fs::read(path).with_context(|| path.display().to_string())?;
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    database().attach(page.node.id, &name, mime, &bytes)?;
+                    let mut breadcrumbs = page.breadcrumbs.clone();
+                    let (id, _) = breadcrumbs.pop().unwrap();
+                    self.attachment_path.clear();
+                    self.state = State::Detail(DetailPage::load(id, breadcrumbs)?);
+                }
+            }
+        }
+        Ok(Command::none())
+    }
+}
+
+impl ViewNpcTab {
+    fn bundle_path(&self) -> Result<&Path> {
+        let path = self.bundle_path.trim();
+        ensure!(!path.is_empty(), "Enter the path of the bundle file first");
+        Ok(Path::new(path))
+    }
+
+    fn import(&mut self, bundle: &Bundle, policy: ConflictPolicy) -> Result<Command<Message>> {
+        let n = bundle::import(bundle, policy)?;
+        self.notice = Some(format!("Imported {} new nodes", n));
+        Ok(self.show_list())
+    }
+}
+
+/// the entry of the filter pick lists that doesn't filter
+const ANY: &str = "(any)";
+
+fn from_option(option: String) -> Option<String> {
+    (option != ANY).then_some(option)
+}
+
+/// ANY followed by the options
+fn with_any(options: Vec<String>) -> Vec<String> {
+    std::iter::once(ANY.to_string()).chain(options).collect()
+}
+
+impl DetailPage {
+    fn load(id: i64, mut breadcrumbs: Vec<(i64, String)>) -> Result<DetailPage> {
+        let mut db = database();
+        let node = db.select_node(id)?;
+        let relations = relations::relations(&mut db, id, 2)?;
+        let (images, files): (Vec<Attachment>, Vec<Attachment>) = db
+            .select_attachments(id)?
+            .into_iter()
+            .partition(Attachment::is_image);
+
+        let (npc, notes) = if node.r#type == npc_store::NPC_NODE_TYPE {
+            (Some(npc_store::npc_from_node(&node)?), None)
+        } else {
+            (None, Some(String::from_utf8_lossy(&node.data).to_string()))
+        };
+        let text_fields = npc.iter().flat_map(|npc| {
+            npc.fields
+                .iter()
+                .filter(|f| f.kind == FieldKind::Text)
+                .flat_map(|f| f.values.iter().map(String::as_str))
+        });
+        let references = wiki_links::resolve(&mut db, text_fields.chain(notes.as_deref()))?;
+        drop(db);
+
+        breadcrumbs.push((id, node.name.clone()));
+        Ok(DetailPage {
+            breadcrumbs,
+            node,
+            npc,
+            notes,
+            references,
+            display: DisplayConfig::default(),
+            relations,
+            images: images
+                .into_iter()
+                .map(|a| image::Handle::from_memory(a.bytes))
+                .collect(),
+            files: files.into_iter().map(|a| a.name).collect(),
+        })
+    }
 }
 
 impl Tab for ViewNpcTab {
@@ -27,6 +376,332 @@ impl Tab for ViewNpcTab {
     }
 
     fn content(&self) -> Element<'_, Self::Message> {
-        Text::new("Under Construction").into()
+        let content: Element<'_, ViewNpcMessage> = match &self.state {
+            State::Loading => render_loading(),
+            State::Error(e) => render_error(
+                e,
+                Button::new("Back to List").on_press(ViewNpcMessage::ShowList),
+            )
+            .into(),
+            State::List(entries) => render_list(self, entries),
+            State::Detail(page) => render_detail(self, page),
+            State::ConfirmBulkTag(op) => render_confirm_bulk_tag(op),
+            State::ConfirmImport(_, conflicts) => render_confirm_import(conflicts),
+        };
+        content.map(Message::ViewNpcMsg)
+    }
+}
+
+fn render_list<'a>(tab: &'a ViewNpcTab, entries: &'a [Entry]) -> Element<'a, ViewNpcMessage> {
+    let query = &tab.query;
+    let selected = |option: &Option<String>| option.clone().unwrap_or_else(|| ANY.into());
+    let mut col = column!(
+        row!(
+            TextInput::new(
+                "Search names and fields",
+                &query.text,
+                ViewNpcMessage::SearchChanged
+            )
+            .padding(5),
+            Text::new("Blueprint:"),
+            PickList::new(
+                with_any(npc_search::blueprints(entries)),
+                Some(selected(&query.blueprint)),
+                ViewNpcMessage::BlueprintFilterChanged
+            ),
+            Text::new("Tag:"),
+            PickList::new(
+                with_any(npc_search::tags(entries)),
+                Some(selected(&query.tag)),
+                ViewNpcMessage::TagFilterChanged
+            ),
+            Button::new("Refresh").on_press(ViewNpcMessage::ShowList)
+        )
+        .spacing(10)
+        .align_items(Alignment::Center),
+        row!(
+            TextInput::new("Tag", &tab.tag_input, ViewNpcMessage::TagInputChanged).padding(5),
+            Button::new("Tag all listed").on_press(ViewNpcMessage::PrepareBulkTag(true)),
+            Button::new("Untag all listed").on_press(ViewNpcMessage::PrepareBulkTag(false))
+        )
+        .spacing(10),
+        row!(
+            TextInput::new(
+                "Bundle file (.toml or .json)",
+                &tab.bundle_path,
+                ViewNpcMessage::BundlePathChanged
+            )
+            .padding(5),
+            Button::new("Export listed").on_press(ViewNpcMessage::ExportListed),
+            Button::new("Import").on_press(ViewNpcMessage::Import)
+        )
+        .spacing(10)
+    )
+    .spacing(10);
+    if let Some(notice) = &tab.notice {
+        col = col.push(Text::new(notice));
+    }
+    let matches: Vec<&Entry> = npc_search::search(entries, query).collect();
+    col = col.push(Text::new(format!(
+        "{} of {} NPCs",
+        matches.len(),
+        entries.len()
+    )));
+    col.push(Scrollable::new(
+        Column::with_children(
+            matches
+                .into_iter()
+                .map(|e| {
+                    Button::new(Text::new(describe_entry(e)))
+                        .on_press(ViewNpcMessage::Open(e.node.id))
+                        .width(Length::Fill)
+                        .into()
+                })
+                .collect(),
+        )
+        .spacing(5),
+    ))
+    .into()
+}
+
+/// the name, followed by the blueprint and the tags if there are any
+fn describe_entry(entry: &Entry) -> String {
+    let mut text = entry.node.name.clone();
+    if let Some(blueprint) = entry.blueprint() {
+        text += &format!(" ({})", blueprint);
+    }
+    if !entry.tags.is_empty() {
+        text += &format!(" [{}]", entry.tags.join(", "));
+    }
+    text
+}
+
+fn render_confirm_import(conflicts: &[String]) -> Element<'_, ViewNpcMessage> {
+    column!(
+        Text::new("These nodes already exist in the campaign:").size(24),
+        Scrollable::new(
+            Column::with_children(conflicts.iter().map(|n| Text::new(n).into()).collect())
+                .spacing(5)
+        ),
+        row!(
+            Button::new("Keep existing")
+                .on_press(ViewNpcMessage::ConfirmImport(ConflictPolicy::KeepExisting)),
+            Button::new("Replace").on_press(ViewNpcMessage::ConfirmImport(ConflictPolicy::Replace)),
+            Button::new("Import as copies")
+                .on_press(ViewNpcMessage::ConfirmImport(ConflictPolicy::Copy)),
+            Button::new("Cancel").on_press(ViewNpcMessage::ShowList)
+        )
+        .spacing(10)
+    )
+    .spacing(10)
+    .into()
+}
+
+fn render_confirm_bulk_tag(op: &BulkTag) -> Element<'_, ViewNpcMessage> {
+    let question = if op.add {
+        format!("Add the tag {} to these {} NPCs?", op.tag, op.nodes.len())
+    } else {
+        format!(
+            "Remove the tag {} from these {} NPCs?",
+            op.tag,
+            op.nodes.len()
+        )
+    };
+    column!(
+        Text::new(question).size(24),
+        row!(
+            Button::new("Confirm").on_press(ViewNpcMessage::ConfirmBulkTag),
+            Button::new("Cancel").on_press(ViewNpcMessage::ShowList)
+        )
+        .spacing(10),
+        Scrollable::new(
+            Column::with_children(op.nodes.iter().map(|n| Text::new(&n.name).into()).collect())
+                .spacing(5)
+        )
+    )
+    .spacing(10)
+    .into()
+}
+
+fn render_detail<'a>(tab: &'a ViewNpcTab, page: &'a DetailPage) -> Element<'a, ViewNpcMessage> {
+    let mut crumbs: Vec<Element<'_, ViewNpcMessage>> = vec![Button::new("NPCs")
+        .on_press(ViewNpcMessage::ShowList)
+        .into()];
+    let last = page.breadcrumbs.len() - 1;
+    for (i, (_, name)) in page.breadcrumbs.iter().enumerate() {
+        crumbs.push(Text::new(">").into());
+        crumbs.push(if i == last {
+            Text::new(name).into()
+        } else {
+            Button::new(Text::new(name))
+                .on_press(ViewNpcMessage::Breadcrumb(i))
+                .into()
+        });
+    }
+
+    // references in the text follow a link, so they extend the breadcrumbs
+    let links = Some((
+        &page.references,
+        ViewNpcMessage::Follow as fn(i64) -> ViewNpcMessage,
+    ));
+    let body: Element<'_, ViewNpcMessage> = match &page.npc {
+        Some(npc) => render_npc(npc, &page.display, links),
+        None => column!(
+            Text::new(format!("{} ({})", page.node.name, page.node.r#type)),
+            render_linked_text(
+                page.notes.as_deref().unwrap_or_default(),
+                &page.references,
+                ViewNpcMessage::Follow,
+                20
+            )
+        )
+        .spacing(10)
+        .into(),
+    };
+    let meta = page
+        .node
+        .meta
+        .iter()
+        .map(|(k, v)| format!("{}: {}", k, v))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut groups: BTreeMap<&str, Vec<&Relation>> = BTreeMap::new();
+    for relation in &page.relations {
+        groups.entry(&relation.label).or_default().push(relation);
+    }
+    let link_groups = groups.into_iter().map(|(label, relations)| {
+        column!(
+            Text::new(format!("{}:", label)).size(24),
+            Column::with_children(relations.into_iter().map(render_relation).collect()).spacing(5)
+        )
+        .spacing(5)
+        .into()
+    });
+
+    let images = page
+        .images
+        .iter()
+        .map(|handle| Image::new(handle.clone()).width(Length::Units(200)).into());
+    let mut attachments = column!(Row::with_children(images.collect()).spacing(10)).spacing(10);
+    if !page.files.is_empty() {
+        attachments = attachments.push(Text::new(format!(
+            "Attached files: {}",
+            page.files.join(", ")
+        )));
+    }
+
+    Scrollable::new(
+        column!(
+            Row::with_children(crumbs)
+                .spacing(5)
+                .align_items(Alignment::Center),
+            row!(
+                Text::new(&page.node.name).size(32).width(Length::Fill),
+                Button::new("Delete").on_press(ViewNpcMessage::Delete)
+            )
+            .align_items(Alignment::Center),
+            attachments,
+            body,
+            Text::new(meta),
+            render_exporters(tab, page),
+            render_add_relation(tab),
+            render_attach(tab),
+            Column::with_children(link_groups.collect()).spacing(10)
+        )
+        .spacing(20),
+    )
+    .into()
+}
+
+/// a button that follows the link, followed by the relations of the linked node
+fn render_relation(relation: &Relation) -> Element<'_, ViewNpcMessage> {
+    let further = relation.relations.iter().map(|r| {
+        row!(
+            Text::new(format!("{}:", r.label)),
+            Button::new(Text::new(&r.node.name)).on_press(ViewNpcMessage::Follow(r.node.id))
+        )
+        .spacing(5)
+        .align_items(Alignment::Center)
+        .into()
+    });
+    row!(
+        Button::new(Text::new(&relation.node.name))
+            .on_press(ViewNpcMessage::Follow(relation.node.id)),
+        Column::with_children(further.collect()).spacing(5)
+    )
+    .spacing(20)
+    .into()
+}
+
+fn render_add_relation(tab: &ViewNpcTab) -> Element<'_, ViewNpcMessage> {
+    let types = RELATION_TYPES.iter().map(|(t, _)| t.to_string()).collect();
+    let add = (!tab.relation_target.trim().is_empty()).then_some(ViewNpcMessage::AddRelation);
+    let mut button = Button::new("Add relationship");
+    if let Some(msg) = add {
+        button = button.on_press(msg);
+    }
+    row!(
+        PickList::new(
+            types,
+            Some(tab.relation_type.clone()),
+            ViewNpcMessage::RelationTypeSelected
+        ),
+        TextInput::new(
+            "Name of the node",
+            &tab.relation_target,
+            ViewNpcMessage::RelationTargetChanged
+        )
+        .on_submit(ViewNpcMessage::AddRelation)
+        .padding(5)
+        .width(Length::Units(300)),
+        button
+    )
+    .spacing(10)
+    .align_items(Alignment::Center)
+    .into()
+}
+
+fn render_attach(tab: &ViewNpcTab) -> Element<'_, ViewNpcMessage> {
+    let mut button = Button::new("Attach file");
+    if !tab.attachment_path.trim().is_empty() {
+        button = button.on_press(ViewNpcMessage::Attach);
+    }
+    row!(
+        TextInput::new(
+            "Path of an image or handout",
+            &tab.attachment_path,
+            ViewNpcMessage::AttachmentPathChanged
+        )
+        .on_submit(ViewNpcMessage::Attach)
+        .padding(5)
+        .width(Length::Units(300)),
+        button
+    )
+    .spacing(10)
+    .align_items(Alignment::Center)
+    .into()
+}
+
+/// a button per plugin exporter, and the result of the last export
+fn render_exporters<'a>(tab: &'a ViewNpcTab, page: &'a DetailPage) -> Element<'a, ViewNpcMessage> {
+    let mut col = Column::new().spacing(10);
+    if page.npc.is_some() {
+        let buttons = tab
+            .plugins
+            .borrow()
+            .exporters
+            .iter()
+            .map(|e| {
+                Button::new(Text::new(format!("Export {}", e.name)))
+                    .on_press(ViewNpcMessage::PluginExport(e.name.clone()))
+                    .into()
+            })
+            .collect();
+        col = col.push(Row::with_children(buttons).spacing(10));
+    }
+    if let Some(notice) = &tab.notice {
+        col = col.push(Text::new(notice));
     }
+    col.into()
 }
@@ -0,0 +1,161 @@
+//! Notes can reference other nodes wiki-style, like `[[Captain Mora]]`. References are resolved
+//! against the names of the nodes in the database, ignoring case, so they can be previewed and
+//! followed.
+use std::collections::HashMap;
+
+use anyhow::Result;
+use database::db::{Node, DB};
+
+use crate::npc_store;
+
+/// how many lines of a node are shown in its preview
+const PREVIEW_LINES: usize = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    /// the name inside the brackets
+    Link(&'a str),
+}
+
+/// the node a reference points to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub id: i64,
+    pub preview: String,
+}
+
+/// resolved references by their lowercase name. Names without a node are missing
+pub type Targets = HashMap<String, Target>;
+
+/// splits the text into plain text and references. Brackets that aren't closed are plain text
+pub fn parse(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let len = match rest[start + 2..].find("]]") {
+            Some(len) => len,
+            None => break,
+        };
+        let name = rest[start + 2..start + 2 + len].trim();
+        if name.is_empty() {
+            segments.push(Segment::Text(&rest[..start + 4 + len]));
+        } else {
+            if start > 0 {
+                segments.push(Segment::Text(&rest[..start]));
+            }
+            segments.push(Segment::Link(name));
+        }
+        rest = &rest[start + 4 + len..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+pub fn lookup<'a>(targets: &'a Targets, name: &str) -> Option<&'a Target> {
+    targets.get(&name.to_lowercase())
+}
+
+/// resolves the references of all texts. If several nodes have the name, the oldest one wins
+pub fn resolve<'a>(db: &mut DB, texts: impl IntoIterator<Item = &'a str>) -> Result<Targets> {
+    let mut names: Vec<String> = texts
+        .into_iter()
+        .flat_map(parse)
+        .filter_map(|s| match s {
+            Segment::Link(name) => Some(name.to_lowercase()),
+            Segment::Text(_) => None,
+        })
+        .collect();
+    if names.is_empty() {
+        return Ok(Targets::new());
+    }
+    names.sort();
+    names.dedup();
+
+    let mut ids: HashMap<String, i64> = HashMap::new();
+    for (id, name, _) in db.select_node_names()? {
+        ids.entry(name.to_lowercase())
+            .and_modify(|oldest| *oldest = (*oldest).min(id))
+            .or_insert(id);
+    }
+    let mut targets = Targets::new();
+    for name in names {
+        if let Some(&id) = ids.get(&name) {
+            if let Some(node) = db.try_select_node(id)? {
+                let preview = preview(&node);
+                targets.insert(name, Target { id, preview });
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// the type of the node and the start of its content: the fields of NPCs, the text of others
+pub fn preview(node: &Node) -> String {
+    let mut lines = vec![format!("{} ({})", node.name, node.r#type)];
+    if node.r#type == npc_store::NPC_NODE_TYPE {
+        match npc_store::npc_from_node(node) {
+            Ok(npc) => lines.extend(
+                npc.fields
+                    .iter()
+                    .map(|f| format!("{}: {}", f.display_name, f.values.join(", "))),
+            ),
+            Err(e) => lines.push(format!("{:#}", e)),
+        }
+    } else {
+        lines.extend(
+            String::from_utf8_lossy(&node.data)
+                .lines()
+                .map(String::from),
+        );
+    }
+    lines.truncate(PREVIEW_LINES);
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("Ask [[ Mira Quell ]] about [[Harborton]]. [[]] and [[open"),
+            [
+                Segment::Text("Ask "),
+                Segment::Link("Mira Quell"),
+                Segment::Text(" about "),
+                Segment::Link("Harborton"),
+                Segment::Text(". [[]]"),
+                Segment::Text(" and [[open"),
+            ]
+        );
+        assert_eq!(parse(""), []);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut db = DB::new(std::path::Path::new(":memory:")).unwrap();
+        demo::populate(&mut db).unwrap();
+        let targets = resolve(
+            &mut db,
+            ["[[captain oderic]] met [[Nobody]]", "in [[Harborton]]"],
+        )
+        .unwrap();
+        assert_eq!(targets.len(), 2);
+        assert!(lookup(&targets, "Nobody").is_none());
+        let oderic = lookup(&targets, "Captain Oderic").unwrap();
+        assert_eq!(
+            oderic.preview,
+            "Captain Oderic (npc)\nname: Captain Oderic\nrole: Harbor master\n\
+             note: Looks the other way for the right price."
+        );
+        assert!(lookup(&targets, "harborton")
+            .unwrap()
+            .preview
+            .ends_with("grew rich on smuggling."));
+    }
+}
@@ -0,0 +1,60 @@
+//! Where the app is drawn: the terminal, or a buffer that the tests read back, see test_utils.
+//! The states are kept in boxes of dyn State, so they render into a Frame of this one type,
+//! which forwards to the backend it holds.
+use std::io::{self, Stdout};
+use tui::{
+    backend::{self as tui_backend, CrosstermBackend, TestBackend},
+    buffer::Cell,
+    layout::Rect,
+};
+
+pub enum Backend {
+    Terminal(CrosstermBackend<Stdout>),
+    Test(TestBackend),
+}
+
+macro_rules! forward {
+    ($self:ident, $backend:ident => $call:expr) => {
+        match $self {
+            Backend::Terminal($backend) => $call,
+            Backend::Test($backend) => $call,
+        }
+    };
+}
+
+impl tui_backend::Backend for Backend {
+    fn draw<'a, I>(&mut self, content: I) -> io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        forward!(self, b => b.draw(content))
+    }
+
+    fn hide_cursor(&mut self) -> io::Result<()> {
+        forward!(self, b => b.hide_cursor())
+    }
+
+    fn show_cursor(&mut self) -> io::Result<()> {
+        forward!(self, b => b.show_cursor())
+    }
+
+    fn get_cursor(&mut self) -> io::Result<(u16, u16)> {
+        forward!(self, b => b.get_cursor())
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+        forward!(self, b => b.set_cursor(x, y))
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        forward!(self, b => b.clear())
+    }
+
+    fn size(&self) -> io::Result<Rect> {
+        forward!(self, b => b.size())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        forward!(self, b => b.flush())
+    }
+}
@@ -1,38 +1,306 @@
-use anyhow::{anyhow, ensure, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use derive_new::new;
 use persistent_structs::PersistentStruct;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str::FromStr;
 
-use crate::utils;
+use crate::conditions::Conditions;
+use crate::utils::{self, DiceExpr};
 
-#[derive(PersistentStruct, Default, Clone, new)]
+#[derive(PersistentStruct, Default, Clone, new, Serialize, Deserialize)]
 pub struct CombatState {
     pub current_round: usize,
     pub current_idx: usize,
-    pub participants: Vec<Participant>,
+    /// shared with the snapshots of the history, a participant is only copied when it changes
+    pub participants: Vec<Rc<Participant>>,
+    #[new(default)]
+    pub turn_order: TurnOrder,
+    /// reminders for future rounds
+    #[new(default)]
+    pub events: Vec<ScheduledEvent>,
+    /// what happened in the fight, oldest first
+    #[new(default)]
+    pub log: Vec<LogEntry>,
+    /// how initiatives are rolled
+    #[new(default)]
+    #[serde(default)]
+    pub ini_roll: DiceExpr,
+    /// the presets that can be picked when adding modifiers
+    #[new(default)]
+    #[serde(default)]
+    pub conditions: Conditions,
+    /// whether participants that are down are skipped when the turn passes with fixed
+    /// initiative
+    #[new(default)]
+    #[serde(default)]
+    pub skip_down: bool,
+    /// how participants with the same initiative are ordered when initiatives are rolled
+    #[new(default)]
+    #[serde(default)]
+    pub tie_break: TieBreak,
+    /// whether rolling initiatives and sorting keep the order of the list, so an ongoing fight
+    /// isn't reshuffled by accident. Participants can still be moved one by one
+    #[new(default)]
+    #[serde(default)]
+    pub order_locked: bool,
+    /// what the participants are ordered by when the list is sorted
+    #[new(default)]
+    #[serde(default)]
+    pub sort_by: SortBy,
+    /// whether the terminal bell rings when a round starts
+    #[new(default)]
+    #[serde(default)]
+    pub ring_bell: bool,
+    /// the modifiers that expired when the turn passed last, like "Bless expired on Alia". It
+    /// isn't saved
+    #[new(default)]
+    #[serde(skip)]
+    pub expired: Vec<String>,
+    /// earlier and undone versions of this state. It isn't saved
+    #[new(default)]
+    #[serde(skip)]
+    pub history: History,
+    /// the participants that were deleted in this session, with their index, the last one
+    /// first. It isn't saved
+    #[new(default)]
+    #[serde(skip)]
+    pub graveyard: VecDeque<(usize, Rc<Participant>)>,
 }
 
-#[derive(PersistentStruct, Clone, Copy, Default, PartialEq, Eq, PartialOrd)]
+/// the number of changes that can be undone
+const HISTORY_LEN: usize = 100;
+/// the number of deleted participants that can be restored
+const GRAVEYARD_LEN: usize = 10;
+
+/// a ring buffer of snapshots for undo, and the undone snapshots for redo. The snapshots don't
+/// have a history themselves
+#[derive(Clone, Default)]
+pub struct History {
+    undo: VecDeque<CombatState>,
+    redo: Vec<CombatState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledEvent {
+    /// the round it happens in, or the first one if it happens every round
+    pub round: usize,
+    pub text: String,
+    /// the initiative count it happens at, like lair actions on 20. Without it, the event
+    /// happens when the round starts
+    #[serde(default)]
+    pub ini: Option<u8>,
+    #[serde(default)]
+    pub every_round: bool,
+}
+
+/// damage that is dealt with the quick damage prompt
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Damage {
+    pub target: String,
+    pub amount: u16,
+    pub r#type: Option<String>,
+    /// whether the targets made their save and take half the damage, like against a fireball
+    pub half: bool,
+}
+
+/// how a participant is affected by a damage type. Participants declare it with modifiers
+/// named like `resist fire`, `vulnerable cold` or `immune poison`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Affinity {
+    Resistant,
+    Vulnerable,
+    Immune,
+}
+
+/// how it is determined who acts next
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TurnOrder {
+    /// participants act in the order of the list
+    #[default]
+    Fixed,
+    /// after every turn, the GM picks who acts next from those that haven't acted this round
+    Popcorn,
+}
+
+/// how participants with the same initiative are ordered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TieBreak {
+    /// the higher DEX stat goes first. Remaining ties keep the order of the list
+    #[default]
+    Dex,
+    /// the tied participants roll a d20 against each other until they are ordered
+    Reroll,
+    /// the GM orders the tied participants
+    Ask,
+}
+
+impl TieBreak {
+    pub fn next(self) -> TieBreak {
+        match self {
+            TieBreak::Dex => TieBreak::Reroll,
+            TieBreak::Reroll => TieBreak::Ask,
+            TieBreak::Ask => TieBreak::Dex,
+        }
+    }
+}
+
+impl fmt::Display for TieBreak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TieBreak::Dex => "dex",
+            TieBreak::Reroll => "reroll",
+            TieBreak::Ask => "ask",
+        })
+    }
+}
+
+impl FromStr for TieBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TieBreak, String> {
+        match s {
+            "dex" => Ok(TieBreak::Dex),
+            "reroll" => Ok(TieBreak::Reroll),
+            "ask" => Ok(TieBreak::Ask),
+            _ => Err(format!(
+                "{} is not a tie break rule, use dex, reroll or ask",
+                s
+            )),
+        }
+    }
+}
+
+/// what the list of participants can be sorted by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortBy {
+    /// the highest initiative first, participants without one last
+    #[default]
+    Initiative,
+    /// alphabetically, ignoring case
+    Name,
+    /// the most HP first
+    Hp,
+}
+
+impl SortBy {
+    pub fn next(self) -> SortBy {
+        match self {
+            SortBy::Initiative => SortBy::Name,
+            SortBy::Name => SortBy::Hp,
+            SortBy::Hp => SortBy::Initiative,
+        }
+    }
+}
+
+impl fmt::Display for SortBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SortBy::Initiative => "ini",
+            SortBy::Name => "name",
+            SortBy::Hp => "hp",
+        })
+    }
+}
+
+/// whether a participant is still in the fight. Participants at 0 HP are down, even if they are
+/// conscious
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Status {
+    #[default]
+    Conscious,
+    Stable,
+    Dead,
+}
+
+#[derive(
+    PersistentStruct, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Serialize, Deserialize,
+)]
 pub struct TimeVec {
     pub round: usize,
     pub sub_round_time: SubRoundTime,
 }
 
-#[derive(PersistentStruct, Clone)]
+#[derive(PersistentStruct, Clone, Serialize, Deserialize)]
 pub struct Participant {
     pub name: String,
     pub hp: u16,
+    /// heals stop here
+    #[serde(default)]
+    pub max_hp: Option<u16>,
+    /// temporary HP are lost before the real ones
+    #[serde(default)]
+    pub temp_hp: u16,
     pub modifiers: Vec<Modifier>,
+    /// whether the participant had its turn in the current round. Only used with popcorn
+    /// initiative
+    pub has_acted: bool,
+    #[serde(default)]
+    pub ini: Option<u8>,
+    /// added to the roll when the initiative is rolled, from `Name: HP: +3`
+    #[serde(default)]
+    pub ini_bonus: Option<i64>,
+    #[serde(default)]
+    pub stats: Stats,
+    #[serde(default)]
+    pub macros: Macros,
+    #[serde(default)]
+    pub status: Status,
+    /// the HP when the fight started, so they can be restored when it ends
+    #[serde(default)]
+    pub hp_before_fight: Option<u16>,
+    /// free text like AC, attacks and abilities, shown next to the table on the participant's
+    /// turn
+    #[serde(default)]
+    pub notes: String,
+    /// like Goblins. All members of a group can be damaged at once
+    #[serde(default)]
+    pub group: Option<String>,
+    /// whether the participant gave up its turn to act later in the round. It acts in its old
+    /// slot again if nobody lets it act before
+    #[serde(default)]
+    pub delayed: bool,
+    /// a player character. Players see its HP in the shared view
+    #[serde(default)]
+    pub pc: bool,
 }
 
-#[derive(PersistentStruct, Clone, new)]
+/// values like DEX=2, by their uppercase name
+pub type Stats = BTreeMap<String, i64>;
+
+/// rolls like Scimitar=1d20+4,1d6+2, by their name
+pub type Macros = BTreeMap<String, Vec<DiceExpr>>;
+
+#[derive(PersistentStruct, Clone, new, Serialize, Deserialize)]
 pub struct Modifier {
     pub name: String,
     pub introduced_at: TimeVec,
     pub duration: Option<usize>,
+    /// the name of the color it is shown in, set by condition presets
+    #[new(default)]
+    #[serde(default)]
+    pub color: Option<String>,
+    /// the uses that are left, like the 3 of `Shield of Faith x3`. The modifier is removed when
+    /// the last one is used
+    #[new(default)]
+    #[serde(default)]
+    pub charges: Option<u16>,
+    /// the HP that are gained, or lost if it is negative, whenever the turn of the participant
+    /// starts, like the -3 of `Burning -3/round`
+    #[new(default)]
+    #[serde(default)]
+    pub hp_per_round: Option<i16>,
+    /// the participant that concentrates on the modifier, like Alia of `Bless @Alia`. The
+    /// modifier ends when the caster goes down or loses concentration
+    #[new(default)]
+    #[serde(default)]
+    pub concentration: Option<String>,
 }
 
-#[derive(Clone, Copy, new, Eq, Default)]
+#[derive(Clone, Copy, new, Eq, Default, Serialize, Deserialize)]
 pub struct SubRoundTime {
     nom: usize,
     denom: usize,
@@ -82,37 +350,606 @@ impl CombatState {
     pub fn now(&self) -> TimeVec {
         TimeVec {
             round: self.current_round,
-            sub_round_time: SubRoundTime::new(self.current_idx, self.participants.len()),
+            sub_round_time: SubRoundTime::new(self.turns_this_round(), self.participants.len()),
+        }
+    }
+
+    /// the number of turns that were taken in the current round before the current one
+    fn turns_this_round(&self) -> usize {
+        match self.turn_order {
+            TurnOrder::Fixed => self.current_idx,
+            // the current participant is marked as acted already
+            TurnOrder::Popcorn => self
+                .participants
+                .iter()
+                .filter(|p| p.has_acted)
+                .count()
+                .saturating_sub(1),
         }
     }
 
-    pub fn with_next_turn(self) -> CombatState {
+    /// passes the turn to the next participant, or to the next one that isn't down if skip_down
+    /// is set. If everybody is down, the turn passes once
+    pub fn with_next_turn(mut self) -> CombatState {
+        self.expired.clear();
+        for _ in 0..self.participants.len() {
+            self = self.with_turn_passed();
+            if !self.skip_down || !self.participants[self.current_idx].is_down() {
+                break;
+            }
+        }
+        self
+    }
+
+    /// the participant that acts after the current one, the way with_next_turn passes the turn.
+    /// None with popcorn initiative, where the GM picks who is next
+    pub fn next_up(&self) -> Option<usize> {
+        let len = self.participants.len();
+        if self.turn_order == TurnOrder::Popcorn || len == 0 {
+            return None;
+        }
+        (1..=len)
+            .map(|step| (self.current_idx + step) % len)
+            .find(|i| !self.skip_down || !self.participants[*i].is_down())
+            .or(Some((self.current_idx + 1) % len))
+    }
+
+    fn with_turn_passed(self) -> CombatState {
         let mut next_state = if self.current_idx == self.participants.len() - 1 {
-            self.update_current_round(|r| r + 1).with_current_idx(0)
+            self.update_current_round(|r| r + 1)
+                .with_current_idx(0)
+                .with_round_logged()
         } else {
             self.update_current_idx(|i| i + 1)
         };
-        let now = next_state.now();
-        for p in &mut next_state.participants {
-            p.modifiers.retain(|x| {
-                if let Some(dur) = x.remaining_rounds(&now) {
-                    dur > 0
-                } else {
-                    true
-                }
-            })
+        let p = Rc::make_mut(&mut next_state.participants[next_state.current_idx]);
+        if p.delayed {
+            p.delayed = false;
+            let entry = format!("{} stops delaying", p.name);
+            next_state.log.push(LogEntry::Text(entry));
         }
         next_state
+            .without_expired_modifiers()
+            .with_ongoing_effects()
+    }
+
+    /// the modifiers of the current participant that change its HP every round do so, and it is
+    /// logged. Modifiers that expired are removed before
+    fn with_ongoing_effects(mut self) -> CombatState {
+        let p = Rc::make_mut(&mut self.participants[self.current_idx]);
+        let effects: Vec<(String, i16)> = p
+            .modifiers
+            .iter()
+            .filter_map(|m| Some((m.name.clone(), m.hp_per_round?)))
+            .collect();
+        for (name, delta) in effects {
+            let entry = if delta < 0 {
+                p.take_damage(delta.unsigned_abs());
+                format!(
+                    "{} takes {} damage from {}",
+                    p.name,
+                    delta.unsigned_abs(),
+                    name
+                )
+            } else {
+                p.heal(delta.unsigned_abs());
+                format!("{} heals {} from {}", p.name, delta, name)
+            };
+            self.log.push(LogEntry::Text(entry));
+        }
+        self.without_lapsed_concentrations()
+    }
+
+    /// the current participant delays its turn, and the turn passes on. Only used with fixed
+    /// initiative
+    pub fn with_turn_delayed(mut self) -> CombatState {
+        let p = Rc::make_mut(&mut self.participants[self.current_idx]);
+        p.delayed = true;
+        let entry = format!("{} delays", p.name);
+        self.log.push(LogEntry::Text(entry));
+        self.with_next_turn()
+    }
+
+    /// the delayed nth participant acts now, before the current one, whose initiative it takes
+    /// over. The current participant acts after it
+    pub fn with_delay_ended(mut self, n: usize) -> CombatState {
+        let mut p = self.participants.remove(n);
+        if n < self.current_idx {
+            self.current_idx -= 1;
+        }
+        let ini = self.participants[self.current_idx].ini;
+        let delayed = Rc::make_mut(&mut p);
+        delayed.delayed = false;
+        delayed.ini = ini;
+        self.log
+            .push(LogEntry::Text(format!("{} acts after delaying", p.name)));
+        self.participants.insert(self.current_idx, p);
+        self
+    }
+
+    /// the indices of the participants that delay their turn
+    pub fn delayed(&self) -> Vec<usize> {
+        (0..self.participants.len())
+            .filter(|i| self.participants[*i].delayed)
+            .collect()
+    }
+
+    /// whether the nth participant may be picked to act next with popcorn initiative
+    pub fn can_act(&self, n: usize) -> bool {
+        let round_is_over = self.participants.iter().all(|p| p.has_acted);
+        self.participants
+            .get(n)
+            .map(|p| round_is_over || !p.has_acted)
+            .unwrap_or(false)
+    }
+
+    /// popcorn initiative: the nth participant acts next. If everybody acted already, a new
+    /// round starts with them.
+    pub fn with_turn_of(mut self, n: usize) -> Result<CombatState> {
+        ensure!(
+            self.can_act(n),
+            "{} already acted this round",
+            self.participants[n].name
+        );
+        self.expired.clear();
+        if self.participants.iter().all(|p| p.has_acted) {
+            self.current_round += 1;
+            for p in self.participants.iter_mut().map(Rc::make_mut) {
+                p.has_acted = false;
+            }
+            self = self.with_round_logged();
+        }
+        Rc::make_mut(&mut self.participants[n]).has_acted = true;
+        self.current_idx = n;
+        Ok(self.without_expired_modifiers().with_ongoing_effects())
+    }
+
+    /// rolls the initiatives that aren't set yet, and sorts the participants by them, the
+    /// highest first. Ties are broken according to tie_break, with Ask they stay in the order
+    /// of the list. If the order is locked, the participants aren't sorted
+    pub fn with_rolled_initiatives(mut self) -> CombatState {
+        let unrolled = self.participants.iter_mut().filter(|p| p.ini.is_none());
+        for p in unrolled.map(Rc::make_mut) {
+            p.ini.get_or_insert_with(|| {
+                self.ini_roll
+                    .roll_ini(&p.stats, p.ini_bonus.unwrap_or_default())
+            });
+        }
+        if self.order_locked {
+            return self;
+        }
+        match self.tie_break {
+            TieBreak::Dex => self.participants.sort_by_key(|p| {
+                std::cmp::Reverse((p.ini, p.stats.get("DEX").copied().unwrap_or(0)))
+            }),
+            TieBreak::Reroll | TieBreak::Ask => {
+                self.participants.sort_by_key(|p| std::cmp::Reverse(p.ini))
+            }
+        }
+        if self.tie_break == TieBreak::Reroll {
+            for range in self.initiative_ties() {
+                let tied: Vec<Rc<Participant>> = self.participants[range.clone()].to_vec();
+                let order = roll_off(tied.len());
+                for (slot, i) in range.zip(order) {
+                    self.participants[slot] = tied[i].clone();
+                }
+            }
+        }
+        self
+    }
+
+    /// sorts the participants by sort_by, unless the order is locked. Participants that are equal
+    /// keep their order
+    pub fn sorted(mut self) -> CombatState {
+        if self.order_locked {
+            return self;
+        }
+        match self.sort_by {
+            SortBy::Initiative => self.participants.sort_by_key(|p| std::cmp::Reverse(p.ini)),
+            SortBy::Name => self
+                .participants
+                .sort_by_cached_key(|p| p.name.to_lowercase()),
+            SortBy::Hp => self.participants.sort_by_key(|p| std::cmp::Reverse(p.hp)),
+        }
+        self
+    }
+
+    /// the ranges of adjacent participants that have the same initiative
+    pub fn initiative_ties(&self) -> Vec<Range<usize>> {
+        let mut res = vec![];
+        let mut start = 0;
+        for i in 1..=self.participants.len() {
+            let ini = self.participants[start].ini;
+            if i == self.participants.len() || self.participants[i].ini != ini {
+                if i - start > 1 && ini.is_some() {
+                    res.push(start..i);
+                }
+                start = i;
+            }
+        }
+        res
+    }
+
+    /// switches the turn order. Nobody has acted yet in the new order
+    pub fn with_toggled_turn_order(mut self) -> CombatState {
+        self.turn_order = match self.turn_order {
+            TurnOrder::Fixed => TurnOrder::Popcorn,
+            TurnOrder::Popcorn => TurnOrder::Fixed,
+        };
+        for p in self.participants.iter_mut().map(Rc::make_mut) {
+            p.has_acted = false;
+        }
+        self
+    }
+
+    fn without_expired_modifiers(mut self) -> CombatState {
+        let now = self.now();
+        let is_expired = |m: &Modifier| m.remaining_rounds(&now).is_some_and(|dur| dur <= 0);
+        // only the participants that lose a modifier are copied
+        let affected = self
+            .participants
+            .iter_mut()
+            .filter(|p| p.modifiers.iter().any(is_expired));
+        for p in affected.map(Rc::make_mut) {
+            let (kept, expired): (Vec<Modifier>, Vec<Modifier>) =
+                p.modifiers.drain(..).partition(|x| {
+                    if let Some(dur) = x.remaining_rounds(&now) {
+                        dur > 0
+                    } else {
+                        true
+                    }
+                });
+            p.modifiers = kept;
+            for m in expired {
+                self.log
+                    .push(LogEntry::Text(format!("{} of {} ended", m.name, p.name)));
+                self.expired
+                    .push(format!("{} expired on {}", m.name, p.name));
+            }
+        }
+        self
+    }
+
+    fn with_round_logged(mut self) -> CombatState {
+        self.log.push(LogEntry::Round(self.current_round));
+        self
+    }
+
+    /// adds the modifier to the nth participant, and logs it
+    pub fn with_modifier(mut self, n: usize, modifier: Modifier) -> CombatState {
+        let p = Rc::make_mut(&mut self.participants[n]);
+        let mut entry = format!("{} gets {}", p.name, modifier.name);
+        if let Some(caster) = &modifier.concentration {
+            entry.push_str(&format!(" by {}", caster));
+        }
+        if let Some(delta) = modifier.hp_per_round {
+            entry.push_str(&format!(" ({:+} HP per round)", delta));
+        }
+        if let Some(duration) = modifier.duration {
+            entry.push_str(&format!(" for {} rounds", duration));
+        }
+        p.modifiers.push(modifier);
+        self.log.push(LogEntry::Text(entry));
+        self
+    }
+
+    /// rolls the macro of the nth participant with its stats, and logs the results
+    pub fn with_macro_rolled(mut self, n: usize, name: &str) -> CombatState {
+        let p = &self.participants[n];
+        if let Some(rolls) = p.macros.get(name) {
+            let results = rolls
+                .iter()
+                .map(|r| r.roll(&p.stats).to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let entry = format!("{} rolls {}: {}", p.name, name, results);
+            self.log.push(LogEntry::Text(entry));
+        }
+        self
+    }
+
+    /// changes the HP of the nth participant by one, like the HP keys do, and logs it.
+    /// Consecutive changes of the same participant are logged as one
+    pub fn with_hp_step(mut self, n: usize, heal: bool) -> CombatState {
+        let p = Rc::make_mut(&mut self.participants[n]);
+        let before = p.hp;
+        if heal {
+            p.heal(1);
+        } else {
+            p.take_damage(1);
+        }
+        let after = p.hp;
+        let start = match self.log.last() {
+            Some(LogEntry::Hp { who, from, .. }) if *who == p.name => {
+                let start = *from;
+                self.log.pop();
+                start
+            }
+            _ => before,
+        };
+        if start != after {
+            self.log.push(LogEntry::Hp {
+                who: p.name.clone(),
+                from: start,
+                to: after,
+            });
+        }
+        self.without_lapsed_concentrations()
     }
 
     pub fn from_participants(participants: Vec<Participant>) -> CombatState {
         CombatState {
-            participants,
+            participants: participants.into_iter().map(Rc::new).collect(),
             current_idx: 0,
             current_round: 0,
+            turn_order: TurnOrder::Fixed,
+            events: vec![],
+            log: vec![],
+            ini_roll: DiceExpr::default(),
+            conditions: Conditions::default(),
+            skip_down: false,
+            tie_break: TieBreak::default(),
+            order_locked: false,
+            sort_by: SortBy::default(),
+            ring_bell: false,
+            expired: vec![],
+            history: History::default(),
+            graveyard: VecDeque::new(),
+        }
+    }
+
+    /// applies the change so that it can be undone. Redoing changes that were undone before is
+    /// not possible anymore afterwards
+    pub fn recorded(mut self, change: impl FnOnce(CombatState) -> CombatState) -> CombatState {
+        let mut history = std::mem::take(&mut self.history);
+        if history.undo.len() == HISTORY_LEN {
+            history.undo.pop_front();
+        }
+        history.undo.push_back(self.clone());
+        history.redo.clear();
+        change(self).with_history(history)
+    }
+
+    /// the state before the last recorded change, or this one if there is none
+    pub fn undone(mut self) -> CombatState {
+        let mut history = std::mem::take(&mut self.history);
+        match history.undo.pop_back() {
+            Some(prev) => {
+                history.redo.push(self);
+                prev.with_history(history)
+            }
+            None => self.with_history(history),
+        }
+    }
+
+    /// the state before the last undo, or this one if there is none
+    pub fn redone(mut self) -> CombatState {
+        let mut history = std::mem::take(&mut self.history);
+        match history.redo.pop() {
+            Some(next) => {
+                history.undo.push_back(self);
+                next.with_history(history)
+            }
+            None => self.with_history(history),
+        }
+    }
+
+    /// schedules the event, and keeps the events sorted by their round
+    pub fn with_event(mut self, event: ScheduledEvent) -> CombatState {
+        let idx = self.events.partition_point(|e| e.round <= event.round);
+        self.events.insert(idx, event);
+        self
+    }
+
+    /// the events of the current round whose initiative count was reached. They stay due until
+    /// the round ends
+    pub fn due_events(&self) -> impl Iterator<Item = &ScheduledEvent> {
+        let ini = self.participants.get(self.current_idx).and_then(|p| p.ini);
+        self.events
+            .iter()
+            .filter(move |e| e.is_due(self.current_round, ini))
+    }
+
+    /// the events that happen in this round or later
+    pub fn upcoming_events(&self) -> impl Iterator<Item = &ScheduledEvent> {
+        self.events
+            .iter()
+            .filter(move |e| e.every_round || e.round >= self.current_round)
+    }
+
+    /// the index of the participant with that name, or the only one whose name starts with it
+    pub fn find_participant(&self, name: &str) -> Result<usize> {
+        let name = name.trim().to_lowercase();
+        let names: Vec<String> = self
+            .participants
+            .iter()
+            .map(|p| p.name.to_lowercase())
+            .collect();
+        if let Some(idx) = names.iter().position(|n| *n == name) {
+            return Ok(idx);
+        }
+        let candidates: Vec<usize> = (0..names.len())
+            .filter(|i| names[*i].starts_with(&name))
+            .collect();
+        match candidates.as_slice() {
+            [idx] => Ok(*idx),
+            [] => Err(anyhow!("There is no participant named {}", name)),
+            _ => Err(anyhow!("{} matches more than one participant", name)),
+        }
+    }
+
+    /// the participant with the name, or all members of the group with the name. Like with
+    /// find_participant, a name can be abbreviated if it is unique
+    pub fn find_targets(&self, name: &str) -> Result<Vec<usize>> {
+        let lower = name.trim().to_lowercase();
+        let is_participant = self
+            .participants
+            .iter()
+            .any(|p| p.name.to_lowercase() == lower);
+        let members: Vec<usize> = (0..self.participants.len())
+            .filter(|i| {
+                self.participants[*i]
+                    .group
+                    .as_ref()
+                    .is_some_and(|g| g.to_lowercase() == lower)
+            })
+            .collect();
+        if is_participant || members.is_empty() {
+            Ok(vec![self.find_participant(name)?])
+        } else {
+            Ok(members)
+        }
+    }
+
+    /// deals the damage, adjusted by the affinities of the target, and logs it
+    pub fn with_damage(mut self, n: usize, damage: &Damage) -> CombatState {
+        let p = Rc::make_mut(&mut self.participants[n]);
+        let affinities = damage
+            .r#type
+            .as_deref()
+            .map(|t| p.affinities(t))
+            .unwrap_or_default();
+        let mut amount = damage.amount;
+        let mut adjustments = vec![];
+        if damage.half {
+            amount /= 2;
+            adjustments.push("saved");
         }
+        for affinity in affinities {
+            let (adjusted, note) = match affinity {
+                Affinity::Immune => (0, "immune"),
+                Affinity::Resistant => (amount / 2, "halved"),
+                Affinity::Vulnerable => (amount.saturating_mul(2), "doubled"),
+            };
+            amount = adjusted;
+            adjustments.push(note);
+        }
+        p.take_damage(amount);
+        let type_str = damage
+            .r#type
+            .as_ref()
+            .map(|t| format!(" {}", t))
+            .unwrap_or_default();
+        let mut entry = format!("{} takes {}{} damage", p.name, amount, type_str);
+        if !adjustments.is_empty() {
+            entry.push_str(&format!(
+                " ({} {})",
+                damage.amount,
+                adjustments.join(", then ")
+            ));
+        }
+        self.log.push(LogEntry::Text(entry));
+        self.without_lapsed_concentrations()
+    }
+
+    /// ends the modifiers that the nth participant concentrates on, on all participants, and
+    /// logs it
+    pub fn without_concentration_of(mut self, n: usize) -> CombatState {
+        let caster = self.participants[n].name.clone();
+        let mut ended = vec![];
+        let is_linked = |m: &Modifier| m.concentration.as_ref() == Some(&caster);
+        let affected = self
+            .participants
+            .iter_mut()
+            .filter(|p| p.modifiers.iter().any(is_linked));
+        for p in affected.map(Rc::make_mut) {
+            p.modifiers.retain(|m| {
+                let linked = m.concentration.as_ref() == Some(&caster);
+                if linked {
+                    ended.push(format!("{} on {}", m.name, p.name));
+                }
+                !linked
+            });
+        }
+        if !ended.is_empty() {
+            let entry = format!("{} loses concentration: {}", caster, ended.join(", "));
+            self.log.push(LogEntry::Text(entry));
+        }
+        self
+    }
+
+    /// whether a modifier of any participant is concentrated on by the nth participant
+    pub fn concentrates(&self, n: usize) -> bool {
+        let caster = &self.participants[n].name;
+        self.participants
+            .iter()
+            .flat_map(|p| &p.modifiers)
+            .any(|m| m.concentration.as_ref() == Some(caster))
     }
-    pub fn with_nth_participant_popped(self, n: usize) -> (Self, Participant) {
+
+    /// casters that are down lose their concentration
+    fn without_lapsed_concentrations(self) -> CombatState {
+        (0..self.participants.len()).fold(self, |cs, n| {
+            if cs.participants[n].is_down() && cs.concentrates(n) {
+                cs.without_concentration_of(n)
+            } else {
+                cs
+            }
+        })
+    }
+
+    /// the caster of the modifier by its full name, so abbreviations like `Bless @al` work.
+    /// Errors if there is no such participant
+    pub fn with_caster_resolved(&self, fac: ModifierFac) -> Result<ModifierFac> {
+        let caster = match fac(self.now()).concentration {
+            Some(caster) => caster,
+            None => return Ok(fac),
+        };
+        let n = self.find_participant(&caster)?;
+        let name = self.participants[n].name.clone();
+        Ok(Box::new(move |start| {
+            fac(start).with_concentration(Some(name.clone()))
+        }))
+    }
+
+    /// remembers the HP of everybody, for with_hp_before_fight
+    pub fn with_fight_started(mut self) -> CombatState {
+        for p in self.participants.iter_mut().map(Rc::make_mut) {
+            p.hp_before_fight = Some(p.hp);
+        }
+        self.with_round_logged()
+    }
+
+    pub fn with_fight_ended(mut self) -> CombatState {
+        for p in self.participants.iter_mut().map(Rc::make_mut) {
+            p.hp_before_fight = None;
+            p.delayed = false;
+        }
+        self
+    }
+
+    /// restores the HP that everybody had when the fight started
+    pub fn with_hp_before_fight(mut self) -> CombatState {
+        for p in self.participants.iter_mut().map(Rc::make_mut) {
+            p.hp = p.hp_before_fight.unwrap_or(p.hp);
+        }
+        self
+    }
+
+    /// removes those that are dead, or at 0 HP without being stable
+    pub fn without_dead(mut self) -> CombatState {
+        let is_dead =
+            |p: &Participant| p.status == Status::Dead || (p.hp == 0 && p.status != Status::Stable);
+        // from the back, so the indices of the others don't change
+        for n in (0..self.participants.len()).rev() {
+            if is_dead(&self.participants[n]) {
+                self = self.without_participant(n);
+            }
+        }
+        self.current_idx = 0;
+        self
+    }
+
+    /// changes the status of the nth participant, and logs it
+    pub fn with_status(mut self, n: usize, status: Status) -> CombatState {
+        let p = Rc::make_mut(&mut self.participants[n]);
+        p.status = status;
+        let entry = format!("{} is {}", p.name, status);
+        self.log.push(LogEntry::Text(entry));
+        self.without_lapsed_concentrations()
+    }
+
+    pub fn with_nth_participant_popped(self, n: usize) -> (Self, Rc<Participant>) {
         let (res, participants) = utils::with_popped_n(self.participants, n);
         (
             CombatState {
@@ -123,15 +960,85 @@ impl CombatState {
         )
     }
 
-    pub fn without_participant(self, n: usize) -> Self {
-        self.update_participants(|mut ps| {
-            ps.remove(n);
-            ps
-        })
+    /// changes the nth participant in place. Prefer this over update_participants for frequent
+    /// operations like HP changes, as the participant vector isn't copied, and the participant
+    /// only if a snapshot of the history shares it
+    pub fn with_nth_participant_mut(mut self, n: usize, f: impl FnOnce(&mut Participant)) -> Self {
+        if let Some(p) = self.participants.get_mut(n) {
+            f(Rc::make_mut(p));
+        }
+        self
+    }
+
+    /// uses a charge of the mth modifier of the nth participant, and logs it. The modifier is
+    /// removed when no charge is left
+    pub fn with_charge_used(mut self, n: usize, m: usize) -> Self {
+        let p = Rc::make_mut(&mut self.participants[n]);
+        let left = match p.modifiers.get(m).and_then(|modifier| modifier.charges) {
+            Some(charges) => charges - 1,
+            None => return self,
+        };
+        let entry = if left == 0 {
+            let modifier = p.modifiers.remove(m);
+            format!("{} of {} is used up", modifier.name, p.name)
+        } else {
+            p.modifiers[m].charges = Some(left);
+            format!("{} uses {} ({} left)", p.name, p.modifiers[m].name, left)
+        };
+        self.log.push(LogEntry::Text(entry));
+        self
+    }
+
+    /// removes the participant, which can be restored with with_restored_participant
+    pub fn without_participant(mut self, n: usize) -> Self {
+        let p = self.participants.remove(n);
+        if self.graveyard.len() == GRAVEYARD_LEN {
+            self.graveyard.pop_back();
+        }
+        self.graveyard.push_front((n, p));
+        self
+    }
+
+    /// brings back the participant that was deleted last, at its old position if possible.
+    /// Returns the new index, or None if nobody was deleted
+    pub fn with_restored_participant(mut self) -> (Self, Option<usize>) {
+        match self.graveyard.pop_front() {
+            Some((n, p)) => {
+                let n = n.min(self.participants.len());
+                self.participants.insert(n, p);
+                (self, Some(n))
+            }
+            None => (self, None),
+        }
     }
 }
 
 impl Participant {
+    /// the participant in the syntax of the insert mode
+    pub fn input_line(&self) -> String {
+        let mut res = self.to_string();
+        // a rolled initiative replaces the bonus
+        match (self.ini, self.ini_bonus) {
+            (Some(ini), _) => res.push_str(&format!(": {}", ini)),
+            (None, Some(bonus)) => res.push_str(&format!(": {:+}", bonus)),
+            (None, None) => {}
+        }
+        if let Some(group) = &self.group {
+            res.push_str(&format!(" group={}", group));
+        }
+        if self.pc {
+            res.push_str(" pc");
+        }
+        for (name, value) in &self.stats {
+            res.push_str(&format!(" {}={}", name, value));
+        }
+        for (name, rolls) in &self.macros {
+            let rolls: Vec<String> = rolls.iter().map(|r| r.to_string()).collect();
+            res.push_str(&format!(" {}={}", name, rolls.join(",")));
+        }
+        res
+    }
+
     pub fn parse(s: &str) -> Result<Participant> {
         let splits: Vec<&str> = s.split(':').collect();
         Participant::parse_splits(splits)
@@ -141,21 +1048,292 @@ impl Participant {
         let mut splits: Vec<&str> = s.into_iter().collect();
 
         ensure!(splits.len() > 1, "Didn't find a :");
-        let hp_split = splits.pop().unwrap().trim();
-        let hp = hp_split
-            .parse()
-            .context(format!("parsing {} as u8", hp_split))?;
+        let (hp, max_hp, temp_hp) = parse_hp(splits.pop().unwrap().trim())?;
         Ok(Participant {
             hp,
+            max_hp,
+            temp_hp,
             name: splits.join(":"),
             modifiers: vec![],
+            has_acted: false,
+            ini: None,
+            ini_bonus: None,
+            stats: Stats::new(),
+            macros: Macros::new(),
+            status: Status::default(),
+            hp_before_fight: None,
+            notes: String::new(),
+            group: None,
+            delayed: false,
+            pc: false,
         })
     }
 }
 
+/// the order of n tied participants, by the indices they had. Everybody rolls a d20, and those
+/// that roll the same roll again, until nobody is tied anymore
+fn roll_off(n: usize) -> Vec<usize> {
+    let mut rolls: Vec<Vec<u16>> = vec![vec![]; n];
+    loop {
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|a, b| rolls[*b].cmp(&rolls[*a]));
+        let mut tied = vec![false; n];
+        for pair in order.windows(2) {
+            if rolls[pair[0]] == rolls[pair[1]] {
+                tied[pair[0]] = true;
+                tied[pair[1]] = true;
+            }
+        }
+        if !tied.contains(&true) {
+            return order;
+        }
+        for (i, rolls) in rolls.iter_mut().enumerate() {
+            if tied[i] {
+                rolls.push(utils::roll(1, 20));
+            }
+        }
+    }
+}
+
+/// what happened in the fight. Entries are turned into text only when they are shown or
+/// exported, so they can be merged and grouped by their fields
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogEntry {
+    /// a round starts
+    Round(usize),
+    /// the HP of a participant were changed with the HP keys. Consecutive changes of the same
+    /// participant are one entry
+    Hp {
+        who: String,
+        from: u16,
+        to: u16,
+    },
+    Text(String),
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogEntry::Round(round) => write!(f, "Round {}", round),
+            LogEntry::Hp { who, from, to } => write!(f, "{} HP: {} → {}", who, from, to),
+            LogEntry::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+/// parses `<HP>[/<Max HP>][+<Temp HP>]`
+fn parse_hp(s: &str) -> Result<(u16, Option<u16>, u16)> {
+    let (hp, temp_hp) = match s.split_once('+') {
+        Some((hp, temp)) => (
+            hp,
+            temp.trim()
+                .parse()
+                .context(format!("parsing {} as temporary HP", temp))?,
+        ),
+        None => (s, 0),
+    };
+    let (hp, max_hp) = match hp.split_once('/') {
+        Some((hp, max)) => (
+            hp,
+            Some(
+                max.trim()
+                    .parse()
+                    .context(format!("parsing {} as max HP", max))?,
+            ),
+        ),
+        None => (hp, None),
+    };
+    let hp = hp.trim().parse().context(format!("parsing {} as HP", hp))?;
+    if let Some(max_hp) = max_hp {
+        ensure!(
+            hp <= max_hp,
+            "{} HP are more than the max HP of {}",
+            hp,
+            max_hp
+        );
+    }
+    Ok((hp, max_hp, temp_hp))
+}
+
+impl Participant {
+    /// the HP in the syntax of the input, like `20/25+5`
+    pub fn hp_text(&self) -> String {
+        let mut res = self.hp.to_string();
+        if let Some(max_hp) = self.max_hp {
+            res.push_str(&format!("/{}", max_hp));
+        }
+        if self.temp_hp > 0 {
+            res.push_str(&format!("+{}", self.temp_hp));
+        }
+        res
+    }
+
+    /// the damage is taken from the temporary HP first
+    pub fn take_damage(&mut self, amount: u16) {
+        let from_temp = amount.min(self.temp_hp);
+        self.temp_hp -= from_temp;
+        self.hp = self.hp.saturating_sub(amount - from_temp);
+    }
+
+    /// heals up to the max HP
+    pub fn heal(&mut self, amount: u16) {
+        let hp = self.hp.saturating_add(amount);
+        self.hp = self
+            .max_hp
+            .map(|max| hp.min(max.max(self.hp)))
+            .unwrap_or(hp);
+    }
+
+    /// whether the participant is out of the fight
+    pub fn is_down(&self) -> bool {
+        self.hp == 0 || self.status != Status::Conscious
+    }
+
+    /// the affinities to the damage type, immunity first, as it makes the others irrelevant
+    pub fn affinities(&self, damage_type: &str) -> Vec<Affinity> {
+        let damage_type = damage_type.to_lowercase();
+        let mut res: Vec<Affinity> = self
+            .modifiers
+            .iter()
+            .filter_map(|m| {
+                let name = m.name.to_lowercase();
+                let (kind, t) = name.split_once(' ')?;
+                if t.trim() != damage_type {
+                    return None;
+                }
+                match kind {
+                    "resist" | "resistant" => Some(Affinity::Resistant),
+                    "vulnerable" => Some(Affinity::Vulnerable),
+                    "immune" => Some(Affinity::Immune),
+                    _ => None,
+                }
+            })
+            .collect();
+        res.sort_by_key(|a| *a != Affinity::Immune);
+        res.dedup();
+        if res.first() == Some(&Affinity::Immune) {
+            res.truncate(1);
+        }
+        res
+    }
+}
+
 impl fmt::Display for Participant {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}: {}", self.name, self.hp)
+        write!(f, "{}: {}", self.name, self.hp_text())
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Status::Conscious => "conscious",
+            Status::Stable => "stable",
+            Status::Dead => "dead",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl ScheduledEvent {
+    /// parses `<Text>:<Round>`, `<Text>:+<Rounds>` for an event that many rounds after the
+    /// current one, or `<Text>:*` for one that happens every round. All of them can be followed by
+    /// `@<Ini>`, to happen at that initiative count instead of the start of the round
+    pub fn parse(s: &str, current_round: usize) -> Result<ScheduledEvent> {
+        let format_err = || {
+            anyhow!(
+                "Events must have the format <Text>:<Round>, <Text>:+<Rounds> or <Text>:*, \
+                 optionally followed by @<Ini>"
+            )
+        };
+        let (text, when) = s.rsplit_once(':').ok_or_else(format_err)?;
+        let text = text.trim();
+        ensure!(!text.is_empty(), format_err());
+        let (round, ini) = match when.split_once('@') {
+            Some((round, ini)) => (
+                round.trim(),
+                Some(ini.trim().parse().context("Parsing the initiative")?),
+            ),
+            None => (when.trim(), None),
+        };
+        let every_round = round == "*";
+        let round = if every_round {
+            current_round
+        } else if let Some(n) = round.strip_prefix('+') {
+            current_round + n.trim().parse::<usize>().context("Parsing the rounds")?
+        } else {
+            round.parse().context("Parsing the round")?
+        };
+        ensure!(
+            round >= current_round,
+            "Round {} is over, the current round is {}",
+            round,
+            current_round
+        );
+        Ok(ScheduledEvent {
+            round,
+            text: text.into(),
+            ini,
+            every_round,
+        })
+    }
+
+    /// whether the event happens in the round, once the participant with the initiative has
+    /// their turn. Participants without initiative don't delay events
+    pub fn is_due(&self, round: usize, ini: Option<u8>) -> bool {
+        let round_reached = if self.every_round {
+            round >= self.round
+        } else {
+            round == self.round
+        };
+        let ini_reached = match (self.ini, ini) {
+            (Some(event_ini), Some(ini)) => ini <= event_ini,
+            _ => true,
+        };
+        round_reached && ini_reached
+    }
+}
+
+impl fmt::Display for ScheduledEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.every_round {
+            write!(f, "Every round")?;
+        } else {
+            write!(f, "Round {}", self.round)?;
+        }
+        if let Some(ini) = self.ini {
+            write!(f, " at {}", ini)?;
+        }
+        write!(f, ": {}", self.text)
+    }
+}
+
+impl Damage {
+    /// parses `<Target>:<Amount>` or `<Target>:<Amount> <Type>`. `<Amount>/2` halves the damage
+    pub fn parse(s: &str) -> Result<Damage> {
+        let format_err = || {
+            anyhow!(
+                "Damage must have the format <Target>:<Amount>[/2] or <Target>:<Amount>[/2] <Type>"
+            )
+        };
+        let (target, rest) = s.rsplit_once(':').ok_or_else(format_err)?;
+        let target = target.trim();
+        ensure!(!target.is_empty(), format_err());
+        let mut words = rest.split_whitespace();
+        let amount = words.next().ok_or_else(format_err)?;
+        let (amount, half) = match amount.strip_suffix("/2") {
+            Some(amount) => (amount, true),
+            None => (amount, false),
+        };
+        let amount = amount.parse().context("Parsing the amount")?;
+        let r#type = words.collect::<Vec<_>>().join(" ");
+        Ok(Damage {
+            target: target.into(),
+            amount,
+            r#type: (!r#type.is_empty()).then_some(r#type),
+            half,
+        })
     }
 }
 
@@ -166,25 +1344,93 @@ impl Modifier {
         let elems: Vec<&str> = s.split(":").collect();
         ensure!(
             elems.len() >= 1,
-            "Modifiers must have the following format: <Name>[:<Duration>]"
+            "Modifiers must have the following format: \
+             <Name>[ @<Caster>][ <HP>/round][ x<Charges>][:<Duration>]"
         );
-        let name = elems[0].trim().to_string();
-        match elems.len() {
-            1 => Ok(Box::new(move |start| {
-                Modifier::new(name.clone(), start, None)
-            })),
-            2 => {
-                let dur: usize = elems[1]
+        let template = Modifier::parse_label(elems[0])?;
+        let duration = match elems.len() {
+            1 => None,
+            2 => Some(
+                elems[1]
                     .trim()
                     .parse()
-                    .context("Parsing Modifier Duration")?;
-                Ok(Box::new(move |start| {
-                    Modifier::new(name.clone(), start, Some(dur))
-                }))
+                    .context("Parsing Modifier Duration")?,
+            ),
+            _ => bail!(
+                "Modifiers must have the following format: \
+                 <Name>[ @<Caster>][ <HP>/round][ x<Charges>][:<Duration>]"
+            ),
+        };
+        Ok(Box::new(move |start| {
+            template
+                .clone()
+                .with_introduced_at(start)
+                .with_duration(duration)
+        }))
+    }
+
+    /// the modifier of a label like `Hold Person @Alia -3/round x3`, without duration. The
+    /// caster, the HP per round and the charges are optional. The caster is marked with an @,
+    /// so names like `Blessed by Pelor` stay plain modifiers
+    pub fn parse_label(s: &str) -> Result<Modifier> {
+        let s = s.trim();
+        let charges = s
+            .rsplit_once(' ')
+            .and_then(|(name, last)| Some((name, last.strip_prefix('x')?)))
+            .filter(|(_, n)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        let (s, charges) = match charges {
+            Some((name, n)) => {
+                let n: u16 = n.parse().context("Parsing the charges")?;
+                ensure!(n > 0, "A modifier needs at least one charge");
+                (name.trim_end(), Some(n))
             }
-            _ => Err(anyhow!(
-                "Modifiers must have the following format: <Name>[:<Duration>]"
-            )),
+            None => (s, None),
+        };
+        let hp_per_round = s
+            .rsplit_once(' ')
+            .and_then(|(name, last)| Some((name, last.strip_suffix("/round")?)))
+            .filter(|(_, hp)| hp.starts_with(['+', '-']));
+        let (s, hp_per_round) = match hp_per_round {
+            Some((name, hp)) => {
+                let hp: i16 = hp.parse().context("Parsing the HP per round")?;
+                ensure!(hp != 0, "A modifier can't change the HP by 0 per round");
+                (name.trim_end(), Some(hp))
+            }
+            None => (s, None),
+        };
+        let (name, caster) = match s.rsplit_once(" @") {
+            Some((name, caster)) => {
+                ensure!(!caster.trim().is_empty(), "The caster is missing after @");
+                (name.trim_end(), Some(caster.trim().to_string()))
+            }
+            None => (s, None),
+        };
+        Ok(Modifier::new(name.to_string(), TimeVec::default(), None)
+            .with_concentration(caster)
+            .with_hp_per_round(hp_per_round)
+            .with_charges(charges))
+    }
+
+    /// the name, with the caster, the HP per round and the charges that are left
+    pub fn label(&self) -> String {
+        let mut res = self.name.clone();
+        if let Some(caster) = &self.concentration {
+            res.push_str(&format!(" @{}", caster));
+        }
+        if let Some(hp) = self.hp_per_round {
+            res.push_str(&format!(" {:+}/round", hp));
+        }
+        if let Some(charges) = self.charges {
+            res.push_str(&format!(" x{}", charges));
+        }
+        res
+    }
+
+    /// the modifier in the syntax of parse_factory, with the rounds it has left as duration
+    pub fn input_line(&self, now: &TimeVec) -> String {
+        match self.remaining_rounds(now) {
+            Some(rounds) => format!("{}:{}", self.label(), rounds),
+            None => self.label(),
         }
     }
 
@@ -0,0 +1,184 @@
+//! Condition presets, like Stunned for one round, which can be picked when adding modifiers
+//! instead of typing them. They are read from a TOML file, which lists them like this:
+//!
+//! ```toml
+//! [[condition]]
+//! name = "Stunned"
+//! duration = 1
+//! color = "yellow"
+//! ```
+//!
+//! The duration is in rounds, and the modifier lasts until it is removed without one. Without a
+//! file, a few common conditions are used.
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use tui::style::Color;
+
+use crate::combat_state::{Modifier, ModifierFac};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    pub name: String,
+    #[serde(default)]
+    pub duration: Option<usize>,
+    /// the name of the color the modifier is shown in, like `yellow` or `light-red`
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Conditions(pub Vec<Condition>);
+
+/// the layout of the conditions file
+#[derive(Deserialize)]
+struct ConditionsFile {
+    #[serde(default)]
+    condition: Vec<Condition>,
+}
+
+impl Default for Conditions {
+    fn default() -> Conditions {
+        let preset = |name: &str, duration, color: &str| Condition {
+            name: name.into(),
+            duration,
+            color: Some(color.into()),
+        };
+        Conditions(vec![
+            preset("Blessed", Some(10), "green"),
+            preset("Frightened", None, "magenta"),
+            preset("Grappled", None, "cyan"),
+            preset("Poisoned", None, "light-green"),
+            preset("Prone", None, "gray"),
+            preset("Stunned", Some(1), "yellow"),
+        ])
+    }
+}
+
+impl Conditions {
+    pub fn load(path: &Path) -> Result<Conditions> {
+        let content = fs::read_to_string(path).with_context(|| path.display().to_string())?;
+        Conditions::parse(&content).with_context(|| path.display().to_string())
+    }
+
+    pub fn parse(content: &str) -> Result<Conditions> {
+        let file: ConditionsFile = toml::from_str(content)?;
+        for c in &file.condition {
+            if let Some(name) = &c.color {
+                color(name).ok_or_else(|| anyhow!("{} of {} is not a color", name, c.name))?;
+            }
+        }
+        Ok(Conditions(file.condition))
+    }
+
+    /// the presets whose name contains the query, ignoring case
+    pub fn search(&self, query: &str) -> Vec<&Condition> {
+        let query = query.trim().to_lowercase();
+        self.0
+            .iter()
+            .filter(|c| c.name.to_lowercase().contains(&query))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Condition> {
+        self.0.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    /// parses a modifier like Modifier::parse_factory. If it is named like a preset, it gets the
+    /// color of the preset, and its duration if none is given
+    pub fn parse_factory(&self, s: &str) -> Result<ModifierFac> {
+        let fac = Modifier::parse_factory(s)?;
+        let name = Modifier::parse_label(s.split(':').next().unwrap_or_default())?.name;
+        match self.get(&name).cloned() {
+            Some(preset) => Ok(Box::new(move |start| {
+                let modifier = fac(start);
+                let duration = modifier.duration.or(preset.duration);
+                modifier
+                    .with_duration(duration)
+                    .with_color(preset.color.clone())
+            })),
+            None => Ok(fac),
+        }
+    }
+}
+
+impl Condition {
+    pub fn factory(&self) -> ModifierFac {
+        let preset = self.clone();
+        Box::new(move |start| {
+            Modifier::new(preset.name.clone(), start, preset.duration)
+                .with_color(preset.color.clone())
+        })
+    }
+}
+
+/// the color with the name, ignoring case and separators, so `Light-Red` is LightRed
+pub fn color(name: &str) -> Option<Color> {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .collect::<String>()
+        .to_lowercase();
+    Some(match name.as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combat_state::TimeVec;
+
+    #[test]
+    fn test_conditions() {
+        let conditions = Conditions::parse(
+            "[[condition]]\nname = \"Stunned\"\nduration = 1\ncolor = \"Light-Red\"\n\n\
+             [[condition]]\nname = \"Hasted\"\n",
+        )
+        .unwrap();
+        assert_eq!(conditions.0.len(), 2);
+        assert_eq!(
+            conditions.search("st"),
+            [&conditions.0[0], &conditions.0[1]]
+        );
+        assert_eq!(conditions.search("hAs"), [&conditions.0[1]]);
+
+        let now = TimeVec::default();
+        let stunned = conditions.parse_factory("stunned").unwrap()(now);
+        assert_eq!(
+            (stunned.duration, stunned.color.as_deref()),
+            (Some(1), Some("Light-Red"))
+        );
+        assert_eq!(
+            conditions.parse_factory("Stunned: 3").unwrap()(now).duration,
+            Some(3)
+        );
+        assert_eq!(conditions.parse_factory("Hexed").unwrap()(now).color, None);
+
+        assert!(Conditions::parse("[[condition]]\nname = \"Hasted\"\ncolor = \"plaid\"").is_err());
+        assert!(Conditions::parse("[[condition]]\nduration = 1").is_err());
+        assert!(Conditions::parse("").unwrap().0.is_empty());
+        // the example file has the built-in presets
+        assert_eq!(
+            Conditions::parse(include_str!("../conditions.toml")).unwrap(),
+            Conditions::default()
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! `combat-tracker fmt` reads participant files, and writes them back as one normalized file:
+//! one `<Name>: <HP>[/<Max HP>][+<Temp HP>][: <Ini>][ group=<Group>][ <Stat>=<Value>...]
+//! [ <Macro>=<Rolls>...]` per line, without duplicates, and sorted.
+use anyhow::{anyhow, Context, Result};
+use std::{fmt, fs, path::PathBuf, str::FromStr};
+
+use crate::{
+    combat_state::{Macros, Participant, Stats},
+    utils,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// highest initiative first, participants without initiative last
+    Ini,
+    Name,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SortKey, String> {
+        match s {
+            "ini" => Ok(SortKey::Ini),
+            "name" => Ok(SortKey::Name),
+            _ => Err(format!("{} is not a sort key, use ini or name", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub ini: Option<u8>,
+    pub ini_bonus: Option<i64>,
+    pub name: String,
+    /// like `20/25+5`
+    pub hp: String,
+    pub group: Option<String>,
+    pub pc: bool,
+    pub stats: Stats,
+    pub macros: Macros,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.hp)?;
+        if let Some(ini) = self.ini {
+            write!(f, ": {}", ini)?;
+        } else if let Some(bonus) = self.ini_bonus {
+            write!(f, ": {:+}", bonus)?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, " group={}", group)?;
+        }
+        if self.pc {
+            write!(f, " pc")?;
+        }
+        for (name, value) in &self.stats {
+            write!(f, " {}={}", name, value)?;
+        }
+        for (name, rolls) in &self.macros {
+            let rolls: Vec<String> = rolls.iter().map(|r| r.to_string()).collect();
+            write!(f, " {}={}", name, rolls.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// parses the lines of a participant file, empty lines are skipped. Errors name the line they
+/// occurred in.
+pub fn parse(source: &str, content: &str) -> Result<Vec<Line>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let p = utils::parse_participant_with_ini(line)
+                .with_context(|| format!("{}:{}: invalid participant {:?}", source, i + 1, line))?;
+            let hp = p.hp_text();
+            let Participant {
+                name,
+                ini,
+                ini_bonus,
+                group,
+                pc,
+                stats,
+                macros,
+                ..
+            } = p;
+            let name = name.trim().to_string();
+            if name.is_empty() {
+                return Err(anyhow!("{}:{}: the name is missing", source, i + 1));
+            }
+            Ok(Line {
+                ini,
+                ini_bonus,
+                name,
+                hp,
+                group,
+                pc,
+                stats,
+                macros,
+            })
+        })
+        .collect()
+}
+
+/// removes all but the first line of every name, and sorts the rest. The returned warnings name
+/// the duplicates that differed from the line that was kept
+pub fn normalize(lines: Vec<Line>, sort: SortKey) -> (Vec<Line>, Vec<String>) {
+    let mut res: Vec<Line> = Vec::with_capacity(lines.len());
+    let mut warnings = vec![];
+    for line in lines {
+        match res.iter().find(|l| l.name == line.name) {
+            Some(kept) if *kept != line => {
+                warnings.push(format!("Dropped \"{}\", as \"{}\" came first", line, kept))
+            }
+            Some(_) => {}
+            None => res.push(line),
+        }
+    }
+    match sort {
+        SortKey::Ini => res.sort_by_key(|l| std::cmp::Reverse(l.ini)),
+        SortKey::Name => res.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+    (res, warnings)
+}
+
+/// reads the files and writes the normalized result to output, or stdout. Warnings go to stderr
+pub fn run(files: &[PathBuf], sort: SortKey, output: Option<&PathBuf>) -> Result<()> {
+    let mut lines = vec![];
+    for file in files {
+        let content =
+            fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+        lines.extend(parse(&file.display().to_string(), &content)?);
+    }
+    let (lines, warnings) = normalize(lines, sort);
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    let content: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+    match output {
+        Some(path) => {
+            fs::write(path, content).with_context(|| format!("writing {}", path.display()))
+        }
+        None => {
+            print!("{}", content);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let mut lines = parse("a.txt", "Orc:10:12\n\nGoblin : 7 dex=2\n").unwrap();
+        lines.extend(parse("b.txt", "Elf: 8: 15\nOrc: 10: 12\nGoblin: 9").unwrap());
+        let (by_ini, warnings) = normalize(lines.clone(), SortKey::Ini);
+        let by_ini: Vec<String> = by_ini.iter().map(Line::to_string).collect();
+        assert_eq!(by_ini, ["Elf: 8: 15", "Orc: 10: 12", "Goblin: 7 DEX=2"]);
+        assert_eq!(
+            warnings,
+            ["Dropped \"Goblin: 9\", as \"Goblin: 7 DEX=2\" came first"]
+        );
+
+        let (by_name, _) = normalize(lines, SortKey::Name);
+        let names: Vec<&str> = by_name.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["Elf", "Goblin", "Orc"]);
+
+        let err = parse("c.txt", "Orc: 10\nTroll: many").unwrap_err();
+        assert!(format!("{}", err).starts_with("c.txt:2:"));
+    }
+}
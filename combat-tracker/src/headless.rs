@@ -0,0 +1,253 @@
+//! The tracker without a terminal, for bots, overlays and scripts. Every line of the input is a
+//! command as JSON, like `{"command": "damage", "damage": "Goblin: 3 fire"}`, and every command is
+//! answered with one line of JSON on the output: a snapshot of the fight, or `{"error": "..."}`.
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::{
+    combat_state::{CombatState, Damage, TurnOrder},
+    utils,
+};
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case", deny_unknown_fields)]
+enum Command {
+    /// a participant line, like in the participant files
+    Add {
+        participant: String,
+    },
+    /// rolls the missing initiatives and starts the fight
+    Start,
+    /// like the quick damage prompt: `<Target>:<Amount>[/2] [<Type>]`
+    Damage {
+        damage: String,
+    },
+    NextTurn,
+    /// ends the fight and keeps the current HP
+    End,
+    Undo,
+    Redo,
+}
+
+#[derive(Serialize)]
+struct Snapshot {
+    fighting: bool,
+    round: usize,
+    /// whose turn it is, during the fight
+    current: Option<String>,
+    participants: Vec<ParticipantSnapshot>,
+    log: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ParticipantSnapshot {
+    name: String,
+    hp: u16,
+    max_hp: Option<u16>,
+    temp_hp: u16,
+    ini: Option<u8>,
+    status: String,
+    group: Option<String>,
+    /// like `Bless:3`, with the rounds that are left
+    modifiers: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ErrorReply {
+    error: String,
+}
+
+/// the combat state, and whether the fight was started and not ended yet. Like the mode of the
+/// terminal app, the phase only changes with start and end, not with undo and redo
+#[derive(Clone)]
+struct Session {
+    cs: CombatState,
+    fighting: bool,
+}
+
+fn execute(session: Session, command: Command) -> Result<Session> {
+    let Session { cs, fighting } = session;
+    let cs = match command {
+        Command::Add { participant } => {
+            let p = utils::parse_participant_with_ini(&participant)
+                .with_context(|| format!("invalid participant {:?}", participant))?;
+            cs.recorded(|cs| {
+                cs.update_participants(|mut ps| {
+                    ps.push(Rc::new(p));
+                    ps
+                })
+            })
+        }
+        Command::Start => {
+            if fighting {
+                bail!("The fight was started already");
+            }
+            if cs.participants.is_empty() {
+                bail!("A fight needs at least one participant");
+            }
+            let cs = cs.recorded(|cs| cs.with_rolled_initiatives().with_fight_started());
+            return Ok(Session { cs, fighting: true });
+        }
+        Command::Damage { damage } => {
+            let damage = Damage::parse(&damage)?;
+            let targets = cs.find_targets(&damage.target)?;
+            cs.recorded(|cs| {
+                targets
+                    .into_iter()
+                    .fold(cs, |cs, target| cs.with_damage(target, &damage))
+            })
+        }
+        Command::NextTurn => {
+            if !fighting {
+                bail!("The fight wasn't started yet");
+            }
+            if cs.turn_order == TurnOrder::Popcorn {
+                bail!("Popcorn initiative can't be used without a terminal");
+            }
+            cs.recorded(CombatState::with_next_turn)
+        }
+        Command::End => {
+            if !fighting {
+                bail!("The fight wasn't started yet");
+            }
+            let cs = cs.recorded(CombatState::with_fight_ended);
+            return Ok(Session {
+                cs,
+                fighting: false,
+            });
+        }
+        Command::Undo => cs.undone(),
+        Command::Redo => cs.redone(),
+    };
+    Ok(Session { cs, fighting })
+}
+
+fn snapshot(session: &Session) -> Snapshot {
+    let (cs, fighting) = (&session.cs, session.fighting);
+    let now = cs.now();
+    Snapshot {
+        fighting,
+        round: cs.current_round,
+        current: fighting.then(|| cs.participants[cs.current_idx].name.clone()),
+        participants: cs
+            .participants
+            .iter()
+            .map(|p| ParticipantSnapshot {
+                name: p.name.clone(),
+                hp: p.hp,
+                max_hp: p.max_hp,
+                temp_hp: p.temp_hp,
+                ini: p.ini,
+                status: p.status.to_string(),
+                group: p.group.clone(),
+                modifiers: p.modifiers.iter().map(|m| m.input_line(&now)).collect(),
+            })
+            .collect(),
+        log: cs.log.iter().map(ToString::to_string).collect(),
+    }
+}
+
+/// answers the commands of input until it ends. Invalid commands are answered with an error, and
+/// don't stop the loop
+pub fn run(
+    input: impl BufRead,
+    mut output: impl Write,
+    cs: CombatState,
+    fighting: bool,
+) -> Result<()> {
+    let mut session = Session { cs, fighting };
+    for line in input.lines() {
+        let line = line.context("reading a command")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let res = serde_json::from_str(&line)
+            .context("invalid command")
+            .and_then(|command| execute(session.clone(), command));
+        let reply = match res {
+            Ok(new_session) => {
+                session = new_session;
+                serde_json::to_string(&snapshot(&session))?
+            }
+            // the causes on one line, without a backtrace
+            Err(e) => serde_json::to_string(&ErrorReply {
+                error: format!("{:#}", e),
+            })?,
+        };
+        writeln!(output, "{}", reply)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replies(commands: &[&str]) -> Vec<serde_json::Value> {
+        let mut output = vec![];
+        run(
+            commands.join("\n").as_bytes(),
+            &mut output,
+            CombatState::default(),
+            false,
+        )
+        .unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_headless() {
+        let replies = replies(&[
+            r#"{"command": "add", "participant": "Orc: 10: 12"}"#,
+            r#"{"command": "add", "participant": "Goblin: 7: 8"}"#,
+            r#"{"command": "next-turn"}"#,
+            r#"{"command": "start"}"#,
+            r#"{"command": "damage", "damage": "Goblin: 3"}"#,
+            r#"{"command": "next-turn"}"#,
+            r#"{"command": "fly"}"#,
+            r#"{"command": "undo"}"#,
+            r#"{"command": "end"}"#,
+        ]);
+        assert_eq!(replies.len(), 9);
+        assert_eq!(replies[1]["participants"][1]["name"], "Goblin");
+        assert_eq!(replies[1]["fighting"], false);
+        assert_eq!(replies[2]["error"], "The fight wasn't started yet");
+        assert_eq!(replies[3]["current"], "Orc");
+        assert_eq!(replies[4]["participants"][1]["hp"], 4);
+        assert_eq!(replies[5]["current"], "Goblin");
+        assert_eq!(replies[5]["round"], 0);
+        assert!(replies[6]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid command"));
+        assert_eq!(replies[7]["current"], "Orc");
+        assert_eq!(replies[8]["fighting"], false);
+        assert_eq!(replies[8]["participants"][1]["hp"], 4);
+    }
+
+    #[test]
+    fn test_headless_phase() {
+        let replies = replies(&[
+            r#"{"command": "add", "participant": "Orc: 10: 12"}"#,
+            r#"{"command": "start"}"#,
+            r#"{"command": "end"}"#,
+            r#"{"command": "undo"}"#,
+            r#"{"command": "next-turn"}"#,
+            r#"{"command": "start"}"#,
+        ]);
+        assert_eq!(replies[1]["fighting"], true);
+        assert_eq!(replies[2]["fighting"], false);
+        // undoing the end brings back the state of the fight, but doesn't resume it
+        assert_eq!(replies[3]["fighting"], false);
+        assert_eq!(replies[3]["current"], serde_json::Value::Null);
+        assert_eq!(replies[4]["error"], "The fight wasn't started yet");
+        assert_eq!(replies[5]["fighting"], true);
+    }
+}
@@ -0,0 +1,396 @@
+//! The keys of the commands, for keyboard layouts on which the built-in ones are awkward. They are
+//! read from a TOML file, which is written with the built-in keys on the first run:
+//!
+//! ```toml
+//! # the keys that change the rows of the fight, three per row
+//! fight-rows = "',.aoe;qj"
+//!
+//! [normal]
+//! # the command down of the participant list is triggered with h instead of j
+//! down = "h"
+//! ```
+//!
+//! Every section belongs to a few states, see keymap.toml for all commands. The keymap is applied
+//! after the profiles, so profiles use the keys of the keymap, and the key hints show them too.
+use anyhow::{bail, ensure, Context, Result};
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use crate::{profiles::Key, states::State};
+
+pub const DEFAULT_KEYMAP: &str = include_str!("../keymap.toml");
+pub const DEFAULT_FIGHT_ROWS: &str = "qweasdzxcrtyfghvbnuiojklm,.;p/QWEASDZXCRTYFGHVBNUIOJKLM<>P:?";
+
+/// the fight rows of the keymap that was loaded at startup
+static FIGHT_ROWS: OnceCell<String> = OnceCell::new();
+
+thread_local! {
+    /// the keymap whose keys the states show in their key hints. The app draws on one thread,
+    /// and every test drives its states on its own
+    static HINT_KEYMAP: RefCell<Keymap> = RefCell::new(Keymap::default());
+}
+
+/// the states that share a section of the keymap
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Normal,
+    Fight,
+    /// picking who is next, who ends their delay, breaking ties and rolling macros
+    List,
+    Status,
+    EndFight,
+}
+
+/// the scope, name and built-in key of every command that can be rebound
+const COMMANDS: &[(Scope, &str, &str)] = &[
+    (Scope::Normal, "down", "j"),
+    (Scope::Normal, "up", "k"),
+    (Scope::Normal, "move-down", "J"),
+    (Scope::Normal, "move-up", "K"),
+    (Scope::Normal, "change", "c"),
+    (Scope::Normal, "edit-ini", "e"),
+    (Scope::Normal, "notes", "n"),
+    (Scope::Normal, "delete", "d"),
+    (Scope::Normal, "restore", "R"),
+    (Scope::Normal, "roll-ini", "r"),
+    (Scope::Normal, "tie-break", "t"),
+    (Scope::Normal, "sort", "s"),
+    (Scope::Normal, "sort-by", "o"),
+    (Scope::Normal, "lock-order", "l"),
+    (Scope::Normal, "insert", "i"),
+    (Scope::Normal, "popcorn", "p"),
+    (Scope::Normal, "skip-down", "x"),
+    (Scope::Normal, "undo", "u"),
+    (Scope::Normal, "redo", "ctrl+r"),
+    (Scope::Normal, "schedule-event", "ctrl+e"),
+    (Scope::Normal, "save", "ctrl+s"),
+    (Scope::Normal, "load", "ctrl+o"),
+    (Scope::Fight, "next-turn", "ctrl+n"),
+    (Scope::Fight, "delay", "ctrl+y"),
+    (Scope::Fight, "end-delay", "ctrl+g"),
+    (Scope::Fight, "schedule-event", "ctrl+e"),
+    (Scope::Fight, "damage", "ctrl+d"),
+    (Scope::Fight, "modifiers", "ctrl+t"),
+    (Scope::Fight, "status", "ctrl+x"),
+    (Scope::Fight, "use-charge", "ctrl+v"),
+    (Scope::Fight, "break-concentration", "ctrl+k"),
+    (Scope::Fight, "notes", "ctrl+b"),
+    (Scope::Fight, "roll-macro", "ctrl+a"),
+    (Scope::Fight, "save", "ctrl+s"),
+    (Scope::Fight, "undo", "ctrl+u"),
+    (Scope::Fight, "redo", "ctrl+r"),
+    (Scope::Fight, "log", "ctrl+l"),
+    (Scope::Fight, "timer", "ctrl+w"),
+    (Scope::List, "down", "j"),
+    (Scope::List, "up", "k"),
+    (Scope::Status, "down", "j"),
+    (Scope::Status, "up", "k"),
+    (Scope::Status, "conscious", "c"),
+    (Scope::Status, "stable", "s"),
+    (Scope::Status, "dead", "d"),
+    (Scope::EndFight, "keep", "k"),
+    (Scope::EndFight, "restore-hp", "r"),
+    (Scope::EndFight, "remove-dead", "d"),
+    (Scope::EndFight, "export-log", "l"),
+    (Scope::EndFight, "export-result", "x"),
+];
+
+/// the pressed key, and the built-in key of the command it triggers. None for built-in keys
+/// whose command was moved to another key
+type Translation = HashMap<Key, Option<Key>>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    keys: HashMap<Scope, Translation>,
+    pub fight_rows: String,
+}
+
+/// the layout of the keymap file
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct KeymapFile {
+    fight_rows: Option<String>,
+    #[serde(default)]
+    normal: HashMap<String, String>,
+    #[serde(default)]
+    fight: HashMap<String, String>,
+    #[serde(default)]
+    list: HashMap<String, String>,
+    #[serde(default)]
+    status: HashMap<String, String>,
+    #[serde(default)]
+    end_fight: HashMap<String, String>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap {
+            keys: HashMap::new(),
+            fight_rows: DEFAULT_FIGHT_ROWS.into(),
+        }
+    }
+}
+
+impl Scope {
+    fn section(&self) -> &'static str {
+        match self {
+            Scope::Normal => "normal",
+            Scope::Fight => "fight",
+            Scope::List => "list",
+            Scope::Status => "status",
+            Scope::EndFight => "end-fight",
+        }
+    }
+}
+
+impl Keymap {
+    pub fn load(path: &Path) -> Result<Keymap> {
+        let content = fs::read_to_string(path).with_context(|| path.display().to_string())?;
+        Keymap::parse(&content).with_context(|| path.display().to_string())
+    }
+
+    /// loads the keymap from the config dir, and writes the default one there if there is none
+    pub fn load_or_create() -> Result<Keymap> {
+        let path = match dirs::config_dir() {
+            Some(dir) => dir.join("combat-tracker").join("keymap.toml"),
+            None => return Ok(Keymap::default()),
+        };
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())
+                .and_then(|_| fs::write(&path, DEFAULT_KEYMAP))
+                .with_context(|| format!("writing the default keymap to {}", path.display()))?;
+        }
+        Keymap::load(&path)
+    }
+
+    pub fn parse(content: &str) -> Result<Keymap> {
+        let file: KeymapFile = toml::from_str(content)?;
+        let sections = [
+            (Scope::Normal, &file.normal),
+            (Scope::Fight, &file.fight),
+            (Scope::List, &file.list),
+            (Scope::Status, &file.status),
+            (Scope::EndFight, &file.end_fight),
+        ];
+        let mut keys = HashMap::new();
+        let mut fight_keys = HashSet::new();
+        for (scope, section) in sections {
+            let (translation, bound) = bindings(scope, section)
+                .with_context(|| format!("in the section {}", scope.section()))?;
+            if scope == Scope::Fight {
+                fight_keys = bound;
+            }
+            keys.insert(scope, translation);
+        }
+        let fight_rows = file.fight_rows.unwrap_or_else(|| DEFAULT_FIGHT_ROWS.into());
+        check_fight_rows(&fight_rows, &fight_keys).context("in the fight rows")?;
+        Ok(Keymap { keys, fight_rows })
+    }
+
+    /// the event the state should process, or None if the key doesn't trigger a command anymore
+    pub fn translate(&self, state: &dyn State, ev: Event) -> Option<Event> {
+        let key_event = match ev {
+            Event::Key(key_event) => key_event,
+            ev => return Some(ev),
+        };
+        if state.has_input() {
+            return Some(ev);
+        }
+        let keys = match state.key_scope().and_then(|scope| self.keys.get(&scope)) {
+            Some(keys) => keys,
+            None => return Some(ev),
+        };
+        match keys.get(&Key::from(key_event)) {
+            Some(Some(command)) => Some(command.to_event()),
+            Some(None) => None,
+            None => Some(ev),
+        }
+    }
+
+    /// makes the fight rows the ones that are used by every fight, which can only be done once,
+    /// and shows the keys of the keymap in the key hints
+    pub fn install(&self) {
+        let _ = FIGHT_ROWS.set(self.fight_rows.clone());
+        self.show_in_hints();
+    }
+
+    /// makes the key hints of the states that are drawn on this thread show the keys of this
+    /// keymap
+    pub fn show_in_hints(&self) {
+        HINT_KEYMAP.with(|keymap| *keymap.borrow_mut() = self.clone());
+    }
+
+    /// the key that triggers the command with the built-in key
+    fn key_of(&self, scope: Scope, builtin: Key) -> Key {
+        self.keys
+            .get(&scope)
+            .and_then(|keys| keys.iter().find(|(_, command)| **command == Some(builtin)))
+            .map(|(key, _)| *key)
+            .unwrap_or(builtin)
+    }
+}
+
+/// the key of a command for the key hints, like `ctrl+n`. Panics if the scope has no such command
+pub fn key(scope: Scope, command: &str) -> String {
+    let (_, _, builtin) = COMMANDS
+        .iter()
+        .find(|(s, name, _)| *s == scope && *name == command)
+        .unwrap_or_else(|| panic!("{} is not a command of {}", command, scope.section()));
+    let builtin = Key::parse(builtin).expect("the built-in keys are valid");
+    HINT_KEYMAP.with(|keymap| keymap.borrow().key_of(scope, builtin).to_string())
+}
+
+/// the fight rows of the keymap that was installed, or the built-in ones
+pub fn fight_rows() -> &'static str {
+    FIGHT_ROWS
+        .get()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_FIGHT_ROWS)
+}
+
+/// enter, esc, the arrows and the digits keep their meaning in every state, and ctrl+c quits
+fn is_reserved(key: &Key) -> bool {
+    let plain = |code| Key::new(code, KeyModifiers::NONE);
+    [KeyCode::Enter, KeyCode::Esc, KeyCode::Up, KeyCode::Down]
+        .into_iter()
+        .map(plain)
+        .any(|k| k == *key)
+        || ('1'..='9').any(|c| plain(KeyCode::Char(c)) == *key)
+        || *key == Key::new(KeyCode::Char('c'), KeyModifiers::CONTROL)
+}
+
+/// the translation of the keys of one section, and the keys that trigger its commands
+fn bindings(
+    scope: Scope,
+    section: &HashMap<String, String>,
+) -> Result<(Translation, HashSet<Key>)> {
+    let commands: Vec<_> = COMMANDS.iter().filter(|(s, ..)| *s == scope).collect();
+    for name in section.keys() {
+        ensure!(
+            commands.iter().any(|(_, command, _)| command == name),
+            "{} is not a command",
+            name
+        );
+    }
+    // the pressed key, and the name and built-in key of its command
+    let mut bound: HashMap<Key, (&str, Key)> = HashMap::new();
+    for (_, name, default) in commands {
+        let builtin = Key::parse(default)?;
+        let key = match section.get(*name) {
+            Some(key) => Key::parse(key).with_context(|| format!("the key of {}", name))?,
+            None => builtin,
+        };
+        ensure!(!is_reserved(&key), "{} can't be bound to {}", name, key);
+        if let Some((other, _)) = bound.insert(key, (name, builtin)) {
+            bail!("{} and {} are both bound to {}", other, name, key);
+        }
+    }
+    let mut res: Translation = bound
+        .iter()
+        .filter(|(key, (_, builtin))| key != &builtin)
+        .map(|(key, (_, builtin))| (*key, Some(*builtin)))
+        .collect();
+    for (_, builtin) in bound.values() {
+        if !bound.contains_key(builtin) {
+            res.insert(*builtin, None);
+        }
+    }
+    Ok((res, bound.into_keys().collect()))
+}
+
+/// three unique chars per row, which don't trigger other commands of the fight
+fn check_fight_rows(rows: &str, fight_keys: &HashSet<Key>) -> Result<()> {
+    let chars: Vec<char> = rows.chars().collect();
+    ensure!(
+        !chars.is_empty() && chars.len().is_multiple_of(3),
+        "There must be three keys per row, but there are {}",
+        chars.len()
+    );
+    let mut seen = HashSet::new();
+    for c in chars {
+        let key = Key::new(KeyCode::Char(c), KeyModifiers::NONE);
+        ensure!(seen.insert(c), "{} is used twice", c);
+        ensure!(
+            !is_reserved(&key) && !fight_keys.contains(&key),
+            "{} is also a command",
+            c
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::Driver;
+    use crossterm::event::KeyCode;
+
+    #[test]
+    fn test_keymap_parse() {
+        let default = Keymap::parse(DEFAULT_KEYMAP).unwrap();
+        assert!(default.keys.values().all(HashMap::is_empty));
+        assert_eq!(default.fight_rows, DEFAULT_FIGHT_ROWS);
+        assert_eq!(Keymap::parse("").unwrap(), default);
+
+        let keymap = Keymap::parse("[normal]\ndown = \"h\"\nup = \"j\"\n").unwrap();
+        let key = |s| Key::parse(s).unwrap();
+        let normal = &keymap.keys[&Scope::Normal];
+        assert_eq!(normal[&key("h")], Some(key("j")));
+        assert_eq!(normal[&key("j")], Some(key("k")));
+        // k doesn't trigger up anymore
+        assert_eq!(normal[&key("k")], None);
+        assert_eq!(normal.len(), 3);
+
+        for invalid in [
+            "[normal]\ndown = \"k\"",
+            "[normal]\nflip = \"f\"",
+            "[normal]\ndown = \"enter\"",
+            "[fight]\nlog = \"q\"",
+            "[figt]\nlog = \"ctrl+q\"",
+            "fight-rows = \"qwea\"",
+            "fight-rows = \"qwq\"",
+            "fight-rows = \"123\"",
+        ] {
+            assert!(Keymap::parse(invalid).is_err(), "{}", invalid);
+        }
+        // the same key can be used in different sections
+        assert!(Keymap::parse("[fight]\nlog = \"ctrl+o\"\n[list]\ndown = \"ctrl+o\"").is_ok());
+    }
+
+    #[test]
+    fn test_keymap() {
+        let keymap = Keymap::parse(
+            "[normal]\ndown = \"h\"\nchange = \"C\"\n[fight]\nnext-turn = \"ctrl+q\"\n\
+             [end-fight]\nkeep = \"ctrl+k\"",
+        )
+        .unwrap();
+        let mut d = Driver::new(Insert::default().boxed()).with_keymap(keymap);
+        d.line("Orc: 10").line("Goblin: 7").key(KeyCode::Esc);
+        // the key hints show the keys of the keymap
+        assert!(d.screen_contains("- C: change; e: edit ini"));
+
+        // j does nothing anymore, and keys that are typed into an input are not remapped
+        d.type_str("jhe");
+        assert_eq!(d.state().title(), "Editing Initiative of Goblin");
+        d.line("12");
+        assert_eq!(d.combat_state().participants[1].ini, Some(12));
+
+        d.key(KeyCode::Enter);
+        assert!(d.screen_contains("- esc: end fight; ctrl+q: next turn"));
+        d.ctrl('q');
+        assert_eq!(d.combat_state().current_idx, 1);
+        d.key(KeyCode::Esc).type_str("k");
+        assert_eq!(d.state().title(), "Ending Fight");
+        assert!(d.screen_contains("- ctrl+k or enter: keep current HP"));
+        d.ctrl('k');
+        assert_eq!(d.state().title(), "Participants");
+    }
+}
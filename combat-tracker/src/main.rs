@@ -1,97 +1,289 @@
 use anyhow::{Context, Result};
 use argh::FromArgs;
-use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-};
-
-use std::{fs, io, path::PathBuf};
-use tui::{backend::CrosstermBackend, Terminal};
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+
+use std::{fs, path::PathBuf};
 // use unicode_width::UnicodeWidthStr;
 
+mod backend;
 mod combat_state;
+mod conditions;
+mod fmt;
+mod headless;
+mod keymap;
+mod profiles;
+mod save;
+mod share;
 mod states;
 mod utils;
 mod view_utils;
 
-use states::{Boxable, StateBox};
+#[cfg(test)]
+mod test_utils;
+
+use backend::Backend;
+use states::StateBox;
 
 pub type Frame<'a> = tui::Frame<'a, Backend>;
-pub type Backend = CrosstermBackend<io::Stdout>;
 
 #[derive(FromArgs)]
 /// Pass a List of files to prepopulate the fight
 struct Cli {
+    #[argh(subcommand)]
+    command: Option<CliCommand>,
+    #[argh(option, short = 'l')]
+    /// an encounter that was saved with ctrl+s, to resume instead of loading files
+    load: Option<PathBuf>,
+    #[argh(option)]
+    /// how initiatives are rolled, like 1d20+DEX or 2d20kh+DEX, 2d6 by default. Stats like DEX
+    /// are given after the HP or initiative of a participant: "Goblin: 7 DEX=2". Roll macros
+    /// follow the same way, and are rolled with ctrl+a in the fight:
+    /// "Goblin: 7 Scimitar=1d20+4,1d6+2". An initiative bonus is added to the roll: "Goblin: 7: +3"
+    ini: Option<utils::DiceExpr>,
+    #[argh(option)]
+    /// how participants with the same initiative are ordered: dex (the default) puts the higher
+    /// DEX stat first, reroll lets them roll a d20 against each other, and ask lets you order
+    /// them. It can be switched with t before the fight
+    tie_break: Option<combat_state::TieBreak>,
+    #[argh(switch)]
+    /// ring the terminal bell when a round starts
+    bell: bool,
+    #[argh(option)]
+    /// serve a read-only view of the fight for the players over HTTP, like 0.0.0.0:8080. Only
+    /// the HP of player characters are shown, which are marked with pc: "Kidd: 8 pc". Needs the
+    /// share feature
+    share: Option<std::net::SocketAddr>,
+    #[argh(switch)]
+    /// run without a terminal: commands are read as JSON lines from stdin, which name the
+    /// command in their "command" field, and answered with a snapshot of the fight as JSON on
+    /// stdout. The commands are add, start, damage, next-turn, end, undo and redo
+    headless: bool,
+    #[argh(option)]
+    /// a TOML file with condition presets, which can be picked when adding modifiers, instead of
+    /// the built-in ones. See conditions.toml for the format
+    conditions: Option<PathBuf>,
+    #[argh(option)]
+    /// a TOML file with keymap profiles for several people at one keyboard, which are switched
+    /// with f2. See profiles.toml for the format
+    profiles: Option<PathBuf>,
+    #[argh(option)]
+    /// a TOML file that binds the commands to other keys, instead of keymap.toml in the config
+    /// dir, which is written with the built-in keys on the first run
+    keymap: Option<PathBuf>,
+    #[argh(positional)]
+    /// files to load. Lines like "@Goblin: 7 DEX=2" declare templates, which are used by name in
+    /// all files: "Goblin: xN=4" adds Goblin 1 to 4
+    files: Vec<PathBuf>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum CliCommand {
+    Fmt(FmtArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "fmt")]
+/// Merge participant files into one file without duplicates, sorted by initiative or name
+struct FmtArgs {
+    #[argh(option, default = "fmt::SortKey::Ini")]
+    /// what to sort by, ini (the default) or name
+    sort: fmt::SortKey,
+    #[argh(option, short = 'o')]
+    /// the file to write to, instead of stdout
+    output: Option<PathBuf>,
     #[argh(positional)]
-    /// files to load
+    /// the participant files
     files: Vec<PathBuf>,
 }
 
 fn main() -> Result<()> {
-    // setup terminal
+    use crossterm::{
+        event::{DisableMouseCapture, EnableMouseCapture},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    };
+    use states::Boxable;
+    use std::io;
+    use tui::{backend::CrosstermBackend, Terminal};
+
     let args: Cli = argh::from_env();
-    let init_state = get_initial_state(&args.files).context("get initial state")?;
+    if let Some(CliCommand::Fmt(fmt_args)) = args.command {
+        return fmt::run(&fmt_args.files, fmt_args.sort, fmt_args.output.as_ref());
+    }
+
+    let conditions = args
+        .conditions
+        .as_deref()
+        .map(conditions::Conditions::load)
+        .transpose()?;
+    let profiles = args
+        .profiles
+        .as_deref()
+        .map(profiles::Profiles::load)
+        .transpose()?
+        .unwrap_or_default();
+    // a saved encounter continues in the fight if it was saved during one
+    let (init_cs, fighting) = match &args.load {
+        Some(path) => {
+            let file = save::load(path)?;
+            let mut cs = file.combat_state;
+            if let Some(ini_roll) = args.ini {
+                cs.ini_roll = ini_roll;
+            }
+            if let Some(tie_break) = args.tie_break {
+                cs.tie_break = tie_break;
+            }
+            if let Some(conditions) = conditions {
+                cs.conditions = conditions;
+            }
+            cs.ring_bell |= args.bell;
+            (cs, file.fighting)
+        }
+        None => {
+            let cs = get_initial_combat_state(
+                &args.files,
+                args.ini.unwrap_or_default(),
+                args.tie_break.unwrap_or_default(),
+                conditions.unwrap_or_default(),
+                args.bell,
+            )
+            .context("get initial state")?;
+            (cs, false)
+        }
+    };
+    if args.headless {
+        return headless::run(io::stdin().lock(), io::stdout().lock(), init_cs, fighting);
+    }
+
+    let keymap = match &args.keymap {
+        Some(path) => keymap::Keymap::load(path)?,
+        None => keymap::Keymap::load_or_create()?,
+    };
+    // the rows of every fight use the keys of the keymap
+    keymap.install();
+
+    let share = args.share.map(share::Share::start).transpose()?;
+
+    // setup terminal
+    let init_state = if fighting {
+        states::Fighting::new(init_cs).boxed()
+    } else if init_cs.participants.is_empty() {
+        states::Insert::new(init_cs, "".into()).boxed()
+    } else {
+        states::Normal::new(init_cs)?.boxed()
+    };
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+    let backend = Backend::Terminal(CrosstermBackend::new(stdout));
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
 
-    let res = run_app(init_state, &mut terminal);
+    let res = run_app(init_state, profiles, keymap, share, &mut terminal);
 
     // restore terminal
     disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
+    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
     terminal.show_cursor()?;
 
     res
 }
 
-fn get_initial_state(files: &Vec<PathBuf>) -> Result<StateBox> {
+fn get_initial_combat_state(
+    files: &[PathBuf],
+    ini_roll: utils::DiceExpr,
+    tie_break: combat_state::TieBreak,
+    conditions: conditions::Conditions,
+    ring_bell: bool,
+) -> Result<combat_state::CombatState> {
     if files.len() == 0 {
-        Ok(states::Insert::default().boxed())
+        Ok(combat_state::CombatState::default()
+            .with_ini_roll(ini_roll)
+            .with_tie_break(tie_break)
+            .with_conditions(conditions)
+            .with_ring_bell(ring_bell))
     } else {
-        let mut content = String::new();
-        for file in files {
-            let file_contents = fs::read_to_string(file)?;
-            content.push_str(&file_contents);
+        let contents = files
+            .iter()
+            .map(|file| {
+                let content = fs::read_to_string(file)
+                    .with_context(|| format!("reading {}", file.display()))?;
+                Ok((file.display().to_string(), content))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // templates can be used in every file, so e.g. a bestiary can be passed before or after
+        // the encounter
+        let mut templates = utils::Templates::new();
+        for (source, content) in &contents {
+            templates.extend(utils::parse_templates(source, content)?);
         }
-        let lines: Vec<&str> = content.lines().collect();
-        let mut participants = Vec::with_capacity(lines.len());
-        let mut initiatives = Vec::with_capacity(lines.len());
-        for line in lines {
-            let (ini, p) = utils::parse_participant_with_ini(line).context("parse with ini")?;
-            participants.push(p);
-            initiatives.push(ini);
+        let mut participants = vec![];
+        for (source, content) in &contents {
+            participants.extend(utils::parse_participants(source, content, &templates)?);
         }
-        Ok(states::Normal::new(
-            combat_state::CombatState::from_participants(participants),
-            initiatives,
-        )?
-        .boxed())
+        Ok(combat_state::CombatState::from_participants(participants)
+            .with_ini_roll(ini_roll)
+            .with_tie_break(tie_break)
+            .with_conditions(conditions)
+            .with_ring_bell(ring_bell))
     }
 }
 
-fn run_app(mut current_state: StateBox, terminal: &mut Terminal<Backend>) -> Result<()> {
-    terminal.draw(|f| current_state.render(f))?;
+fn run_app(
+    mut current_state: StateBox,
+    mut profiles: profiles::Profiles,
+    keymap: keymap::Keymap,
+    share: Option<share::Share>,
+    terminal: &mut tui::Terminal<Backend>,
+) -> Result<()> {
+    fn draw(
+        terminal: &mut tui::Terminal<Backend>,
+        state: &mut StateBox,
+        profiles: &profiles::Profiles,
+    ) -> Result<()> {
+        terminal.draw(|f| {
+            state.render(f);
+            profiles.render(f);
+        })?;
+        Ok(())
+    }
+    let update_share = |state: &StateBox| {
+        if let Some(share) = &share {
+            share.update(state.combat_state(), state.mode() == states::Mode::Fight);
+        }
+    };
+
+    draw(terminal, &mut current_state, &profiles)?;
+    update_share(&current_state);
     loop {
-        let ev = event::read()?;
-        if let Event::Key(key) = ev {
-            if let KeyCode::Char('c') = key.code {
-                if key.modifiers.contains(KeyModifiers::CONTROL) {
-                    return Ok(());
-                }
-            }
+        // without input, the screen is redrawn every second, so the turn timer keeps running
+        if !crossterm::event::poll(std::time::Duration::from_secs(1))? {
+            draw(terminal, &mut current_state, &profiles)?;
+            continue;
+        }
+        let ev = crossterm::event::read()?;
+        if is_quit(&ev) {
+            return Ok(());
+        }
+        let ev = profiles
+            .translate(current_state.as_ref(), ev)
+            .and_then(|ev| keymap.translate(current_state.as_ref(), ev));
+        if let Some(ev) = ev {
+            current_state = current_state.process(ev)?;
+            update_share(&current_state);
         }
-        current_state = current_state.process(ev)?;
-        terminal.draw(|f| current_state.render(f))?;
+        draw(terminal, &mut current_state, &profiles)?;
+    }
+}
+
+/// ctrl+c quits the app from every state
+fn is_quit(ev: &Event) -> bool {
+    if let Event::Key(key) = ev {
+        key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+    } else {
+        false
     }
 }
@@ -0,0 +1,362 @@
+//! Keymap profiles, so several people can share the keyboard, like a co-GM, or a player who
+//! manages the HP of their own character. Every profile can remap keys, and can be restricted to
+//! a few commands. They are read from a TOML file:
+//!
+//! ```toml
+//! # the key that switches to the next profile, f2 by default
+//! switch = "f2"
+//!
+//! [[profile]]
+//! name = "GM"
+//!
+//! [[profile]]
+//! name = "Player"
+//! # only these commands can be used, after the keys were remapped
+//! allowed = ["j", "k", "d", "h", "enter", "esc"]
+//! [profile.keys]
+//! # pressing x does what d does
+//! "x" = "d"
+//! ```
+//!
+//! The first profile is active at the start. Keys that are typed into an input are neither
+//! remapped nor restricted, and restricted profiles can't use the mouse.
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path};
+use tui::{
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::Span,
+    widgets::Paragraph,
+};
+
+use crate::{states::State, Frame};
+
+/// a key with the modifiers that matter, shift is part of the char
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// the pressed key, and the key of the command it triggers
+    keys: HashMap<Key, Key>,
+    /// None if every command can be used
+    allowed: Option<Vec<Key>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+    active: usize,
+    switch: Key,
+    /// the key that was refused last, until the next key is pressed
+    refused: Option<String>,
+}
+
+/// the layout of the profiles file
+#[derive(Deserialize)]
+struct ProfilesFile {
+    switch: Option<String>,
+    #[serde(default)]
+    profile: Vec<ProfileEntry>,
+}
+
+#[derive(Deserialize)]
+struct ProfileEntry {
+    name: String,
+    #[serde(default)]
+    keys: HashMap<String, String>,
+    allowed: Option<Vec<String>>,
+}
+
+impl Default for Profiles {
+    fn default() -> Profiles {
+        Profiles {
+            profiles: vec![Profile {
+                name: "Default".into(),
+                keys: HashMap::new(),
+                allowed: None,
+            }],
+            active: 0,
+            switch: Key::new(KeyCode::F(2), KeyModifiers::NONE),
+            refused: None,
+        }
+    }
+}
+
+impl Profiles {
+    pub fn load(path: &Path) -> Result<Profiles> {
+        let content = fs::read_to_string(path).with_context(|| path.display().to_string())?;
+        Profiles::parse(&content).with_context(|| path.display().to_string())
+    }
+
+    pub fn parse(content: &str) -> Result<Profiles> {
+        let file: ProfilesFile = toml::from_str(content)?;
+        ensure!(!file.profile.is_empty(), "There are no profiles");
+        let profiles = file
+            .profile
+            .into_iter()
+            .map(|entry| {
+                let keys = entry
+                    .keys
+                    .iter()
+                    .map(|(pressed, command)| Ok((Key::parse(pressed)?, Key::parse(command)?)))
+                    .collect::<Result<_>>()
+                    .with_context(|| format!("in the keys of {}", entry.name))?;
+                let allowed = entry
+                    .allowed
+                    .map(|keys| keys.iter().map(|k| Key::parse(k)).collect::<Result<_>>())
+                    .transpose()
+                    .with_context(|| format!("in the allowed commands of {}", entry.name))?;
+                Ok(Profile {
+                    name: entry.name,
+                    keys,
+                    allowed,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let default = Profiles::default();
+        Ok(Profiles {
+            profiles,
+            switch: file
+                .switch
+                .as_deref()
+                .map(Key::parse)
+                .transpose()?
+                .unwrap_or(default.switch),
+            ..default
+        })
+    }
+
+    pub fn active(&self) -> &Profile {
+        &self.profiles[self.active]
+    }
+
+    /// the event the state should process, or None if it switched the profile, or was refused
+    pub fn translate(&mut self, state: &dyn State, ev: Event) -> Option<Event> {
+        let key_event = match ev {
+            Event::Key(key_event) => key_event,
+            Event::Mouse(_) if self.active().allowed.is_some() => return None,
+            ev => return Some(ev),
+        };
+        self.refused = None;
+        let key = Key::from(key_event);
+        if key == self.switch {
+            self.active = (self.active + 1) % self.profiles.len();
+            return None;
+        }
+        if state.has_input() {
+            return Some(ev);
+        }
+        let profile = self.active();
+        let command = profile.keys.get(&key).copied().unwrap_or(key);
+        if let Some(allowed) = &profile.allowed {
+            if !allowed.contains(&command) {
+                self.refused = Some(format!("{} can't use {}", profile.name, command));
+                return None;
+            }
+        }
+        Some(command.to_event())
+    }
+
+    /// the name of the active profile in the top right corner, if there is more than one
+    pub fn render(&self, f: &mut Frame) {
+        if self.profiles.len() < 2 {
+            return;
+        }
+        let text = match &self.refused {
+            Some(refused) => format!(" {} ", refused),
+            None => format!(" {} ({}: switch) ", self.active().name, self.switch),
+        };
+        let area = f.size();
+        let width = (text.chars().count() as u16).min(area.width);
+        let target_rect = Rect::new(area.right() - width, area.top(), width, 1.min(area.height));
+        let color = if self.refused.is_some() {
+            Color::Red
+        } else {
+            Color::Yellow
+        };
+        let badge = Span::styled(
+            text,
+            Style::default()
+                .fg(Color::Black)
+                .bg(color)
+                .add_modifier(Modifier::BOLD),
+        );
+        f.render_widget(Paragraph::new(badge), target_rect);
+    }
+}
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        Key { code, modifiers }
+    }
+
+    /// parses keys like `d`, `R`, `ctrl+s`, `enter` or `f2`
+    pub fn parse(s: &str) -> Result<Key> {
+        let mut parts: Vec<&str> = s.split('+').collect();
+        let name = match parts.pop() {
+            // the last part is empty if the key is + itself, like in ctrl++
+            Some("") if parts.last() == Some(&"") => {
+                parts.pop();
+                "+"
+            }
+            Some(name) => name,
+            None => bail!("Empty key"),
+        };
+        let mut modifiers = KeyModifiers::NONE;
+        for part in parts {
+            modifiers |= match part.to_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                _ => bail!("{} is not a modifier in {}", part, s),
+            };
+        }
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match name.to_lowercase().as_str() {
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "space" => KeyCode::Char(' '),
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                lower => lower
+                    .strip_prefix('f')
+                    .and_then(|n| n.parse().ok())
+                    .map(KeyCode::F)
+                    .ok_or_else(|| anyhow!("{} is not a key", name))?,
+            },
+        };
+        Ok(Key::new(code, modifiers))
+    }
+
+    /// a key press of this key
+    pub fn to_event(self) -> Event {
+        Event::Key(KeyEvent::new(self.code, self.modifiers))
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(ev: KeyEvent) -> Key {
+        Key::new(ev.code, ev.modifiers)
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => write!(f, "{}", format!("{:?}", code).to_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::{hp_snapshot, hps, Driver};
+    use crossterm::event::KeyCode;
+
+    #[test]
+    fn test_keys() {
+        let key = |code, modifiers| Key::new(code, modifiers);
+        assert_eq!(
+            Key::parse("d").unwrap(),
+            key(KeyCode::Char('d'), KeyModifiers::NONE)
+        );
+        // shift is part of the char
+        assert_eq!(
+            Key::parse("R").unwrap(),
+            key(KeyCode::Char('R'), KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            Key::parse("Ctrl+s").unwrap(),
+            key(KeyCode::Char('s'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            Key::parse("ctrl++").unwrap(),
+            key(KeyCode::Char('+'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            Key::parse("F12").unwrap(),
+            key(KeyCode::F(12), KeyModifiers::NONE)
+        );
+        for s in [
+            "enter",
+            "esc",
+            "ctrl+s",
+            "alt+x",
+            "f2",
+            "space",
+            "backspace",
+        ] {
+            assert_eq!(Key::parse(s).unwrap().to_string(), s);
+        }
+        assert!(Key::parse("hyper+x").is_err());
+        assert!(Key::parse("pagedown").is_err());
+        assert!(Key::parse("").is_err());
+
+        let profiles = Profiles::parse(
+            "switch = \"f3\"\n[[profile]]\nname = \"GM\"\n\n\
+             [[profile]]\nname = \"Player\"\nallowed = [\"d\"]\n[profile.keys]\nx = \"d\"\n",
+        )
+        .unwrap();
+        assert_eq!(profiles.active().name, "GM");
+        assert_eq!(profiles.switch, key(KeyCode::F(3), KeyModifiers::NONE));
+        assert_eq!(
+            profiles.profiles[1].allowed,
+            Some(vec![Key::parse("d").unwrap()])
+        );
+        assert!(Profiles::parse("switch = \"f2\"").is_err());
+        assert!(Profiles::parse("[[profile]]\nname = \"P\"\nallowed = [\"nope\"]").is_err());
+        let example = Profiles::parse(include_str!("../profiles.toml")).unwrap();
+        assert_eq!(example.profiles.len(), 2);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let profiles = Profiles::parse(
+            "[[profile]]\nname = \"GM\"\n\n[[profile]]\nname = \"Player\"\n\
+             allowed = [\"j\", \"k\", \"e\"]\n[profile.keys]\nx = \"j\"\n",
+        )
+        .unwrap();
+        let mut d = Driver::new(Insert::default().boxed()).with_profiles(profiles);
+        d.line("Orc: 10").line("Goblin: 7").key(KeyCode::Esc);
+        assert!(d.screen()[0].ends_with(" GM (f2: switch) "));
+
+        d.key(KeyCode::F(2));
+        assert!(d.screen()[0].contains(" Player (f2: switch) "));
+        d.type_str("d");
+        assert_eq!(d.combat_state().participants.len(), 2);
+        assert!(d.screen()[0].contains("Player can't use d"));
+
+        // x is remapped to j, and keys that are typed into an input are not restricted
+        d.type_str("xe");
+        assert_eq!(d.state().title(), "Editing Initiative of Goblin");
+        d.line("12");
+        assert_eq!(d.combat_state().participants[1].ini, Some(12));
+
+        d.key(KeyCode::F(2)).type_str("d");
+        assert_eq!(hp_snapshot(d.combat_state()), hps(&[("Orc", 10)]));
+    }
+}
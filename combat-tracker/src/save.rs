@@ -0,0 +1,141 @@
+//! Saving a fight to a file, with everything that is needed to resume it later: the
+//! participants with their HP and modifiers, the current round and turn, and the scheduled
+//! events.
+use anyhow::{bail, ensure, Context, Result};
+use file_format::Format;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::combat_state::{CombatState, LogEntry};
+
+/// version 1 is the first one, saves always had a version
+const SAVE_FORMAT: Format = Format {
+    name: "saved encounter",
+    legacy_key: Some("version"),
+    migrations: &[file_format::unchanged],
+};
+
+/// a saved encounter, which is resumed in the fight if it was saved during one, and before the
+/// fight otherwise
+#[derive(Deserialize)]
+pub struct SaveFile {
+    pub combat_state: CombatState,
+    pub fighting: bool,
+}
+
+pub fn save(combat_state: &CombatState, fighting: bool, path: &Path) -> Result<()> {
+    #[derive(Serialize)]
+    struct SaveFileRef<'a> {
+        combat_state: &'a CombatState,
+        fighting: bool,
+    }
+    let content = SAVE_FORMAT.to_json(&SaveFileRef {
+        combat_state,
+        fighting,
+    })?;
+    fs::write(path, content).with_context(|| format!("writing {}", path.display()))
+}
+
+/// writes the combat log as markdown, with a heading for every round
+pub fn export_log(combat_state: &CombatState, path: &Path) -> Result<()> {
+    let mut content = String::from("# Combat Log\n");
+    for entry in &combat_state.log {
+        match entry {
+            LogEntry::Round(_) => content.push_str(&format!("\n## {}\n\n", entry)),
+            _ => content.push_str(&format!("- {}\n", entry)),
+        }
+    }
+    fs::write(path, content).with_context(|| format!("writing {}", path.display()))
+}
+
+/// how a participant left the fight, for export_result
+#[derive(Serialize)]
+struct ResultRow {
+    name: String,
+    hp: u16,
+    max_hp: Option<u16>,
+    temp_hp: u16,
+    status: String,
+    /// like `Bless:3`, with the rounds that were left
+    modifiers: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct FightResult {
+    /// the rounds that were started
+    rounds: usize,
+    participants: Vec<ResultRow>,
+}
+
+/// writes the participants with their HP, status and modifiers, and the number of rounds, as
+/// CSV or JSON, depending on the extension of the path
+pub fn export_result(combat_state: &CombatState, path: &Path) -> Result<()> {
+    let now = combat_state.now();
+    let result = FightResult {
+        rounds: combat_state.current_round + 1,
+        participants: combat_state
+            .participants
+            .iter()
+            .map(|p| ResultRow {
+                name: p.name.clone(),
+                hp: p.hp,
+                max_hp: p.max_hp,
+                temp_hp: p.temp_hp,
+                status: p.status.to_string(),
+                modifiers: p.modifiers.iter().map(|m| m.input_line(&now)).collect(),
+            })
+            .collect(),
+    };
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    let content = match extension.to_lowercase().as_str() {
+        "json" => serde_json::to_string_pretty(&result)?,
+        "csv" => result_csv(&result),
+        _ => bail!("The result can be exported to a .csv or a .json file"),
+    };
+    fs::write(path, content).with_context(|| format!("writing {}", path.display()))
+}
+
+/// one line per participant, the rounds are repeated in every line
+fn result_csv(result: &FightResult) -> String {
+    let mut content = String::from("name,hp,max_hp,temp_hp,status,modifiers,rounds\n");
+    for row in &result.participants {
+        let fields = [
+            csv_field(&row.name),
+            row.hp.to_string(),
+            row.max_hp.map(|hp| hp.to_string()).unwrap_or_default(),
+            row.temp_hp.to_string(),
+            row.status.clone(),
+            csv_field(&row.modifiers.join("; ")),
+            result.rounds.to_string(),
+        ];
+        content.push_str(&fields.join(","));
+        content.push('\n');
+    }
+    content
+}
+
+/// quotes the field if it contains a separator, a quote or a line break
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn load(path: &Path) -> Result<SaveFile> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let file: SaveFile = SAVE_FORMAT
+        .from_json(&content)
+        .with_context(|| path.display().to_string())?;
+    ensure!(
+        !file.combat_state.participants.is_empty(),
+        "{} contains no participants",
+        path.display()
+    );
+    Ok(file)
+}
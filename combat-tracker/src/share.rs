@@ -0,0 +1,214 @@
+//! A read-only view of the fight for the players, which is served over HTTP with `--share`, so
+//! they can follow the initiative order on their phones. The HP are only shown for player
+//! characters, which are marked with `pc` in the participant line. The server needs the `share`
+//! feature.
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::combat_state::{CombatState, Status};
+
+/// what the players see
+#[derive(Clone, Default, Serialize)]
+struct View {
+    /// the round, once the fight started
+    round: Option<usize>,
+    /// whose turn it is
+    current: Option<String>,
+    participants: Vec<ViewRow>,
+}
+
+#[derive(Clone, Serialize)]
+struct ViewRow {
+    name: String,
+    ini: Option<u8>,
+    /// only for player characters
+    hp: Option<String>,
+    /// like down or dead, empty while the participant is up
+    status: String,
+    modifiers: Vec<String>,
+}
+
+/// the view the server currently serves, which is updated after every event
+pub struct Share {
+    view: Arc<Mutex<View>>,
+}
+
+impl Share {
+    /// starts the server in the background
+    pub fn start(addr: SocketAddr) -> Result<Share> {
+        let view = Arc::new(Mutex::new(View::default()));
+        server::spawn(addr, view.clone())?;
+        Ok(Share { view })
+    }
+
+    pub fn update(&self, cs: &CombatState, fighting: bool) {
+        *self.view.lock().unwrap() = view_of(cs, fighting);
+    }
+}
+
+fn view_of(cs: &CombatState, fighting: bool) -> View {
+    let now = cs.now();
+    View {
+        round: fighting.then_some(cs.current_round),
+        current: fighting.then(|| cs.participants[cs.current_idx].name.clone()),
+        participants: cs
+            .participants
+            .iter()
+            .map(|p| ViewRow {
+                name: p.name.clone(),
+                ini: p.ini,
+                hp: p.pc.then(|| p.hp_text()),
+                status: match p.status {
+                    Status::Conscious if p.hp == 0 => "down".into(),
+                    Status::Conscious => "".into(),
+                    _ => p.status.to_string(),
+                },
+                modifiers: p.modifiers.iter().map(|m| m.input_line(&now)).collect(),
+            })
+            .collect(),
+    }
+}
+
+/// a page that reloads itself every two seconds
+#[cfg_attr(not(feature = "share"), allow(dead_code))]
+fn html(view: &View) -> String {
+    let mut rows = String::new();
+    for p in &view.participants {
+        let is_current = view.current.as_ref() == Some(&p.name);
+        let status: Vec<&str> = std::iter::once(p.status.as_str())
+            .chain(p.modifiers.iter().map(String::as_str))
+            .filter(|s| !s.is_empty())
+            .collect();
+        rows.push_str(&format!(
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            if is_current { " class=\"current\"" } else { "" },
+            p.ini.map(|ini| ini.to_string()).unwrap_or_default(),
+            escape(&p.name),
+            escape(p.hp.as_deref().unwrap_or("")),
+            escape(&status.join(", ")),
+        ));
+    }
+    let heading = match view.round {
+        Some(round) => format!("Round {}", round),
+        None => "Waiting for the fight".into(),
+    };
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <meta http-equiv=\"refresh\" content=\"2\">\n<title>Combat Tracker</title>\n\
+         <style>td {{ padding: 0.2em 0.6em; }} \
+         .current {{ font-weight: bold; background: #aee; }}</style>\n\
+         </head>\n<body>\n<h1>{}</h1>\n<table>\n\
+         <tr><th>Ini</th><th>Name</th><th>HP</th><th>Status</th></tr>\n{}</table>\n\
+         </body>\n</html>\n",
+        heading, rows
+    )
+}
+
+#[cfg_attr(not(feature = "share"), allow(dead_code))]
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(feature = "share")]
+mod server {
+    use super::{html, View};
+    use anyhow::{Context, Result};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    pub fn spawn(addr: SocketAddr, view: Arc<Mutex<View>>) -> Result<()> {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("sharing the fight on {}", addr))?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let view = view.clone();
+                // a slow phone doesn't hold up the others
+                thread::spawn(move || {
+                    // a client that hangs up early is no reason to stop serving
+                    let _ = respond(stream, &view);
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// the page on /, the view as JSON on /state.json
+    fn respond(mut stream: TcpStream, view: &Mutex<View>) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // the headers are not needed
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let view = view.lock().unwrap().clone();
+        let (status, content_type, body) = match path {
+            "/" => ("200 OK", "text/html; charset=utf-8", html(&view)),
+            "/state.json" => ("200 OK", "application/json", serde_json::to_string(&view)?),
+            _ => ("404 Not Found", "text/plain", "Not Found".into()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "share"))]
+mod server {
+    use super::View;
+    use anyhow::{bail, Result};
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    pub fn spawn(_addr: SocketAddr, _view: Arc<Mutex<View>>) -> Result<()> {
+        bail!("combat-tracker was built without the share feature")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_participant_with_ini;
+
+    #[test]
+    fn test_shared_view() {
+        let participants = ["Kidd: 8/10: 15 pc", "Goblin <3>: 0: 12", "Orc: 10: 9"]
+            .map(|line| parse_participant_with_ini(line).unwrap());
+        let cs = CombatState::from_participants(participants.to_vec()).with_fight_started();
+
+        let view = view_of(&cs, true);
+        let hps: Vec<Option<&str>> = view.participants.iter().map(|p| p.hp.as_deref()).collect();
+        assert_eq!(hps, [Some("8/10"), None, None]);
+        assert_eq!(view.participants[1].status, "down");
+        assert_eq!(view.current.as_deref(), Some("Kidd"));
+
+        let page = html(&view);
+        assert!(page.contains("<h1>Round 0</h1>"));
+        assert!(page.contains("<tr class=\"current\"><td>15</td><td>Kidd</td><td>8/10</td>"));
+        assert!(page.contains("<td>Goblin &lt;3&gt;</td><td></td><td>down</td>"));
+        assert!(!page.contains("<td>10</td>"));
+        assert!(html(&view_of(&cs, false)).contains("Waiting for the fight"));
+    }
+}
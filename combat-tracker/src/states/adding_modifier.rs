@@ -1,65 +1,300 @@
+use super::{Boxable, Fighting, Mode, State, StateBox};
 use crate::{
-    combat_state::{CombatState, Modifier, ModifierFac},
+    combat_state::{CombatState, ModifierFac},
+    conditions::Condition,
     states, utils as ut, view_utils as vu,
 };
 use anyhow::Result;
 use crossterm::event::{Event, KeyCode};
 use derive_new::new;
 use persistent_structs::PersistentStruct;
-use tui::text::Span;
-use tui::widgets::Paragraph;
-
-use super::{Boxable, Fighting, State, StateBox};
+use tui::layout::{Constraint, Direction, Layout};
 
 #[derive(Clone, new, PersistentStruct)]
 pub struct AddingModifiers {
     parent_state: Box<Fighting>,
     target_participant: usize,
     input_buffer: String,
+    /// the picked condition preset, among the ones that match the input
+    #[new(default)]
+    selection: Option<usize>,
 }
 
 impl State for AddingModifiers {
     fn render(&mut self, f: &mut crate::Frame) {
         let chunks = vu::input_layout(f.size());
-        let info_text = Span::from(format!(
-            "Fight - Esc: To normal; Current Round: {}",
-            self.parent_state.combat_state.current_round
-        ));
-        f.render_widget(Paragraph::new(info_text), chunks[0]);
+        vu::render_top_bar(f, self, chunks[0]);
         vu::render_input_block(f, "New Modifier", &self.input_buffer, chunks[1]);
+        let areas = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(1), Constraint::Length(30)].as_ref())
+            .split(chunks[2]);
         vu::render_fighting_mode_table(
             f,
             &self.parent_state.combat_state,
             &self.parent_state.key_infos,
-            chunks[2],
+            areas[0],
         );
+        vu::render_conditions(f, &self.presets(), self.selection, areas[1]);
     }
 
     fn process(self: Box<Self>, ev: crossterm::event::Event) -> Result<StateBox> {
         if let Event::Key(key) = ev {
             match key.code {
                 KeyCode::Esc => Ok(self.parent_state),
-                KeyCode::Enter => Ok(match Modifier::parse_factory(&self.input_buffer) {
-                    Ok(mod_fac) => self.parent_with_modifier(mod_fac),
-                    Err(e) => states::Msg::new(self, ut::err_to_string(&e)).boxed(),
-                }),
+                KeyCode::Tab => Ok(states::EditingModifiers::new(
+                    self.parent_state,
+                    self.target_participant,
+                )
+                .boxed()),
+                KeyCode::Down => {
+                    let n_presets = self.presets().len();
+                    Ok(self
+                        .update_selection(|s| match s {
+                            Some(i) => Some((i + 1).min(n_presets.saturating_sub(1))),
+                            None if n_presets > 0 => Some(0),
+                            None => None,
+                        })
+                        .boxed())
+                }
+                KeyCode::Up => Ok(self
+                    .update_selection(|s| s.and_then(|i| i.checked_sub(1)))
+                    .boxed()),
+                KeyCode::Enter => {
+                    if let Some(preset) = self.picked_preset() {
+                        return Ok(self.parent_with_modifier(preset.factory()));
+                    }
+                    let cs = &self.parent_state.combat_state;
+                    let fac = cs
+                        .conditions
+                        .parse_factory(&self.input_buffer)
+                        .and_then(|fac| cs.with_caster_resolved(fac));
+                    Ok(match fac {
+                        Ok(mod_fac) => self.parent_with_modifier(mod_fac),
+                        Err(e) => states::Msg::new(self, ut::err_to_string(&e)).boxed(),
+                    })
+                }
                 code => Ok(self
                     .update_input_buffer(|b| ut::update_buffer(b, code))
+                    .with_selection(None)
                     .boxed()),
             }
         } else {
             Ok(self)
         }
     }
+
+    fn mode(&self) -> Mode {
+        Mode::Mod
+    }
+
+    fn title(&self) -> String {
+        let participants = &self.parent_state.combat_state.participants;
+        format!(
+            "Adding Modifier for {}",
+            participants[self.target_participant].name
+        )
+    }
+
+    fn key_hints(&self) -> String {
+        "enter: add; up & down: pick a condition; tab: edit existing; esc: back to fight".into()
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
 }
 
 impl AddingModifiers {
+    /// the condition presets that match the input
+    fn presets(&self) -> Vec<&Condition> {
+        self.parent_state
+            .combat_state
+            .conditions
+            .search(&self.input_buffer)
+    }
+
+    fn picked_preset(&self) -> Option<Condition> {
+        self.selection
+            .and_then(|i| self.presets().get(i).map(|c| (*c).clone()))
+    }
+
     pub fn parent_with_modifier(self, fac: ModifierFac) -> StateBox {
-        let mut parent = self.parent_state;
-        let new_mod = fac(parent.combat_state.now());
-        parent.combat_state.participants[self.target_participant]
+        let target = self.target_participant;
+        self.parent_state
+            .update_combat_state(|cs| {
+                cs.recorded(|cs| {
+                    let new_mod = fac(cs.now());
+                    cs.with_modifier(target, new_mod)
+                })
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::{hp_snapshot, hps, Driver};
+
+    #[test]
+    fn test_modifier_charges() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        d.type_str("e").line("Shield of Faith x2:10");
+        d.type_str("e").line("Rage");
+        assert!(d.screen_contains("[Shield of Faith x2:10, Rage]"));
+        d.ctrl('v');
+        assert!(d.screen_contains("[Shield of Faith x1:10, Rage]"));
+        d.ctrl('v').ctrl('v');
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter);
+        assert_eq!(d.combat_state().participants[0].modifiers.len(), 1);
+        let log = d.log();
+        assert_eq!(
+            log[log.len() - 2..],
+            [
+                "Orc uses Shield of Faith (1 left)",
+                "Shield of Faith of Orc is used up"
+            ]
+        );
+
+        // the selected modifier is used when editing them
+        d.type_str("d").line("Bardic Inspiration x3");
+        d.ctrl('n').ctrl('t').ctrl('u');
+        assert_eq!(
+            d.combat_state().participants[1].modifiers[0].charges,
+            Some(2)
+        );
+        assert!(d.screen_contains("Bardic Inspiration x2"));
+        d.key(KeyCode::Esc);
+        d.type_str("d").line("Bless x0");
+        assert_eq!(d.state().title(), "Error");
+    }
+
+    #[test]
+    fn test_ongoing_effects() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10/12").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        d.type_str("d").line("Burning -3/round:2");
+        d.type_str("e").line("Regeneration +5/round");
+        assert!(d.screen_contains("[Burning -3/round:2]"));
+        d.ctrl('n');
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 10), ("Goblin", 4)])
+        );
+        d.ctrl('n').ctrl('n');
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 12), ("Goblin", 1)])
+        );
+        // Burning expired before the third turn of the goblin
+        d.ctrl('n').ctrl('n');
+        assert_eq!(d.state().title(), "Expired");
+        d.key(KeyCode::Enter);
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 12), ("Goblin", 1)])
+        );
+        let log = d.log();
+        assert!(log.contains(&"Goblin takes 3 damage from Burning".to_string()));
+        assert!(log.contains(&"Orc heals 5 from Regeneration".to_string()));
+
+        d.type_str("e").line("Blessed +0/round");
+        assert_eq!(d.state().title(), "Error");
+    }
+
+    #[test]
+    fn test_concentration() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Alia: 10").line("Orc: 12").line("Wolf: 8");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        d.type_str("d").line("Bless @al:10");
+        d.type_str("c").line("Hold Person @Alia");
+        d.type_str("c").line("Bane @Nobody");
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter).key(KeyCode::Esc);
+        let cs = d.combat_state();
+        assert_eq!(cs.participants[1].modifiers[0].label(), "Bless @Alia");
+        assert_eq!(cs.participants[2].modifiers.len(), 1);
+
+        // without an @ there is no caster, even if nobody is called like the end of the name
+        d.type_str("e").line("Marked by the hunter");
+        let cs = d.combat_state();
+        let mut modifiers = cs.participants.iter().flat_map(|p| &p.modifiers);
+        let marked = modifiers.find(|m| m.name == "Marked by the hunter");
+        assert_eq!(marked.unwrap().concentration, None);
+        d.ctrl('u');
+        d.ctrl('k');
+        let cs = d.combat_state();
+        assert!(cs.participants.iter().all(|p| p.modifiers.is_empty()));
+        assert_eq!(
+            d.log().last().unwrap(),
+            "Alia loses concentration: Bless on Orc, Hold Person on Wolf"
+        );
+        d.ctrl('k');
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter);
+
+        // going down ends the concentration too
+        d.ctrl('u');
+        assert_eq!(d.combat_state().participants[1].modifiers.len(), 1);
+        d.ctrl('d').line("Alia: 10");
+        let cs = d.combat_state();
+        assert!(cs.participants.iter().all(|p| p.modifiers.is_empty()));
+        assert!(d
+            .log()
+            .last()
+            .unwrap()
+            .starts_with("Alia loses concentration"));
+    }
+
+    #[test]
+    fn test_condition_presets() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        // the input filters the presets, and typing drops the selection
+        d.type_str("e").type_str("s").key(KeyCode::Down);
+        assert!(d.screen_contains("Stunned:1"));
+        d.type_str("tu").key(KeyCode::Down).key(KeyCode::Enter);
+        // typed modifiers get the duration and color of the preset with their name
+        d.type_str("e").line("poisoned: 2");
+        d.type_str("e").line("Prone");
+        d.type_str("e")
+            .key(KeyCode::Down)
+            .key(KeyCode::Up)
+            .line("Hexed");
+        let mods: Vec<(String, Option<usize>, Option<String>)> = d.combat_state().participants[0]
             .modifiers
-            .push(new_mod);
-        parent
+            .iter()
+            .map(|m| (m.name.clone(), m.duration, m.color.clone()))
+            .collect();
+        assert_eq!(
+            mods,
+            [
+                ("Stunned".into(), Some(1), Some("yellow".into())),
+                ("poisoned".into(), Some(2), Some("light-green".into())),
+                ("Prone".into(), None, Some("gray".into())),
+                ("Hexed".into(), None, None),
+            ]
+        );
     }
 }
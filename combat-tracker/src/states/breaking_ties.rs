@@ -0,0 +1,186 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use std::ops::Range;
+use tui::{
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+use super::{Boxable, Mode, Normal, State, StateBox};
+use crate::{
+    combat_state::CombatState,
+    keymap::{self, Scope},
+    view_utils as vu, Frame,
+};
+
+/// the GM orders participants with the same initiative, one tie after the other, by picking who
+/// goes first among those that aren't placed yet
+#[derive(Clone)]
+pub struct BreakingTies {
+    parent_state: Box<Normal>,
+    /// the participants that aren't placed yet, the first range is shown
+    ties: Vec<Range<usize>>,
+    selection: usize,
+}
+
+impl BreakingTies {
+    /// the parent, if there are no ties
+    pub fn enter(parent_state: Normal) -> StateBox {
+        let ties = parent_state.combat_state.initiative_ties();
+        BreakingTies {
+            parent_state: Box::new(parent_state),
+            ties,
+            selection: 0,
+        }
+        .next_tie()
+    }
+
+    /// drops ties that are resolved, and returns to the parent once there are none left
+    fn next_tie(mut self) -> StateBox {
+        self.ties.retain(|range| range.len() > 1);
+        match self.ties.first() {
+            Some(range) => {
+                self.selection = range.start;
+                self.boxed()
+            }
+            None => self.parent_state,
+        }
+    }
+
+    /// moves the selected participant before the other tied ones
+    fn pick(mut self) -> StateBox {
+        let range = self.ties[0].clone();
+        let selection = self.selection;
+        self.parent_state.combat_state =
+            self.parent_state
+                .combat_state
+                .update_participants(|mut ps| {
+                    let p = ps.remove(selection);
+                    ps.insert(range.start, p);
+                    ps
+                });
+        self.ties[0].start += 1;
+        self.next_tie()
+    }
+
+    fn move_selection(mut self, forward: bool) -> BreakingTies {
+        let range = &self.ties[0];
+        self.selection = if forward {
+            (self.selection + 1).min(range.end - 1)
+        } else {
+            self.selection.saturating_sub(1).max(range.start)
+        };
+        self
+    }
+}
+
+impl State for BreakingTies {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Char('j') | KeyCode::Down => Ok(self.move_selection(true).boxed()),
+                KeyCode::Char('k') | KeyCode::Up => Ok(self.move_selection(false).boxed()),
+                KeyCode::Enter => Ok(self.pick()),
+                _ => Ok(self),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::select_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+
+        let range = self.ties[0].clone();
+        let items: Vec<ListItem> = self.parent_state.combat_state.participants[range.clone()]
+            .iter()
+            .map(|p| ListItem::new(format!("{} - Initiative: {}", p.name, p.ini.unwrap_or(0))))
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Who goes first?"),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default();
+        list_state.select(Some(self.selection - range.start));
+        f.render_stateful_widget(list, chunks[2], &mut list_state);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Normal
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::List)
+    }
+
+    fn title(&self) -> String {
+        "Breaking Initiative Ties".into()
+    }
+
+    fn key_hints(&self) -> String {
+        format!(
+            "{} & {}: navigate; enter: goes first; esc: keep the rest as it is",
+            keymap::key(Scope::List, "down"),
+            keymap::key(Scope::List, "up")
+        )
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_initiative_ties() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10: 12 DEX=1")
+            .line("Wolf: 11: 12")
+            .line("Goblin: 7: 12 DEX=3")
+            .line("Elf: 8: 15");
+        d.key(KeyCode::Esc).type_str("r");
+        assert_eq!(d.names(), ["Elf", "Goblin", "Orc", "Wolf"]);
+
+        d.type_str("t");
+        assert_eq!(
+            d.combat_state().tie_break,
+            crate::combat_state::TieBreak::Reroll
+        );
+        for _ in 0..10 {
+            d.type_str("r");
+            let mut tied = d.names();
+            assert_eq!(tied.remove(0), "Elf");
+            tied.sort();
+            assert_eq!(tied, ["Goblin", "Orc", "Wolf"]);
+        }
+
+        d.type_str("t");
+        let before = d.names();
+        d.type_str("r");
+        assert_eq!(d.state().title(), "Breaking Initiative Ties");
+        assert!(d.screen_contains("Initiative: 12"));
+        // the second tied participant goes first, then the third one before the first
+        d.type_str("j").key(KeyCode::Enter);
+        d.type_str("j").key(KeyCode::Enter);
+        assert_eq!(d.state().mode(), Mode::Normal);
+        assert_eq!(
+            d.names(),
+            [&before[0], &before[2], &before[3], &before[1]].map(String::as_str)
+        );
+    }
+}
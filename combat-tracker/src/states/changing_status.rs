@@ -0,0 +1,165 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use persistent_structs::PersistentStruct;
+use tui::{
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+use super::{Boxable, Fighting, Mode, State, StateBox};
+use crate::{
+    combat_state::{CombatState, Status},
+    keymap::{self, Scope},
+    view_utils as vu, Frame,
+};
+
+/// marks a participant as conscious, stable or dead
+#[derive(Clone, PersistentStruct)]
+pub struct ChangingStatus {
+    parent_state: Box<Fighting>,
+    selection: usize,
+}
+
+impl ChangingStatus {
+    /// the first participant at 0 HP is selected, as it is the most likely one to change
+    pub fn new(parent_state: Box<Fighting>) -> ChangingStatus {
+        let cs = &parent_state.combat_state;
+        let selection = cs
+            .participants
+            .iter()
+            .position(|p| p.hp == 0)
+            .unwrap_or(cs.current_idx);
+        ChangingStatus {
+            parent_state,
+            selection,
+        }
+    }
+
+    fn move_selection(self, forward: bool) -> ChangingStatus {
+        let len = self.parent_state.combat_state.participants.len();
+        self.update_selection(|s| {
+            if forward {
+                (s + 1) % len
+            } else {
+                (s + len - 1) % len
+            }
+        })
+    }
+
+    fn set_status(self, status: Status) -> StateBox {
+        let n = self.selection;
+        self.parent_state
+            .update_combat_state(|cs| cs.recorded(|cs| cs.with_status(n, status)))
+            .boxed()
+    }
+}
+
+impl State for ChangingStatus {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Char('j') | KeyCode::Down => Ok(self.move_selection(true).boxed()),
+                KeyCode::Char('k') | KeyCode::Up => Ok(self.move_selection(false).boxed()),
+                KeyCode::Char('c') => Ok(self.set_status(Status::Conscious)),
+                KeyCode::Char('s') => Ok(self.set_status(Status::Stable)),
+                KeyCode::Char('d') => Ok(self.set_status(Status::Dead)),
+                _ => Ok(self),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::select_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+
+        let items: Vec<ListItem> = self
+            .parent_state
+            .combat_state
+            .participants
+            .iter()
+            .map(|p| {
+                ListItem::new(format!("{} - HP: {} ({})", p.name, p.hp_text(), p.status))
+                    .style(vu::down_style(p))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Status"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default();
+        list_state.select(Some(self.selection));
+        f.render_stateful_widget(list, chunks[2], &mut list_state);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Fight
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::Status)
+    }
+
+    fn title(&self) -> String {
+        "Changing Status".into()
+    }
+
+    fn key_hints(&self) -> String {
+        let key = |command| keymap::key(Scope::Status, command);
+        format!(
+            "{} & {}: navigate; {}: conscious; {}: stable; {}: dead; esc: back to fight",
+            key("down"),
+            key("up"),
+            key("conscious"),
+            key("stable"),
+            key("dead")
+        )
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::combat_state::Status;
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_down_participants() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 1").line("Elf: 8");
+        d.key(KeyCode::Esc).type_str("x");
+        assert!(d.combat_state().skip_down);
+
+        // the goblin drops to 0 HP, and its turn is skipped
+        d.key(KeyCode::Enter).type_str("a").ctrl('n');
+        assert_eq!(d.combat_state().current_idx, 2);
+        d.ctrl('n');
+        assert_eq!(d.combat_state().current_idx, 0);
+
+        // the participant at 0 HP is preselected
+        d.ctrl('x');
+        assert_eq!(d.state().title(), "Changing Status");
+        d.type_str("d");
+        assert_eq!(d.state().mode(), Mode::Fight);
+        assert_eq!(d.combat_state().participants[1].status, Status::Dead);
+        assert_eq!(d.log().last().unwrap(), "Goblin is dead");
+
+        // healing doesn't bring the dead back, but a stable participant is skipped as well
+        d.type_str("s").ctrl('x').type_str("jjs").ctrl('n');
+        assert_eq!(d.combat_state().current_idx, 0);
+        assert_eq!(d.combat_state().current_round, 2);
+        d.ctrl('u').ctrl('u');
+        assert_eq!(d.combat_state().participants[2].status, Status::Conscious);
+    }
+}
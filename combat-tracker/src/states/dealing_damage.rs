@@ -0,0 +1,204 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use derive_new::new;
+use persistent_structs::PersistentStruct;
+use tui::widgets::{Block, Borders, List, ListItem};
+
+use super::{Boxable, Fighting, Mode, State, StateBox};
+use crate::{
+    combat_state::{CombatState, Damage},
+    states, utils as ut, view_utils as vu, Frame,
+};
+
+/// the quick damage prompt. Typed damage is adjusted by the resistances, vulnerabilities and
+/// immunities of the target. A group as target damages all of its members
+#[derive(Clone, new, PersistentStruct)]
+pub struct DealingDamage {
+    parent_state: Box<Fighting>,
+    input_buffer: String,
+}
+
+impl DealingDamage {
+    fn deal(self) -> StateBox {
+        let res = Damage::parse(&self.input_buffer).and_then(|damage| {
+            let targets = self
+                .parent_state
+                .combat_state
+                .find_targets(&damage.target)?;
+            Ok((targets, damage))
+        });
+        match res {
+            Ok((targets, damage)) => self
+                .parent_state
+                .update_combat_state(|cs| {
+                    cs.recorded(|cs| {
+                        targets
+                            .into_iter()
+                            .fold(cs, |cs, target| cs.with_damage(target, &damage))
+                    })
+                })
+                .boxed(),
+            Err(e) => states::Msg::new(self.boxed(), ut::err_to_string(&e)).boxed(),
+        }
+    }
+}
+
+impl State for DealingDamage {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Enter => Ok(self.deal()),
+                code => Ok(self
+                    .update_input_buffer(|b| ut::update_buffer(b, code))
+                    .boxed()),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::input_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        vu::render_input_block(
+            f,
+            "Damage (<Target>:<Amount> or <Target>:<Amount> <Type>, /2 after the amount halves it)",
+            &self.input_buffer,
+            chunks[1],
+        );
+        let items: Vec<ListItem> = self
+            .parent_state
+            .combat_state
+            .log
+            .iter()
+            .rev()
+            .map(|entry| ListItem::new(entry.to_string()))
+            .collect();
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title("Log"));
+        f.render_widget(list, chunks[2]);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Fight
+    }
+
+    fn title(&self) -> String {
+        "Dealing Damage".into()
+    }
+
+    fn key_hints(&self) -> String {
+        "enter: deal; esc: back to fight".into()
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::{hp_snapshot, hps, Driver};
+
+    #[test]
+    fn test_typed_damage() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Fire Elemental: 50").line("Frost Giant: 60");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        // the modifier keys of the first participant and the second one
+        d.type_str("e").line("immune fire");
+        d.type_str("d")
+            .line("resist fire")
+            .type_str("d")
+            .line("vulnerable fire");
+        d.type_str("e").line("vulnerable cold");
+
+        d.ctrl('d').line("fire: 10 fire");
+        d.ctrl('d').line("Frost: 9 fire");
+        d.ctrl('d').line("fire elemental: 7 cold");
+        d.ctrl('d').line("Frost Giant: 5");
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Fire Elemental", 36), ("Frost Giant", 47)])
+        );
+        let log = d.log();
+        assert_eq!(
+            log[log.len() - 4..],
+            [
+                "Fire Elemental takes 0 fire damage (10 immune)",
+                "Frost Giant takes 8 fire damage (9 halved, then doubled)",
+                "Fire Elemental takes 14 cold damage (7 doubled)",
+                "Frost Giant takes 5 damage",
+            ]
+        );
+        assert!(d.screen_contains("Frost Giant takes 5 damage"));
+
+        d.ctrl('d').line("Goblin: 3");
+        assert_eq!(d.state().title(), "Error");
+    }
+
+    #[test]
+    fn test_group_damage() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Goblin 1: 7 group=Goblins")
+            .line("Goblin 2: 7 group=Goblins")
+            .line("Goblin Boss: 12 group=Goblins")
+            .line("Ogre: 30");
+        d.key(KeyCode::Esc);
+        assert!(d.screen_contains("Goblin 1 - HP: 7; Group: Goblins"));
+        d.key(KeyCode::Enter);
+
+        // the boss resists fire
+        d.type_str("c").line("resist fire");
+        d.ctrl('d').line("goblins: 3");
+        d.ctrl('d').line("Goblins: 9/2 fire");
+        d.ctrl('d').line("Ogre: 9/2");
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[
+                ("Goblin 1", 0),
+                ("Goblin 2", 0),
+                ("Goblin Boss", 7),
+                ("Ogre", 26)
+            ])
+        );
+        let log = d.log();
+        assert_eq!(
+            log[log.len() - 4..],
+            [
+                "Goblin 1 takes 4 fire damage (9 saved)",
+                "Goblin 2 takes 4 fire damage (9 saved)",
+                "Goblin Boss takes 2 fire damage (9 saved, then halved)",
+                "Ogre takes 4 damage (9 saved)",
+            ]
+        );
+
+        // a single damage is undone at once
+        d.ctrl('u');
+        d.ctrl('u');
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[
+                ("Goblin 1", 4),
+                ("Goblin 2", 4),
+                ("Goblin Boss", 9),
+                ("Ogre", 30)
+            ])
+        );
+        // a participant is found before a group, and abbreviations only find participants
+        d.ctrl('d').line("Goblin: 1");
+        assert_eq!(d.state().title(), "Error");
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use crossterm::event::{Event, KeyCode};
+use persistent_structs::PersistentStruct;
+use tui::{
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListState},
+};
+
+use super::{Boxable, Mode, Normal, State, StateBox};
+use crate::{combat_state::CombatState, states, utils as ut, view_utils as vu, Frame};
+
+/// edits the initiative of the selected participant, and leaves the rest of it as it is. An
+/// empty input removes the initiative, so it is rolled again
+#[derive(Clone, PersistentStruct)]
+pub struct EditingIni {
+    parent_state: Box<Normal>,
+    input_buffer: String,
+}
+
+impl EditingIni {
+    pub fn new(parent_state: Box<Normal>) -> EditingIni {
+        let cs = &parent_state.combat_state;
+        let input_buffer = cs.participants[parent_state.current_selection]
+            .ini
+            .map(|ini| ini.to_string())
+            .unwrap_or_default();
+        EditingIni {
+            parent_state,
+            input_buffer,
+        }
+    }
+
+    fn apply(self) -> StateBox {
+        let input = self.input_buffer.trim();
+        let res = if input.is_empty() {
+            Ok(None)
+        } else {
+            input
+                .parse::<u8>()
+                .map(Some)
+                .with_context(|| format!("{} is not a valid initiative", input))
+        };
+        match res {
+            Ok(ini) => {
+                let idx = self.parent_state.current_selection;
+                self.parent_state
+                    .update_combat_state(|cs| {
+                        cs.recorded(|cs| cs.with_nth_participant_mut(idx, |p| p.ini = ini))
+                    })
+                    .boxed()
+            }
+            Err(e) => states::Msg::new(self.boxed(), ut::err_to_string(&e)).boxed(),
+        }
+    }
+}
+
+impl State for EditingIni {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Enter => Ok(self.apply()),
+                code => Ok(self
+                    .update_input_buffer(|b| ut::update_buffer(b, code))
+                    .boxed()),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::input_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        vu::render_input_block(f, "Initiative", &self.input_buffer, chunks[1]);
+        let list = List::new(vu::participants_list_items(
+            &self.parent_state.combat_state.participants,
+        ))
+        .block(Block::default().borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default();
+        list_state.select(Some(self.parent_state.current_selection));
+        f.render_stateful_widget(list, chunks[2], &mut list_state);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn title(&self) -> String {
+        let cs = &self.parent_state.combat_state;
+        format!(
+            "Editing Initiative of {}",
+            cs.participants[self.parent_state.current_selection].name
+        )
+    }
+
+    fn key_hints(&self) -> String {
+        "enter: set (empty: roll again); esc: back".into()
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_editing_ini() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10/12: 12 DEX=1 Axe=1d12+3")
+            .line("Goblin: 7")
+            .key(KeyCode::Esc);
+
+        d.type_str("e");
+        assert_eq!(d.state().title(), "Editing Initiative of Orc");
+        d.key(KeyCode::Backspace).line("8");
+        let orc = &d.combat_state().participants[0];
+        assert_eq!(orc.input_line(), "Orc: 10/12: 18 DEX=1 Axe=1d12+3");
+
+        // invalid input shows a message, and keeps the input
+        d.type_str("j").type_str("e").line("x");
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter).key(KeyCode::Backspace).line("15");
+        assert_eq!(d.combat_state().participants[1].ini, Some(15));
+
+        // an empty input removes the initiative, and undo restores it
+        d.type_str("e")
+            .key(KeyCode::Backspace)
+            .key(KeyCode::Backspace);
+        d.key(KeyCode::Enter);
+        assert_eq!(d.combat_state().participants[1].ini, None);
+        d.type_str("u");
+        assert_eq!(d.combat_state().participants[1].ini, Some(15));
+        assert_eq!(d.state().mode(), Mode::Normal);
+    }
+}
@@ -1,42 +1,271 @@
 use anyhow::Result;
-use crossterm::event::Event;
-use tui::style::Style;
-use tui::text::Span;
-use tui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use persistent_structs::PersistentStruct;
+use tui::{
+    style::{Modifier as StyleModifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
 
-use super::{State, StateBox};
-use crate::{combat_state::CombatState, view_utils as vu};
+use super::{Boxable, Fighting, Mode, State, StateBox};
+use crate::{
+    combat_state::{CombatState, Modifier, Participant},
+    states, utils as ut, view_utils as vu, Frame,
+};
 
-#[derive(Clone)]
+/// lists the modifiers of a participant, to rename, reorder or delete them, or to change their
+/// duration. The input holds the selected modifier, with the rounds it has left
+#[derive(Clone, PersistentStruct)]
 pub struct EditingModifiers {
-    combat_state: CombatState,
+    parent_state: Box<Fighting>,
     participant_idx: usize,
     modifier_idx: usize,
-    buffer: String,
+    input_buffer: String,
+}
+
+impl EditingModifiers {
+    pub fn new(parent_state: Box<Fighting>, participant_idx: usize) -> EditingModifiers {
+        EditingModifiers {
+            parent_state,
+            participant_idx,
+            modifier_idx: 0,
+            input_buffer: "".into(),
+        }
+        .with_selected(0)
+    }
+
+    fn participant(&self) -> &Participant {
+        &self.parent_state.combat_state.participants[self.participant_idx]
+    }
+
+    /// selects the modifier, and puts it into the input
+    fn with_selected(self, idx: usize) -> EditingModifiers {
+        let now = self.parent_state.combat_state.now();
+        let modifiers = &self.participant().modifiers;
+        let idx = idx.min(modifiers.len().saturating_sub(1));
+        let input_buffer = modifiers
+            .get(idx)
+            .map(|m| m.input_line(&now))
+            .unwrap_or_default();
+        self.with_modifier_idx(idx).with_input_buffer(input_buffer)
+    }
+
+    /// changes the modifiers of the participant so that it can be undone
+    fn update_modifiers(self, f: impl FnOnce(&mut Vec<Modifier>)) -> EditingModifiers {
+        let n = self.participant_idx;
+        self.update_parent_state(|parent| {
+            Box::new(parent.update_combat_state(|cs| {
+                cs.recorded(|cs| cs.with_nth_participant_mut(n, |p| f(&mut p.modifiers)))
+            }))
+        })
+    }
+
+    /// replaces the selected modifier with the one in the input. If the rounds that are left
+    /// didn't change, the modifier keeps expiring at the same time
+    fn update_selected(self) -> StateBox {
+        let idx = self.modifier_idx;
+        let old = match self.participant().modifiers.get(idx) {
+            Some(old) => old.clone(),
+            None => return self.boxed(),
+        };
+        let cs = &self.parent_state.combat_state;
+        let fac = cs
+            .conditions
+            .parse_factory(&self.input_buffer)
+            .and_then(|fac| cs.with_caster_resolved(fac));
+        match fac {
+            Ok(fac) => {
+                let now = self.parent_state.combat_state.now();
+                let mut new = fac(now);
+                if new.remaining_rounds(&now) == old.remaining_rounds(&now) {
+                    new.introduced_at = old.introduced_at;
+                    new.duration = old.duration;
+                }
+                self.update_modifiers(|mods| mods[idx] = new)
+                    .with_selected(idx)
+                    .boxed()
+            }
+            Err(e) => states::Msg::new(self.boxed(), ut::err_to_string(&e)).boxed(),
+        }
+    }
+
+    fn use_charge(self) -> EditingModifiers {
+        let (n, idx) = (self.participant_idx, self.modifier_idx);
+        self.update_parent_state(|parent| {
+            Box::new(parent.update_combat_state(|cs| cs.recorded(|cs| cs.with_charge_used(n, idx))))
+        })
+        .with_selected(idx)
+    }
+
+    fn delete_selected(self) -> EditingModifiers {
+        let idx = self.modifier_idx;
+        if idx >= self.participant().modifiers.len() {
+            return self;
+        }
+        self.update_modifiers(|mods| {
+            mods.remove(idx);
+        })
+        .with_selected(idx)
+    }
+
+    /// swaps the selected modifier with the next one (true) or the previous one (false)
+    fn move_selected(self, down: bool) -> EditingModifiers {
+        let idx = self.modifier_idx;
+        let len = self.participant().modifiers.len();
+        let target = if down {
+            idx + 1
+        } else {
+            match idx.checked_sub(1) {
+                Some(target) => target,
+                None => return self,
+            }
+        };
+        if target >= len {
+            return self;
+        }
+        self.update_modifiers(|mods| mods.swap(idx, target))
+            .with_selected(target)
+    }
 }
 
 impl State for EditingModifiers {
     fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
-        Ok(self)
+        if let Event::Key(key) = ev {
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Enter => Ok(self.update_selected()),
+                KeyCode::Char('d') if ctrl => Ok(self.delete_selected().boxed()),
+                KeyCode::Char('u') if ctrl => Ok(self.use_charge().boxed()),
+                KeyCode::Char('j') if ctrl => Ok(self.move_selected(true).boxed()),
+                KeyCode::Char('k') if ctrl => Ok(self.move_selected(false).boxed()),
+                KeyCode::Down => {
+                    let idx = self.modifier_idx + 1;
+                    Ok(self.with_selected(idx).boxed())
+                }
+                KeyCode::Up => {
+                    let idx = self.modifier_idx.saturating_sub(1);
+                    Ok(self.with_selected(idx).boxed())
+                }
+                code => Ok(self
+                    .update_input_buffer(|b| ut::update_buffer(b, code))
+                    .boxed()),
+            }
+        } else {
+            Ok(self)
+        }
     }
 
-    fn render(&mut self, f: &mut crate::Frame) {
-        let participant = self.combat_state.participants[self.participant_idx];
-
-        let chunks = vu::select_layout(f.size());
-        let info_text = Span::from(
-            "Editing Modifiers - enter: update, ctrl+j/k: move, ctrl+d: delete, esc: normal",
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::input_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        vu::render_input_block(
+            f,
+            "Modifier (<Name>[ @<Caster>][ <HP>/round][ x<Charges>][:<Rounds left>])",
+            &self.input_buffer,
+            chunks[1],
         );
-        f.render_widget(Paragraph::new(info_text), chunks[0]);
-
-        let list_lines: Vec<ListItem> =
-            vu::participants_list_items(&self.combat_state.participants, &self.initiatives);
-        let list = List::new(list_lines)
-            .block(Block::default().borders(Borders::ALL).title("Messages"))
-            .highlight_style(Style::default().add_modifier(tui::style::Modifier::REVERSED));
 
+        let now = self.parent_state.combat_state.now();
+        let items: Vec<ListItem> = self
+            .participant()
+            .modifiers
+            .iter()
+            .map(|m| ListItem::new(m.input_line(&now)))
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Modifiers"))
+            .highlight_style(Style::default().add_modifier(StyleModifier::REVERSED));
         let mut list_state = ListState::default();
         list_state.select(Some(self.modifier_idx));
         f.render_stateful_widget(list, chunks[2], &mut list_state);
     }
+
+    fn mode(&self) -> Mode {
+        Mode::Mod
+    }
+
+    fn title(&self) -> String {
+        format!("Editing Modifiers of {}", self.participant().name)
+    }
+
+    fn key_hints(&self) -> String {
+        "enter: update; up & down: select; ctrl+j/k: move; ctrl+u: use a charge; ctrl+d: delete; \
+         esc: back to fight"
+            .into()
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_editing_modifiers() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+        d.type_str("e").line("Poisoned: 3");
+        d.type_str("e").line("Prone");
+        d.type_str("e").line("Blessed: 2");
+        let modifiers = |d: &Driver| -> Vec<String> {
+            d.combat_state().participants[0]
+                .modifiers
+                .iter()
+                .map(|m| m.name.clone())
+                .collect()
+        };
+
+        d.ctrl('t');
+        assert_eq!(d.state().title(), "Editing Modifiers of Orc");
+        assert!(d.screen_contains("Poisoned:3"));
+
+        // moving the first one down, and renaming it with a shorter duration
+        d.ctrl('j');
+        assert_eq!(modifiers(&d), vec!["Prone", "Poisoned", "Blessed"]);
+        for _ in 0.."Poisoned:3".len() {
+            d.key(KeyCode::Backspace);
+        }
+        d.line("Weakened: 1");
+        assert_eq!(modifiers(&d), vec!["Prone", "Weakened", "Blessed"]);
+        assert_eq!(
+            d.combat_state().participants[0].modifiers[1].duration,
+            Some(1)
+        );
+
+        // the modifier keeps its duration if only the name changes
+        d.key(KeyCode::Down);
+        for _ in 0.."Blessed:2".len() {
+            d.key(KeyCode::Backspace);
+        }
+        d.line("Blessed!: 2");
+        let blessed = &d.combat_state().participants[0].modifiers[2];
+        assert_eq!(blessed.name, "Blessed!");
+        assert_eq!(blessed.duration, Some(2));
+
+        d.key(KeyCode::Up).key(KeyCode::Up).ctrl('d');
+        assert_eq!(modifiers(&d), vec!["Weakened", "Blessed!"]);
+        d.key(KeyCode::Esc).ctrl('u');
+        assert_eq!(modifiers(&d), vec!["Prone", "Weakened", "Blessed!"]);
+
+        // the modifiers of other participants are edited from the add prompt
+        d.type_str("d").key(KeyCode::Tab);
+        assert_eq!(d.state().title(), "Editing Modifiers of Goblin");
+        d.ctrl('d').key(KeyCode::Esc);
+        assert_eq!(d.state().mode(), Mode::Fight);
+    }
 }
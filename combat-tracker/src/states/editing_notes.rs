@@ -0,0 +1,161 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use tui::{
+    text::Text,
+    widgets::{Block, Borders, Paragraph, Wrap},
+};
+
+use super::{Boxable, Fighting, Mode, Normal, State, StateBox};
+use crate::{combat_state::CombatState, utils as ut, view_utils as vu, Frame};
+
+/// the states the notes of a participant can be edited from
+pub trait NotesParent: State + Clone + 'static {
+    fn with_notes(self, idx: usize, notes: String) -> Self;
+}
+
+impl NotesParent for Fighting {
+    fn with_notes(self, idx: usize, notes: String) -> Fighting {
+        self.update_combat_state(|cs| {
+            cs.recorded(|cs| cs.with_nth_participant_mut(idx, |p| p.notes = notes))
+        })
+    }
+}
+
+impl NotesParent for Normal {
+    fn with_notes(self, idx: usize, notes: String) -> Normal {
+        self.update_combat_state(|cs| {
+            cs.recorded(|cs| cs.with_nth_participant_mut(idx, |p| p.notes = notes))
+        })
+    }
+}
+
+/// edits the notes of a participant, like its AC, attacks and abilities. Enter starts a new
+/// line, so saving needs ctrl+s
+#[derive(Clone)]
+pub struct EditingNotes<P: NotesParent> {
+    parent_state: Box<P>,
+    idx: usize,
+    input_buffer: String,
+}
+
+impl<P: NotesParent> EditingNotes<P> {
+    pub fn new(parent_state: Box<P>, idx: usize) -> EditingNotes<P> {
+        let input_buffer = parent_state.combat_state().participants[idx].notes.clone();
+        EditingNotes {
+            parent_state,
+            idx,
+            input_buffer,
+        }
+    }
+}
+
+impl<P: NotesParent> State for EditingNotes<P> {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let notes = self.input_buffer.trim_end().to_string();
+                    Ok(self.parent_state.with_notes(self.idx, notes).boxed())
+                }
+                KeyCode::Enter => {
+                    let mut state = *self;
+                    state.input_buffer.push('\n');
+                    Ok(state.boxed())
+                }
+                code => {
+                    let mut state = *self;
+                    state.input_buffer = ut::update_buffer(state.input_buffer, code);
+                    Ok(state.boxed())
+                }
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::select_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        let editor = Paragraph::new(Text::raw(self.input_buffer.as_str()))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Notes and stat block"),
+            )
+            .wrap(Wrap { trim: false });
+        f.render_widget(editor, chunks[2]);
+        let last_line = self.input_buffer.rsplit('\n').next().unwrap_or_default();
+        let n_lines = self.input_buffer.matches('\n').count() as u16;
+        f.set_cursor(
+            chunks[2].x + last_line.chars().count() as u16 + 1,
+            chunks[2].y + n_lines + 1,
+        );
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn title(&self) -> String {
+        format!(
+            "Editing Notes of {}",
+            self.combat_state().participants[self.idx].name
+        )
+    }
+
+    fn key_hints(&self) -> String {
+        "enter: new line; ctrl+s: save; esc: back".into()
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        self.parent_state.combat_state()
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_participant_notes() {
+        let mut d = Driver::with_size(Insert::default().boxed(), 120, 24);
+        d.line("Orc: 10").line("Goblin: 7").key(KeyCode::Esc);
+
+        d.type_str("n");
+        assert_eq!(d.state().title(), "Editing Notes of Orc");
+        d.line("AC 13").type_str("Greataxe +5");
+        d.key(KeyCode::Esc);
+        assert!(d.combat_state().participants[0].notes.is_empty());
+        d.type_str("n")
+            .line("AC 13")
+            .type_str("Greataxe +5")
+            .ctrl('s');
+        assert_eq!(d.state().mode(), Mode::Normal);
+        assert_eq!(d.combat_state().participants[0].notes, "AC 13\nGreataxe +5");
+
+        // the pane shows the notes of the current participant only
+        d.key(KeyCode::Enter);
+        let screen = d.screen().join("\n");
+        assert!(screen.contains("AC 13"));
+        assert!(screen.contains("Greataxe +5"));
+        d.ctrl('n');
+        assert!(!d.screen_contains("AC 13"));
+        d.ctrl('b').line("AC 15").ctrl('s');
+        assert_eq!(d.state().mode(), Mode::Fight);
+        assert!(d.screen_contains("AC 15"));
+        d.ctrl('u');
+        assert!(d.combat_state().participants[1].notes.is_empty());
+    }
+}
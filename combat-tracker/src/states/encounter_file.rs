@@ -0,0 +1,166 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use derive_new::new;
+use persistent_structs::PersistentStruct;
+use std::path::Path;
+
+use super::{Boxable, Fighting, Mode, Normal, State, StateBox};
+use crate::{combat_state::CombatState, save, states, utils as ut, view_utils as vu, Frame};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileAction {
+    Save,
+    /// replaces the current state with the saved one, in the fight if it was saved during one
+    Load,
+    /// writes the combat log as markdown, for session notes
+    ExportLog,
+    /// writes how the participants left the fight as CSV or JSON
+    ExportResult,
+}
+
+/// asks for the path of an encounter file to save to, or to load from
+#[derive(Clone, new, PersistentStruct)]
+pub struct EncounterFile {
+    parent_state: StateBox,
+    action: FileAction,
+    input_buffer: String,
+}
+
+/// the state a saved encounter continues in: the fight if it was saved during one, and the
+/// participant list otherwise
+pub fn resumed(file: save::SaveFile) -> Result<StateBox> {
+    Ok(if file.fighting {
+        Fighting::new(file.combat_state).boxed()
+    } else {
+        Normal::new(file.combat_state)?.boxed()
+    })
+}
+
+impl EncounterFile {
+    fn run(self) -> StateBox {
+        let path = Path::new(self.input_buffer.trim());
+        let res = match self.action {
+            FileAction::Save => {
+                let fighting = self.parent_state.mode() == Mode::Fight;
+                save::save(self.parent_state.combat_state(), fighting, path)
+                    .map(|_| self.parent_state.clone())
+            }
+            FileAction::Load => save::load(path).and_then(resumed),
+            FileAction::ExportLog => save::export_log(self.parent_state.combat_state(), path)
+                .map(|_| self.parent_state.clone()),
+            FileAction::ExportResult => save::export_result(self.parent_state.combat_state(), path)
+                .map(|_| self.parent_state.clone()),
+        };
+        match res {
+            Ok(state) => state,
+            Err(e) => states::Msg::new(self.boxed(), ut::err_to_string(&e)).boxed(),
+        }
+    }
+}
+
+impl State for EncounterFile {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Enter => Ok(self.run()),
+                code => Ok(self
+                    .update_input_buffer(|b| ut::update_buffer(b, code))
+                    .boxed()),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::input_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        vu::render_input_block(f, "Path", &self.input_buffer, chunks[1]);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn title(&self) -> String {
+        match self.action {
+            FileAction::Save => "Saving Encounter".into(),
+            FileAction::Load => "Loading Encounter".into(),
+            FileAction::ExportLog => "Exporting Log".into(),
+            FileAction::ExportResult => "Exporting Result".into(),
+        }
+    }
+
+    fn key_hints(&self) -> String {
+        match self.action {
+            FileAction::Save => "enter: save; esc: back".into(),
+            FileAction::Load => "enter: load and resume the encounter; esc: back".into(),
+            FileAction::ExportLog => "enter: export as markdown; esc: back".into(),
+            FileAction::ExportResult => {
+                "enter: export as CSV or JSON, by the extension; esc: back".into()
+            }
+        }
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        self.parent_state.combat_state()
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::{hp_snapshot, hps, Driver};
+
+    #[test]
+    fn test_save_and_load_encounter() {
+        let path = std::env::temp_dir().join(format!("encounter-{}.json", std::process::id()));
+        let path_str = path.to_str().unwrap();
+
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+        d.type_str("q").type_str("d").line("Poisoned:3");
+        d.ctrl('n').ctrl('e').line("Reinforcements: +2");
+        d.ctrl('s').line(path_str);
+        assert_eq!(d.state().title(), "Fighting (Round 0)");
+
+        let mut loaded = Driver::new(Insert::default().boxed());
+        loaded.ctrl('o').line(path_str);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.state().mode(), Mode::Fight);
+        let cs = loaded.combat_state();
+        assert_eq!(hp_snapshot(cs), hps(&[("Orc", 9), ("Goblin", 7)]));
+        assert_eq!(cs.current_idx, 1);
+        assert_eq!(cs.participants[1].modifiers[0].name, "Poisoned");
+        assert_eq!(cs.events[0].text, "Reinforcements");
+
+        // loading a file that doesn't exist anymore shows an error
+        loaded
+            .key(KeyCode::Esc)
+            .type_str("k")
+            .ctrl('o')
+            .line(path_str);
+        assert_eq!(loaded.state().title(), "Error");
+
+        // an encounter that was saved before the fight is loaded before the fight
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").key(KeyCode::Esc);
+        d.ctrl('s').line(path_str);
+        let mut loaded = Driver::new(Insert::default().boxed());
+        loaded.ctrl('o').line(path_str);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.state().mode(), Mode::Normal);
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use tui::{
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+use super::{Boxable, Fighting, Mode, State, StateBox};
+use crate::{
+    combat_state::CombatState,
+    keymap::{self, Scope},
+    states, view_utils as vu, Frame,
+};
+
+/// the GM picks a delayed participant, which acts now, before the current one
+#[derive(Clone)]
+pub struct EndingDelay {
+    parent_state: Box<Fighting>,
+    /// the indices of the delayed participants
+    delayed: Vec<usize>,
+    /// an index into delayed
+    selection: usize,
+}
+
+impl EndingDelay {
+    /// a message, if nobody delays
+    pub fn enter(parent_state: Box<Fighting>) -> StateBox {
+        let delayed = parent_state.combat_state.delayed();
+        if delayed.is_empty() {
+            return states::Msg::new(parent_state, "Nobody delays their turn".into()).boxed();
+        }
+        EndingDelay {
+            parent_state,
+            delayed,
+            selection: 0,
+        }
+        .boxed()
+    }
+
+    fn move_selection(mut self, forward: bool) -> EndingDelay {
+        let len = self.delayed.len();
+        self.selection = if forward {
+            (self.selection + 1) % len
+        } else {
+            (self.selection + len - 1) % len
+        };
+        self
+    }
+
+    fn pick(self) -> StateBox {
+        let n = self.delayed[self.selection];
+        self.parent_state
+            .update_combat_state(|cs| cs.recorded(|cs| cs.with_delay_ended(n)))
+            .with_scroll(None)
+            .boxed()
+    }
+}
+
+impl State for EndingDelay {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Char('j') | KeyCode::Down => Ok(self.move_selection(true).boxed()),
+                KeyCode::Char('k') | KeyCode::Up => Ok(self.move_selection(false).boxed()),
+                KeyCode::Enter => Ok(self.pick()),
+                _ => Ok(self),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::select_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+
+        let cs = &self.parent_state.combat_state;
+        let items: Vec<ListItem> = self
+            .delayed
+            .iter()
+            .map(|i| {
+                let p = &cs.participants[*i];
+                ListItem::new(format!("{} - HP: {}", p.name, p.hp_text()))
+            })
+            .collect();
+        let title = format!("Who acts before {}?", cs.participants[cs.current_idx].name);
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default();
+        list_state.select(Some(self.selection));
+        f.render_stateful_widget(list, chunks[2], &mut list_state);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Fight
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::List)
+    }
+
+    fn title(&self) -> String {
+        "Ending a Delay".into()
+    }
+
+    fn key_hints(&self) -> String {
+        format!(
+            "{} & {}: navigate; enter: acts now; esc: back to fight",
+            keymap::key(Scope::List, "down"),
+            keymap::key(Scope::List, "up")
+        )
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_delayed_turns() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Ana: 10: 15").line("Bo: 10: 12").line("Cy: 10: 10");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        d.ctrl('y');
+        assert!(d.combat_state().participants[0].delayed);
+        assert_eq!(d.combat_state().current_idx, 1);
+        d.ctrl('n').ctrl('g').key(KeyCode::Enter);
+        let cs = d.combat_state();
+        assert_eq!(d.names(), ["Bo", "Ana", "Cy"]);
+        assert_eq!((cs.current_idx, cs.participants[1].ini), (1, Some(10)));
+        assert!(!cs.participants[1].delayed);
+        d.ctrl('n');
+        assert_eq!(d.combat_state().current_idx, 2);
+
+        // a delay that nobody ends is over when the slot comes around again
+        d.ctrl('n').ctrl('y').ctrl('n').ctrl('n');
+        let cs = d.combat_state();
+        assert_eq!((cs.current_round, cs.current_idx), (2, 0));
+        assert!(!cs.participants[0].delayed);
+        assert_eq!(
+            d.log()[d.log().len() - 3..],
+            ["Bo delays", "Round 2", "Bo stops delaying"]
+        );
+
+        d.ctrl('g');
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter).ctrl('u').ctrl('u');
+        assert_eq!(d.combat_state().current_idx, 1);
+        assert!(d.combat_state().participants[0].delayed);
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use derive_new::new;
+use tui::widgets::{Block, Borders, List, ListItem};
+
+use super::{Boxable, Fighting, Mode, State, StateBox};
+use crate::{
+    combat_state::CombatState,
+    keymap::{self, Scope},
+    states, view_utils as vu, Frame,
+};
+
+/// asks what happens to the HP of the participants when the fight is left
+#[derive(Clone, new)]
+pub struct EndingFight {
+    parent_state: Box<Fighting>,
+}
+
+impl EndingFight {
+    /// goes back to normal mode, or to insert mode if nobody is left
+    fn end(self, f: impl FnOnce(CombatState) -> CombatState) -> Result<StateBox> {
+        let cs = self
+            .parent_state
+            .combat_state
+            .recorded(|cs| f(cs).with_fight_ended());
+        if cs.participants.is_empty() {
+            Ok(states::Insert::new(cs, "".into()).boxed())
+        } else {
+            Ok(states::Normal::new(cs)?.boxed())
+        }
+    }
+}
+
+impl State for EndingFight {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Char('k') | KeyCode::Enter => self.end(|cs| cs),
+                KeyCode::Char('r') => self.end(CombatState::with_hp_before_fight),
+                KeyCode::Char('d') => self.end(CombatState::without_dead),
+                KeyCode::Char('l') => {
+                    Ok(
+                        states::EncounterFile::new(self, states::FileAction::ExportLog, "".into())
+                            .boxed(),
+                    )
+                }
+                KeyCode::Char('x') => Ok(states::EncounterFile::new(
+                    self,
+                    states::FileAction::ExportResult,
+                    "".into(),
+                )
+                .boxed()),
+                _ => Ok(self),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::select_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        let items: Vec<ListItem> = self
+            .parent_state
+            .combat_state
+            .participants
+            .iter()
+            .map(|p| {
+                let before = match p.hp_before_fight {
+                    Some(hp) if hp != p.hp => format!(" (before the fight: {})", hp),
+                    _ => "".into(),
+                };
+                ListItem::new(format!("{} - HP: {}{}", p.name, p.hp_text(), before))
+                    .style(vu::down_style(p))
+            })
+            .collect();
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("What happens to the HP?"),
+        );
+        f.render_widget(list, chunks[2]);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Fight
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::EndFight)
+    }
+
+    fn title(&self) -> String {
+        "Ending Fight".into()
+    }
+
+    fn key_hints(&self) -> String {
+        let key = |command| keymap::key(Scope::EndFight, command);
+        format!(
+            "{} or enter: keep current HP; {}: restore HP from before the fight; \
+             {}: remove the dead; {}: export the log; {}: export the result as CSV or JSON; \
+             esc: back to fight",
+            key("keep"),
+            key("restore-hp"),
+            key("remove-dead"),
+            key("export-log"),
+            key("export-result")
+        )
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::{hp_snapshot, hps, Driver};
+
+    #[test]
+    fn test_ending_fight() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 2").line("Elf: 8");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+        d.type_str("qqaa");
+
+        // esc goes back to the fight
+        d.key(KeyCode::Esc);
+        assert_eq!(d.state().title(), "Ending Fight");
+        assert!(d.screen_contains("Orc - HP: 8 (before the fight: 10)"));
+        d.key(KeyCode::Esc);
+        assert_eq!(d.state().mode(), Mode::Fight);
+
+        d.key(KeyCode::Esc).type_str("r");
+        assert_eq!(d.state().mode(), Mode::Normal);
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 10), ("Goblin", 2), ("Elf", 8)])
+        );
+
+        // the elf is stable, and stays
+        d.key(KeyCode::Enter).type_str("aa").type_str("zzzzzzzz");
+        // the goblin at 0 HP is preselected
+        d.ctrl('x').type_str("js");
+        d.key(KeyCode::Esc).type_str("d");
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 10), ("Elf", 0)])
+        );
+        assert!(d.combat_state().participants[0].hp_before_fight.is_none());
+    }
+
+    #[test]
+    fn test_export_result() {
+        let dir = std::env::temp_dir();
+        let csv = dir.join(format!("combat-result-{}.csv", std::process::id()));
+        let json = dir.join(format!("combat-result-{}.json", std::process::id()));
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc, Chief: 10").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+        d.type_str("qq").type_str("d").line("Bless:3");
+        d.ctrl('n').ctrl('n');
+
+        d.key(KeyCode::Esc).type_str("x").line("result.txt");
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter).key(KeyCode::Esc);
+        d.type_str("x").line(csv.to_str().unwrap());
+        assert_eq!(d.state().title(), "Ending Fight");
+        let exported = std::fs::read_to_string(&csv).unwrap();
+        std::fs::remove_file(&csv).unwrap();
+        assert_eq!(
+            exported,
+            "name,hp,max_hp,temp_hp,status,modifiers,rounds\n\
+             \"Orc, Chief\",8,,0,conscious,,2\n\
+             Goblin,7,,0,conscious,Bless:2,2\n"
+        );
+
+        d.type_str("x").line(json.to_str().unwrap());
+        let exported: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        std::fs::remove_file(&json).unwrap();
+        assert_eq!(exported["rounds"], 2);
+        assert_eq!(exported["participants"][1]["name"], "Goblin");
+        assert_eq!(exported["participants"][1]["modifiers"][0], "Bless:2");
+    }
+}
@@ -1,27 +1,30 @@
 use anyhow::Result;
-use crossterm::event::{Event, KeyCode, KeyModifiers};
+use crossterm::event::{Event, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
 use lazy_static::lazy_static;
 use pad::PadStr;
 use persistent_structs::PersistentStruct;
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, rc::Rc, time::Instant};
 use tui::{
-    layout::Constraint,
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Modifier, Style},
-    text::Span,
-    widgets::{Block, Borders, Paragraph, Row, Table, TableState},
+    widgets::{Block, Borders, Row, Table, TableState},
 };
 
 use crate::{
-    combat_state::{CombatState, Participant, SubRoundTime, TimeVec},
-    states::{self, Boxable, State, StateBox},
-    utils, view_utils as vu, Frame,
+    combat_state::{CombatState, Participant, SubRoundTime, TimeVec, TurnOrder},
+    keymap,
+    keymap::Scope,
+    states::{self, Boxable, Mode, State, StateBox},
+    view_utils as vu, Frame,
 };
 
-use super::AddingModifiers;
+use super::{
+    AddingModifiers, ChangingStatus, DealingDamage, EditingModifiers, EditingNotes, EncounterFile,
+    EndingDelay, EndingFight, FileAction, PickingNext, RollingMacro, SchedulingEvent,
+};
 
 lazy_static! {
-    static ref KEY_INFOS: Vec<KeyInfo> =
-        to_key_infos("qweasdzxcrtyfghvbnuiojklm,.;p/QWEASDZXCRTYFGHVBNUIOJKLM<>P:?");
+    static ref KEY_INFOS: Vec<KeyInfo> = to_key_infos(keymap::fight_rows());
 }
 
 #[derive(Clone, PersistentStruct)]
@@ -30,6 +33,35 @@ pub struct Fighting {
     pub hp_mod_map: Rc<HashMap<char, HpCallbackBox>>,
     pub tag_add_map: Rc<HashMap<char, TagCallbackBox>>,
     pub key_infos: Vec<KeyInfo>,
+    /// whether the combat log is shown next to the participants
+    pub show_log: bool,
+    /// whether the time of the current turn is shown
+    pub show_timer: bool,
+    pub turn_clock: TurnClock,
+    /// the first participant that is shown when the table was scrolled with the mouse. None
+    /// follows the current participant
+    pub scroll: Option<usize>,
+    /// where the table was rendered last, to find the participant that is clicked
+    pub table_area: Rect,
+    /// the round that was rendered last, to ring the bell when the next one starts
+    pub rendered_round: usize,
+}
+
+/// when the current turn started
+#[derive(Clone)]
+pub struct TurnClock {
+    /// round and index of the participant
+    turn: (usize, usize),
+    started: Instant,
+}
+
+impl TurnClock {
+    fn new(turn: (usize, usize)) -> TurnClock {
+        TurnClock {
+            turn,
+            started: Instant::now(),
+        }
+    }
 }
 
 pub type HpCallbackBox = Box<dyn Fn(CombatState) -> CombatState>;
@@ -60,22 +92,9 @@ impl Fighting {
                     vec![
                         (
                             keys.decrement,
-                            Box::new(move |cs| {
-                                cs.update_participants(|ps| {
-                                    utils::update_nth(ps, i, |p| {
-                                        p.clone().update_hp(|hp| if hp == 0 { 0 } else { hp - 1 })
-                                    })
-                                })
-                            }),
-                        ),
-                        (
-                            keys.increment,
-                            Box::new(move |cs| {
-                                cs.update_participants(|ps| {
-                                    utils::update_nth(ps, i, |p| p.clone().update_hp(|hp| hp + 1))
-                                })
-                            }),
+                            Box::new(move |cs| cs.with_hp_step(i, false)),
                         ),
+                        (keys.increment, Box::new(move |cs| cs.with_hp_step(i, true))),
                     ]
                 },
             )
@@ -93,11 +112,102 @@ impl Fighting {
                         }),
                     )
                 });
+        let turn = (combat_state.current_round, combat_state.current_idx);
+        let rendered_round = combat_state.current_round;
         Fighting {
             combat_state,
             hp_mod_map: Rc::new(HashMap::from_iter(key_map_iter)),
             tag_add_map: Rc::new(HashMap::from_iter(tag_callback_map_iter)),
             key_infos,
+            show_log: false,
+            show_timer: false,
+            turn_clock: TurnClock::new(turn),
+            scroll: None,
+            table_area: Rect::default(),
+            rendered_round,
+        }
+    }
+
+    /// undo and redo can change the participants, which the keys are derived from, so they
+    /// are built anew. The view settings are kept
+    fn with_history_step(self, step: fn(CombatState) -> CombatState) -> Fighting {
+        Fighting {
+            show_log: self.show_log,
+            show_timer: self.show_timer,
+            turn_clock: self.turn_clock,
+            rendered_round: self.rendered_round,
+            ..Fighting::new(step(self.combat_state))
+        }
+    }
+
+    /// the modifiers that expired when the turn passed are shown in a popup, so the GM notices
+    pub fn with_expirations_shown(self: Box<Fighting>) -> StateBox {
+        if self.combat_state.expired.is_empty() {
+            return self;
+        }
+        let msg = self.combat_state.expired.join("\n");
+        states::Msg::notice(self, "Expired", msg).boxed()
+    }
+
+    /// uses a charge of the first modifier of the current participant that has charges
+    fn with_charge_used(self: Box<Fighting>) -> StateBox {
+        let n = self.combat_state.current_idx;
+        let p = &self.combat_state.participants[n];
+        match p.modifiers.iter().position(|m| m.charges.is_some()) {
+            Some(m) => self
+                .update_combat_state(|cs| cs.recorded(|cs| cs.with_charge_used(n, m)))
+                .boxed(),
+            None => {
+                let msg = format!("{} has no modifier with charges", p.name);
+                states::Msg::new(self, msg).boxed()
+            }
+        }
+    }
+
+    /// the current participant loses concentration, which ends the modifiers it concentrates on
+    fn with_concentration_broken(self: Box<Fighting>) -> StateBox {
+        let n = self.combat_state.current_idx;
+        if self.combat_state.concentrates(n) {
+            self.update_combat_state(|cs| cs.recorded(|cs| cs.without_concentration_of(n)))
+                .boxed()
+        } else {
+            let msg = format!(
+                "{} doesn't concentrate on a modifier",
+                self.combat_state.participants[n].name
+            );
+            states::Msg::new(self, msg).boxed()
+        }
+    }
+
+    fn first_visible(&self) -> usize {
+        let last = self.combat_state.participants.len().saturating_sub(1);
+        let max_first = vu::first_visible(last, self.table_area);
+        match self.scroll {
+            Some(first) => first.min(max_first),
+            None => vu::first_visible(self.combat_state.current_idx, self.table_area),
+        }
+    }
+
+    /// clicking on an HP button changes the HP, the wheel scrolls the table
+    fn process_mouse(self: Box<Fighting>, ev: MouseEvent) -> StateBox {
+        let first = self.first_visible();
+        match ev.kind {
+            MouseEventKind::Down(MouseButton::Left) => match vu::hp_button_at(
+                &self.combat_state,
+                &self.key_infos,
+                first,
+                self.table_area,
+                ev.column,
+                ev.row,
+            ) {
+                Some((i, heal)) => self
+                    .update_combat_state(|cs| cs.recorded(|cs| cs.with_hp_step(i, heal)))
+                    .boxed(),
+                None => self,
+            },
+            MouseEventKind::ScrollDown => self.with_scroll(Some(first + 1)).boxed(),
+            MouseEventKind::ScrollUp => self.with_scroll(Some(first.saturating_sub(1))).boxed(),
+            _ => self,
         }
     }
 }
@@ -121,13 +231,81 @@ impl State for Fighting {
     fn process(self: Box<Fighting>, ev: Event) -> Result<StateBox> {
         if let Event::Key(key) = ev {
             match key.code {
-                KeyCode::Esc => Ok(states::Normal::from_combat_state(self.combat_state)?.boxed()),
-                KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => Ok(self
-                    .update_combat_state(CombatState::with_next_turn)
-                    .boxed()),
+                KeyCode::Esc => Ok(EndingFight::new(self).boxed()),
+                KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(match self.combat_state.turn_order {
+                        TurnOrder::Fixed => Box::new(
+                            self.update_combat_state(|cs| cs.recorded(CombatState::with_next_turn))
+                                .with_scroll(None),
+                        )
+                        .with_expirations_shown(),
+                        TurnOrder::Popcorn => PickingNext::new(self).boxed(),
+                    })
+                }
+                KeyCode::Char('y') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(match self.combat_state.turn_order {
+                        TurnOrder::Fixed => Box::new(
+                            self.update_combat_state(|cs| {
+                                cs.recorded(CombatState::with_turn_delayed)
+                            })
+                            .with_scroll(None),
+                        )
+                        .with_expirations_shown(),
+                        // everybody can act later anyway
+                        TurnOrder::Popcorn => states::Msg::new(
+                            self,
+                            "With popcorn initiative, turns can't be delayed".into(),
+                        )
+                        .boxed(),
+                    })
+                }
+                KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(self.with_charge_used())
+                }
+                KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(EndingDelay::enter(self))
+                }
+                KeyCode::Char('k') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(self.with_concentration_broken())
+                }
+                KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(SchedulingEvent::new(self, "".into()).boxed())
+                }
+                KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(DealingDamage::new(self, "".into()).boxed())
+                }
+                KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let current = self.combat_state.current_idx;
+                    Ok(EditingModifiers::new(self, current).boxed())
+                }
+                KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(ChangingStatus::new(self).boxed())
+                }
+                KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let current = self.combat_state.current_idx;
+                    Ok(EditingNotes::new(self, current).boxed())
+                }
+                KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(RollingMacro::enter(self))
+                }
+                KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(EncounterFile::new(self, FileAction::Save, "".into()).boxed())
+                }
+                KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(self.with_history_step(CombatState::undone).boxed())
+                }
+                KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(self.with_history_step(CombatState::redone).boxed())
+                }
+                KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(self.update_show_log(|s| !s).boxed())
+                }
+                KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(self.update_show_timer(|s| !s).boxed())
+                }
                 KeyCode::Char(c) => {
                     if let Some(f) = self.hp_mod_map.clone().get(&c) {
-                        Ok(self.update_combat_state(f).boxed())
+                        Ok(self.update_combat_state(|cs| cs.recorded(f)).boxed())
                     } else if let Some(f) = self.tag_add_map.clone().get(&c) {
                         Ok(f(self))
                     } else {
@@ -136,6 +314,8 @@ impl State for Fighting {
                 }
                 _ => Ok(self),
             }
+        } else if let Event::Mouse(mouse) = ev {
+            Ok(self.process_mouse(mouse))
         } else {
             Ok(self)
         }
@@ -143,12 +323,335 @@ impl State for Fighting {
 
     fn render(&mut self, f: &mut Frame) {
         let chunks = vu::select_layout(f.size());
-        let info_text = Span::from(format!(
-            "Fight - Esc: To normal; Current Round: {}",
-            self.combat_state.current_round
-        ));
-        f.render_widget(Paragraph::new(info_text), chunks[0]);
+        vu::render_top_bar(f, self, chunks[0]);
+        let round = self.combat_state.current_round;
+        if self.combat_state.ring_bell && round > self.rendered_round {
+            vu::ring_bell();
+        }
+        self.rendered_round = round;
+
+        let mut info_rect = chunks[1];
+        if self.show_timer {
+            let turn = (
+                self.combat_state.current_round,
+                self.combat_state.current_idx,
+            );
+            if self.turn_clock.turn != turn {
+                self.turn_clock = TurnClock::new(turn);
+            }
+            let split = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(1), Constraint::Length(12)].as_ref())
+                .split(chunks[1]);
+            info_rect = split[0];
+            vu::render_turn_timer(f, self.turn_clock.started.elapsed(), split[1]);
+        }
+        if self.combat_state.due_events().next().is_some() {
+            vu::render_event_banner(f, &self.combat_state, info_rect);
+        } else {
+            vu::render_last_log_entry(f, &self.combat_state, info_rect);
+        }
+
+        let main = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(1)].as_ref())
+            .split(chunks[2]);
+        vu::render_turn_banner(f, &self.combat_state, main[0]);
+
+        // the notes of the current participant and the log share a pane next to the table
+        let current = self
+            .combat_state
+            .participants
+            .get(self.combat_state.current_idx)
+            .filter(|p| !p.notes.is_empty());
+        self.table_area = if self.show_log || current.is_some() {
+            let split = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+                .split(main[1]);
+            match current {
+                Some(p) if self.show_log => {
+                    let side = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints(
+                            [Constraint::Percentage(50), Constraint::Percentage(50)].as_ref(),
+                        )
+                        .split(split[1]);
+                    vu::render_notes(f, p, side[0]);
+                    vu::render_log(f, &self.combat_state.log, side[1]);
+                }
+                Some(p) => vu::render_notes(f, p, split[1]),
+                None => vu::render_log(f, &self.combat_state.log, split[1]),
+            }
+            split[0]
+        } else {
+            main[1]
+        };
+        vu::render_fighting_mode_window(
+            f,
+            &self.combat_state,
+            &self.key_infos,
+            self.first_visible(),
+            self.table_area,
+        );
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Fight
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::Fight)
+    }
+
+    fn title(&self) -> String {
+        format!("Fighting (Round {})", self.combat_state.current_round)
+    }
+
+    fn key_hints(&self) -> String {
+        let turns: &[(&str, &str)] = match self.combat_state.turn_order {
+            TurnOrder::Fixed => &[
+                ("next-turn", "next turn"),
+                ("delay", "delay turn"),
+                ("end-delay", "end a delay"),
+            ],
+            TurnOrder::Popcorn => &[("next-turn", "pick who acts next")],
+        };
+        let commands = [
+            ("damage", "damage"),
+            ("status", "status"),
+            ("modifiers", "edit modifiers of current"),
+            ("use-charge", "use a charge of current"),
+            ("break-concentration", "break concentration of current"),
+            ("notes", "notes of current"),
+            ("schedule-event", "schedule event"),
+            ("roll-macro", "roll macro of current"),
+            ("undo", "undo"),
+            ("redo", "redo"),
+            ("save", "save"),
+            ("log", "log"),
+            ("timer", "timer"),
+        ];
+        let hints = turns
+            .iter()
+            .chain(&commands)
+            .map(|(command, text)| format!("{}: {}", keymap::key(Scope::Fight, command), text));
+        std::iter::once("esc: end fight".to_string())
+            .chain(hints)
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.combat_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use std::rc::Rc;
+
+    use crate::combat_state::LogEntry;
+    use crate::states::{breadcrumbs, Boxable, Insert, Mode};
+    use crate::test_utils::{hp_snapshot, hps, Driver};
+
+    #[test]
+    fn test_insert_roll_fight_damage_modifier_expiry() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Goblin: 7: 3").line("Orc: 10: 12");
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Goblin", 7), ("Orc", 10)])
+        );
+
+        d.key(KeyCode::Esc).type_str("r");
+        assert_eq!(d.state().mode(), Mode::Normal);
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 10), ("Goblin", 7)])
+        );
+
+        // keys of the first row are qwe, those of the second asd
+        d.key(KeyCode::Enter).type_str("aaw");
+        assert_eq!(d.state().mode(), Mode::Fight);
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 11), ("Goblin", 5)])
+        );
+
+        d.type_str("d");
+        assert_eq!(d.state().mode(), Mode::Mod);
+        assert_eq!(
+            breadcrumbs(d.state()),
+            vec!["Fighting (Round 0)", "Adding Modifier for Goblin"]
+        );
+        d.line("Stunned: 1");
+        assert_eq!(d.state().mode(), Mode::Fight);
+        assert_eq!(d.combat_state().participants[1].modifiers.len(), 1);
+
+        d.ctrl('n');
+        assert_eq!(d.combat_state().participants[1].modifiers.len(), 1);
+        d.ctrl('n');
+        assert_eq!(d.combat_state().current_round, 1);
+        assert!(d.combat_state().participants[1].modifiers.is_empty());
+    }
+
+    #[test]
+    fn test_turn_banner() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Ogre: 30").line("Alia: 0").line("Bram: 12");
+        d.key(KeyCode::Esc)
+            .key(KeyCode::Char('x'))
+            .key(KeyCode::Enter);
+        let banner = |d: &mut Driver| d.screen()[4].trim().to_string();
+        // Alia is down and skipped
+        assert_eq!(banner(&mut d), "Now: Ogre — Next: Bram");
+        d.ctrl('n');
+        assert_eq!(banner(&mut d), "Now: Bram — Next: Ogre");
+        d.ctrl('y');
+        assert_eq!(banner(&mut d), "Now: Ogre — Next: Bram");
+    }
+
+    #[test]
+    fn test_combat_log() {
+        let path = std::env::temp_dir().join(format!("combat-log-{}.md", std::process::id()));
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        // consecutive HP changes of one participant are one entry
+        d.type_str("qqqw").type_str("a");
+        d.type_str("d").line("Poisoned:1");
+        // expired modifiers are shown until the GM dismisses them
+        d.ctrl('n').ctrl('n');
+        assert_eq!(d.state().title(), "Expired");
+        assert!(d.screen_contains("Poisoned expired on Goblin"));
+        d.ctrl('n').key(KeyCode::Enter).ctrl('n').ctrl('n');
+        assert_eq!(
+            d.combat_state().log[1],
+            LogEntry::Hp {
+                who: "Orc".into(),
+                from: 10,
+                to: 8
+            }
+        );
+        assert_eq!(
+            d.log(),
+            [
+                "Round 0",
+                "Orc HP: 10 → 8",
+                "Goblin HP: 7 → 6",
+                "Goblin gets Poisoned for 1 rounds",
+                "Round 1",
+                "Poisoned of Goblin ended",
+                "Round 2",
+            ]
+        );
+
+        // the log pane and the timer don't get lost on undo
+        d.ctrl('l').ctrl('w').ctrl('u');
+        let screen = d.screen().join("\n");
+        assert!(screen.contains("Log"));
+        assert!(screen.contains("Turn 0:00"));
+
+        d.key(KeyCode::Esc)
+            .type_str("l")
+            .line(path.to_str().unwrap());
+        assert_eq!(d.state().title(), "Ending Fight");
+        let exported = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(exported.starts_with("# Combat Log\n\n## Round 0\n\n- Orc HP: 10 → 8\n"));
+    }
+
+    #[test]
+    fn test_mouse() {
+        let mut d = Driver::with_size(Insert::default().boxed(), 80, 12);
+        for i in 1..=8 {
+            d.line(&format!("Goblin {}: 7", i));
+        }
+        d.key(KeyCode::Esc);
+
+        let (column, row) = d.position_of("Goblin 3");
+        d.click(column, row).type_str("d");
+        assert_eq!(d.combat_state().participants.len(), 7);
+        assert!(!d.screen_contains("Goblin 3"));
+        // the wheel moves the selection, and the list follows it
+        d.scroll(true)
+            .scroll(true)
+            .scroll(true)
+            .scroll(true)
+            .scroll(true);
+        assert!(d.screen_contains("Goblin 8"));
+        d.type_str("d");
+        assert_eq!(d.combat_state().participants[5].name, "Goblin 7");
+
+        d.key(KeyCode::Enter);
+        let (column, row) = d.position_of("<a-");
+        d.click(column + 1, row).click(column + 1, row);
+        let (column, row) = d.position_of("-w>");
+        d.click(column, row);
+        let hp: Vec<u16> = d.combat_state().participants.iter().map(|p| p.hp).collect();
+        assert_eq!(hp, [8, 5, 7, 7, 7, 7]);
+        assert_eq!(d.log().last().unwrap(), "Goblin 1 HP: 7 → 8");
+
+        // scrolling the table, until the current participant is moved again
+        assert!(!d.screen_contains("Goblin 7"));
+        d.scroll(true).scroll(true).scroll(true).scroll(true);
+        let screen = d.screen().join("\n");
+        assert!(screen.contains("Goblin 7") && !screen.contains(">>"));
+        d.ctrl('n');
+        assert!(!d.screen_contains("Goblin 7"));
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7").line("Elf: 8");
+        d.key(KeyCode::Esc);
+
+        // deleting and reordering in normal mode
+        d.type_str("jd").type_str("u");
+        assert_eq!(d.combat_state().participants.len(), 3);
+        d.ctrl('r');
+        assert_eq!(d.combat_state().participants.len(), 2);
+        d.type_str("u");
+
+        // a misclicked HP key in the fight
+        d.key(KeyCode::Enter).type_str("qqa").ctrl('n');
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 8), ("Goblin", 6), ("Elf", 8)])
+        );
+        d.ctrl('u').ctrl('u');
+        assert_eq!(d.combat_state().current_idx, 0);
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 8), ("Goblin", 7), ("Elf", 8)])
+        );
+        d.ctrl('r');
+        assert_eq!(d.combat_state().participants[1].hp, 6);
+
+        // a new change drops what was undone
+        d.type_str("w").ctrl('r');
+        assert_eq!(d.combat_state().current_idx, 0);
+
+        // undoing the deletion from the fight brings back the keys of the participant
+        // k keeps the HP when the fight ends
+        d.key(KeyCode::Esc)
+            .type_str("kkd")
+            .key(KeyCode::Enter)
+            .ctrl('u');
+        assert_eq!(d.combat_state().participants.len(), 3);
+        d.type_str("z");
+        assert_eq!(d.combat_state().participants[2].hp, 7);
 
-        vu::render_fighting_mode_table(f, &self.combat_state, &self.key_infos, chunks[2]);
+        // the snapshots share the participants that didn't change
+        let before = d.combat_state().clone();
+        d.type_str("q");
+        let after = d.combat_state();
+        assert!(!Rc::ptr_eq(&before.participants[0], &after.participants[0]));
+        assert!(Rc::ptr_eq(&before.participants[1], &after.participants[1]));
     }
 }
@@ -1,35 +1,29 @@
 use anyhow::Result;
-use crossterm::event::{Event, KeyCode};
+use crossterm::event::{Event, KeyCode, KeyModifiers};
 use persistent_structs::PersistentStruct;
+use std::rc::Rc;
 use tui::{
-    text::Span,
-    widgets::{Block, Borders, List, Paragraph},
+    layout::{Constraint, Direction, Layout},
+    widgets::{Block, Borders, List},
 };
 
 use crate::{
     combat_state::{CombatState, Participant},
-    states::{self, Boxable, State, StateBox},
-    utils::{self, err_to_string},
-    view_utils as vu, Frame,
+    states::{self, Boxable, Mode, State, StateBox},
+    utils, view_utils as vu, Frame,
 };
 
 #[derive(Clone, Default, PersistentStruct)]
 pub struct Insert {
     pub combat_state: CombatState,
     pub input_buffer: String,
-    pub initiatives: Vec<Option<u8>>,
 }
 
 impl Insert {
-    pub fn new(
-        combat_state: CombatState,
-        input_buffer: String,
-        initiatives: impl IntoIterator<Item = Option<u8>>,
-    ) -> Insert {
+    pub fn new(combat_state: CombatState, input_buffer: String) -> Insert {
         Insert {
             combat_state,
             input_buffer,
-            initiatives: Vec::from_iter(initiatives),
         }
     }
     pub fn with_char_push(self, c: char) -> StateBox {
@@ -48,17 +42,13 @@ impl Insert {
         .boxed()
     }
 
-    pub fn with_new_participant(self, p: Participant, ini: Option<u8>) -> Self {
+    pub fn with_new_participant(self, p: Participant) -> Self {
         self.update_combat_state(|cs| {
             cs.update_participants(|mut ps| {
-                ps.push(p);
+                ps.push(Rc::new(p));
                 ps
             })
         })
-        .update_initiatives(|mut is| {
-            is.push(ini);
-            is
-        })
     }
 }
 
@@ -66,17 +56,21 @@ impl State for Insert {
     fn process(self: Box<Insert>, ev: Event) -> Result<StateBox> {
         if let Event::Key(key) = ev {
             match key.code {
+                KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => Ok(
+                    states::EncounterFile::new(self, states::FileAction::Load, "".into()).boxed(),
+                ),
                 KeyCode::Char(c) => Ok(self.with_char_push(c)),
                 KeyCode::Backspace => Ok(self.with_char_pop()),
                 KeyCode::Esc if self.combat_state.participants.len() > 0 => {
-                    Ok(states::Normal::new(self.combat_state, self.initiatives)?.boxed())
+                    Ok(states::Normal::new(self.combat_state)?.boxed())
                 }
+                // invalid input stays, the preview shows what is wrong with it
                 KeyCode::Enter => match utils::parse_participant_with_ini(&self.input_buffer) {
-                    Ok((ini, p)) => Ok(self
-                        .with_new_participant(p, ini)
+                    Ok(p) => Ok(self
+                        .with_new_participant(p)
                         .with_input_buffer("".into())
                         .boxed()),
-                    Err(e) => Ok(states::Msg::new(self, err_to_string(&e)).boxed()),
+                    Err(_) => Ok(self),
                 },
                 _ => Ok(self),
             }
@@ -87,17 +81,66 @@ impl State for Insert {
 
     fn render(&mut self, f: &mut Frame) {
         let chunks = vu::input_layout(f.size());
-        let info_text =
-            Span::from("Enter Participant syntax: \"Name: HP[: Inititive]\" (Esc: To Normal)");
-        f.render_widget(Paragraph::new(info_text), chunks[0]);
+        vu::render_top_bar(f, self, chunks[0]);
 
         vu::render_input_block(f, "New Participant", &self.input_buffer, chunks[1]);
 
-        let list_lines =
-            vu::participants_list_items(&self.combat_state.participants, &self.initiatives);
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(chunks[2]);
+
+        let list_lines = vu::participants_list_items(&self.combat_state.participants);
+        let list = List::new(list_lines)
+            .block(Block::default().borders(Borders::ALL).title("Participants"));
+        f.render_widget(list, columns[0]);
+        vu::render_participant_preview(f, &self.input_buffer, columns[1]);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn title(&self) -> String {
+        "Adding Participants".into()
+    }
+
+    fn key_hints(&self) -> String {
+        "syntax: \"Name: HP[/Max][+Temp][: Initiative]\"; enter: add; esc: to normal; ctrl+o: load"
+            .into()
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.combat_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_invalid_input_stays_in_buffer_with_preview_error() {
+        // input that isn't a participant stays in the buffer, and the preview says why
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Goblin");
+        assert_eq!(d.state().title(), "Adding Participants");
+        assert!(d.screen_contains("Didn't"));
+        assert!(d.combat_state().participants.is_empty());
 
-        let list =
-            List::new(list_lines).block(Block::default().borders(Borders::ALL).title("Messages"));
-        f.render_widget(list, chunks[2]);
+        // the preview follows every keystroke
+        d.type_str(": 7/9: 12");
+        let screen = d.screen().join("\n");
+        assert!(screen.contains("HP: 7/9"));
+        assert!(screen.contains("Initiative: 12"));
+        d.key(KeyCode::Enter);
+        assert_eq!(d.combat_state().participants.len(), 1);
     }
 }
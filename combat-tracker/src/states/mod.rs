@@ -1,7 +1,8 @@
 use anyhow::Result;
 use crossterm::event::Event;
+use tui::style::Color;
 
-use crate::Frame;
+use crate::{combat_state::CombatState, keymap::Scope, Frame};
 
 pub trait Boxable {
     fn boxed(self) -> StateBox;
@@ -30,6 +31,67 @@ impl Clone for Box<dyn State> {
 pub trait State: Boxable {
     fn process(self: Box<Self>, ev: Event) -> Result<StateBox>;
     fn render(&mut self, f: &mut Frame);
+
+    /// the mode that is shown in the badge of the top bar
+    fn mode(&self) -> Mode;
+    /// the name of this state in the breadcrumb
+    fn title(&self) -> String;
+    /// the keys that can be used in this state
+    fn key_hints(&self) -> String;
+    /// whether keys are typed into an input, instead of triggering commands. Profiles leave
+    /// these keys alone
+    fn has_input(&self) -> bool {
+        false
+    }
+    /// the section of the keymap that applies to this state, None if its keys can't be rebound
+    fn key_scope(&self) -> Option<Scope> {
+        None
+    }
+    /// the state this one was entered from, and which it will return to
+    fn parent(&self) -> Option<&dyn State> {
+        None
+    }
+    fn combat_state(&self) -> &CombatState;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Fight,
+    Mod,
+}
+
+impl Mode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Fight => "FIGHT",
+            Mode::Mod => "MOD",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Mode::Normal => Color::Blue,
+            Mode::Insert => Color::Green,
+            Mode::Fight => Color::Red,
+            Mode::Mod => Color::Magenta,
+        }
+    }
+}
+
+/// the titles of all states from the outermost one to the given one
+pub fn breadcrumbs(state: &dyn State) -> Vec<String> {
+    let mut res = vec![state.title()];
+    let mut current = state.parent();
+    while let Some(parent) = current {
+        res.push(parent.title());
+        current = parent.parent();
+    }
+    res.reverse();
+    res
 }
 
 pub type StateBox = Box<dyn State>;
@@ -49,5 +111,54 @@ pub use fighting::Fighting;
 pub mod adding_modifier;
 pub use adding_modifier::AddingModifiers;
 
-//pub mod editing_modifiers;
-//pub use editing_modifiers::EditingModifiers;
+pub mod picking_next;
+pub use picking_next::PickingNext;
+
+pub mod rolling_macro;
+pub use rolling_macro::RollingMacro;
+
+pub mod dealing_damage;
+pub use dealing_damage::DealingDamage;
+
+pub mod encounter_file;
+pub use encounter_file::{EncounterFile, FileAction};
+
+pub mod scheduling_event;
+pub use scheduling_event::SchedulingEvent;
+
+pub mod changing_status;
+pub use changing_status::ChangingStatus;
+
+pub mod ending_fight;
+pub use ending_fight::EndingFight;
+
+pub mod editing_modifiers;
+pub use editing_modifiers::EditingModifiers;
+
+pub mod editing_ini;
+pub use editing_ini::EditingIni;
+
+pub mod editing_notes;
+pub use editing_notes::EditingNotes;
+
+pub mod breaking_ties;
+pub use breaking_ties::BreakingTies;
+
+pub mod ending_delay;
+pub use ending_delay::EndingDelay;
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_top_bar_is_rendered() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Goblin: 7").key(KeyCode::Esc).key(KeyCode::Enter);
+        assert!(d.screen_contains("FIGHT"));
+        assert!(d.screen_contains("Goblin"));
+    }
+}
@@ -6,13 +6,26 @@ use tui::{
     widgets::{Block, Borders, Paragraph},
 };
 
-use super::State;
-use crate::{Frame, StateBox};
+use super::{Mode, State};
+use crate::{combat_state::CombatState, Frame, StateBox};
 
+/// a popup that is dismissed with enter. Errors are shown with new, other news with notice
 #[derive(Clone, new)]
 pub struct Msg {
     pub parent: StateBox,
     pub msg: String,
+    #[new(value = "\"Error\".into()")]
+    pub title: String,
+}
+
+impl Msg {
+    pub fn notice(parent: StateBox, title: &str, msg: String) -> Msg {
+        Msg {
+            parent,
+            msg,
+            title: title.into(),
+        }
+    }
 }
 
 impl State for Msg {
@@ -31,7 +44,11 @@ impl State for Msg {
         // self.parent.render(f);
         let msg = Paragraph::new(&self.msg[..])
             .alignment(tui::layout::Alignment::Center)
-            .block(Block::default().borders(Borders::ALL).title("Error"));
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(self.title.as_str()),
+            );
         let rect = f.size();
         f.render_widget(
             msg,
@@ -41,4 +58,24 @@ impl State for Msg {
             }),
         );
     }
+
+    fn mode(&self) -> Mode {
+        self.parent.mode()
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn key_hints(&self) -> String {
+        "enter: dismiss".into()
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        self.parent.combat_state()
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent.as_ref())
+    }
 }
@@ -1,35 +1,37 @@
 use anyhow::{ensure, Result};
-use crossterm::event::{Event, KeyCode};
+use crossterm::event::{Event, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
 use persistent_structs::PersistentStruct;
 use tui::{
+    layout::Rect,
     style::{Modifier, Style},
-    text::Span,
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, List, ListItem, ListState},
 };
 
 use crate::{
-    combat_state::CombatState,
-    states::{self, Boxable, State, StateBox},
-    utils, view_utils as vu, Frame,
+    combat_state::{CombatState, SortBy, TieBreak, TurnOrder},
+    keymap::{self, Scope},
+    states::{self, Boxable, Mode, State, StateBox},
+    view_utils as vu, Frame,
 };
 
 #[derive(Clone, PersistentStruct)]
 pub struct Normal {
     pub combat_state: CombatState,
-    pub initiatives: Vec<Option<u8>>,
     pub current_selection: usize,
+    /// where the list was rendered last, to find the participant that is clicked
+    pub list_area: Rect,
 }
 
 impl Normal {
-    pub fn new(combat_state: CombatState, initiatives: Vec<Option<u8>>) -> Result<Normal> {
+    pub fn new(combat_state: CombatState) -> Result<Normal> {
         ensure!(
             combat_state.participants.len() > 0,
             "Normal mode can only be used with at least one participant"
         );
         Ok(Normal {
             combat_state,
-            initiatives,
             current_selection: 0,
+            list_area: Rect::default(),
         })
     }
 
@@ -45,33 +47,18 @@ impl Normal {
 
     fn change_selection(self) -> StateBox {
         let idx = self.current_selection;
-        let (combat_state, editee) = self.combat_state.with_nth_participant_popped(idx);
-        let (editee_ini, initiatives) = utils::with_popped_n(self.initiatives, idx);
+        // undoing restores the participant as it was before the edit
+        let (combat_state, editee) = self
+            .combat_state
+            .recorded(|cs| cs)
+            .with_nth_participant_popped(idx);
 
-        states::Insert::new(
-            combat_state,
-            format!(
-                "{}{}",
-                editee,
-                if let Some(ini) = editee_ini {
-                    format!(":{}", ini)
-                } else {
-                    "".to_string()
-                }
-            ),
-            initiatives,
-        )
-        .boxed()
+        states::Insert::new(combat_state, editee.input_line()).boxed()
     }
 
     fn delete_selection(self) -> Normal {
         let idx = self.current_selection;
-        let res = self
-            .update_combat_state(|s| s.without_participant(idx))
-            .update_initiatives(|mut is| {
-                is.remove(idx);
-                is
-            });
+        let res = self.update_combat_state(|cs| cs.recorded(|cs| cs.without_participant(idx)));
         let new_index = if idx == res.combat_state.participants.len() {
             idx - 1
         } else {
@@ -80,29 +67,56 @@ impl Normal {
         res.with_current_selection(new_index)
     }
 
-    pub fn from_combat_state(cs: CombatState) -> Result<Self> {
-        ensure!(
-            cs.participants.len() > 0,
-            "Normal mode must always have at least one entry"
-        );
-        let initiatives = vec![None; cs.participants.len()];
-        Normal::new(cs, initiatives)
+    /// with the Ask tie break, the GM orders the tied participants afterwards, unless the order
+    /// is locked
+    pub fn roll_initiatives(self) -> StateBox {
+        let res = self.update_combat_state(|cs| cs.recorded(CombatState::with_rolled_initiatives));
+        match res.combat_state.tie_break {
+            TieBreak::Ask if !res.combat_state.order_locked => states::BreakingTies::enter(res),
+            _ => res.boxed(),
+        }
     }
 
-    pub fn roll_initiatives(self) -> Normal {
-        let mut res = self.update_initiatives(|inis| {
-            inis.into_iter()
-                .map(|ini| match ini {
-                    None => Some(utils::roll(2, 6)),
-                    ini => ini,
-                })
-                .collect()
+    fn restore_deleted(self) -> Normal {
+        if self.combat_state.graveyard.is_empty() {
+            return self;
+        }
+        let mut restored = None;
+        let res = self.update_combat_state(|cs| {
+            cs.recorded(|cs| {
+                let (cs, n) = cs.with_restored_participant();
+                restored = n;
+                cs
+            })
         });
-        // even though it is mut, it will not be mutated, according to the docs
-        let mut sorter = permutation::sort_by(&res.initiatives, |a, b| b.unwrap().cmp(&a.unwrap()));
-        sorter.apply_slice_in_place(&mut res.initiatives);
-        sorter.apply_slice_in_place(&mut res.combat_state.participants);
-        res
+        match restored {
+            Some(n) => res.with_current_selection(n),
+            None => res,
+        }
+    }
+
+    /// undo and redo can change the number of participants, so the selection is kept in range
+    fn with_history_step(self, step: fn(CombatState) -> CombatState) -> Normal {
+        let res = self.update_combat_state(step);
+        let last = res.combat_state.participants.len() - 1;
+        res.update_current_selection(|s| s.min(last))
+    }
+
+    /// clicking selects a participant, the wheel moves the selection
+    fn process_mouse(self, ev: MouseEvent) -> Normal {
+        let last = self.combat_state.participants.len() - 1;
+        match ev.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let first = vu::first_visible(self.current_selection, self.list_area);
+                match vu::row_at(self.list_area, first, last + 1, ev.column, ev.row) {
+                    Some(i) => self.with_current_selection(i),
+                    None => self,
+                }
+            }
+            MouseEventKind::ScrollDown => self.update_current_selection(|s| (s + 1).min(last)),
+            MouseEventKind::ScrollUp => self.update_current_selection(|s| s.saturating_sub(1)),
+            _ => self,
+        }
     }
 
     pub fn move_selected_down(self) -> Normal {
@@ -118,9 +132,11 @@ impl Normal {
     fn swap_current_selection_with(self, swap_pos: usize) -> Normal {
         let sel = self.current_selection;
         self.update_combat_state(|cs| {
-            cs.update_participants(|mut ps| {
-                ps.swap(sel, swap_pos);
-                ps
+            cs.recorded(|cs| {
+                cs.update_participants(|mut ps| {
+                    ps.swap(sel, swap_pos);
+                    ps
+                })
             })
         })
     }
@@ -130,22 +146,65 @@ impl State for Normal {
     fn process(self: Box<Normal>, ev: Event) -> Result<StateBox> {
         if let Event::Key(key) = ev {
             match key.code {
+                KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => Ok(
+                    states::EncounterFile::new(self, states::FileAction::Save, "".into()).boxed(),
+                ),
+                KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => Ok(
+                    states::EncounterFile::new(self, states::FileAction::Load, "".into()).boxed(),
+                ),
+                KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(states::SchedulingEvent::new(self, "".into()).boxed())
+                }
+                KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Ok(self.with_history_step(CombatState::redone).boxed())
+                }
+                KeyCode::Char('u') => Ok(self.with_history_step(CombatState::undone).boxed()),
                 KeyCode::Char('j') => Ok(self.increment_selection().boxed()),
                 KeyCode::Char('k') => Ok(self.decrement_selection().boxed()),
                 KeyCode::Char('J') => Ok(self.move_selected_down().boxed()),
                 KeyCode::Char('K') => Ok(self.move_selected_up().boxed()),
                 KeyCode::Char('c') => Ok(self.change_selection()),
+                KeyCode::Char('e') => Ok(states::EditingIni::new(self).boxed()),
+                KeyCode::Char('n') => {
+                    let selection = self.current_selection;
+                    Ok(states::EditingNotes::new(self, selection).boxed())
+                }
                 KeyCode::Char('d') => Ok(self.delete_selection().boxed()),
-                KeyCode::Char('r') => Ok(self.roll_initiatives().boxed()),
+                KeyCode::Char('R') => Ok(self.restore_deleted().boxed()),
+                KeyCode::Char('r') => Ok(self.roll_initiatives()),
+                KeyCode::Char('t') => Ok(self
+                    .update_combat_state(|cs| cs.recorded(|cs| cs.update_tie_break(TieBreak::next)))
+                    .boxed()),
+                KeyCode::Char('s') => Ok(self
+                    .update_combat_state(|cs| cs.recorded(CombatState::sorted))
+                    .boxed()),
+                KeyCode::Char('o') => Ok(self
+                    .update_combat_state(|cs| cs.recorded(|cs| cs.update_sort_by(SortBy::next)))
+                    .boxed()),
+                KeyCode::Char('l') => Ok(self
+                    .update_combat_state(|cs| cs.recorded(|cs| cs.update_order_locked(|l| !l)))
+                    .boxed()),
                 KeyCode::Char('i') => {
-                    Ok(
-                        states::Insert::new(self.combat_state, "".to_string(), self.initiatives)
-                            .boxed(),
-                    )
+                    Ok(states::Insert::new(self.combat_state, "".to_string()).boxed())
+                }
+                KeyCode::Char('p') => Ok(self
+                    .update_combat_state(|cs| cs.recorded(CombatState::with_toggled_turn_order))
+                    .boxed()),
+                KeyCode::Char('x') => Ok(self
+                    .update_combat_state(|cs| cs.recorded(|cs| cs.update_skip_down(|s| !s)))
+                    .boxed()),
+                KeyCode::Enter => {
+                    let fighting = states::Fighting::new(self.combat_state.with_fight_started());
+                    Ok(match fighting.combat_state.turn_order {
+                        TurnOrder::Fixed => fighting.boxed(),
+                        // the GM picks who starts
+                        TurnOrder::Popcorn => states::PickingNext::new(Box::new(fighting)).boxed(),
+                    })
                 }
-                KeyCode::Enter => Ok(states::Fighting::new(self.combat_state).boxed()),
                 _ => Ok(self),
             }
+        } else if let Event::Mouse(mouse) = ev {
+            Ok(self.process_mouse(mouse).boxed())
         } else {
             Ok(self)
         }
@@ -153,19 +212,174 @@ impl State for Normal {
 
     fn render(&mut self, f: &mut Frame) {
         let chunks = vu::select_layout(f.size());
-        let info_text = Span::from(
-            "Normal - c: change; d: delete; j & k: navigate; r: roll ini; enter: start fight",
-        );
-        f.render_widget(Paragraph::new(info_text), chunks[0]);
+        vu::render_top_bar(f, self, chunks[0]);
 
         let list_lines: Vec<ListItem> =
-            vu::participants_list_items(&self.combat_state.participants, &self.initiatives);
+            vu::participants_list_items(&self.combat_state.participants);
         let list = List::new(list_lines)
             .block(Block::default().borders(Borders::ALL).title("Messages"))
             .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
         let mut list_state = ListState::default();
         list_state.select(Some(self.current_selection));
+        self.list_area = chunks[2];
         f.render_stateful_widget(list, chunks[2], &mut list_state);
     }
+
+    fn mode(&self) -> Mode {
+        Mode::Normal
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::Normal)
+    }
+
+    fn title(&self) -> String {
+        let cs = &self.combat_state;
+        let notes: Vec<&str> = [
+            (cs.turn_order == TurnOrder::Popcorn, "popcorn initiative"),
+            (cs.order_locked, "order locked"),
+        ]
+        .into_iter()
+        .filter_map(|(shown, note)| shown.then_some(note))
+        .collect();
+        if notes.is_empty() {
+            "Participants".into()
+        } else {
+            format!("Participants ({})", notes.join(", "))
+        }
+    }
+
+    fn key_hints(&self) -> String {
+        let key = |command| keymap::key(Scope::Normal, command);
+        let on_off = |on| if on { "on" } else { "off" };
+        let cs = &self.combat_state;
+        [
+            format!("{}: change", key("change")),
+            format!("{}: edit ini", key("edit-ini")),
+            format!("{}: notes", key("notes")),
+            format!("{}: delete", key("delete")),
+            format!("{}: restore deleted", key("restore")),
+            format!("{} & {}: navigate", key("down"), key("up")),
+            format!("{}: roll ini ({})", key("roll-ini"), cs.ini_roll),
+            format!("{}: ties ({})", key("tie-break"), cs.tie_break),
+            format!("{}: sort by {}", key("sort"), cs.sort_by),
+            format!("{}: change sort", key("sort-by")),
+            format!(
+                "{}: lock order ({})",
+                key("lock-order"),
+                on_off(cs.order_locked)
+            ),
+            format!("{}: toggle popcorn initiative", key("popcorn")),
+            format!(
+                "{}: skip downed ({})",
+                key("skip-down"),
+                on_off(cs.skip_down)
+            ),
+            "enter: start fight".into(),
+            format!("{}: schedule event", key("schedule-event")),
+            format!("{}: undo", key("undo")),
+            format!("{}: redo", key("redo")),
+            format!("{}: save", key("save")),
+            format!("{}: load", key("load")),
+        ]
+        .join("; ")
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.combat_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::combat_state::CombatState;
+    use crate::states::{Boxable, Insert, Normal};
+    use crate::test_utils::{hp_snapshot, hps, Driver};
+
+    #[test]
+    fn test_restore_deleted() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10: 12").line("Goblin: 7: 9").line("Elf: 8: 3");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+        d.type_str("d").line("Poisoned");
+        d.key(KeyCode::Esc).type_str("k");
+
+        // nothing to restore yet
+        d.type_str("R");
+        assert_eq!(d.combat_state().participants.len(), 3);
+
+        d.type_str("jd").type_str("d");
+        assert_eq!(hp_snapshot(d.combat_state()), hps(&[("Orc", 10)]));
+        d.type_str("R");
+        assert_eq!(
+            hp_snapshot(d.combat_state()),
+            hps(&[("Orc", 10), ("Elf", 8)])
+        );
+        d.type_str("R");
+        let goblin = &d.combat_state().participants[1];
+        assert_eq!(goblin.name, "Goblin");
+        assert_eq!(goblin.ini, Some(9));
+        assert_eq!(goblin.modifiers[0].name, "Poisoned");
+
+        // the dead that are removed at the end of a fight can be restored as well
+        d.key(KeyCode::Enter).type_str("qqqqqqqqqq");
+        d.key(KeyCode::Esc).type_str("d").type_str("R");
+        assert_eq!(d.combat_state().participants[0].name, "Orc");
+        assert_eq!(d.combat_state().participants.len(), 3);
+    }
+
+    #[test]
+    fn test_sort_and_lock_order() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("orc: 10: 12").line("Wolf: 11: 15").line("Elf: 8: 3");
+        d.key(KeyCode::Esc).type_str("s");
+        assert_eq!(d.names(), ["Wolf", "orc", "Elf"]);
+        d.type_str("o").type_str("s");
+        assert_eq!(d.names(), ["Elf", "orc", "Wolf"]);
+        d.type_str("o").type_str("s");
+        assert_eq!(d.names(), ["Wolf", "orc", "Elf"]);
+
+        d.type_str("l");
+        assert_eq!(d.state().title(), "Participants (order locked)");
+        d.type_str("o").type_str("s").type_str("r");
+        assert_eq!(d.names(), ["Wolf", "orc", "Elf"]);
+        // single participants can still be moved
+        d.type_str("J");
+        assert_eq!(d.names(), ["orc", "Wolf", "Elf"]);
+        d.type_str("l").type_str("r");
+        assert_eq!(d.names(), ["Wolf", "orc", "Elf"]);
+        assert_eq!(d.state().title(), "Participants");
+    }
+
+    #[test]
+    fn test_initiative_bonus() {
+        let participants = ["Goblin: 7: -2 DEX=1", "Elf: 8: 2", "Orc: 10: +3"]
+            .map(|line| crate::utils::parse_participant_with_ini(line).unwrap());
+        let cs = CombatState::from_participants(participants.to_vec())
+            .with_ini_roll("1d1+DEX".parse().unwrap());
+        let mut d = Driver::new(Normal::new(cs).unwrap().boxed());
+        assert!(d.screen_contains("Orc - HP: 10; Ini: +3"));
+
+        d.type_str("r");
+        let inis: Vec<(&str, Option<u8>)> = d
+            .combat_state()
+            .participants
+            .iter()
+            .map(|p| (p.name.as_str(), p.ini))
+            .collect();
+        assert_eq!(
+            inis,
+            [("Orc", Some(4)), ("Elf", Some(2)), ("Goblin", Some(0))]
+        );
+
+        // a removed initiative is rolled with the bonus again
+        d.type_str("e").key(KeyCode::Backspace).line("9");
+        assert_eq!(d.combat_state().participants[0].ini, Some(9));
+        d.type_str("e").key(KeyCode::Backspace).line("");
+        d.type_str("r");
+        assert_eq!(d.combat_state().participants[0].ini, Some(4));
+    }
 }
@@ -0,0 +1,175 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use persistent_structs::PersistentStruct;
+use tui::{
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+use super::{Boxable, Fighting, Mode, State, StateBox};
+use crate::{
+    combat_state::CombatState,
+    keymap::{self, Scope},
+    states, utils as ut, view_utils as vu, Frame,
+};
+
+/// popcorn initiative: the GM picks who acts next from the participants that haven't acted in
+/// this round
+#[derive(Clone, PersistentStruct)]
+pub struct PickingNext {
+    parent_state: Box<Fighting>,
+    selection: usize,
+}
+
+impl PickingNext {
+    pub fn new(parent_state: Box<Fighting>) -> PickingNext {
+        let cs = &parent_state.combat_state;
+        let selection = (0..cs.participants.len())
+            .find(|i| cs.can_act(*i))
+            .unwrap_or(0);
+        PickingNext {
+            parent_state,
+            selection,
+        }
+    }
+
+    /// moves the selection to the next participant that can act, in the given direction
+    fn move_selection(self, forward: bool) -> PickingNext {
+        let cs = &self.parent_state.combat_state;
+        let len = cs.participants.len();
+        let next = (1..=len)
+            .map(|step| {
+                if forward {
+                    (self.selection + step) % len
+                } else {
+                    (self.selection + len - step) % len
+                }
+            })
+            .find(|i| cs.can_act(*i))
+            .unwrap_or(self.selection);
+        self.with_selection(next)
+    }
+
+    fn pick(self) -> StateBox {
+        let cs = self.parent_state.combat_state.clone();
+        match cs.clone().with_turn_of(self.selection) {
+            Ok(next) => Box::new(self.parent_state.with_combat_state(cs.recorded(|_| next)))
+                .with_expirations_shown(),
+            Err(e) => states::Msg::new(self.boxed(), ut::err_to_string(&e)).boxed(),
+        }
+    }
+}
+
+impl State for PickingNext {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Char('j') | KeyCode::Down => Ok(self.move_selection(true).boxed()),
+                KeyCode::Char('k') | KeyCode::Up => Ok(self.move_selection(false).boxed()),
+                KeyCode::Enter => Ok(self.pick()),
+                _ => Ok(self),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::select_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+
+        let cs = &self.parent_state.combat_state;
+        let items: Vec<ListItem> = cs
+            .participants
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                if cs.can_act(i) {
+                    ListItem::new(format!("{} - HP: {}", p.name, p.hp_text()))
+                } else {
+                    ListItem::new(format!("{} - HP: {} (acted)", p.name, p.hp_text()))
+                        .style(Style::default().fg(Color::DarkGray))
+                }
+            })
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Who acts next?"),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default();
+        list_state.select(Some(self.selection));
+        f.render_stateful_widget(list, chunks[2], &mut list_state);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Fight
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::List)
+    }
+
+    fn title(&self) -> String {
+        "Picking who acts next".into()
+    }
+
+    fn key_hints(&self) -> String {
+        format!(
+            "{} & {}: navigate; enter: pick; esc: back to fight",
+            keymap::key(Scope::List, "down"),
+            keymap::key(Scope::List, "up")
+        )
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_popcorn_initiative() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7").line("Elf: 8");
+        d.key(KeyCode::Esc).type_str("p");
+        assert_eq!(d.state().title(), "Participants (popcorn initiative)");
+
+        // the GM picks who starts, and then who follows
+        d.key(KeyCode::Enter);
+        assert_eq!(d.state().title(), "Picking who acts next");
+        d.type_str("j").key(KeyCode::Enter);
+        assert_eq!(d.state().mode(), Mode::Fight);
+        assert_eq!(d.combat_state().current_idx, 1);
+
+        // the selection skips the Goblin, which acted already
+        d.ctrl('n').type_str("j").key(KeyCode::Enter);
+        assert_eq!(d.combat_state().current_idx, 2);
+        assert!(d.screen_contains("Elf"));
+        d.ctrl('n').key(KeyCode::Enter);
+        assert_eq!(d.combat_state().current_idx, 0);
+        assert_eq!(d.combat_state().current_round, 0);
+
+        // everybody acted, so anybody can start the next round
+        d.ctrl('n').type_str("k").key(KeyCode::Enter);
+        assert_eq!(d.combat_state().current_idx, 2);
+        assert_eq!(d.combat_state().current_round, 1);
+
+        let cs = d.combat_state().clone();
+        assert!(!cs.can_act(2));
+        assert!(cs.with_turn_of(2).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use persistent_structs::PersistentStruct;
+use tui::{
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+};
+
+use super::{Boxable, Fighting, Mode, State, StateBox};
+use crate::{
+    combat_state::CombatState,
+    keymap::{self, Scope},
+    states, view_utils as vu, Frame,
+};
+
+/// rolls the macros of the current participant. The results are logged, and shown until the
+/// next roll
+#[derive(Clone, PersistentStruct)]
+pub struct RollingMacro {
+    parent_state: Box<Fighting>,
+    selection: usize,
+    /// the log entry of the last roll
+    result: Option<String>,
+}
+
+impl RollingMacro {
+    /// the state, or a message if the current participant has no macros
+    pub fn enter(parent_state: Box<Fighting>) -> StateBox {
+        let cs = &parent_state.combat_state;
+        let p = &cs.participants[cs.current_idx];
+        if p.macros.is_empty() {
+            let msg = format!(
+                "{} has no roll macros. Add them like Scimitar=1d20+4,1d6+2 after the stats",
+                p.name
+            );
+            return states::Msg::new(parent_state, msg).boxed();
+        }
+        RollingMacro {
+            parent_state,
+            selection: 0,
+            result: None,
+        }
+        .boxed()
+    }
+
+    fn macro_names(&self) -> Vec<String> {
+        let cs = &self.parent_state.combat_state;
+        cs.participants[cs.current_idx]
+            .macros
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    fn roll(self, idx: usize) -> RollingMacro {
+        let name = match self.macro_names().get(idx) {
+            Some(name) => name.clone(),
+            None => return self,
+        };
+        let parent_state = self.parent_state.update_combat_state(|cs| {
+            let current = cs.current_idx;
+            cs.recorded(|cs| cs.with_macro_rolled(current, &name))
+        });
+        RollingMacro {
+            result: parent_state
+                .combat_state
+                .log
+                .last()
+                .map(ToString::to_string),
+            parent_state: Box::new(parent_state),
+            selection: idx,
+        }
+    }
+}
+
+impl State for RollingMacro {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            let n_macros = self.macro_names().len();
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Char('j') | KeyCode::Down => {
+                    Ok(self.update_selection(|s| (s + 1) % n_macros).boxed())
+                }
+                KeyCode::Char('k') | KeyCode::Up => Ok(self
+                    .update_selection(|s| (s + n_macros - 1) % n_macros)
+                    .boxed()),
+                KeyCode::Enter => {
+                    let selection = self.selection;
+                    Ok(self.roll(selection).boxed())
+                }
+                KeyCode::Char(c) if c.is_ascii_digit() && c != '0' => {
+                    let idx = c.to_digit(10).unwrap() as usize - 1;
+                    Ok(self.roll(idx).boxed())
+                }
+                _ => Ok(self),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::select_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(3)].as_ref())
+            .split(chunks[2]);
+
+        let cs = &self.parent_state.combat_state;
+        let items: Vec<ListItem> = cs.participants[cs.current_idx]
+            .macros
+            .iter()
+            .enumerate()
+            .map(|(i, (name, rolls))| {
+                let rolls: Vec<String> = rolls.iter().map(|r| r.to_string()).collect();
+                ListItem::new(format!("{}. {}: {}", i + 1, name, rolls.join(", ")))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Macros"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut list_state = ListState::default();
+        list_state.select(Some(self.selection));
+        f.render_stateful_widget(list, areas[0], &mut list_state);
+
+        let result = Paragraph::new(self.result.clone().unwrap_or_default())
+            .block(Block::default().borders(Borders::ALL).title("Result"));
+        f.render_widget(result, areas[1]);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Fight
+    }
+
+    fn key_scope(&self) -> Option<Scope> {
+        Some(Scope::List)
+    }
+
+    fn title(&self) -> String {
+        let cs = &self.parent_state.combat_state;
+        format!("Rolling Macro of {}", cs.participants[cs.current_idx].name)
+    }
+
+    fn key_hints(&self) -> String {
+        format!(
+            "{} & {}: navigate; enter or 1-9: roll; esc: back to fight",
+            keymap::key(Scope::List, "down"),
+            keymap::key(Scope::List, "up")
+        )
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        &self.parent_state.combat_state
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_roll_macros() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Goblin: 7: 15 Scimitar=1d1+4,1d1+2 Bow=1d1+DEX DEX=2")
+            .line("Orc: 10: 12");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        d.ctrl('a');
+        assert_eq!(d.state().title(), "Rolling Macro of Goblin");
+        d.key(KeyCode::Enter).type_str("2");
+        assert!(d.screen_contains("Goblin rolls Scimitar: 5, 3"));
+        d.key(KeyCode::Esc);
+        assert_eq!(
+            d.log()[1..],
+            ["Goblin rolls Bow: 3", "Goblin rolls Scimitar: 5, 3"]
+        );
+        d.ctrl('u');
+        assert_eq!(d.combat_state().log.len(), 2);
+
+        // Orc has no macros
+        d.ctrl('n').ctrl('a');
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter);
+        assert_eq!(d.combat_state().log.len(), 2);
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode};
+use derive_new::new;
+use tui::widgets::{Block, Borders, List, ListItem};
+
+use super::{Boxable, Fighting, Mode, Normal, State, StateBox};
+use crate::{
+    combat_state::{CombatState, ScheduledEvent},
+    states, utils as ut, view_utils as vu, Frame,
+};
+
+/// the states events can be scheduled from
+pub trait Schedulable: State + Clone + 'static {
+    fn with_event(self, event: ScheduledEvent) -> Self;
+}
+
+impl Schedulable for Fighting {
+    fn with_event(self, event: ScheduledEvent) -> Fighting {
+        self.update_combat_state(|cs| cs.recorded(|cs| cs.with_event(event)))
+    }
+}
+
+impl Schedulable for Normal {
+    fn with_event(self, event: ScheduledEvent) -> Normal {
+        self.update_combat_state(|cs| cs.recorded(|cs| cs.with_event(event)))
+    }
+}
+
+#[derive(Clone, new)]
+pub struct SchedulingEvent<P: Schedulable> {
+    parent_state: Box<P>,
+    input_buffer: String,
+}
+
+impl<P: Schedulable> State for SchedulingEvent<P> {
+    fn process(self: Box<Self>, ev: Event) -> Result<StateBox> {
+        if let Event::Key(key) = ev {
+            match key.code {
+                KeyCode::Esc => Ok(self.parent_state),
+                KeyCode::Enter => {
+                    let round = self.parent_state.combat_state().current_round;
+                    Ok(match ScheduledEvent::parse(&self.input_buffer, round) {
+                        Ok(event) => self.parent_state.with_event(event).boxed(),
+                        Err(e) => states::Msg::new(self, ut::err_to_string(&e)).boxed(),
+                    })
+                }
+                code => Ok(SchedulingEvent {
+                    input_buffer: ut::update_buffer(self.input_buffer, code),
+                    parent_state: self.parent_state,
+                }
+                .boxed()),
+            }
+        } else {
+            Ok(self)
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = vu::input_layout(f.size());
+        vu::render_top_bar(f, self, chunks[0]);
+        vu::render_input_block(
+            f,
+            "New Event (<Text>:<Round>, <Text>:+<Rounds> or <Text>:*, then optionally @<Ini>)",
+            &self.input_buffer,
+            chunks[1],
+        );
+        let items: Vec<ListItem> = self
+            .parent_state
+            .combat_state()
+            .upcoming_events()
+            .map(|e| ListItem::new(e.to_string()))
+            .collect();
+        let list =
+            List::new(items).block(Block::default().borders(Borders::ALL).title("Scheduled"));
+        f.render_widget(list, chunks[2]);
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn title(&self) -> String {
+        "Scheduling Event".into()
+    }
+
+    fn key_hints(&self) -> String {
+        "enter: schedule; esc: back".into()
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+
+    fn combat_state(&self) -> &CombatState {
+        self.parent_state.combat_state()
+    }
+
+    fn parent(&self) -> Option<&dyn State> {
+        Some(self.parent_state.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use crate::states::{Boxable, Insert, Mode};
+    use crate::test_utils::Driver;
+
+    #[test]
+    fn test_scheduled_events() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10").line("Goblin: 7");
+        d.key(KeyCode::Esc).key(KeyCode::Enter);
+
+        d.ctrl('e').line("Ritual completes");
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter).key(KeyCode::Esc);
+        assert_eq!(d.state().mode(), Mode::Fight);
+
+        d.ctrl('e').line("Ritual completes: 2");
+        d.ctrl('e').line("Reinforcements: +1");
+        let rounds: Vec<usize> = d.combat_state().events.iter().map(|e| e.round).collect();
+        assert_eq!(rounds, vec![1, 2]);
+        assert!(!d.screen_contains("Reinforcements"));
+
+        d.ctrl('n').ctrl('n');
+        assert_eq!(d.combat_state().current_round, 1);
+        let screen = d.screen().join("\n");
+        assert!(screen.contains("Round 1: Reinforcements"));
+        assert!(!screen.contains("Ritual"));
+    }
+
+    #[test]
+    fn test_events_at_initiative() {
+        let mut d = Driver::new(Insert::default().boxed());
+        d.line("Orc: 10: 22").line("Goblin: 7: 15").line("Wolf: 11");
+        d.key(KeyCode::Esc);
+
+        // events can be scheduled before the fight
+        d.ctrl('e').line("Lair action: * @ 20");
+        d.ctrl('e').line("Reinforcements: +1@x");
+        assert_eq!(d.state().title(), "Error");
+        d.key(KeyCode::Enter).key(KeyCode::Esc);
+        assert_eq!(d.state().mode(), Mode::Normal);
+        d.ctrl('e').line("Bridge collapses: 1 @ 20");
+        d.ctrl('e');
+        assert!(d.screen_contains("Every round at 20: Lair action"));
+        d.key(KeyCode::Esc);
+
+        d.key(KeyCode::Enter);
+        assert!(!d.screen_contains("Lair action"));
+        // the banner shows once initiative 20 is reached, and stays until the round ends
+        d.ctrl('n');
+        assert!(d.screen_contains("Round 0: Lair action"));
+        d.ctrl('n');
+        assert!(d.screen_contains("Round 0: Lair action"));
+        d.ctrl('n');
+        assert!(!d.screen_contains("Lair action"));
+        d.ctrl('n');
+        let screen = d.screen().join("\n");
+        assert!(screen.contains("Round 1: Lair action | Bridge collapses"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Drives the state machine with synthetic events, the way run_app does with real ones, so
+//! tests can assert on the resulting CombatState and on what is rendered.
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use tui::{backend::TestBackend, buffer::Buffer, Terminal};
+
+use crate::{
+    backend::Backend,
+    combat_state::CombatState,
+    keymap::Keymap,
+    profiles::Profiles,
+    states::{State, StateBox},
+    Frame,
+};
+
+pub struct Driver {
+    /// only None while an event is processed
+    state: Option<StateBox>,
+    profiles: Profiles,
+    keymap: Keymap,
+    terminal: Terminal<Backend>,
+}
+
+impl Driver {
+    pub fn new(state: StateBox) -> Driver {
+        Driver::with_size(state, 80, 24)
+    }
+
+    pub fn with_size(state: StateBox, width: u16, height: u16) -> Driver {
+        let keymap = Keymap::default();
+        keymap.show_in_hints();
+        Driver {
+            state: Some(state),
+            profiles: Profiles::default(),
+            keymap,
+            terminal: test_terminal(width, height),
+        }
+    }
+
+    pub fn with_profiles(mut self, profiles: Profiles) -> Driver {
+        self.profiles = profiles;
+        self
+    }
+
+    pub fn with_keymap(mut self, keymap: Keymap) -> Driver {
+        keymap.show_in_hints();
+        self.keymap = keymap;
+        self
+    }
+
+    /// processes the event and renders the new state, like a real keypress would. Panics if
+    /// the state machine returns an error.
+    pub fn send(&mut self, ev: Event) -> &mut Self {
+        let state = self.state.take().unwrap();
+        let ev = self
+            .profiles
+            .translate(state.as_ref(), ev)
+            .and_then(|ev| self.keymap.translate(state.as_ref(), ev));
+        let state = match ev {
+            Some(ev) => state.process(ev).expect("processing the event failed"),
+            None => state,
+        };
+        self.state = Some(state);
+        self.render();
+        self
+    }
+
+    pub fn key(&mut self, code: KeyCode) -> &mut Self {
+        self.send(key_event(code, KeyModifiers::NONE))
+    }
+
+    pub fn ctrl(&mut self, c: char) -> &mut Self {
+        self.send(key_event(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    pub fn click(&mut self, column: u16, row: u16) -> &mut Self {
+        self.send(mouse_event(
+            MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+        ))
+    }
+
+    /// turns the mouse wheel once, the mouse is in the top left corner
+    pub fn scroll(&mut self, down: bool) -> &mut Self {
+        let kind = if down {
+            MouseEventKind::ScrollDown
+        } else {
+            MouseEventKind::ScrollUp
+        };
+        self.send(mouse_event(kind, 0, 0))
+    }
+
+    /// the column and row at which the text is rendered first. Panics if it isn't on screen
+    pub fn position_of(&mut self, text: &str) -> (u16, u16) {
+        self.screen()
+            .iter()
+            .enumerate()
+            .find_map(|(row, line)| {
+                let idx = line.find(text)?;
+                Some((line[..idx].chars().count() as u16, row as u16))
+            })
+            .unwrap_or_else(|| panic!("{} is not on screen", text))
+    }
+
+    /// sends every char of the string as a key press
+    pub fn type_str(&mut self, s: &str) -> &mut Self {
+        for c in s.chars() {
+            self.key(KeyCode::Char(c));
+        }
+        self
+    }
+
+    /// types the line and presses enter
+    pub fn line(&mut self, s: &str) -> &mut Self {
+        self.type_str(s).key(KeyCode::Enter)
+    }
+
+    pub fn state(&self) -> &dyn State {
+        self.state.as_deref().unwrap()
+    }
+
+    pub fn combat_state(&self) -> &CombatState {
+        self.state().combat_state()
+    }
+
+    pub fn render(&mut self) -> &Buffer {
+        let state = self.state.as_mut().unwrap();
+        let profiles = &self.profiles;
+        self.terminal
+            .draw(|f| {
+                state.render(f);
+                profiles.render(f);
+            })
+            .unwrap();
+        test_buffer(&self.terminal)
+    }
+
+    /// the rendered screen, one string per line
+    pub fn screen(&mut self) -> Vec<String> {
+        buffer_lines(self.render())
+    }
+
+    /// whether the text is rendered anywhere on the screen
+    pub fn screen_contains(&mut self, text: &str) -> bool {
+        self.screen().join("\n").contains(text)
+    }
+
+    /// the combat log as it is shown
+    pub fn log(&self) -> Vec<String> {
+        let log = &self.combat_state().log;
+        log.iter().map(ToString::to_string).collect()
+    }
+
+    /// the names of the participants, in order
+    pub fn names(&self) -> Vec<String> {
+        self.combat_state()
+            .participants
+            .iter()
+            .map(|p| p.name.clone())
+            .collect()
+    }
+}
+
+pub fn key_event(code: KeyCode, modifiers: KeyModifiers) -> Event {
+    Event::Key(KeyEvent::new(code, modifiers))
+}
+
+pub fn mouse_event(kind: MouseEventKind, column: u16, row: u16) -> Event {
+    Event::Mouse(MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers: KeyModifiers::NONE,
+    })
+}
+
+/// renders into a fresh buffer of the given size, and returns its lines
+pub fn render_lines(width: u16, height: u16, draw: impl FnOnce(&mut Frame)) -> Vec<String> {
+    let mut terminal = test_terminal(width, height);
+    terminal.draw(draw).unwrap();
+    buffer_lines(test_buffer(&terminal))
+}
+
+fn test_terminal(width: u16, height: u16) -> Terminal<Backend> {
+    Terminal::new(Backend::Test(TestBackend::new(width, height))).unwrap()
+}
+
+/// what was drawn into a terminal of test_terminal
+fn test_buffer(terminal: &Terminal<Backend>) -> &Buffer {
+    match terminal.backend() {
+        Backend::Test(backend) => backend.buffer(),
+        Backend::Terminal(_) => unreachable!("the tests don't draw into the terminal"),
+    }
+}
+
+pub fn buffer_lines(buffer: &Buffer) -> Vec<String> {
+    let area = buffer.area;
+    (area.top()..area.bottom())
+        .map(|y| {
+            (area.left()..area.right())
+                .map(|x| buffer.get(x, y).symbol.as_str())
+                .collect()
+        })
+        .collect()
+}
+
+/// the expected result of hp_snapshot
+pub fn hps(xs: &[(&str, u16)]) -> Vec<(String, u16)> {
+    xs.iter().map(|(n, hp)| (n.to_string(), *hp)).collect()
+}
+
+/// name and hp of every participant, in order
+pub fn hp_snapshot(cs: &CombatState) -> Vec<(String, u16)> {
+    cs.participants
+        .iter()
+        .map(|p| (p.name.clone(), p.hp))
+        .collect()
+}
@@ -1,40 +1,277 @@
-use crate::combat_state::Participant;
-use anyhow::{Context, Result};
+use crate::combat_state::{Macros, Participant, Stats};
+use anyhow::{anyhow, ensure, Context, Result};
 use crossterm::event::{Event, KeyCode};
-use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, str::FromStr};
 
-pub fn parse_participant_with_ini(s: &str) -> Result<(Option<u8>, Participant)> {
+/// parses `Name: HP[/Max HP][+Temp HP][: Ini]`, optionally followed by stats like `DEX=2`, which
+/// can be used in the initiative roll, and roll macros like `Scimitar=1d20+4,1d6+2`, which can be
+/// rolled in the fight. `group=Goblins` makes the participant a member of a group, which can be
+/// damaged at once. An initiative with a sign, like `+3`, is a bonus that is added when the
+/// initiative is rolled. `pc` marks a player character, whose HP are shown to the players with
+/// `--share`
+pub fn parse_participant_with_ini(s: &str) -> Result<Participant> {
     let mut splits: Vec<&str> = s.split(':').collect();
-    let ini = if splits.len() > 2 {
-        Some(splits.pop().unwrap().trim().parse()?)
+    let last = splits.pop().unwrap_or_default();
+    let mut tokens = last.split_whitespace();
+    let value = tokens.next().unwrap_or_default();
+    let mut stats = Stats::new();
+    let mut macros = Macros::new();
+    let mut group = None;
+    let mut pc = false;
+    for token in tokens {
+        if token == "pc" {
+            pc = true;
+            continue;
+        }
+        let (name, value) = token.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "Stats and roll macros must have the format <Name>=<Value>, got {}",
+                token
+            )
+        })?;
+        if name == "group" {
+            group = Some(value.to_string());
+        } else if value.parse::<i64>().is_ok() {
+            let (name, value) = parse_stat(token)?;
+            stats.insert(name, value);
+        } else {
+            let (name, rolls) = parse_macro(name, value)?;
+            macros.insert(name, rolls);
+        }
+    }
+    splits.push(value);
+    let (ini, ini_bonus) = if splits.len() > 2 {
+        let ini = splits.pop().unwrap().trim();
+        if ini.starts_with(['+', '-']) {
+            let bonus = ini
+                .parse()
+                .with_context(|| format!("{} is not an initiative bonus", ini))?;
+            (None, Some(bonus))
+        } else {
+            (Some(ini.parse()?), None)
+        }
     } else {
-        None
+        (None, None)
     };
-    Ok((
+    Ok(Participant {
         ini,
-        Participant::parse_splits(splits).context("Participant::parse_splits")?,
+        ini_bonus,
+        group,
+        pc,
+        stats,
+        macros,
+        ..Participant::parse_splits(splits).context("Participant::parse_splits")?
+    })
+}
+
+/// templates by name, declared with `@<Name>: <HP>...` in participant files
+pub type Templates = HashMap<String, Participant>;
+
+/// collects the template declarations of a participant file
+pub fn parse_templates(source: &str, content: &str) -> Result<Templates> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| line.trim().strip_prefix('@').map(|t| (i, t)))
+        .map(|(i, line)| {
+            let (line, count) = take_spawn_count(line)?;
+            ensure!(
+                count == 1,
+                "{}:{}: templates can't be spawned",
+                source,
+                i + 1
+            );
+            let p = parse_participant_with_ini(&line)
+                .with_context(|| format!("{}:{}: invalid template {:?}", source, i + 1, line))?;
+            Ok((p.name.trim().to_string(), p))
+        })
+        .collect()
+}
+
+/// parses the participants of a file. A line can use a template by its name instead of giving
+/// the HP, and `xN=<Count>` adds the participant that often, numbered. Template declarations and
+/// empty lines are skipped
+pub fn parse_participants(
+    source: &str,
+    content: &str,
+    templates: &Templates,
+) -> Result<Vec<Participant>> {
+    let mut participants = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('@') {
+            continue;
+        }
+        let (line, count) = take_spawn_count(trimmed)
+            .with_context(|| format!("{}:{}: invalid spawn count", source, i + 1))?;
+        let p = if line.contains(':') {
+            parse_participant_with_ini(&line)
+                .with_context(|| format!("{}:{}: invalid participant {:?}", source, i + 1, line))?
+        } else {
+            templates.get(&line).cloned().ok_or_else(|| {
+                anyhow!("{}:{}: there is no template called {}", source, i + 1, line)
+            })?
+        };
+        if count == 1 {
+            participants.push(p);
+        } else {
+            let name = p.name.trim().to_string();
+            participants.extend((1..=count).map(|n| Participant {
+                name: format!("{} {}", name, n),
+                ..p.clone()
+            }));
+        }
+    }
+    Ok(participants)
+}
+
+/// removes the `xN=<Count>` token from the line, together with a colon that it leaves at the
+/// end. The count is 1 without the token
+fn take_spawn_count(line: &str) -> Result<(String, usize)> {
+    let mut count = 1;
+    let mut rest = vec![];
+    for token in line.split(' ') {
+        match token.strip_prefix("xN=") {
+            Some(n) => {
+                count = n
+                    .parse()
+                    .with_context(|| format!("parsing {} as count", n))?;
+                ensure!(count > 0, "the count must be at least 1");
+            }
+            None => rest.push(token),
+        }
+    }
+    let rest = rest.join(" ");
+    Ok((
+        rest.trim().trim_end_matches(':').trim_end().to_string(),
+        count,
     ))
 }
 
-pub fn with_popped_n<T>(mut xs: Vec<T>, n: usize) -> (T, Vec<T>) {
-    let elem = xs.remove(n);
-    (elem, xs)
+fn parse_stat(s: &str) -> Result<(String, i64)> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Stats must have the format <Name>=<Value>, got {}", s))?;
+    ensure!(
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic()),
+        "{} is not a valid stat name, use letters only",
+        name
+    );
+    let value = value
+        .parse()
+        .with_context(|| format!("Parsing the value of {}", name))?;
+    Ok((name.to_uppercase(), value))
 }
 
-pub fn roll(n: u8, dice: u8) -> u8 {
-    let mut rng = rand::thread_rng();
-    let dist = rand::distributions::Uniform::new_inclusive(1, dice);
-    (0..n).map(|_| rng.sample(dist) as u8).fold(0, |a, b| a + b)
+/// parses a roll macro like `Scimitar=1d20+4,1d6+2`, given as name and rolls. Each roll has to
+/// contain dice, so mistyped stats don't end up as macros
+fn parse_macro(name: &str, rolls: &str) -> Result<(String, Vec<DiceExpr>)> {
+    ensure!(
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+        "{} is not a valid macro name, use letters, digits, _ and - only",
+        name
+    );
+    let rolls = rolls
+        .split(',')
+        .map(|roll| {
+            let expr: DiceExpr = roll
+                .parse()
+                .map_err(|e| anyhow!("{}", e))
+                .with_context(|| format!("Parsing the rolls of {}", name))?;
+            ensure!(
+                expr.has_dice(),
+                "{} of {} doesn't roll any dice",
+                roll,
+                name
+            );
+            Ok(expr)
+        })
+        .collect::<Result<_>>()?;
+    Ok((name.to_string(), rolls))
 }
 
-pub fn update_nth<T, F>(mut xs: Vec<T>, n: usize, f: F) -> Vec<T>
-where
-    F: FnOnce(&T) -> T,
-{
-    if let Some(x) = xs.get(n) {
-        xs[n] = f(x);
+/// a sum of dice, constants and stats, like `1d20+DEX` or `2d6+1`. The dice can keep or drop
+/// some of their rolls, or explode, like `2d20kh+DEX`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DiceExpr(dice::Expr);
+
+impl Default for DiceExpr {
+    fn default() -> DiceExpr {
+        "2d6".parse().unwrap()
     }
-    xs
+}
+
+impl DiceExpr {
+    /// the stats that the participant doesn't have are 0
+    pub fn roll(&self, stats: &Stats) -> i64 {
+        self.0
+            .roll_with(&mut rand::thread_rng(), |name| {
+                Some(stats.get(name).copied().unwrap_or(0))
+            })
+            .expect("every stat has a value")
+            .total
+    }
+
+    pub fn has_dice(&self) -> bool {
+        self.0.has_dice()
+    }
+
+    /// rolls an initiative, which can't be negative
+    pub fn roll_ini(&self, stats: &Stats, bonus: i64) -> u8 {
+        (self.roll(stats) + bonus).clamp(0, u8::MAX as i64) as u8
+    }
+}
+
+impl FromStr for DiceExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiceExpr, String> {
+        let expr: dice::Expr = s.parse().map_err(|e| format!("{:#}", e))?;
+        // products like 2d6*10 are for loot, not for fights
+        if expr.terms.iter().any(|term| term.factors.len() > 1) {
+            return Err(format!(
+                "{} is not a sum of dice like 2d6, numbers and stats like DEX",
+                s
+            ));
+        }
+        Ok(DiceExpr(expr))
+    }
+}
+
+impl fmt::Display for DiceExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<String> for DiceExpr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<DiceExpr, String> {
+        s.parse()
+    }
+}
+
+impl From<DiceExpr> for String {
+    fn from(expr: DiceExpr) -> String {
+        expr.to_string()
+    }
+}
+
+pub fn with_popped_n<T>(mut xs: Vec<T>, n: usize) -> (T, Vec<T>) {
+    let elem = xs.remove(n);
+    (elem, xs)
+}
+
+pub fn roll(n: u8, sides: u8) -> u16 {
+    dice::Dice::new(n.into(), sides.into())
+        .roll_with(&mut rand::thread_rng())
+        .total() as u16
 }
 
 pub fn update_buffer(mut buffer: String, key_code: KeyCode) -> String {
@@ -53,3 +290,128 @@ pub fn update_buffer(mut buffer: String, key_code: KeyCode) -> String {
 pub fn err_to_string(e: &anyhow::Error) -> String {
     format!("{:?}", e)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dice_expr() {
+        let expr: DiceExpr = "1d20 + dex - 1".parse().unwrap();
+        assert_eq!(expr.to_string(), "1d20+DEX-1");
+        let one: DiceExpr = "d1+DEX+2".parse().unwrap();
+        let stats = Stats::from([("DEX".to_string(), 3)]);
+        assert_eq!(one.roll(&stats), 6);
+        assert_eq!(one.roll(&Stats::new()), 3);
+        assert_eq!("1d1-5".parse::<DiceExpr>().unwrap().roll_ini(&stats, 0), 0);
+        assert_eq!("1d1".parse::<DiceExpr>().unwrap().roll_ini(&stats, 3), 4);
+        for _ in 0..20 {
+            assert!((2..=12).contains(&DiceExpr::default().roll(&stats)));
+            assert_eq!("2d1kh+dex".parse::<DiceExpr>().unwrap().roll(&stats), 4);
+        }
+        for invalid in ["", "2d", "1d20+", "1d0", "d20*2"] {
+            assert!(invalid.parse::<DiceExpr>().is_err(), "{}", invalid);
+        }
+
+        let p = parse_participant_with_ini("Goblin: 7: 12 DEX=2 wis=-1").unwrap();
+        assert_eq!((p.hp, p.ini), (7, Some(12)));
+        assert_eq!(p.input_line(), "Goblin: 7: 12 DEX=2 WIS=-1");
+        let p = parse_participant_with_ini("Orc: 10 STR=3").unwrap();
+        assert_eq!((p.hp, p.ini, p.stats["STR"]), (10, None, 3));
+        assert!(parse_participant_with_ini("Orc: 10 STR").is_err());
+        let p = parse_participant_with_ini("Goblin 1: 7: 12 group=Goblins DEX=2").unwrap();
+        assert_eq!(p.group.as_deref(), Some("Goblins"));
+        assert_eq!(p.input_line(), "Goblin 1: 7: 12 group=Goblins DEX=2");
+        let p = parse_participant_with_ini("Orc: 10: +3 DEX=1").unwrap();
+        assert_eq!((p.ini, p.ini_bonus), (None, Some(3)));
+        assert_eq!(p.input_line(), "Orc: 10: +3 DEX=1");
+        let p = parse_participant_with_ini("Zombie: 12: -2").unwrap();
+        assert_eq!(p.ini_bonus, Some(-2));
+        assert!(parse_participant_with_ini("Orc: 10: +x").is_err());
+        let p = parse_participant_with_ini("Kidd: 8: +2 pc DEX=3").unwrap();
+        assert!(p.pc);
+        assert_eq!(p.input_line(), "Kidd: 8: +2 pc DEX=3");
+    }
+
+    #[test]
+    fn test_roll_macros() {
+        // rolls can't contain spaces
+        let err = parse_participant_with_ini("Goblin: 7 Scimitar=1d20 + 4")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("format <Name>=<Value>"));
+
+        let p =
+            parse_participant_with_ini("Goblin: 7 DEX=2 Scimitar=1d20+4,1d6+2 Short-bow=1d1+dex")
+                .unwrap();
+        assert_eq!(p.macros.len(), 2);
+        assert_eq!(
+            p.input_line(),
+            "Goblin: 7 DEX=2 Scimitar=1d20+4,1d6+2 Short-bow=1d1+DEX"
+        );
+        assert_eq!(p.macros["Short-bow"][0].roll(&p.stats), 3);
+        for invalid in [
+            "Orc: 10 Axe=4+STR",
+            "Orc: 10 Axe=1d12,x",
+            "Orc: 10 Gr€at=1d6",
+        ] {
+            assert!(parse_participant_with_ini(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_templates_and_spawn_counts() {
+        let bestiary = "@Goblin: 7/7 DEX=2\n@Orc Chief: 30: 15\n";
+        let encounter = "Goblin: xN=3\n\nOrc Chief\nWolf: 11: xN=2\nGoblin: 9 xN=2";
+        let templates = parse_templates("bestiary", bestiary).unwrap();
+        let participants = parse_participants("encounter", encounter, &templates).unwrap();
+        let lines: Vec<String> = participants.iter().map(|p| p.input_line()).collect();
+        assert_eq!(
+            lines,
+            [
+                "Goblin 1: 7/7 DEX=2",
+                "Goblin 2: 7/7 DEX=2",
+                "Goblin 3: 7/7 DEX=2",
+                "Orc Chief: 30: 15",
+                "Wolf 1: 11",
+                "Wolf 2: 11",
+                "Goblin 1: 9",
+                "Goblin 2: 9",
+            ]
+        );
+        assert!(parse_participants("bestiary", bestiary, &templates)
+            .unwrap()
+            .is_empty());
+
+        let err = parse_participants("encounter", "Orc: 10\nTroll: xN=2", &templates)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "encounter:2: there is no template called Troll"
+        );
+        assert!(parse_participants("encounter", "Goblin: xN=0", &templates).is_err());
+        assert!(parse_templates("bestiary", "@Goblin: 7 xN=2").is_err());
+    }
+
+    #[test]
+    fn test_max_and_temp_hp() {
+        let mut p = parse_participant_with_ini("Paladin: 20/25+5: 14").unwrap();
+        assert_eq!(
+            (p.hp, p.max_hp, p.temp_hp, p.ini),
+            (20, Some(25), 5, Some(14))
+        );
+        assert_eq!(p.input_line(), "Paladin: 20/25+5: 14");
+
+        p.take_damage(8);
+        assert_eq!((p.hp, p.temp_hp), (17, 0));
+        p.heal(10);
+        assert_eq!(p.hp, 25);
+        assert_eq!(p.input_line(), "Paladin: 25/25: 14");
+
+        let p = parse_participant_with_ini("Goblin: 7+3").unwrap();
+        assert_eq!((p.hp, p.max_hp, p.temp_hp), (7, None, 3));
+        assert!(parse_participant_with_ini("Orc: 30/25").is_err());
+        assert!(parse_participant_with_ini("Orc: 10/x").is_err());
+    }
+}
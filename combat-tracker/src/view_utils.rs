@@ -1,18 +1,17 @@
-use itertools::Itertools;
 use pad::PadStr;
+use std::{rc::Rc, time::Duration};
 use tui::{
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, ListItem, Paragraph, Row, Table, TableState},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Row, Table, TableState, Wrap},
 };
 
-use std::iter;
-
 use crate::{
-    combat_state::{self as cs, CombatState, Participant, TimeVec},
-    states::fighting::KeyInfo,
-    Frame,
+    combat_state::{self as cs, CombatState, LogEntry, Participant, TimeVec, TurnOrder},
+    conditions::{self, Condition},
+    states::{self, fighting::KeyInfo, State},
+    utils, Frame,
 };
 
 pub fn input_layout(r: Rect) -> Vec<Rect> {
@@ -45,26 +44,184 @@ pub fn select_layout(r: Rect) -> Vec<Rect> {
         .split(r)
 }
 
+/// renders the mode badge, the breadcrumb and the key hints of the state into a single line
+pub fn render_top_bar(f: &mut Frame, state: &dyn State, target_rect: Rect) {
+    let mode = state.mode();
+    let badge = Span::styled(
+        format!(" {} ", mode.label()),
+        Style::default()
+            .fg(Color::Black)
+            .bg(mode.color())
+            .add_modifier(Modifier::BOLD),
+    );
+    let crumbs = states::breadcrumbs(state).join(" → ");
+    let line = Spans::from(vec![
+        badge,
+        Span::styled(
+            format!(" {} ", crumbs),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(
+            format!("- {}", state.key_hints()),
+            Style::default().fg(Color::DarkGray),
+        ),
+    ]);
+    f.render_widget(Paragraph::new(line), target_rect);
+}
+
+/// the events that were scheduled for the current round, in a single highlighted line
+pub fn render_event_banner(f: &mut Frame, combat_state: &CombatState, target_rect: Rect) {
+    let texts: Vec<&str> = combat_state.due_events().map(|e| e.text.as_str()).collect();
+    if texts.is_empty() {
+        return;
+    }
+    let banner = Span::styled(
+        format!(
+            "Round {}: {}",
+            combat_state.current_round,
+            texts.join(" | ")
+        ),
+        Style::default()
+            .fg(Color::Black)
+            .bg(Color::Yellow)
+            .add_modifier(Modifier::BOLD),
+    );
+    f.render_widget(Paragraph::new(Spans::from(banner)), target_rect);
+}
+
+/// "Now: Ogre — Next: Alia" above the table, so the GM can announce the turns
+pub fn render_turn_banner(f: &mut Frame, combat_state: &CombatState, target_rect: Rect) {
+    let current = match combat_state.participants.get(combat_state.current_idx) {
+        Some(p) => p,
+        None => return,
+    };
+    let style = Style::default().fg(Color::Black).bg(Color::Cyan);
+    let mut spans = vec![Span::styled(
+        format!(" Now: {} ", current.name),
+        style.add_modifier(Modifier::BOLD),
+    )];
+    if let Some(next) = combat_state.next_up() {
+        spans.push(Span::styled(
+            format!("— Next: {} ", combat_state.participants[next].name),
+            style,
+        ));
+    }
+    f.render_widget(Paragraph::new(Spans::from(spans)), target_rect);
+}
+
+/// tests render into a buffer, so there is no terminal to ring
+pub fn ring_bell() {
+    #[cfg(not(test))]
+    {
+        use std::io::Write;
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+    }
+}
+
+pub fn render_last_log_entry(f: &mut Frame, combat_state: &CombatState, target_rect: Rect) {
+    if let Some(entry) = combat_state.log.last() {
+        let line = Span::styled(entry.to_string(), Style::default().fg(Color::DarkGray));
+        f.render_widget(Paragraph::new(Spans::from(line)), target_rect);
+    }
+}
+
+/// the entries of the combat log that fit, the newest at the bottom
+pub fn render_log(f: &mut Frame, log: &[LogEntry], target_rect: Rect) {
+    let n_visible = target_rect.height.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = log[log.len().saturating_sub(n_visible)..]
+        .iter()
+        .map(|entry| ListItem::new(entry.to_string()))
+        .collect();
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title("Log"));
+    f.render_widget(list, target_rect);
+}
+
+/// the notes of the participant, wrapped to the width of the pane
+pub fn render_notes(f: &mut Frame, p: &Participant, target_rect: Rect) {
+    let notes = Paragraph::new(p.notes.as_str())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(p.name.as_str()),
+        )
+        .wrap(Wrap { trim: false });
+    f.render_widget(notes, target_rect);
+}
+
+/// the time of the current turn as minutes and seconds, aligned to the right
+pub fn render_turn_timer(f: &mut Frame, elapsed: Duration, target_rect: Rect) {
+    let secs = elapsed.as_secs();
+    let text = Span::styled(
+        format!("Turn {}:{:02}", secs / 60, secs % 60),
+        Style::default().add_modifier(Modifier::BOLD),
+    );
+    f.render_widget(
+        Paragraph::new(Spans::from(text)).alignment(Alignment::Right),
+        target_rect,
+    );
+}
+
 pub fn render_input_block(f: &mut Frame, title: &str, buffer: &str, chunk: Rect) {
     let input = Paragraph::new(buffer).block(Block::default().borders(Borders::ALL).title(title));
     f.render_widget(input, chunk);
     f.set_cursor(chunk.x + buffer.len() as u16 + 1, chunk.y + 1);
 }
 
-pub fn participants_list_items(
-    participants: &Vec<Participant>,
-    inis: &Vec<Option<u8>>,
-) -> Vec<ListItem<'static>> {
+/// what the input of the insert mode would add, or why it can't be added
+pub fn render_participant_preview(f: &mut Frame, buffer: &str, target_rect: Rect) {
+    let block = Block::default().borders(Borders::ALL).title("Preview");
+    let text = if buffer.trim().is_empty() {
+        Text::styled(
+            "Type a participant, like Goblin: 7/7+2: 12 DEX=2",
+            Style::default().fg(Color::DarkGray),
+        )
+    } else {
+        match utils::parse_participant_with_ini(buffer) {
+            Ok(p) => {
+                let mut lines = vec![
+                    Spans::from(format!("Name: {}", p.name.trim())),
+                    Spans::from(format!("HP: {}", p.hp_text())),
+                    Spans::from(match (p.ini, p.ini_bonus) {
+                        (Some(ini), _) => format!("Initiative: {}", ini),
+                        (None, Some(bonus)) => {
+                            format!("Initiative: rolled in normal mode, {:+}", bonus)
+                        }
+                        (None, None) => "Initiative: rolled in normal mode".into(),
+                    }),
+                ];
+                if !p.stats.is_empty() {
+                    let stats: Vec<String> = p
+                        .stats
+                        .iter()
+                        .map(|(k, v)| format!("{}={}", k, v))
+                        .collect();
+                    lines.push(Spans::from(format!("Stats: {}", stats.join(" "))));
+                }
+                Text::from(lines)
+            }
+            Err(e) => Text::styled(format!("{:#}", e), Style::default().fg(Color::Red)),
+        }
+    };
+    let paragraph = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
+    f.render_widget(paragraph, target_rect);
+}
+
+pub fn participants_list_items(participants: &[Rc<Participant>]) -> Vec<ListItem<'static>> {
     participants
         .iter()
-        .zip(inis)
-        .map(|(p, ini)| {
+        .map(|p| {
             ListItem::new(format!(
-                "{} - HP: {};{}",
+                "{} - HP: {};{}{}",
                 p.name,
-                p.hp,
-                if let Some(ini) = ini {
-                    format!(" Ini: {}", ini)
+                p.hp_text(),
+                match (p.ini, p.ini_bonus) {
+                    (Some(ini), _) => format!(" Ini: {}", ini),
+                    (None, Some(bonus)) => format!(" Ini: {:+}", bonus),
+                    (None, None) => "".to_string(),
+                },
+                if let Some(group) = &p.group {
+                    format!(" Group: {}", group)
                 } else {
                     "".to_string()
                 }
@@ -73,78 +230,395 @@ pub fn participants_list_items(
         .collect()
 }
 
+/// Only the rows that fit into target_rect are built, so the cost of a frame doesn't grow with
+/// the number of participants. The window is chosen the same way tui's TableState would
+/// scroll, so the current participant is always visible.
 pub fn render_fighting_mode_table(
     f: &mut Frame,
     combat_state: &CombatState,
-    key_infos: &Vec<KeyInfo>,
+    key_infos: &[KeyInfo],
     target_rect: Rect,
 ) {
-    let name_col_length = combat_state
-        .participants
-        .iter()
-        .fold(0, |max, p| std::cmp::max(max, p.name.len()))
-        + 1;
+    let first_visible = first_visible(combat_state.current_idx, target_rect);
+    render_fighting_mode_window(f, combat_state, key_infos, first_visible, target_rect);
+}
 
-    let comma_span = Span::from(", ");
+/// renders the fighting mode table starting with the participant first_visible. The current
+/// participant is only highlighted if it is visible
+pub fn render_fighting_mode_window(
+    f: &mut Frame,
+    combat_state: &CombatState,
+    key_infos: &[KeyInfo],
+    first_visible: usize,
+    target_rect: Rect,
+) {
+    let n_visible = n_visible_rows(target_rect);
+    let now = combat_state.now();
+    let next = now.with_next_turn();
+    let popcorn = combat_state.turn_order == TurnOrder::Popcorn;
+    let name_col_length = name_col_length(combat_state);
     let table_rows: Vec<Row> = combat_state
         .participants
         .iter()
         .zip(key_infos.iter())
-        .map(|(p, key_info)| {
-            let mods = render_modifiers(&p.modifiers, combat_state);
-            let tags = mods.iter().intersperse(&comma_span);
-            Row::new(vec![
+        .enumerate()
+        .skip(first_visible)
+        .take(n_visible)
+        .map(|(i, (p, key_info))| {
+            let mut mod_spans = vec![Span::from(format!("Mods({}): [", key_info.edit_modifiers))];
+            for (i, span) in render_modifiers(&p.modifiers, &now, &next).enumerate() {
+                if i > 0 {
+                    mod_spans.push(Span::from(", "));
+                }
+                mod_spans.push(span);
+            }
+            mod_spans.push(Span::from("]"));
+            let row = Row::new(vec![
                 Text::from(
                     p.name
                         .pad_to_width_with_alignment(name_col_length, pad::Alignment::Right),
                 ),
-                Text::from(format!(
-                    " <{}- HP: {} -{}> ",
-                    key_info.decrement, p.hp, key_info.increment
-                )),
-                Text::from(Spans::from(
-                    iter::once(Span::from(format!("Mods({}): [", key_info.edit_modifiers)))
-                        .chain(tags.cloned())
-                        .chain(iter::once(Span::from("]")))
-                        .collect::<Vec<Span>>(),
-                )),
-            ])
+                Text::from(hp_cell(p, key_info)),
+                Text::from(Spans::from(mod_spans)),
+            ]);
+            // with popcorn initiative, those that already acted this round are grayed out, unless
+            // they are down. So are those that delay their turn
+            if p.is_down() {
+                row.style(down_style(p))
+            } else if p.delayed {
+                row.style(
+                    Style::default()
+                        .fg(Color::DarkGray)
+                        .add_modifier(Modifier::ITALIC),
+                )
+            } else if popcorn && p.has_acted && i != combat_state.current_idx {
+                row.style(Style::default().fg(Color::DarkGray))
+            } else {
+                row
+            }
         })
         .collect();
-    let constraints = [
-        Constraint::Length(name_col_length as u16),
-        Constraint::Length(15),
-        Constraint::Length(200),
-    ];
+    let constraints = fighting_mode_columns(combat_state);
     let table = Table::new(table_rows)
         .block(Block::default().borders(Borders::ALL).title("Participants"))
         .widths(&constraints)
         // ...and they can be separated by a fixed spacing.
-        .column_spacing(2)
+        .column_spacing(FIGHTING_MODE_COLUMN_SPACING)
         // If you wish to highlight a row in any specific way when it is selected...
         .highlight_style(Style::default().add_modifier(Modifier::BOLD))
         // ...and potentially show a symbol in front of the selection.
-        .highlight_symbol(">>");
+        .highlight_symbol(HIGHLIGHT_SYMBOL);
     let mut table_state = TableState::default();
-    table_state.select(Some(combat_state.current_idx));
+    table_state.select(visible_current(combat_state, first_visible, target_rect));
     f.render_stateful_widget(table, target_rect, &mut table_state);
 }
 
-fn render_modifiers(mods: &Vec<cs::Modifier>, cs: &CombatState) -> Vec<Span<'static>> {
-    let now = cs.now();
-    let next = cs.clone().with_next_turn().now();
-    mods.iter()
-        .map(|modifier| {
-            if let Some(dur) = modifier.remaining_rounds(&now) {
-                let style = if modifier.remaining_rounds(&next).unwrap() == 0 {
-                    Style::default().fg(Color::Red)
-                } else {
-                    Style::default()
-                };
-                Span::styled(format!("{}:{}", modifier.name, dur), style)
+const HIGHLIGHT_SYMBOL: &str = ">>";
+const FIGHTING_MODE_COLUMN_SPACING: u16 = 2;
+
+/// the index of the first participant in a bordered list or table, so that the participant
+/// shown is visible. That's how tui scrolls a list or a table with a fresh state
+pub fn first_visible(shown: usize, target_rect: Rect) -> usize {
+    (shown + 1).saturating_sub(n_visible_rows(target_rect))
+}
+
+fn n_visible_rows(target_rect: Rect) -> usize {
+    std::cmp::max(target_rect.height.saturating_sub(2) as usize, 1)
+}
+
+/// the row of the current participant in the window, if it is visible
+fn visible_current(
+    combat_state: &CombatState,
+    first_visible: usize,
+    target_rect: Rect,
+) -> Option<usize> {
+    let row = combat_state.current_idx.checked_sub(first_visible)?;
+    (row < n_visible_rows(target_rect)).then_some(row)
+}
+
+/// the index of the row of a bordered list or table at the position, if there is a row
+pub fn row_at(
+    target_rect: Rect,
+    first_visible: usize,
+    n_rows: usize,
+    column: u16,
+    row: u16,
+) -> Option<usize> {
+    let inner = Block::default().borders(Borders::ALL).inner(target_rect);
+    if column < inner.left()
+        || column >= inner.right()
+        || row < inner.top()
+        || row >= inner.bottom()
+    {
+        return None;
+    }
+    let idx = first_visible + (row - inner.top()) as usize;
+    (idx < n_rows).then_some(idx)
+}
+
+/// the participant whose HP button in the fighting mode table is at the position, and whether
+/// the button heals
+pub fn hp_button_at(
+    combat_state: &CombatState,
+    key_infos: &[KeyInfo],
+    first_visible: usize,
+    target_rect: Rect,
+    column: u16,
+    row: u16,
+) -> Option<(usize, bool)> {
+    let n_rows = combat_state.participants.len().min(key_infos.len());
+    let idx = row_at(target_rect, first_visible, n_rows, column, row)?;
+    // the columns are laid out like tui does it when the table is wide enough: the highlight
+    // symbol if a row is highlighted, the names and the spacing come before the HP
+    let highlight = match visible_current(combat_state, first_visible, target_rect) {
+        Some(_) => HIGHLIGHT_SYMBOL.len(),
+        None => 0,
+    };
+    let inner = Block::default().borders(Borders::ALL).inner(target_rect);
+    let hp_col_left = inner.left() as usize
+        + highlight
+        + name_col_length(combat_state)
+        + FIGHTING_MODE_COLUMN_SPACING as usize;
+    let offset = (column as usize).checked_sub(hp_col_left)?;
+    // the buttons are "<q-" and "-w>" in " <q- HP: 7 -w> "
+    let cell_len = hp_cell(&combat_state.participants[idx], &key_infos[idx]).len();
+    if (1..4).contains(&offset) {
+        Some((idx, false))
+    } else if (cell_len - 4..cell_len - 1).contains(&offset) {
+        Some((idx, true))
+    } else {
+        None
+    }
+}
+
+fn name_col_length(combat_state: &CombatState) -> usize {
+    combat_state
+        .participants
+        .iter()
+        .fold(0, |max, p| std::cmp::max(max, p.name.len()))
+        + 1
+}
+
+fn fighting_mode_columns(combat_state: &CombatState) -> [Constraint; 3] {
+    // " <q- HP:  -w>" around the HP, and room for two digits at least
+    let hp_col_length = combat_state
+        .participants
+        .iter()
+        .fold(2, |max, p| std::cmp::max(max, p.hp_text().len()))
+        + 13;
+    [
+        Constraint::Length(name_col_length(combat_state) as u16),
+        Constraint::Length(hp_col_length as u16),
+        Constraint::Length(200),
+    ]
+}
+
+fn hp_cell(p: &Participant, key_info: &KeyInfo) -> String {
+    format!(
+        " <{}- HP: {} -{}> ",
+        key_info.decrement,
+        p.hp_text(),
+        key_info.increment
+    )
+}
+
+/// participants that are down are red, and the dead ones are crossed out
+pub fn down_style(p: &Participant) -> Style {
+    match (p.is_down(), p.status) {
+        (_, cs::Status::Dead) => Style::default()
+            .fg(Color::Red)
+            .add_modifier(Modifier::CROSSED_OUT),
+        (true, _) => Style::default().fg(Color::Red),
+        (false, _) => Style::default(),
+    }
+}
+
+/// modifiers that expire with the next turn are red, others have the color of their condition
+/// preset. next is the time of the next turn.
+fn render_modifiers<'a>(
+    mods: &'a [cs::Modifier],
+    now: &'a TimeVec,
+    next: &'a TimeVec,
+) -> impl Iterator<Item = Span<'static>> + 'a {
+    mods.iter().map(|modifier| {
+        let style = match modifier.color.as_deref().and_then(conditions::color) {
+            Some(color) => Style::default().fg(color),
+            None => Style::default(),
+        };
+        if let Some(dur) = modifier.remaining_rounds(now) {
+            let style = if modifier.remaining_rounds(next).unwrap() == 0 {
+                Style::default().fg(Color::Red)
             } else {
-                Span::from(modifier.name.clone())
-            }
+                style
+            };
+            Span::styled(format!("{}:{}", modifier.label(), dur), style)
+        } else {
+            Span::styled(modifier.label(), style)
+        }
+    })
+}
+
+/// the condition presets that can be picked, in their color
+pub fn render_conditions(
+    f: &mut Frame,
+    presets: &[&Condition],
+    selection: Option<usize>,
+    target_rect: Rect,
+) {
+    let items: Vec<ListItem> = presets
+        .iter()
+        .map(|c| {
+            let text = match c.duration {
+                Some(dur) => format!("{}:{}", c.name, dur),
+                None => c.name.clone(),
+            };
+            let style = match c.color.as_deref().and_then(conditions::color) {
+                Some(color) => Style::default().fg(color),
+                None => Style::default(),
+            };
+            ListItem::new(text).style(style)
         })
-        .collect()
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Conditions"))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut list_state = ListState::default();
+    list_state.select(selection);
+    f.render_stateful_widget(list, target_rect, &mut list_state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combat_state::Modifier;
+    use crate::states::fighting::Fighting;
+    use crate::test_utils::render_lines;
+
+    fn participant(name: &str, hp: u16) -> Participant {
+        Participant::parse(&format!("{}: {}", name, hp)).unwrap()
+    }
+
+    fn combat_state() -> CombatState {
+        let mut cs =
+            CombatState::from_participants(vec![participant("Orc", 10), participant("Goblin", 7)]);
+        let now = cs.now();
+        Rc::make_mut(&mut cs.participants[1]).modifiers = vec![
+            Modifier::new("Stunned".into(), now, Some(1)),
+            Modifier::new("Prone".into(), now, None),
+        ];
+        cs
+    }
+
+    fn fighting_table(width: u16, height: u16) -> Vec<String> {
+        let fighting = Fighting::new(combat_state());
+        render_lines(width, height, |f| {
+            let area = f.size();
+            render_fighting_mode_table(f, &fighting.combat_state, &fighting.key_infos, area)
+        })
+    }
+
+    #[test]
+    fn test_fighting_table_wide() {
+        assert_eq!(
+            fighting_table(60, 5),
+            vec![
+                "┌Participants──────────────────────────────────────────────┐",
+                "│>>    Orc   <q- HP: 10 -w>  Mods(e): []                   │",
+                "│   Goblin   <a- HP: 7 -s>   Mods(d): [Stunned:1, Prone]   │",
+                "│                                                          │",
+                "└──────────────────────────────────────────────────────────┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fighting_table_narrow() {
+        // the modifiers column is cut off
+        assert_eq!(
+            fighting_table(30, 4),
+            vec![
+                "┌Participants────────────────┐",
+                "│>>    Orc   <q- HP: 10 -w>  │",
+                "│   Goblin   <a- HP: 7 -s>   │",
+                "└────────────────────────────┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fighting_table_scrolls_to_current() {
+        let participants = (0..10)
+            .map(|i| participant(&format!("P{}", i), 1))
+            .collect();
+        let fighting =
+            Fighting::new(CombatState::from_participants(participants).with_current_idx(6));
+        let lines = render_lines(25, 5, |f| {
+            let area = f.size();
+            render_fighting_mode_table(f, &fighting.combat_state, &fighting.key_infos, area)
+        });
+        assert_eq!(
+            lines,
+            vec![
+                "┌Participants───────────┐",
+                "│   P4   <f- HP: 1 -g>  │",
+                "│   P5   <v- HP: 1 -b>  │",
+                "│>> P6   <u- HP: 1 -i>  │",
+                "└───────────────────────┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expiring_modifiers_are_red() {
+        let cs = combat_state();
+        let now = cs.now();
+        let next = now.with_next_turn();
+        let spans: Vec<Span> =
+            render_modifiers(&cs.participants[1].modifiers, &now, &next).collect();
+        assert_eq!(spans[0].content, "Stunned:1");
+        assert_eq!(spans[0].style.fg, None);
+        let next = next.with_next_turn();
+        let spans: Vec<Span> =
+            render_modifiers(&cs.participants[1].modifiers, &now, &next).collect();
+        assert_eq!(spans[0].style.fg, Some(Color::Red));
+        assert_eq!(spans[1].content, "Prone");
+    }
+
+    #[test]
+    fn test_participant_list() {
+        let cs = combat_state();
+        let lines = render_lines(30, 2, |f| {
+            let mut participants = cs.participants.clone();
+            Rc::make_mut(&mut participants[0]).ini = Some(12);
+            let items = participants_list_items(&participants);
+            f.render_widget(tui::widgets::List::new(items), f.size())
+        });
+        assert_eq!(
+            lines,
+            vec![
+                "Orc - HP: 10; Ini: 12         ",
+                "Goblin - HP: 7;               ",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_input_block() {
+        for (width, expected) in [
+            (
+                20,
+                vec![
+                    "┌New Modifier──────┐",
+                    "│Stunned: 2        │",
+                    "└──────────────────┘",
+                ],
+            ),
+            (10, vec!["┌New Modi┐", "│Stunned:│", "└────────┘"]),
+        ] {
+            let lines = render_lines(width, 3, |f| {
+                render_input_block(f, "New Modifier", "Stunned: 2", f.size())
+            });
+            assert_eq!(lines, expected);
+        }
+    }
 }
@@ -0,0 +1,118 @@
+//! Files like portraits and handouts, that belong to other nodes. They are nodes of type
+//! ATTACHMENT_NODE_TYPE with the bytes as data and the mime type in their meta, and are linked
+//! from the node they belong to.
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::db::DB;
+use crate::meta::{Meta, MetaValue};
+
+pub const ATTACHMENT_NODE_TYPE: &str = "attachment";
+pub const ATTACHMENT_LINK_TYPE: &str = "attachment";
+const MIME_KEY: &str = "mime";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub id: i64,
+    pub name: String,
+    pub mime: String,
+    pub bytes: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn is_image(&self) -> bool {
+        self.mime.starts_with("image/")
+    }
+}
+
+/// the mime type of the file, guessed from its extension
+pub fn mime_from_path(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        _ => return None,
+    })
+}
+
+impl DB {
+    /// stores the bytes as an attachment of the owner, and returns its id
+    pub fn attach(&mut self, owner: i64, name: &str, mime: &str, bytes: &[u8]) -> Result<i64> {
+        let meta = Meta::from([(MIME_KEY.to_string(), mime.into())]);
+        self.in_transaction(|db| {
+            let id = db.insert_node(name, ATTACHMENT_NODE_TYPE, &meta, bytes)?;
+            db.insert_link(owner, id, ATTACHMENT_LINK_TYPE, None)?;
+            Ok(id)
+        })
+    }
+
+    /// the attachments of the node that aren't deleted, oldest first
+    pub fn select_attachments(&mut self, owner: i64) -> Result<Vec<Attachment>> {
+        let mut res: Vec<Attachment> = self
+            .select_nodes_linked_by(owner, Some(ATTACHMENT_LINK_TYPE))?
+            .into_iter()
+            .filter(|(link, node)| link.left == owner && node.r#type == ATTACHMENT_NODE_TYPE)
+            .map(|(_, node)| Attachment {
+                id: node.id,
+                mime: match node.meta.get(MIME_KEY) {
+                    Some(MetaValue::Text(mime)) => mime.clone(),
+                    _ => "application/octet-stream".into(),
+                },
+                name: node.name,
+                bytes: node.data,
+            })
+            .collect();
+        res.sort_by_key(|a| a.id);
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachments() -> Result<()> {
+        let mut db = DB::new(Path::new(":memory:"))?;
+        let mira = db.insert_node("Mira", "npc", &Meta::new(), &[])?;
+        let oderic = db.insert_node("Oderic", "npc", &Meta::new(), &[])?;
+        let portrait = db.attach(mira, "mira.png", "image/png", &[137, 80, 78, 71])?;
+        db.attach(mira, "letter.txt", "text/plain", b"Meet me at the docks")?;
+        db.insert_link(oderic, mira, ATTACHMENT_LINK_TYPE, None)?;
+
+        let attachments = db.select_attachments(mira)?;
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(
+            attachments[0],
+            Attachment {
+                id: portrait,
+                name: "mira.png".into(),
+                mime: "image/png".into(),
+                bytes: vec![137, 80, 78, 71],
+            }
+        );
+        assert!(attachments[0].is_image());
+        assert!(!attachments[1].is_image());
+        // Mira isn't an attachment of Oderic, even though they are linked like one
+        assert!(db.select_attachments(oderic)?.is_empty());
+
+        db.delete_node(portrait)?;
+        assert_eq!(db.select_attachments(mira)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mime_from_path() {
+        assert_eq!(mime_from_path(Path::new("a/Token.PNG")), Some("image/png"));
+        assert_eq!(mime_from_path(Path::new("map.jpeg")), Some("image/jpeg"));
+        assert_eq!(mime_from_path(Path::new("notes")), None);
+        assert_eq!(mime_from_path(Path::new("song.ogg")), None);
+    }
+}
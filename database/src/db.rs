@@ -1,36 +1,50 @@
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
 
-use anyhow::Result;
-use rusqlite::{Connection, Row};
+use anyhow::{Context, Result};
+use rusqlite::backup::Progress;
+use rusqlite::{params_from_iter, Connection, DatabaseName, OptionalExtension, Row};
 use rusqlite_migration::{Migrations, M};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-use crate::dsl::ToSql;
+use crate::dsl::ToQuery;
+use crate::meta::{self, Meta};
 use crate::schema::*;
 
 use fn_utils::{PullResult, WrapIter};
 
 macro_rules! migrations {
     () => {
-        Migrations::new(vec![M::up(CREATE_STMT)])
+        Migrations::new(vec![
+            M::up(CREATE_STMT),
+            M::up(ADD_DELETED_AT_STMT),
+            M::up(CREATE_SYNC_NODES_STMT),
+            M::up(META_TO_JSON_STMT),
+            M::up(CREATE_NODES_FTS_STMT),
+            M::up(ADD_NODE_TIMESTAMPS_STMT),
+        ])
     };
 }
 
+/// tags are nodes of this type, linked from the tagged node with a link of type TAG_LINK_TYPE
+pub const TAG_NODE_TYPE: &str = "tag";
+pub const TAG_LINK_TYPE: &str = "tag";
+
 pub struct DB {
     conn: Connection,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Node {
     pub id: i64,
     pub name: String,
     pub r#type: String,
-    pub meta: Option<String>,
+    pub meta: Meta,
     pub data: Vec<u8>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Link {
     pub id: i64,
     pub left: i64,
@@ -39,6 +53,37 @@ pub struct Link {
     pub data: Option<Vec<u8>>,
 }
 
+impl Node {
+    /// decodes the data, that was written by insert_typed or update_typed
+    pub fn data_as<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.data)
+            .with_context(|| format!("Could not decode the data of {}", self.name))
+    }
+}
+
+/// unix timestamps of a node
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Timestamps {
+    pub created_at: i64,
+    /// the last change of the name, type, meta or data
+    pub updated_at: i64,
+    /// set while the node is in the trash
+    pub deleted_at: Option<i64>,
+}
+
+/// a node as it was at the last sync. uid identifies the node on all devices
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncedNode {
+    pub uid: String,
+    /// the local id, None if the node was purged
+    pub node: Option<i64>,
+    pub name: String,
+    pub r#type: String,
+    pub meta: Meta,
+    pub data: Vec<u8>,
+    pub deleted: bool,
+}
+
 impl DB {
     pub fn new(path: &Path) -> Result<DB> {
         let mut conn = Connection::open(path)?;
@@ -46,38 +91,455 @@ impl DB {
         Ok(DB { conn })
     }
 
+    /// writes a consistent copy of the database to path, using sqlite's backup api
+    pub fn backup_to(&self, path: &Path) -> Result<()> {
+        self.conn.backup(DatabaseName::Main, path, None)?;
+        Ok(())
+    }
+
+    /// replaces the contents of the database with those of the backup at path
+    pub fn restore_from(&mut self, path: &Path) -> Result<()> {
+        self.conn
+            .restore(DatabaseName::Main, path, None::<fn(Progress)>)?;
+        // the backup might have been taken by an older version
+        migrations!().to_latest(&mut self.conn)?;
+        Ok(())
+    }
+
     pub fn insert_node(
         &mut self,
         name: &str,
         r#type: &str,
-        meta: Option<String>,
+        meta: &Meta,
         data: &[u8],
-    ) -> Result<()> {
+    ) -> Result<i64> {
         let mut stmt = self
             .conn
             .prepare("insert into nodes (name, type, meta, data) values (?, ?, ?, ?)")?;
-        stmt.execute((name, r#type, meta, data))?;
+        stmt.execute((name, r#type, meta::to_column(meta), data))?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn update_node(&mut self, id: i64, meta: &Meta, data: &[u8]) -> Result<()> {
+        self.conn.execute(
+            "update nodes set meta = ?, data = ? where rowid = ?",
+            (meta::to_column(meta), data, id),
+        )?;
+        Ok(())
+    }
+
+    /// inserts a node with the value as json data, and without meta
+    pub fn insert_typed<T: Serialize>(
+        &mut self,
+        name: &str,
+        r#type: &str,
+        value: &T,
+    ) -> Result<i64> {
+        self.insert_node(name, r#type, &Meta::new(), &serde_json::to_vec(value)?)
+    }
+
+    /// replaces the data of the node with the value as json, and keeps its meta
+    pub fn update_typed<T: Serialize>(&mut self, id: i64, value: &T) -> Result<()> {
+        let meta = self.select_node(id)?.meta;
+        self.update_node(id, &meta, &serde_json::to_vec(value)?)
+    }
+
+    pub fn rename_node(&mut self, id: i64, name: &str) -> Result<()> {
+        self.conn
+            .execute("update nodes set name = ? where rowid = ?", (name, id))?;
         Ok(())
     }
 
-    pub fn select_nodes<T: ToSql>(&mut self, filter: &T) -> Result<Vec<Node>> {
+    /// returns the id of the node with the given type and name, if there is one that isn't
+    /// deleted
+    pub fn find_node(&mut self, r#type: &str, name: &str) -> Result<Option<i64>> {
+        find_node_id(&self.conn, r#type, name)
+    }
+
+    pub fn insert_link(
+        &mut self,
+        left: i64,
+        right: i64,
+        r#type: &str,
+        data: Option<Vec<u8>>,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "insert into links (left, right, type, data) values (?, ?, ?, ?)",
+            (left, right, r#type, data),
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn select_links<T: ToQuery>(&mut self, query: &T) -> Result<Vec<Link>> {
+        let (sql, params) = query.to_query();
+        let mut stmt = self.conn.prepare(&format!(
+            "select rowid, left, right, type, data from links where {}",
+            sql
+        ))?;
+        let res = Ok(stmt
+            .query_map(params_from_iter(params), |row| link_from_row(row, 0))?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    /// deletes the link with the given id. Links that were synced to other devices come back
+    /// with the next sync, as syncing never removes links
+    pub fn delete_link(&mut self, id: i64) -> Result<()> {
+        self.conn
+            .execute("delete from links where rowid = ?", [id])?;
+        Ok(())
+    }
+
+    pub fn has_link(&mut self, left: i64, right: i64, r#type: &str) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "select exists (select 1 from links where left = ? and right = ? and type = ?)",
+            (left, right, r#type),
+            |row| row.get(0),
+        )?)
+    }
+
+    /// runs f in a transaction, which is rolled back if f fails. f must not use the methods
+    /// that open their own transaction, like add_tag.
+    pub fn in_transaction<T>(&mut self, f: impl FnOnce(&mut DB) -> Result<T>) -> Result<T> {
+        self.conn.execute_batch("begin")?;
+        match f(self) {
+            Ok(res) => {
+                self.conn.execute_batch("commit")?;
+                Ok(res)
+            }
+            Err(e) => {
+                self.conn.execute_batch("rollback")?;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn select_nodes<T: ToQuery>(&mut self, query: &T) -> Result<Vec<Node>> {
+        let (sql, params) = query.to_query();
         let mut stmt = self.conn.prepare(&format!(
-            "select (rowid as id, name, type, meta, data) from nodes where {}",
-            filter.to_sql()
+            "select rowid, name, type, meta, data from nodes where deleted_at is null and {}",
+            sql
         ))?;
 
         let res = Ok(stmt
-            .query_map((), |row: &Row<'_>| -> rusqlite::Result<Node> {
-                let f = || {
-                    Ok(Node {
-                        id: row.get(0)?,
-                        name: row.get(1)?,
-                        r#type: row.get(2)?,
-                        meta: row.get(3)?,
-                        data: row.get(4)?,
-                    })
-                };
-                f()
+            .query_map(params_from_iter(params), |row: &Row<'_>| {
+                node_from_row(row, 0)
+            })?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    /// like select_nodes, but also decodes the data of the nodes. Fails if the data of any of
+    /// them can't be decoded
+    pub fn select_typed<T: DeserializeOwned, Q: ToQuery>(
+        &mut self,
+        query: &Q,
+    ) -> Result<Vec<(Node, T)>> {
+        self.select_nodes(query)?
+            .into_iter()
+            .map(|node| {
+                let value = node.data_as()?;
+                Ok((node, value))
+            })
+            .collect()
+    }
+
+    /// the nodes that aren't deleted and whose name or meta contain words that start with every
+    /// word of the query, ignoring case, the best matches first
+    pub fn search_nodes(&mut self, query: &str) -> Result<Vec<Node>> {
+        let query = fts_query(query);
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut stmt = self.conn.prepare(
+            "select nodes.rowid, nodes.name, nodes.type, nodes.meta, nodes.data
+             from nodes_fts join nodes on nodes.rowid = nodes_fts.rowid
+             where nodes_fts match ? and nodes.deleted_at is null
+             order by nodes_fts.rank",
+        )?;
+        let res = Ok(stmt
+            .query_map([query], |row| node_from_row(row, 0))?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    pub fn select_node(&mut self, id: i64) -> Result<Node> {
+        let mut stmt = self
+            .conn
+            .prepare("select rowid, name, type, meta, data from nodes where rowid = ?")?;
+        Ok(stmt.query_row([id], |row| node_from_row(row, 0))?)
+    }
+
+    /// like select_node, but returns None for missing and deleted nodes
+    pub fn try_select_node(&mut self, id: i64) -> Result<Option<Node>> {
+        let mut stmt = self.conn.prepare(
+            "select rowid, name, type, meta, data from nodes where rowid = ? and deleted_at is null",
+        )?;
+        Ok(stmt
+            .query_row([id], |row| node_from_row(row, 0))
+            .optional()?)
+    }
+
+    /// returns all nodes that are linked to the node with the given id, together with the
+    /// link that connects them. Links are followed in both directions, so link.left is not
+    /// necessarily the given id.
+    pub fn select_linked_nodes(&mut self, id: i64) -> Result<Vec<(Link, Node)>> {
+        self.select_nodes_linked_by(id, None)
+    }
+
+    /// like select_linked_nodes, but only follows links of the given type, if there is one
+    pub fn select_nodes_linked_by(
+        &mut self,
+        id: i64,
+        link_type: Option<&str>,
+    ) -> Result<Vec<(Link, Node)>> {
+        let mut stmt = self.conn.prepare(
+            "select links.rowid, links.left, links.right, links.type, links.data,
+                    nodes.rowid, nodes.name, nodes.type, nodes.meta, nodes.data
+             from links join nodes on nodes.rowid =
+                 (case when links.left = ?1 then links.right else links.left end)
+             where (links.left = ?1 or links.right = ?1) and nodes.deleted_at is null
+                 and (?2 is null or links.type = ?2)",
+        )?;
+        let res = Ok(stmt
+            .query_map((id, link_type), |row| {
+                Ok((link_from_row(row, 0)?, node_from_row(row, 5)?))
+            })?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    /// returns the id, name and type of all nodes that aren't deleted, without their meta and
+    /// data, for searching by name
+    pub fn select_node_names(&mut self) -> Result<Vec<(i64, String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("select rowid, name, type from nodes where deleted_at is null")?;
+        let res = Ok(stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    /// returns all nodes, including the deleted ones, together with whether they are deleted
+    pub fn select_all_nodes(&mut self) -> Result<Vec<(Node, bool)>> {
+        let mut stmt = self
+            .conn
+            .prepare("select rowid, name, type, meta, data, deleted_at is not null from nodes")?;
+        let res = Ok(stmt
+            .query_map((), |row| Ok((node_from_row(row, 0)?, row.get(5)?)))?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    pub fn select_all_links(&mut self) -> Result<Vec<Link>> {
+        let mut stmt = self
+            .conn
+            .prepare("select rowid, left, right, type, data from links")?;
+        let res = Ok(stmt
+            .query_map((), |row| link_from_row(row, 0))?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    pub fn select_synced_nodes(&mut self) -> Result<Vec<SyncedNode>> {
+        let mut stmt = self
+            .conn
+            .prepare("select uid, node, name, type, meta, data, deleted from sync_nodes")?;
+        let res = Ok(stmt
+            .query_map((), |row| {
+                Ok(SyncedNode {
+                    uid: row.get(0)?,
+                    node: row.get(1)?,
+                    name: row.get(2)?,
+                    r#type: row.get(3)?,
+                    meta: meta::from_column(row.get(4)?),
+                    data: row.get(5)?,
+                    deleted: row.get(6)?,
+                })
+            })?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    /// inserts the synced state of a node, or replaces the existing one with the same uid
+    pub fn save_synced_node(&mut self, node: &SyncedNode) -> Result<()> {
+        self.conn.execute(
+            "insert or replace into sync_nodes (uid, node, name, type, meta, data, deleted)
+             values (?, ?, ?, ?, ?, ?, ?)",
+            (
+                &node.uid,
+                node.node,
+                &node.name,
+                &node.r#type,
+                meta::to_column(&node.meta),
+                &node.data,
+                node.deleted,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// moves the node to the trash. It can be restored until it is purged
+    pub fn delete_node(&mut self, id: i64) -> Result<()> {
+        self.conn.execute(
+            "update nodes set deleted_at = cast(strftime('%s', 'now') as integer) where rowid = ?",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    pub fn restore_node(&mut self, id: i64) -> Result<()> {
+        self.conn
+            .execute("update nodes set deleted_at = null where rowid = ?", [id])?;
+        Ok(())
+    }
+
+    /// like select_nodes, but includes the nodes in the trash, together with whether they are
+    /// deleted
+    pub fn select_nodes_including_deleted<T: ToQuery>(
+        &mut self,
+        query: &T,
+    ) -> Result<Vec<(Node, bool)>> {
+        let (sql, params) = query.to_query();
+        let mut stmt = self.conn.prepare(&format!(
+            "select rowid, name, type, meta, data, deleted_at is not null from nodes where {}",
+            sql
+        ))?;
+        let res = Ok(stmt
+            .query_map(params_from_iter(params), |row| {
+                Ok((node_from_row(row, 0)?, row.get(5)?))
+            })?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    pub fn select_timestamps(&mut self, id: i64) -> Result<Timestamps> {
+        Ok(self.conn.query_row(
+            "select created_at, updated_at, deleted_at from nodes where rowid = ?",
+            [id],
+            |row| {
+                Ok(Timestamps {
+                    created_at: row.get(0)?,
+                    updated_at: row.get(1)?,
+                    deleted_at: row.get(2)?,
+                })
+            },
+        )?)
+    }
+
+    /// returns the nodes in the trash, together with the unix timestamp of their deletion
+    pub fn select_deleted_nodes(&mut self) -> Result<Vec<(Node, i64)>> {
+        let mut stmt = self.conn.prepare(
+            "select rowid, name, type, meta, data, deleted_at from nodes
+             where deleted_at is not null order by deleted_at desc",
+        )?;
+        let res = Ok(stmt
+            .query_map((), |row| Ok((node_from_row(row, 0)?, row.get(5)?)))?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    /// irrevocably deletes the node and all of its links
+    pub fn purge_node(&mut self, id: i64) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("delete from links where left = ?1 or right = ?1", [id])?;
+        tx.execute("delete from nodes where rowid = ?", [id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// purges all nodes that were deleted before the given unix timestamp, and returns how
+    /// many there were
+    pub fn purge_deleted_before(&mut self, timestamp: i64) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "delete from links where left in (select rowid from nodes where deleted_at < ?1)
+                 or right in (select rowid from nodes where deleted_at < ?1)",
+            [timestamp],
+        )?;
+        let n = tx.execute("delete from nodes where deleted_at < ?", [timestamp])?;
+        tx.commit()?;
+        Ok(n)
+    }
+
+    /// tags all given nodes with the tag, and creates the tag if it doesn't exist yet. Either
+    /// all nodes are tagged, or none.
+    pub fn add_tag(&mut self, node_ids: &[i64], tag: &str) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        let tag_id = match find_tag(&tx, tag)? {
+            Some(id) => id,
+            None => {
+                tx.execute(
+                    "insert into nodes (name, type, data) values (?, ?, ?)",
+                    (tag, TAG_NODE_TYPE, Vec::<u8>::new()),
+                )?;
+                tx.last_insert_rowid()
+            }
+        };
+        for id in node_ids {
+            tx.execute(
+                "insert into links (left, right, type) select ?1, ?2, ?3
+                 where not exists
+                     (select 1 from links where left = ?1 and right = ?2 and type = ?3)",
+                (id, tag_id, TAG_LINK_TYPE),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// removes the tag from all given nodes. Either from all, or from none.
+    pub fn remove_tag(&mut self, node_ids: &[i64], tag: &str) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        if let Some(tag_id) = find_tag(&tx, tag)? {
+            for id in node_ids {
+                tx.execute(
+                    "delete from links where left = ? and right = ? and type = ?",
+                    (id, tag_id, TAG_LINK_TYPE),
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn select_tags(&mut self, node_id: i64) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "select nodes.name from links join nodes on nodes.rowid = links.right
+             where links.left = ? and links.type = ? and nodes.type = ?
+                 and nodes.deleted_at is null",
+        )?;
+        let res = Ok(stmt
+            .query_map((node_id, TAG_LINK_TYPE, TAG_NODE_TYPE), |row| row.get(0))?
+            .wrap_iter()
+            .pull_result()?);
+        res
+    }
+
+    /// the tags of all nodes that aren't deleted, as pairs of node id and tag, ordered by tag
+    pub fn select_all_tags(&mut self) -> Result<Vec<(i64, String)>> {
+        let mut stmt = self.conn.prepare(
+            "select links.left, tags.name from links
+             join nodes as tags on tags.rowid = links.right
+             join nodes as tagged on tagged.rowid = links.left
+             where links.type = ? and tags.type = ?
+                 and tags.deleted_at is null and tagged.deleted_at is null
+             order by tags.name, links.left",
+        )?;
+        let res = Ok(stmt
+            .query_map((TAG_LINK_TYPE, TAG_NODE_TYPE), |row| {
+                Ok((row.get(0)?, row.get(1)?))
             })?
             .wrap_iter()
             .pull_result()?);
@@ -85,17 +547,413 @@ impl DB {
     }
 }
 
+fn find_tag(conn: &Connection, tag: &str) -> Result<Option<i64>> {
+    find_node_id(conn, TAG_NODE_TYPE, tag)
+}
+
+fn find_node_id(conn: &Connection, r#type: &str, name: &str) -> Result<Option<i64>> {
+    Ok(conn
+        .query_row(
+            "select rowid from nodes where type = ? and name = ? and deleted_at is null",
+            (r#type, name),
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// turns every word into a quoted prefix query, so the words can't be read as fts5 syntax
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// reads a node from the columns rowid, name, type, meta, data, starting at offset
+fn node_from_row(row: &Row<'_>, offset: usize) -> rusqlite::Result<Node> {
+    Ok(Node {
+        id: row.get(offset)?,
+        name: row.get(offset + 1)?,
+        r#type: row.get(offset + 2)?,
+        meta: meta::from_column(row.get(offset + 3)?),
+        data: row.get(offset + 4)?,
+    })
+}
+
+/// reads a link from the columns rowid, left, right, type, data, starting at offset
+fn link_from_row(row: &Row<'_>, offset: usize) -> rusqlite::Result<Link> {
+    Ok(Link {
+        id: row.get(offset)?,
+        left: row.get(offset + 1)?,
+        right: row.get(offset + 2)?,
+        r#type: row.get(offset + 3)?,
+        data: row.get(offset + 4)?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dsl::{LinkFieldName, MetaKey, NodeFieldName, Order, ToSql};
 
     #[test]
     fn test_db_stuff() -> Result<()> {
         let mut conn = Connection::open_in_memory()?;
         migrations!().to_latest(&mut conn)?;
         let mut db = DB { conn };
-        db.insert_node("Node1", "test", Some("meta info".into()), &vec![])?;
-        db.insert_node("Node2", "test", None, &vec![1, 2, 10])?;
+        let meta = Meta::from([("info".to_string(), "meta info".into())]);
+        let a = db.insert_node("Node1", "test", &meta, &[])?;
+        let b = db.insert_node("Node2", "test", &Meta::new(), &[1, 2, 10])?;
+        db.insert_node("Node3", "other", &Meta::new(), &[])?;
+        let node1 = Node {
+            id: a,
+            name: "Node1".into(),
+            r#type: "test".into(),
+            meta: meta.clone(),
+            data: vec![],
+        };
+        let mut node2 = Node {
+            id: b,
+            name: "Node2".into(),
+            r#type: "test".into(),
+            meta: Meta::new(),
+            data: vec![1, 2, 10],
+        };
+
+        let by_type = NodeFieldName::Type
+            .eq("test")
+            .order_by(NodeFieldName::Name, Order::Asc);
+        assert_eq!(db.select_nodes(&by_type)?, [node1.clone(), node2.clone()]);
+        assert_eq!(
+            db.select_nodes(&NodeFieldName::Name.eq("Node1"))?,
+            std::slice::from_ref(&node1)
+        );
+        assert_eq!(db.select_node(b)?, node2);
+        assert_eq!(db.try_select_node(a)?, Some(node1));
+        assert_eq!(db.try_select_node(100)?, None);
+
+        node2.name = "Renamed".into();
+        node2.meta = meta;
+        node2.data = vec![3];
+        db.rename_node(b, &node2.name)?;
+        db.update_node(b, &node2.meta, &node2.data)?;
+        assert_eq!(db.select_nodes(&NodeFieldName::Name.like("Ren%"))?, [node2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tags() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        db.insert_node("Node1", "test", &Meta::new(), &[])?;
+        db.insert_node("Node2", "test", &Meta::new(), &[])?;
+        db.add_tag(&[1, 2], "villain")?;
+        db.add_tag(&[1], "villain")?;
+        assert_eq!(db.select_tags(1)?, vec!["villain".to_string()]);
+        assert_eq!(db.select_linked_nodes(1)?.len(), 1);
+
+        db.remove_tag(&[1], "villain")?;
+        assert!(db.select_tags(1)?.is_empty());
+        assert_eq!(db.select_tags(2)?, vec!["villain".to_string()]);
+
+        db.add_tag(&[1], "ally")?;
+        assert_eq!(
+            db.select_all_tags()?,
+            vec![(1, "ally".to_string()), (2, "villain".to_string())]
+        );
+        db.delete_node(2)?;
+        assert_eq!(db.select_all_tags()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trash() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        db.insert_node("Node1", "test", &Meta::new(), &[])?;
+        db.insert_node("Node2", "test", &Meta::new(), &[])?;
+        db.add_tag(&[1, 2], "villain")?;
+        let all = NodeFieldName::Type.eq("test");
+
+        db.delete_node(1)?;
+        assert_eq!(db.select_nodes(&all)?.len(), 1);
+        let names: Vec<String> = db.select_node_names()?.into_iter().map(|n| n.1).collect();
+        assert_eq!(names, vec!["Node2", "villain"]);
+        assert_eq!(db.try_select_node(1)?, None);
+        assert!(db.try_select_node(2)?.is_some());
+        assert_eq!(db.select_deleted_nodes()?.len(), 1);
+        db.restore_node(1)?;
+        assert_eq!(db.select_nodes(&all)?.len(), 2);
+
+        db.delete_node(1)?;
+        assert_eq!(db.purge_deleted_before(0)?, 0);
+        db.purge_node(1)?;
+        assert!(db.select_deleted_nodes()?.is_empty());
+        // the tag node only remains linked to Node2
+        assert_eq!(db.select_linked_nodes(3)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamps() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        let a = db.insert_node("Node1", "test", &Meta::new(), &[])?;
+        db.add_tag(&[a], "villain")?;
+        let created = db.select_timestamps(a)?;
+        assert!(created.created_at > 0);
+        assert_eq!(created.updated_at, created.created_at);
+        assert_eq!(created.deleted_at, None);
+        assert!(db.select_timestamps(a + 1)?.created_at > 0);
+
+        db.conn
+            .execute("update nodes set created_at = 1, updated_at = 1", [])?;
+        db.rename_node(a, "Renamed")?;
+        let renamed = db.select_timestamps(a)?;
+        assert_eq!(renamed.created_at, 1);
+        assert!(renamed.updated_at > 1);
+        // tagging doesn't change the node itself
+        db.add_tag(&[a], "ally")?;
+        assert_eq!(db.select_timestamps(a)?.updated_at, renamed.updated_at);
+
+        db.delete_node(a)?;
+        assert!(db.select_timestamps(a)?.deleted_at.is_some());
+        let all = db.select_nodes_including_deleted(&NodeFieldName::Type.eq("test"))?;
+        assert_eq!(all.len(), 1);
+        assert_eq!((all[0].0.id, all[0].1), (a, true));
+        assert!(db.select_nodes(&NodeFieldName::Type.eq("test"))?.is_empty());
+        db.restore_node(a)?;
+        let all = db.select_nodes_including_deleted(&NodeFieldName::Type.eq("test"))?;
+        assert_eq!((all[0].0.id, all[0].1), (a, false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed() -> Result<()> {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Stats {
+            hp: u32,
+            attacks: Vec<String>,
+        }
+
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        let mut goblin = Stats {
+            hp: 7,
+            attacks: vec!["Scimitar".into()],
+        };
+        let id = db.insert_typed("Goblin", "stats", &goblin)?;
+        db.insert_node("Note", "note", &Meta::new(), b"not json")?;
+        let meta = Meta::from([("cr".to_string(), 0.25.into())]);
+        let data = db.select_node(id)?.data;
+        db.update_node(id, &meta, &data)?;
+
+        goblin.hp = 12;
+        db.update_typed(id, &goblin)?;
+        let stats = db.select_typed::<Stats, _>(&NodeFieldName::Type.eq("stats"))?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].0.meta.clone(), &stats[0].1), (meta, &goblin));
+        assert!(db
+            .select_typed::<Stats, _>(&NodeFieldName::Type.ne("tag"))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_links() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        let a = db.insert_node("Node1", "test", &Meta::new(), &[])?;
+        let b = db.insert_node("Node2", "test", &Meta::new(), &[])?;
+        let c = db.insert_node("Node3", "test", &Meta::new(), &[])?;
+        let knows = db.insert_link(a, b, "knows", None)?;
+        db.insert_link(c, a, "knows", Some(vec![1]))?;
+        db.insert_link(a, c, "hates", None)?;
+
+        let from_a = db.select_links(&LinkFieldName::Left.eq(&a.to_string()))?;
+        assert_eq!(from_a.len(), 2);
+        let known = db.select_links(
+            &LinkFieldName::Type
+                .eq("knows")
+                .and(LinkFieldName::Right.eq(&a.to_string())),
+        )?;
+        assert_eq!(known.len(), 1);
+        assert_eq!((known[0].left, known[0].data.clone()), (c, Some(vec![1])));
+        let with_data = db.select_links(&LinkFieldName::Data.like("%"))?;
+        assert_eq!(with_data.len(), 1);
+        assert_eq!(with_data[0].left, c);
+        let not_hated = db.select_links(
+            &LinkFieldName::Left
+                .r#in(&[&a.to_string(), &c.to_string()])
+                .and(LinkFieldName::Type.ne("hates")),
+        )?;
+        assert_eq!(not_hated.len(), 2);
+
+        let names = |linked: Vec<(Link, Node)>| -> Vec<String> {
+            let mut names: Vec<String> = linked.into_iter().map(|(_, n)| n.name).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(db.select_linked_nodes(a)?),
+            ["Node2", "Node3", "Node3"]
+        );
+        assert_eq!(
+            names(db.select_nodes_linked_by(a, Some("knows"))?),
+            ["Node2", "Node3"]
+        );
+        assert_eq!(
+            names(db.select_nodes_linked_by(a, Some("hates"))?),
+            ["Node3"]
+        );
+
+        db.delete_link(knows)?;
+        assert!(!db.has_link(a, b, "knows")?);
+        assert_eq!(db.select_all_links()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_rollback() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        let a = db.insert_node("Node1", "test", &Meta::new(), &[])?;
+        let res: Result<()> = db.in_transaction(|db| {
+            let b = db.insert_node("Node2", "test", &Meta::new(), &[])?;
+            db.insert_link(a, b, "knows", None)?;
+            anyhow::bail!("abort")
+        });
+        assert!(res.is_err());
+        assert_eq!(db.find_node("test", "Node2")?, None);
+        assert!(!db.has_link(a, a + 1, "knows")?);
+        assert_eq!(db.find_node("test", "Node1")?, Some(a));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_params() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        db.insert_node("O'Brien", "npc", &Meta::new(), &[])?;
+        db.insert_node("Mira", "npc", &Meta::new(), &[])?;
+        db.insert_node("Harborton", "place", &Meta::new(), &[])?;
+
+        let nodes = db.select_nodes(&NodeFieldName::Name.eq("O'Brien"))?;
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].name, "O'Brien");
+        assert!(db
+            .select_nodes(&NodeFieldName::Name.eq("x' or '1' = '1"))?
+            .is_empty());
+        assert_eq!(db.select_nodes(&NodeFieldName::Name.like("%'%"))?.len(), 1);
+        let in_filter = NodeFieldName::Name
+            .r#in(&["Mira", "Harborton", "Nobody"])
+            .and(NodeFieldName::Type.ne("place"));
+        let nodes = db.select_nodes(&in_filter)?;
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].name, "Mira");
+        Ok(())
+    }
+
+    #[test]
+    fn test_combinators() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        for (name, r#type) in [("Bob", "npc"), ("Anna", "npc"), ("Bobtown", "place")] {
+            db.insert_node(name, r#type, &Meta::new(), &[])?;
+        }
+        let names =
+            |nodes: Vec<Node>| -> Vec<String> { nodes.into_iter().map(|n| n.name).collect() };
+
+        let bobs = NodeFieldName::Type
+            .eq("npc")
+            .and(NodeFieldName::Name.like("%Bob%"));
+        assert_eq!(names(db.select_nodes(&bobs)?), ["Bob"]);
+        let either = NodeFieldName::Name
+            .eq("Anna")
+            .or(NodeFieldName::Type.eq("place"))
+            .order_by(NodeFieldName::Name, Order::Asc);
+        assert_eq!(names(db.select_nodes(&either)?), ["Anna", "Bobtown"]);
+        let not_bob = NodeFieldName::Name
+            .eq("Bob")
+            .not()
+            .order_by(NodeFieldName::Type, Order::Desc)
+            .order_by(NodeFieldName::Name, Order::Desc);
+        assert_eq!(names(db.select_nodes(&not_bob)?), ["Bobtown", "Anna"]);
+        let first = NodeFieldName::Name
+            .like("%")
+            .order_by(NodeFieldName::Name, Order::Asc)
+            .limit(2);
+        assert_eq!(names(db.select_nodes(&first)?), ["Anna", "Bob"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_nodes() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        let harbor = Meta::from([("location".to_string(), "Harborton".into())]);
+        let mira = db.insert_node("Mira Stonehand", "npc", &harbor, &[])?;
+        let oderic = db.insert_node("Oderic", "npc", &Meta::new(), &[])?;
+        db.insert_node("Harborton", "place", &Meta::new(), &[])?;
+        let ids = |nodes: Vec<Node>| -> Vec<i64> { nodes.into_iter().map(|n| n.id).collect() };
+
+        assert_eq!(ids(db.search_nodes("mira")?), [mira]);
+        assert_eq!(ids(db.search_nodes("stone")?), [mira]);
+        assert_eq!(db.search_nodes("harbor")?.len(), 2);
+        assert_eq!(ids(db.search_nodes("mira harbor")?), [mira]);
+        assert!(db.search_nodes("  ")?.is_empty());
+        assert!(db.search_nodes("\"or* AND (")?.is_empty());
+
+        db.rename_node(oderic, "Oderic the Bold")?;
+        assert_eq!(ids(db.search_nodes("bold")?), [oderic]);
+        db.update_node(mira, &Meta::new(), &[])?;
+        assert_eq!(ids(db.search_nodes("harbor")?).len(), 1);
+        db.delete_node(oderic)?;
+        assert!(db.search_nodes("oderic")?.is_empty());
+        db.restore_node(oderic)?;
+        db.purge_node(oderic)?;
+        assert!(db.search_nodes("oderic")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_meta_filter() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrations!().to_latest(&mut conn)?;
+        let mut db = DB { conn };
+        let harbor = Meta::from([
+            ("location".to_string(), "Harborton".into()),
+            ("level".to_string(), 3.into()),
+        ]);
+        let a = db.insert_node("Node1", "npc", &harbor, &[])?;
+        db.insert_node("Node2", "place", &harbor, &[])?;
+        db.insert_node("Node3", "npc", &Meta::new(), &[])?;
+
+        let filter = NodeFieldName::Type
+            .eq("npc")
+            .and(MetaKey::new("location").eq("Harborton"));
+        let nodes = db.select_nodes(&filter)?;
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].meta, harbor);
+        assert_eq!(db.select_nodes(&MetaKey::new("level").eq(3))?.len(), 2);
+        assert_eq!(db.select_nodes(&MetaKey::new("level").ne(3))?.len(), 0);
+        assert!(db.select_nodes(&MetaKey::new("it's").eq(true))?.is_empty());
+
+        // free-form text from older versions is kept as note
+        db.conn
+            .execute("update nodes set meta = 'old text' where rowid = ?", [a])?;
+        let meta = db.select_node(a)?.meta;
+        assert_eq!(meta["note"], "old text".into());
         Ok(())
     }
 }
@@ -1,4 +1,6 @@
-use std::fmt::Display;
+use rusqlite::types::Value;
+
+use crate::meta::MetaValue;
 
 #[derive(Clone, Copy)]
 pub enum NodeFieldName {
@@ -8,6 +10,17 @@ pub enum NodeFieldName {
     Data,
 }
 
+/// the columns of the links table. Left and Right are node ids, they are compared as numbers.
+/// Data is a blob, that is compared as text, and is null for most links, so `Data.like("%")`
+/// matches the links that have data
+#[derive(Clone, Copy)]
+pub enum LinkFieldName {
+    Left,
+    Right,
+    Type,
+    Data,
+}
+
 #[derive(Clone, Copy)]
 pub enum FilterOp {
     Equals,
@@ -16,14 +29,101 @@ pub enum FilterOp {
     In,
 }
 
+/// sql with a `?` for every parameter, and the parameters in the same order. The values are
+/// bound by rusqlite, so they are never part of the sql
+pub type SqlWithParams = (String, Vec<Value>);
+
+#[derive(Clone, Copy)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
 pub trait ToSql {
-    fn to_sql(&self) -> String;
+    fn to_sql(&self) -> SqlWithParams;
+
+    /// matches the rows that match both filters
+    fn and<T: ToSql>(self, other: T) -> And<Self, T>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// matches the rows that match at least one of the filters
+    fn or<T: ToSql>(self, other: T) -> Or<Self, T>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    /// matches the rows that don't match the filter
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+
+    fn order_by<C: Column>(self, column: C, order: Order) -> Query<Self>
+    where
+        Self: Sized,
+    {
+        Query::new(self).order_by(column, order)
+    }
+
+    fn limit(self, limit: u32) -> Query<Self>
+    where
+        Self: Sized,
+    {
+        Query::new(self).limit(limit)
+    }
+}
+
+/// the sql that follows the where of a select
+pub trait ToQuery {
+    fn to_query(&self) -> SqlWithParams;
+}
+
+impl<T: ToSql> ToQuery for T {
+    fn to_query(&self) -> SqlWithParams {
+        self.to_sql()
+    }
+}
+
+/// a filter with an ordering and a limit. It can't be combined with other filters anymore
+pub struct Query<F: ToSql> {
+    filter: F,
+    order: Vec<(&'static str, Order)>,
+    limit: Option<u32>,
+}
+
+/// a column that can be filtered by
+pub trait Column {
+    fn column(&self) -> &'static str;
+}
+
+pub struct And<A: ToSql, B: ToSql>(A, B);
+
+pub struct Or<A: ToSql, B: ToSql>(A, B);
+
+pub struct Not<A: ToSql>(A);
+
+/// a key of the typed metadata of a node
+pub struct MetaKey(String);
+
+pub struct MetaFilter {
+    op: FilterOp,
+    key: MetaKey,
+    val: MetaValue,
 }
 
-pub struct FieldFilter<T: ToSql> {
+pub struct FieldFilter<T: Column> {
     op: FilterOp,
     field: T,
-    val: String,
+    /// a single value, unless op is In
+    vals: Vec<String>,
 }
 
 macro_rules! descriptor_primitive {
@@ -32,7 +132,20 @@ macro_rules! descriptor_primitive {
             FieldFilter {
                 op: FilterOp::$op_name,
                 field: self,
-                val: term.into(),
+                vals: vec![term.into()],
+            }
+        }
+    };
+}
+
+macro_rules! descriptor_in {
+    ($field_type:tt) => {
+        /// matches if the field equals one of the terms
+        pub fn r#in(self, terms: &[&str]) -> FieldFilter<$field_type> {
+            FieldFilter {
+                op: FilterOp::In,
+                field: self,
+                vals: terms.iter().map(|t| t.to_string()).collect(),
             }
         }
     };
@@ -42,11 +155,11 @@ impl NodeFieldName {
     descriptor_primitive! {NodeFieldName, eq, Equals}
     descriptor_primitive! {NodeFieldName, ne, Nequals}
     descriptor_primitive! {NodeFieldName, like, Like}
-    descriptor_primitive! {NodeFieldName, r#in, In}
+    descriptor_in! {NodeFieldName}
 }
 
-impl ToSql for NodeFieldName {
-    fn to_sql(&self) -> String {
+impl Column for NodeFieldName {
+    fn column(&self) -> &'static str {
         use NodeFieldName::*;
         match self {
             Name => "name",
@@ -54,19 +167,167 @@ impl ToSql for NodeFieldName {
             Meta => "meta",
             Data => "data",
         }
-        .into()
     }
 }
 
-impl<T: ToSql> ToSql for FieldFilter<T> {
-    fn to_sql(&self) -> String {
+impl LinkFieldName {
+    descriptor_primitive! {LinkFieldName, eq, Equals}
+    descriptor_primitive! {LinkFieldName, ne, Nequals}
+    descriptor_primitive! {LinkFieldName, like, Like}
+    descriptor_in! {LinkFieldName}
+}
+
+impl Column for LinkFieldName {
+    fn column(&self) -> &'static str {
+        use LinkFieldName::*;
+        match self {
+            Left => "left",
+            Right => "right",
+            Type => "type",
+            Data => "data",
+        }
+    }
+}
+
+impl FilterOp {
+    fn sql(&self) -> &'static str {
         use FilterOp::*;
-        let opstr = match self.op {
+        match self {
             Equals => "=",
             Nequals => "!=",
             Like => "LIKE",
             In => "IN",
+        }
+    }
+}
+
+impl<T: Column> ToSql for FieldFilter<T> {
+    fn to_sql(&self) -> SqlWithParams {
+        let placeholders = match self.op {
+            FilterOp::In => format!("({})", vec!["?"; self.vals.len()].join(", ")),
+            _ => "?".into(),
+        };
+        let params = self.vals.iter().cloned().map(Value::Text).collect();
+        (
+            format!(
+                "({} {} {})",
+                self.field.column(),
+                self.op.sql(),
+                placeholders
+            ),
+            params,
+        )
+    }
+}
+
+impl<A: ToSql, B: ToSql> ToSql for And<A, B> {
+    fn to_sql(&self) -> SqlWithParams {
+        let (a, mut params) = self.0.to_sql();
+        let (b, b_params) = self.1.to_sql();
+        params.extend(b_params);
+        (format!("({} AND {})", a, b), params)
+    }
+}
+
+impl<A: ToSql, B: ToSql> ToSql for Or<A, B> {
+    fn to_sql(&self) -> SqlWithParams {
+        let (a, mut params) = self.0.to_sql();
+        let (b, b_params) = self.1.to_sql();
+        params.extend(b_params);
+        (format!("({} OR {})", a, b), params)
+    }
+}
+
+impl<A: ToSql> ToSql for Not<A> {
+    fn to_sql(&self) -> SqlWithParams {
+        let (a, params) = self.0.to_sql();
+        (format!("(NOT {})", a), params)
+    }
+}
+
+impl<F: ToSql> Query<F> {
+    fn new(filter: F) -> Query<F> {
+        Query {
+            filter,
+            order: vec![],
+            limit: None,
+        }
+    }
+
+    /// orders by the column, after the columns that were given before
+    pub fn order_by<C: Column>(mut self, column: C, order: Order) -> Query<F> {
+        self.order.push((column.column(), order));
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Query<F> {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+impl<F: ToSql> ToQuery for Query<F> {
+    fn to_query(&self) -> SqlWithParams {
+        let (mut sql, mut params) = self.filter.to_sql();
+        if !self.order.is_empty() {
+            let order = self
+                .order
+                .iter()
+                .map(|(column, order)| match order {
+                    Order::Asc => format!("{} ASC", column),
+                    Order::Desc => format!("{} DESC", column),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            sql = format!("{} ORDER BY {}", sql, order);
+        }
+        if let Some(limit) = self.limit {
+            sql.push_str(" LIMIT ?");
+            params.push(Value::Integer(limit.into()));
+        }
+        (sql, params)
+    }
+}
+
+impl MetaKey {
+    pub fn new(key: &str) -> MetaKey {
+        MetaKey(key.into())
+    }
+
+    pub fn eq(self, val: impl Into<MetaValue>) -> MetaFilter {
+        self.filter(FilterOp::Equals, val.into())
+    }
+
+    pub fn ne(self, val: impl Into<MetaValue>) -> MetaFilter {
+        self.filter(FilterOp::Nequals, val.into())
+    }
+
+    pub fn like(self, pattern: &str) -> MetaFilter {
+        self.filter(FilterOp::Like, pattern.into())
+    }
+
+    fn filter(self, op: FilterOp, val: MetaValue) -> MetaFilter {
+        MetaFilter { op, key: self, val }
+    }
+
+    /// the json path of the key
+    fn path(&self) -> String {
+        // double quotes can't be escaped in json paths
+        format!("$.\"{}\"", self.0.replace('"', ""))
+    }
+}
+
+impl ToSql for MetaFilter {
+    fn to_sql(&self) -> SqlWithParams {
+        // json_extract returns true and false as 1 and 0
+        let val = match &self.val {
+            MetaValue::Bool(b) => Value::Integer(*b as i64),
+            MetaValue::Number(n) => Value::Real(*n),
+            MetaValue::Text(s) => Value::Text(s.clone()),
         };
-        format!("({} {} {})", self.field.to_sql(), opstr, self.val)
+        (
+            format!("(json_extract(meta, ?) {} ?)", self.op.sql()),
+            vec![Value::Text(self.key.path()), val],
+        )
     }
 }
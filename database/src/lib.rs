@@ -1,3 +1,5 @@
+pub mod attachments;
 pub mod db;
 pub mod dsl;
+pub mod meta;
 pub mod schema;
@@ -0,0 +1,92 @@
+//! The meta column of a node holds typed key-value pairs as a json object, so they can be
+//! filtered on with dsl::MetaKey, without decoding the data of the node.
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub type Meta = BTreeMap<String, MetaValue>;
+
+/// free-form text in the meta column from before it was typed is kept under this key
+pub const LEGACY_META_KEY: &str = "note";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MetaValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl Display for MetaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaValue::Bool(b) => write!(f, "{}", b),
+            MetaValue::Number(n) => write!(f, "{}", n),
+            MetaValue::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<bool> for MetaValue {
+    fn from(b: bool) -> MetaValue {
+        MetaValue::Bool(b)
+    }
+}
+
+impl From<f64> for MetaValue {
+    fn from(n: f64) -> MetaValue {
+        MetaValue::Number(n)
+    }
+}
+
+impl From<i64> for MetaValue {
+    fn from(n: i64) -> MetaValue {
+        MetaValue::Number(n as f64)
+    }
+}
+
+impl From<&str> for MetaValue {
+    fn from(s: &str) -> MetaValue {
+        MetaValue::Text(s.into())
+    }
+}
+
+impl From<String> for MetaValue {
+    fn from(s: String) -> MetaValue {
+        MetaValue::Text(s)
+    }
+}
+
+/// decodes the content of the meta column. Anything that isn't a json object is free-form text
+/// from an older version, and kept under LEGACY_META_KEY
+pub fn from_column(text: Option<String>) -> Meta {
+    match text {
+        None => Meta::new(),
+        Some(text) => serde_json::from_str(&text)
+            .unwrap_or_else(|_| Meta::from([(LEGACY_META_KEY.to_string(), MetaValue::Text(text))])),
+    }
+}
+
+/// the content of the meta column, which is null for empty metadata
+pub fn to_column(meta: &Meta) -> Option<String> {
+    if meta.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(meta).expect("metadata can always be serialized"))
+    }
+}
+
+/// deserializes metadata that older versions wrote as the text of the meta column, or as null
+pub fn deserialize_lenient<'de, D: Deserializer<'de>>(d: D) -> Result<Meta, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Lenient {
+        Typed(Meta),
+        Text(Option<String>),
+    }
+    Ok(match Lenient::deserialize(d)? {
+        Lenient::Typed(meta) => meta,
+        Lenient::Text(text) => from_column(text),
+    })
+}
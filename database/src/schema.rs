@@ -14,3 +14,69 @@ CREATE TABLE links (
     type text not null,
     data blob
 );";
+
+/// deleted_at is a unix timestamp. Nodes where it is set are in the trash
+pub const ADD_DELETED_AT_STMT: &str =
+"ALTER TABLE nodes ADD COLUMN deleted_at integer;";
+
+/// the state of every node at the last sync, so local changes can be told apart from remote ones.
+/// node is null if the node was purged locally
+pub const CREATE_SYNC_NODES_STMT: &str =
+"CREATE TABLE sync_nodes (
+    uid text primary key,
+    node int,
+    name text not null,
+    type text not null,
+    meta text,
+    data blob not null,
+    deleted int not null
+);";
+
+/// meta holds a json object now. Free-form text from before is moved to the note key
+pub const META_TO_JSON_STMT: &str =
+"UPDATE nodes SET meta = json_object('note', meta)
+WHERE meta IS NOT NULL
+    AND CASE WHEN json_valid(meta) THEN json_type(meta) != 'object' ELSE 1 END;";
+
+/// full-text index over the names and meta of the nodes. The triggers keep it in sync with the
+/// nodes table, which holds the content
+pub const CREATE_NODES_FTS_STMT: &str =
+"CREATE VIRTUAL TABLE nodes_fts USING fts5(name, meta, content='nodes', content_rowid='rowid');
+
+CREATE TRIGGER nodes_fts_insert AFTER INSERT ON nodes BEGIN
+    INSERT INTO nodes_fts(rowid, name, meta) VALUES (new.rowid, new.name, new.meta);
+END;
+
+CREATE TRIGGER nodes_fts_delete AFTER DELETE ON nodes BEGIN
+    INSERT INTO nodes_fts(nodes_fts, rowid, name, meta)
+    VALUES ('delete', old.rowid, old.name, old.meta);
+END;
+
+CREATE TRIGGER nodes_fts_update AFTER UPDATE OF name, meta ON nodes BEGIN
+    INSERT INTO nodes_fts(nodes_fts, rowid, name, meta)
+    VALUES ('delete', old.rowid, old.name, old.meta);
+    INSERT INTO nodes_fts(rowid, name, meta) VALUES (new.rowid, new.name, new.meta);
+END;
+
+INSERT INTO nodes_fts(nodes_fts) VALUES ('rebuild');";
+
+/// unix timestamps of the creation and the last change of the name, type, meta or data of a node.
+/// The triggers set them, so every insert and update keeps them, no matter where it happens.
+/// Nodes from before get the time of the migration
+pub const ADD_NODE_TIMESTAMPS_STMT: &str =
+"ALTER TABLE nodes ADD COLUMN created_at integer;
+ALTER TABLE nodes ADD COLUMN updated_at integer;
+
+UPDATE nodes SET created_at = cast(strftime('%s', 'now') as integer),
+    updated_at = cast(strftime('%s', 'now') as integer);
+
+CREATE TRIGGER nodes_created AFTER INSERT ON nodes BEGIN
+    UPDATE nodes SET created_at = cast(strftime('%s', 'now') as integer),
+        updated_at = cast(strftime('%s', 'now') as integer)
+    WHERE rowid = new.rowid;
+END;
+
+CREATE TRIGGER nodes_updated AFTER UPDATE OF name, type, meta, data ON nodes BEGIN
+    UPDATE nodes SET updated_at = cast(strftime('%s', 'now') as integer)
+    WHERE rowid = new.rowid;
+END;";
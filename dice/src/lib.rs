@@ -0,0 +1,538 @@
+//! Dice expressions like `2d6+3`, `1d20-1d4` or `2d6*10`, which are parsed once and can be rolled
+//! many times. Dice can keep or drop some of their rolls: `4d6dl` drops the lowest, `2d20kh`
+//! keeps the highest, and `kl`, `dh` take a count like `4d6kh3`. `adv` and `dis` are a d20 with
+//! advantage or disadvantage. Exploding dice like `3d6!` roll again when they show their highest
+//! side, and add the new roll.
+//!
+//! Words like `DEX` are variables, whose values are passed when the expression is rolled.
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use rand::Rng;
+
+/// more dice than this in a single term are most likely a typo
+const MAX_DICE: i64 = 1000;
+
+/// a sum of products, like `2d6*10+3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub terms: Vec<Term>,
+}
+
+/// a product of factors, which is added to or subtracted from the expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    /// 1 or -1
+    pub sign: i64,
+    pub factors: Vec<Factor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Factor {
+    Const(i64),
+    Dice(Dice),
+    /// a value that is passed when rolling, like DEX. The name is uppercase
+    Var(String),
+}
+
+/// like `4d6dl` or `3d6!`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dice {
+    pub n: i64,
+    pub sides: i64,
+    pub explode: bool,
+    pub selection: Option<Selection>,
+}
+
+/// which of the rolls of some dice count, the number is how many dice are kept or dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    KeepHighest(i64),
+    KeepLowest(i64),
+    DropHighest(i64),
+    DropLowest(i64),
+}
+
+/// the total of a roll, and the dice it was rolled with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rolled {
+    /// the expression, as it was entered
+    pub expr: String,
+    pub total: i64,
+    pub dice: Vec<DiceRolls>,
+}
+
+/// the rolls of one dice factor, like the four rolls of 4d6dl
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceRolls {
+    pub sides: i64,
+    /// the value of each die. An exploding die shows the sum of all its rolls
+    pub rolls: Vec<i64>,
+    /// whether the roll with the same index counts, or was dropped
+    pub kept: Vec<bool>,
+}
+
+/// rolls an expression without variables
+pub fn roll(expr: &str) -> Result<i64> {
+    roll_with(expr, &mut rand::thread_rng())
+}
+
+pub fn roll_with<R: Rng + ?Sized>(expr: &str, rng: &mut R) -> Result<i64> {
+    roll_detailed_with(expr, rng).map(|rolled| rolled.total)
+}
+
+/// like roll, but also returns the single dice, to show them
+pub fn roll_detailed(expr: &str) -> Result<Rolled> {
+    roll_detailed_with(expr, &mut rand::thread_rng())
+}
+
+pub fn roll_detailed_with<R: Rng + ?Sized>(expr: &str, rng: &mut R) -> Result<Rolled> {
+    let rolled = expr.parse::<Expr>()?.roll_with(rng, |_| None)?;
+    Ok(Rolled {
+        expr: expr.trim().to_string(),
+        ..rolled
+    })
+}
+
+impl Expr {
+    /// vars gives the values of the variables, an unknown one is an error
+    pub fn roll_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        vars: impl Fn(&str) -> Option<i64>,
+    ) -> Result<Rolled> {
+        let mut total = 0;
+        let mut dice = vec![];
+        for term in &self.terms {
+            let mut product = 1;
+            for factor in &term.factors {
+                product *= match factor {
+                    Factor::Const(c) => *c,
+                    Factor::Dice(d) => {
+                        let rolls = d.roll_with(rng);
+                        let sum = rolls.total();
+                        dice.push(rolls);
+                        sum
+                    }
+                    Factor::Var(name) => {
+                        vars(name).ok_or_else(|| anyhow!("{} is unknown", name))?
+                    }
+                };
+            }
+            total += term.sign * product;
+        }
+        Ok(Rolled {
+            expr: self.to_string(),
+            total,
+            dice,
+        })
+    }
+
+    pub fn has_dice(&self) -> bool {
+        self.factors().any(|f| matches!(f, Factor::Dice(_)))
+    }
+
+    pub fn factors(&self) -> impl Iterator<Item = &Factor> {
+        self.terms.iter().flat_map(|t| &t.factors)
+    }
+}
+
+impl Dice {
+    pub fn new(n: i64, sides: i64) -> Dice {
+        Dice {
+            n,
+            sides,
+            explode: false,
+            selection: None,
+        }
+    }
+
+    pub fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> DiceRolls {
+        let rolls: Vec<i64> = (0..self.n)
+            .map(|_| {
+                let mut value = rng.gen_range(1..=self.sides);
+                let mut last = value;
+                // a d1 would explode forever, the parser doesn't allow it
+                while self.explode && self.sides > 1 && last == self.sides {
+                    last = rng.gen_range(1..=self.sides);
+                    value += last;
+                }
+                value
+            })
+            .collect();
+        let kept = match self.selection {
+            Some(selection) => selection.select(&rolls),
+            None => vec![true; rolls.len()],
+        };
+        DiceRolls {
+            sides: self.sides,
+            rolls,
+            kept,
+        }
+    }
+}
+
+impl Selection {
+    fn select(self, rolls: &[i64]) -> Vec<bool> {
+        let len = rolls.len() as i64;
+        // how many of the highest or lowest rolls are kept
+        let (highest, n_kept) = match self {
+            Selection::KeepHighest(n) => (true, n),
+            Selection::KeepLowest(n) => (false, n),
+            Selection::DropHighest(n) => (false, len - n),
+            Selection::DropLowest(n) => (true, len - n),
+        };
+        let mut order: Vec<usize> = (0..rolls.len()).collect();
+        order.sort_by_key(|i| rolls[*i]);
+        if highest {
+            order.reverse();
+        }
+        let mut kept = vec![false; rolls.len()];
+        for i in &order[..n_kept.clamp(0, len) as usize] {
+            kept[*i] = true;
+        }
+        kept
+    }
+
+    fn count(self) -> i64 {
+        match self {
+            Selection::KeepHighest(n)
+            | Selection::KeepLowest(n)
+            | Selection::DropHighest(n)
+            | Selection::DropLowest(n) => n,
+        }
+    }
+}
+
+impl DiceRolls {
+    /// the sum of the kept rolls
+    pub fn total(&self) -> i64 {
+        self.rolls
+            .iter()
+            .zip(&self.kept)
+            .filter(|(_, kept)| **kept)
+            .map(|(roll, _)| roll)
+            .sum()
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Expr> {
+        let compact: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        ensure!(!compact.is_empty(), "The dice expression is empty");
+        let terms = split_terms(&compact)
+            .into_iter()
+            .map(|(sign, term)| {
+                let factors = term
+                    .split('*')
+                    .map(|factor| parse_factor(factor, &compact))
+                    .collect::<Result<_>>()?;
+                Ok(Term { sign, factors })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Expr { terms })
+    }
+}
+
+/// splits the expression at + and -, and returns the terms with their sign
+fn split_terms(expr: &str) -> Vec<(i64, &str)> {
+    let mut res = vec![];
+    let mut sign = 1;
+    let mut start = 0;
+    for (i, c) in expr.char_indices() {
+        if c == '+' || c == '-' {
+            // a leading sign belongs to the first term
+            if i > 0 {
+                res.push((sign, &expr[start..i]));
+            }
+            sign = if c == '+' { 1 } else { -1 };
+            start = i + 1;
+        }
+    }
+    res.push((sign, &expr[start..]));
+    res
+}
+
+fn parse_factor(factor: &str, expr: &str) -> Result<Factor> {
+    if factor.is_empty() {
+        bail!("Missing number in dice expression {}", expr);
+    }
+    let lower = factor.to_lowercase();
+    let d20 = |selection| {
+        Factor::Dice(Dice {
+            selection: Some(selection),
+            ..Dice::new(2, 20)
+        })
+    };
+    match lower.as_str() {
+        "adv" => return Ok(d20(Selection::KeepHighest(1))),
+        "dis" => return Ok(d20(Selection::KeepLowest(1))),
+        _ => {}
+    }
+    if factor.chars().all(|c| c.is_ascii_digit()) {
+        return parse_number(factor, expr).map(Factor::Const);
+    }
+    if let Some((n, rest)) = lower.split_once('d') {
+        let is_dice = n.chars().all(|c| c.is_ascii_digit())
+            && (!n.is_empty() || rest.starts_with(|c: char| c.is_ascii_digit()));
+        if is_dice {
+            return parse_dice(n, rest, expr).map(Factor::Dice);
+        }
+    }
+    if factor.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok(Factor::Var(factor.to_uppercase()));
+    }
+    bail!(
+        "{} is neither dice like 2d6, a number, nor a variable like DEX",
+        factor
+    )
+}
+
+/// n is the part before the d, rest the sides, followed by ! and the selection, like `6!kh3`
+fn parse_dice(n: &str, rest: &str, expr: &str) -> Result<Dice> {
+    let n = if n.is_empty() {
+        1
+    } else {
+        parse_number(n, expr)?
+    };
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let (sides, rest) = rest.split_at(digits);
+    let sides = parse_number(sides, expr)?;
+    ensure!(n > 0, "Rolling no dice makes no sense: {}", expr);
+    ensure!(n <= MAX_DICE, "{} are too many dice", n);
+    ensure!(sides > 0, "A die needs at least one side: {}", expr);
+    let (explode, selection) = match rest.strip_prefix('!') {
+        Some(selection) => (true, selection),
+        None => (false, rest),
+    };
+    ensure!(!explode || sides > 1, "A d1 can't explode: {}", expr);
+    Ok(Dice {
+        n,
+        sides,
+        explode,
+        selection: parse_selection(selection, n, expr)?,
+    })
+}
+
+/// like `kh3`, which keeps the three highest, or `dl`, which drops the lowest
+fn parse_selection(selection: &str, n: i64, expr: &str) -> Result<Option<Selection>> {
+    if selection.is_empty() {
+        return Ok(None);
+    }
+    let unknown = || {
+        format!(
+            "{} is not a selection in {}, use kh, kl, dh or dl with an optional count",
+            selection, expr
+        )
+    };
+    let mode = selection.get(..2).with_context(unknown)?;
+    let count = match &selection[2..] {
+        "" => 1,
+        count => parse_number(count, expr)?,
+    };
+    ensure!(
+        count <= n,
+        "{} can't keep or drop {} of {} dice",
+        expr,
+        count,
+        n
+    );
+    Ok(Some(match mode {
+        "kh" => Selection::KeepHighest(count),
+        "kl" => Selection::KeepLowest(count),
+        "dh" => Selection::DropHighest(count),
+        "dl" => Selection::DropLowest(count),
+        _ => bail!(unknown()),
+    }))
+}
+
+fn parse_number(s: &str, expr: &str) -> Result<i64> {
+    if s.is_empty() {
+        bail!("Missing number in dice expression {}", expr);
+    }
+    s.parse()
+        .with_context(|| format!("Invalid dice expression {}", expr))
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            match (i, term.sign) {
+                (_, -1) => write!(f, "-")?,
+                (0, _) => {}
+                _ => write!(f, "+")?,
+            }
+            let factors: Vec<String> = term.factors.iter().map(|f| f.to_string()).collect();
+            write!(f, "{}", factors.join("*"))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Factor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Factor::Const(c) => write!(f, "{}", c),
+            Factor::Dice(d) => write!(f, "{}", d),
+            Factor::Var(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}d{}", self.n, self.sides)?;
+        if self.explode {
+            write!(f, "!")?;
+        }
+        if let Some(selection) = self.selection {
+            let mode = match selection {
+                Selection::KeepHighest(_) => "kh",
+                Selection::KeepLowest(_) => "kl",
+                Selection::DropHighest(_) => "dh",
+                Selection::DropLowest(_) => "dl",
+            };
+            write!(f, "{}", mode)?;
+            if selection.count() != 1 {
+                write!(f, "{}", selection.count())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DiceRolls {
+    /// like `d6: 6, 4, 3, (1)`, dropped rolls are in parentheses
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rolls: Vec<String> = self
+            .rolls
+            .iter()
+            .zip(&self.kept)
+            .map(|(roll, kept)| {
+                if *kept {
+                    roll.to_string()
+                } else {
+                    format!("({})", roll)
+                }
+            })
+            .collect();
+        write!(f, "d{}: {}", self.sides, rolls.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_roll() {
+        assert_eq!(roll("2 + 3").unwrap(), 5);
+        assert_eq!(roll("-1d1*10+4").unwrap(), -6);
+        for _ in 0..20 {
+            let gold = roll("2d6*10").unwrap();
+            assert!((20..=120).contains(&gold) && gold % 10 == 0);
+        }
+        for invalid in [
+            "", "2d", "d0", "0d6", "2*", "3x", "1d20+", "4d6dx", "4d6k", "2d6kh3", "2d6dlx",
+            "1d1!", "DEX", "2d6!!",
+        ] {
+            assert!(roll(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_keep_and_drop() {
+        for _ in 0..20 {
+            let rolled = roll_detailed("4d6 dl + 1").unwrap();
+            let dice = &rolled.dice[0];
+            let mut rolls = dice.rolls.clone();
+            rolls.sort();
+            assert_eq!(rolled.total, rolls[1..].iter().sum::<i64>() + 1);
+            assert_eq!(dice.kept.iter().filter(|k| !**k).count(), 1);
+            let lowest = dice.kept.iter().position(|k| !k).unwrap();
+            assert_eq!(dice.rolls[lowest], rolls[0]);
+
+            let adv = roll_detailed("ADV").unwrap();
+            assert_eq!(adv.total, *adv.dice[0].rolls.iter().max().unwrap());
+            let dis = roll_detailed("dis").unwrap();
+            assert_eq!(dis.total, *dis.dice[0].rolls.iter().min().unwrap());
+            assert_eq!(roll("3d1kh2").unwrap(), 2);
+            assert_eq!(roll("3d1dh2").unwrap(), 1);
+        }
+        let dice = DiceRolls {
+            sides: 6,
+            rolls: vec![6, 1, 4],
+            kept: vec![true, false, true],
+        };
+        assert_eq!(dice.to_string(), "d6: 6, (1), 4");
+    }
+
+    #[test]
+    fn test_variables_and_display() {
+        let expr: Expr = "d20 + dex - 1".parse().unwrap();
+        assert_eq!(expr.to_string(), "1d20+DEX-1");
+        let dex = |name: &str| (name == "DEX").then_some(3);
+        let total = "1d1+DEX*2".parse::<Expr>().unwrap();
+        assert_eq!(
+            total.roll_with(&mut rand::thread_rng(), dex).unwrap().total,
+            7
+        );
+        assert!("1d1+WIS"
+            .parse::<Expr>()
+            .unwrap()
+            .roll_with(&mut rand::thread_rng(), dex)
+            .is_err());
+        assert!(!"2+DEX".parse::<Expr>().unwrap().has_dice());
+        assert_eq!("4D6!KH3".parse::<Expr>().unwrap().to_string(), "4d6!kh3");
+        assert_eq!("-adv".parse::<Expr>().unwrap().to_string(), "-2d20kh");
+    }
+
+    fn selection() -> impl Strategy<Value = Option<&'static str>> {
+        prop::option::of(prop::sample::select(vec!["kh", "kl", "dh", "dl"]))
+    }
+
+    proptest! {
+        #[test]
+        fn test_distribution_bounds(
+            n in 1..20i64,
+            sides in 2..100i64,
+            explode: bool,
+            selection in selection(),
+            count in 0..20i64,
+            modifier in -50..50i64,
+        ) {
+            let count = count.min(n);
+            let text = format!(
+                "{}d{}{}{}{:+}",
+                n,
+                sides,
+                if explode { "!" } else { "" },
+                selection.map(|s| format!("{}{}", s, count)).unwrap_or_default(),
+                modifier
+            );
+            let expr: Expr = text.parse().unwrap();
+            prop_assert_eq!(expr.to_string().parse::<Expr>().unwrap(), expr.clone());
+            let rolled = expr.roll_with(&mut rand::thread_rng(), |_| None).unwrap();
+            let dice = &rolled.dice[0];
+            let kept = match selection {
+                None => n,
+                Some("kh") | Some("kl") => count,
+                _ => n - count,
+            };
+            prop_assert_eq!(dice.kept.iter().filter(|k| **k).count() as i64, kept);
+            for roll in &dice.rolls {
+                prop_assert!(*roll >= 1);
+                prop_assert!(explode || *roll <= sides);
+                // an exploded die never ends on its highest side
+                prop_assert!(!explode || roll % sides != 0);
+            }
+            prop_assert!(rolled.total >= kept + modifier);
+            prop_assert!(explode || rolled.total <= kept * sides + modifier);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! The envelope of the files the tools write, like saved encounters and bundles. Every file has
+//! a `format_version` next to its content, and is upgraded to the current version when it is
+//! loaded, so a change of the format doesn't make the files of older versions unreadable.
+//!
+//! A format is upgraded by migrations, which change the content of a file while it is still
+//! untyped. The migration at index i upgrades version i to version i + 1, so the current version
+//! is the number of migrations, and files without a version are version 0.
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+pub const VERSION_KEY: &str = "format_version";
+
+/// changes the top level table of a file to the layout of the next version
+pub type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+pub struct Format {
+    /// what the files are called in errors, like "saved encounter"
+    pub name: &'static str,
+    /// where the version was stored before VERSION_KEY was used
+    pub legacy_key: Option<&'static str>,
+    pub migrations: &'static [Migration],
+}
+
+/// a migration for versions that were written before the format changed, like the version 0
+/// of formats that always had a version
+pub fn unchanged(_: &mut Map<String, Value>) -> Result<()> {
+    Ok(())
+}
+
+impl Format {
+    pub fn version(&self) -> u32 {
+        self.migrations.len() as u32
+    }
+
+    pub fn to_json<T: Serialize>(&self, content: &T) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.wrap(content)?)?)
+    }
+
+    pub fn to_toml<T: Serialize>(&self, content: &T) -> Result<String> {
+        // toml::Value writes plain values before tables, which toml requires
+        let value = toml::Value::try_from(self.wrap(content)?)?;
+        Ok(toml::to_string(&value)?)
+    }
+
+    pub fn from_json<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        let value = serde_json::from_str(text)
+            .with_context(|| format!("This is not a valid {}", self.name))?;
+        self.unwrap(value)
+    }
+
+    pub fn from_toml<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        let value: toml::Value =
+            toml::from_str(text).with_context(|| format!("This is not a valid {}", self.name))?;
+        self.unwrap(serde_json::to_value(value)?)
+    }
+
+    fn wrap<T: Serialize>(&self, content: &T) -> Result<Value> {
+        let mut value = serde_json::to_value(content)?;
+        let table = value
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("A {} must be a table", self.name))?;
+        table.insert(VERSION_KEY.into(), self.version().into());
+        Ok(value)
+    }
+
+    /// upgrades the file to the current version, and decodes its content
+    fn unwrap<T: DeserializeOwned>(&self, value: Value) -> Result<T> {
+        let mut table = match value {
+            Value::Object(table) => table,
+            _ => bail!("A {} must be a table", self.name),
+        };
+        let version = match table.remove(VERSION_KEY) {
+            Some(version) => Some(version),
+            None => self.legacy_key.and_then(|key| table.remove(key)),
+        };
+        let version = match version {
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| anyhow!("The version of a {} must be a number", self.name))?
+                as u32,
+        };
+        ensure!(
+            version <= self.version(),
+            "The {} has version {}, but only versions up to {} are supported. It was written by \
+             a newer version",
+            self.name,
+            version,
+            self.version()
+        );
+        for (v, migrate) in self.migrations.iter().enumerate().skip(version as usize) {
+            migrate(&mut table).with_context(|| {
+                format!("Could not upgrade the {} from version {}", self.name, v)
+            })?;
+        }
+        serde_json::from_value(Value::Object(table))
+            .with_context(|| format!("This is not a valid {}", self.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sheet {
+        name: String,
+        hit_points: i64,
+        skills: Vec<Skill>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Skill {
+        name: String,
+    }
+
+    /// version 1 called the hit points hp
+    fn rename_hp(table: &mut Map<String, Value>) -> Result<()> {
+        let hp = table.remove("hp").ok_or_else(|| anyhow!("hp is missing"))?;
+        table.insert("hit_points".into(), hp);
+        Ok(())
+    }
+
+    const SHEET: Format = Format {
+        name: "character sheet",
+        legacy_key: Some("version"),
+        migrations: &[unchanged, rename_hp],
+    };
+
+    fn sheet() -> Sheet {
+        Sheet {
+            name: "Tilda".into(),
+            hit_points: 7,
+            skills: vec![Skill {
+                name: "Sneak".into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = SHEET.to_json(&sheet()).unwrap();
+        assert!(json.contains("\"format_version\": 2"));
+        assert_eq!(SHEET.from_json::<Sheet>(&json).unwrap(), sheet());
+
+        let toml = SHEET.to_toml(&sheet()).unwrap();
+        assert!(toml.contains("format_version = 2"));
+        assert_eq!(SHEET.from_toml::<Sheet>(&toml).unwrap(), sheet());
+    }
+
+    #[test]
+    fn test_migrations() {
+        let old = r#"{"name": "Tilda", "hp": 7, "skills": [{"name": "Sneak"}]}"#;
+        assert_eq!(SHEET.from_json::<Sheet>(old).unwrap(), sheet());
+        let legacy = "version = 1\nname = \"Tilda\"\nhp = 7\n[[skills]]\nname = \"Sneak\"\n";
+        assert_eq!(SHEET.from_toml::<Sheet>(legacy).unwrap(), sheet());
+
+        let err = SHEET
+            .from_json::<Sheet>(r#"{"format_version": 1, "name": "Tilda"}"#)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Could not upgrade the character sheet from version 1: hp is missing"
+        );
+        assert!(SHEET
+            .from_json::<Sheet>(r#"{"format_version": 3, "name": "Tilda"}"#)
+            .is_err());
+        assert!(SHEET.from_json::<Sheet>("[1, 2]").is_err());
+    }
+}